@@ -0,0 +1,369 @@
+//! Development collector for the UDP target.
+//!
+//! Listens where `Target::UdpJson` sends records, pretty-prints every received
+//! record and reports throughput once per second:
+//!
+//! ```sh
+//! cargo run --example collector -- [--port <port>]... [--raw] [--timestamp-key <key>]
+//! ```
+use serde_json::Value;
+use std::{
+    env,
+    net::UdpSocket,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use vinted_logger::validate;
+
+/// Where `Target::UdpJson` sends records by default, and the port of fluentd
+/// setups listening for the Vinted apps next to 9091.
+const DEFAULT_PORTS: &[u16] = &[9091, 5005];
+
+/// Key of the timestamp of records, unless the logger sets another one
+const DEFAULT_TIMESTAMP_KEY: &str = "@timestamp";
+
+/// Keys every record produced by `VintedJson` carries, besides its timestamp.
+const REQUIRED_KEYS: &[&str] = &["level", "facility", "target", "host", "message"];
+
+const USAGE: &str = "Usage: collector [--port <port>]... [--raw] [--timestamp-key <key>]
+
+Options:
+    -p, --port <port>          UDP port to listen on, can be repeated (default: 9091 and 5005)
+    -r, --raw                  dump received bytes instead of pretty-printed JSON
+    -t, --timestamp-key <key>  key of the timestamp of records (default: @timestamp)
+    -h, --help                 print this message";
+
+#[derive(Debug)]
+struct Args {
+    ports: Vec<u16>,
+    raw: bool,
+    timestamp_key: String,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut ports = Vec::new();
+    let mut raw = false;
+    let mut timestamp_key = DEFAULT_TIMESTAMP_KEY.to_owned();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--port" => {
+                let port = args
+                    .next()
+                    .ok_or_else(|| format!("`{}` expects a port number", arg))?;
+                ports.push(
+                    port.parse()
+                        .map_err(|e| format!("invalid port `{}`: {}", port, e))?,
+                );
+            }
+            "-r" | "--raw" => raw = true,
+            "-t" | "--timestamp-key" => {
+                timestamp_key = args
+                    .next()
+                    .filter(|key| !key.is_empty())
+                    .ok_or_else(|| format!("`{}` expects a key", arg))?;
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+
+    if ports.is_empty() {
+        ports.extend_from_slice(DEFAULT_PORTS);
+    }
+
+    Ok(Args {
+        ports,
+        raw,
+        timestamp_key,
+    })
+}
+
+/// Checks that a line is a single JSON object carrying the envelope keys,
+/// with its timestamp under `timestamp_key`.
+fn validate_record(bytes: &[u8], timestamp_key: &str) -> Result<Value, String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| format!("invalid UTF-8: {}", e))?;
+    let text = text
+        .strip_suffix('\n')
+        .ok_or("record is not terminated by a newline")?;
+    if text.contains('\n') {
+        return Err("record contains more than one line".to_owned());
+    }
+
+    let value: Value = serde_json::from_str(text).map_err(|e| format!("invalid JSON: {}", e))?;
+    let record = value.as_object().ok_or("record is not a JSON object")?;
+    let missing: Vec<_> = std::iter::once(&timestamp_key)
+        .chain(REQUIRED_KEYS)
+        .filter(|key| !record.contains_key(**key))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("missing keys {:?}", missing));
+    }
+
+    Ok(value)
+}
+
+/// Checks every record of a datagram, which holds several of them, one per
+/// line, when the logger batches records.
+fn validate_datagram(bytes: &[u8], timestamp_key: &str) -> Result<Vec<Value>, String> {
+    let lines = validate::decode_batch(bytes).map_err(|e| e.to_string())?;
+    lines
+        .iter()
+        .enumerate()
+        .map(|(n, line)| {
+            validate_record(line.as_bytes(), timestamp_key)
+                .map_err(|e| format!("record {}: {}", n + 1, e))
+        })
+        .collect()
+}
+
+/// Prints the records of a datagram, returning how many it holds.
+fn print_datagram(port: u16, bytes: &[u8], raw: bool, timestamp_key: &str) -> u64 {
+    if raw {
+        println!(
+            "[{}] {} bytes: {:?}",
+            port,
+            bytes.len(),
+            String::from_utf8_lossy(bytes)
+        );
+        return 1;
+    }
+
+    match validate_datagram(bytes, timestamp_key) {
+        Ok(records) => {
+            for record in &records {
+                match serde_json::to_string_pretty(record) {
+                    Ok(pretty) => println!("[{}] {}", port, pretty),
+                    Err(e) => eprintln!("[{}] can't print record: {}", port, e),
+                }
+            }
+            records.len() as u64
+        }
+        Err(e) => {
+            eprintln!(
+                "[{}] invalid datagram ({}): {}",
+                port,
+                e,
+                String::from_utf8_lossy(bytes)
+            );
+            1
+        }
+    }
+}
+
+fn listen(socket: UdpSocket, port: u16, raw: bool, timestamp_key: &str, received: Arc<AtomicU64>) {
+    let mut buf = vec![0; 65_536];
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            // Examples probe for a running collector with empty datagrams.
+            Ok((0, _)) => {}
+            Ok((len, _)) => {
+                let records = print_datagram(port, &buf[..len], raw, timestamp_key);
+                received.fetch_add(records, Ordering::Relaxed);
+            }
+            Err(e) => eprintln!("[{}] can't receive datagram: {}", port, e),
+        }
+    }
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let received = Arc::new(AtomicU64::new(0));
+
+    for &port in &args.ports {
+        let socket = match UdpSocket::bind(("127.0.0.1", port)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("Couldn't bind to 127.0.0.1:{}: {}", port, e);
+                process::exit(1);
+            }
+        };
+        eprintln!("Listening on 127.0.0.1:{}", port);
+
+        let received = received.clone();
+        let raw = args.raw;
+        let timestamp_key = args.timestamp_key.clone();
+        thread::spawn(move || listen(socket, port, raw, &timestamp_key, received));
+    }
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let count = received.swap(0, Ordering::Relaxed);
+        if count > 0 {
+            eprintln!("-- {} records/s", count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD: &str = concat!(
+        r#"{"@timestamp":"2024-01-01T00:00:00.000Z","level":"INFO","#,
+        r#""facility":"svc","target":"app","host":"box","message":"hi"}"#,
+        "\n"
+    );
+
+    #[test]
+    fn valid_record_is_accepted() {
+        let record = validate_record(RECORD.as_bytes(), DEFAULT_TIMESTAMP_KEY).unwrap();
+        assert_eq!(record["message"], "hi");
+    }
+
+    #[test]
+    fn record_without_a_newline_is_rejected() {
+        let err = validate_record(RECORD.trim_end().as_bytes(), DEFAULT_TIMESTAMP_KEY).unwrap_err();
+        assert!(err.contains("newline"), "{}", err);
+    }
+
+    #[test]
+    fn missing_keys_are_listed() {
+        let err = validate_record(
+            b"{\"level\":\"INFO\",\"message\":\"hi\"}\n",
+            DEFAULT_TIMESTAMP_KEY,
+        )
+        .unwrap_err();
+        assert!(err.starts_with("missing keys"), "{}", err);
+        assert!(
+            err.contains("@timestamp") && err.contains("host"),
+            "{}",
+            err
+        );
+        assert!(!err.contains("level"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_json_is_rejected() {
+        let err = validate_record(b"{\"level\":\n", DEFAULT_TIMESTAMP_KEY).unwrap_err();
+        assert!(err.starts_with("invalid JSON"), "{}", err);
+
+        let err = validate_record(b"[1, 2]\n", DEFAULT_TIMESTAMP_KEY).unwrap_err();
+        assert_eq!(err, "record is not a JSON object");
+
+        let err = validate_record(b"\xff\n", DEFAULT_TIMESTAMP_KEY).unwrap_err();
+        assert!(err.starts_with("invalid UTF-8"), "{}", err);
+    }
+
+    #[test]
+    fn timestamps_can_have_another_key() {
+        let args = parse_args(vec!["--timestamp-key".into(), "time".into()]).unwrap();
+        assert_eq!(args.timestamp_key, "time");
+        assert!(parse_args(vec!["-t".into()]).is_err());
+
+        let record = RECORD.replace(
+            "\"@timestamp\":\"2024-01-01T00:00:00.000Z\"",
+            "\"time\":1704067200000",
+        );
+        assert_eq!(
+            validate_record(record.as_bytes(), "time").unwrap()["time"],
+            1704067200000u64
+        );
+        let err = validate_record(RECORD.as_bytes(), "time").unwrap_err();
+        assert!(
+            err.starts_with("missing keys") && err.contains("time"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn ports_default_to_both_collector_ports() {
+        let args = parse_args(Vec::new()).unwrap();
+        assert_eq!(args.ports, [9091, 5005]);
+        assert!(!args.raw);
+
+        let args = parse_args(vec!["-p".into(), "7000".into(), "--raw".into()]).unwrap();
+        assert_eq!(args.ports, [7000]);
+        assert!(args.raw);
+
+        assert_eq!(args.timestamp_key, "@timestamp");
+
+        assert!(parse_args(vec!["--port".into()]).is_err());
+        assert!(parse_args(vec!["--port".into(), "http".into()]).is_err());
+    }
+
+    #[test]
+    fn every_record_of_a_batch_is_checked() {
+        let batch = format!("{}{}", RECORD, RECORD.replace("\"hi\"", "\"again\""));
+        let records = validate_datagram(batch.as_bytes(), DEFAULT_TIMESTAMP_KEY).unwrap();
+        let messages: Vec<_> = records.iter().map(|record| &record["message"]).collect();
+        assert_eq!(messages, ["hi", "again"]);
+
+        let batch = format!("{}{{\"level\":\"INFO\"}}\n", RECORD);
+        let err = validate_datagram(batch.as_bytes(), DEFAULT_TIMESTAMP_KEY).unwrap_err();
+        assert!(err.starts_with("record 2: missing keys"), "{}", err);
+
+        let err =
+            validate_datagram(RECORD.trim_end().as_bytes(), DEFAULT_TIMESTAMP_KEY).unwrap_err();
+        assert!(err.contains("newline"), "{}", err);
+    }
+
+    #[test]
+    fn dict_batches_are_decoded() {
+        let batch = concat!(
+            r#"{"vinted_dict":1,"keys":["@timestamp","level","facility","target","host","message"]}"#,
+            "\n",
+            r#"[0,"2024-01-01T00:00:00.000Z",1,"INFO",2,"svc",3,"app",4,"box",5,"hi"]"#,
+            "\n",
+            r#"[0,"2024-01-01T00:00:01.000Z",1,"WARN",2,"svc",3,"app",4,"box",5,"slow"]"#,
+            "\n",
+        );
+        let records = validate_datagram(batch.as_bytes(), DEFAULT_TIMESTAMP_KEY).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["message"], "hi");
+        assert_eq!(records[1]["level"], "WARN");
+    }
+
+    #[test]
+    fn batches_of_the_logger_are_valid() {
+        use vinted_logger::Target;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // The only test of the collector which installs a logger
+        let _guard = vinted_logger::builder("svc-test", Target::UdpJson)
+            .destination(vinted_logger::LogDestination::from(
+                socket.local_addr().unwrap(),
+            ))
+            .udp_batching_with(4_000, Duration::from_millis(50))
+            .try_init_with_guard()
+            .unwrap();
+        for n in 0..50 {
+            tracing::info!(n, "batched");
+        }
+        assert!(vinted_logger::flush(Duration::from_secs(5)));
+
+        let mut buf = vec![0; 65_536];
+        let mut received = Vec::new();
+        let mut datagrams = 0;
+        while received.len() < 50 {
+            let len = socket.recv(&mut buf).expect("datagram is received");
+            datagrams += 1;
+            received.extend(validate_datagram(&buf[..len], DEFAULT_TIMESTAMP_KEY).unwrap());
+        }
+        assert!(datagrams < 50, "{} datagrams", datagrams);
+        for (n, record) in received.iter().enumerate() {
+            assert_eq!(record["n"], n);
+        }
+    }
+}
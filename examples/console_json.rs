@@ -1,8 +1,8 @@
 use std::{error::Error, io};
-use tracing::{debug, error, info, span, warn, Level};
+use vinted_logger::prelude::*;
 
 fn main() {
-    let _ = vinted_logger::try_init("kubernetes", vinted_logger::Target::ConsoleJson);
+    let _ = try_init("kubernetes", Target::ConsoleJson);
 
     let number_of_yaks = 3;
     // this creates a new event, outside of any spans.
@@ -14,7 +14,7 @@ fn main() {
     );
 }
 
-#[tracing::instrument]
+#[instrument]
 pub fn shave(yak: usize) -> Result<(), Box<dyn Error + 'static>> {
     // this creates an event at the DEBUG level with two fields:
     // - `excitement`, with the key "excitement" and the value "yay!"
@@ -27,7 +27,7 @@ pub fn shave(yak: usize) -> Result<(), Box<dyn Error + 'static>> {
         // note that this is intended to demonstrate `tracing`'s features, not idiomatic
         // error handling! in a library or application, you should consider returning
         // a dedicated `YakError`. libraries like snafu or thiserror make this easy.
-        return Err(io::Error::new(io::ErrorKind::Other, "shaving yak failed!").into());
+        return Err(io::Error::other("shaving yak failed!").into());
     } else {
         debug!("yak shaved successfully");
     }
@@ -0,0 +1,30 @@
+//! Reports changes in how records are delivered.
+//!
+//! Sends records over TCP and prints every `LoggerEvent` to stderr, rather
+//! than logging it, so a degraded logger doesn't swallow its own events:
+//!
+//! ```sh
+//! nc -lk 9092 &
+//! cargo run --example logger_events
+//! ```
+use std::{thread, time::Duration};
+use vinted_logger::prelude::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let guard = vinted_logger::builder("logger-events", Target::TcpJson)
+        .destination("tcp://127.0.0.1:9092".parse::<LogDestination>()?)
+        .try_init_with_guard()?;
+
+    let events = guard.events();
+    thread::spawn(move || {
+        for event in events {
+            eprintln!("logger {}", event);
+        }
+    });
+
+    for n in 0.. {
+        info!(n, "tick");
+        thread::sleep(Duration::from_secs(1));
+    }
+    Ok(())
+}
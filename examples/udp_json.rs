@@ -1,8 +1,21 @@
-use std::{error::Error, io};
-use tracing::{debug, error, info, span, warn, Level};
+use std::{error::Error, io, net::UdpSocket, time::Duration};
+use vinted_logger::prelude::*;
+
+/// Records are sent to the address `Target::UdpJson` logs to; run
+/// `cargo run --example collector` in another terminal to see them.
+const COLLECTOR_ADDR: &str = "127.0.0.1:9091";
 
 fn main() {
-    let _ = vinted_logger::try_init("udp", vinted_logger::Target::UdpJson);
+    if !collector_running() {
+        eprintln!(
+            "Nothing is listening on {}, records will be lost. \
+             Start the dev collector with `cargo run --example collector`.",
+            COLLECTOR_ADDR
+        );
+    }
+
+    // Flushes the records still queued when main returns
+    let _guard = vinted_logger::builder("udp", Target::UdpJson).try_init_with_guard();
 
     let number_of_yaks = 3;
     // this creates a new event, outside of any spans.
@@ -14,7 +27,7 @@ fn main() {
     );
 }
 
-#[tracing::instrument]
+#[instrument]
 pub fn shave(yak: usize) -> Result<(), Box<dyn Error + 'static>> {
     // this creates an event at the DEBUG level with two fields:
     // - `excitement`, with the key "excitement" and the value "yay!"
@@ -27,7 +40,7 @@ pub fn shave(yak: usize) -> Result<(), Box<dyn Error + 'static>> {
         // note that this is intended to demonstrate `tracing`'s features, not idiomatic
         // error handling! in a library or application, you should consider returning
         // a dedicated `YakError`. libraries like snafu or thiserror make this easy.
-        return Err(io::Error::new(io::ErrorKind::Other, "shaving yak failed!").into());
+        return Err(io::Error::other("shaving yak failed!").into());
     } else {
         debug!("yak shaved successfully");
     }
@@ -60,3 +73,20 @@ pub fn shave_all(yaks: usize) -> usize {
     }
     yaks_shaved
 }
+
+/// Sends an empty probe datagram: on loopback an ICMP port unreachable reply
+/// surfaces as an error on the connected socket when nobody is listening.
+fn collector_running() -> bool {
+    let probe = || -> io::Result<()> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.connect(COLLECTOR_ADDR)?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        socket.send(&[])?;
+        match socket.recv(&mut [0; 1]) {
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Err(e),
+            _ => Ok(()),
+        }
+    };
+
+    probe().is_ok()
+}
@@ -0,0 +1,134 @@
+//! Support code for the `*f!` and `gated_debug!` macros, not part of the public API.
+//!
+//! Positional and trailing arguments don't compile:
+//!
+//! ```compile_fail
+//! let user_id = 42;
+//! vinted_logger::infof!("user {} signed in", user_id);
+//! ```
+//!
+//! ```compile_fail
+//! let user_id = 42;
+//! vinted_logger::infof!("user {0} signed in");
+//! ```
+//!
+//! ```compile_fail
+//! vinted_logger::infof!("user {user.id} signed in");
+//! ```
+//!
+//! ```
+//! let user_id = 42;
+//! vinted_logger::infof!("user {user_id} signed in, {{braces}} are fine");
+//! ```
+use std::fmt;
+use tracing_core::field::{display, DisplayValue, Value};
+
+pub use crate::{log_point::LogPoint, vinted_message_template::with_template};
+pub use tracing;
+
+pub struct FieldWrap<'a, T: ?Sized>(pub &'a T);
+
+/// Preferred by method resolution: records the value with its own type.
+pub trait ViaValue<'a, T: ?Sized> {
+    fn field(self) -> &'a T;
+}
+
+impl<'a, T: Value + ?Sized> ViaValue<'a, T> for &&FieldWrap<'a, T> {
+    fn field(self) -> &'a T {
+        self.0
+    }
+}
+
+/// Fallback for values which can be formatted but aren't `Value`s.
+pub trait ViaDisplay<'a, T: fmt::Display + ?Sized> {
+    fn field(self) -> DisplayValue<&'a T>;
+}
+
+impl<'a, T: fmt::Display + ?Sized> ViaDisplay<'a, T> for &FieldWrap<'a, T> {
+    fn field(self) -> DisplayValue<&'a T> {
+        display(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use std::{fmt, net::Ipv4Addr};
+
+    /// Formats but isn't a `tracing::Value`
+    struct Sku(u32);
+
+    impl fmt::Display for Sku {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "SKU-{}", self.0)
+        }
+    }
+
+    fn record(log: impl FnOnce()) -> serde_json::Value {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .default_level(tracing::Level::TRACE)
+            .capture(&capture);
+        with_logger(builder, log);
+        // Untyped fields may be noticed at DEBUG level
+        let mut records: Vec<_> = capture
+            .records()
+            .into_iter()
+            .filter(|record| record["target"] != "vinted_logger")
+            .collect();
+        assert_eq!(records.len(), 1, "{:?}", records);
+        records.remove(0)
+    }
+
+    #[test]
+    fn values_are_rendered_and_kept_as_typed_fields() {
+        let user_id = 42_u64;
+        let balance = -7_i64;
+        let ratio = 0.5_f64;
+        let premium = true;
+        let country = "LT";
+        let record =
+            record(|| crate::infof!("user {user_id} from {country}: {balance} {ratio} {premium}"));
+
+        assert_eq!(record["message"], "user 42 from LT: -7 0.5 true");
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["user_id"], 42);
+        assert_eq!(record["balance"], -7);
+        assert_eq!(record["ratio"], 0.5);
+        assert_eq!(record["premium"], true);
+        assert_eq!(record["country"], "LT");
+    }
+
+    #[test]
+    fn display_only_values_are_recorded_as_strings() {
+        let sku = Sku(7);
+        let ip = Ipv4Addr::LOCALHOST;
+        let record = record(|| crate::warnf!("{sku} out of stock at {ip}"));
+
+        assert_eq!(record["message"], "SKU-7 out of stock at 127.0.0.1");
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["sku"], "SKU-7");
+        assert_eq!(record["ip"], "127.0.0.1");
+    }
+
+    #[test]
+    fn format_specs_and_repeats_record_the_value_once() {
+        let price = 1.5_f64;
+        let record = record(|| crate::errorf!("{price:.2} ({price}), {{literal}}"));
+
+        assert_eq!(record["message"], "1.50 (1.5), {literal}");
+        assert_eq!(record["level"], "ERROR");
+        assert_eq!(record["price"], 1.5);
+        assert!(record.get("literal").is_none());
+    }
+
+    #[test]
+    fn every_level_has_a_macro() {
+        let n = 1;
+        assert_eq!(record(|| crate::tracef!("{n}"))["level"], "TRACE");
+        assert_eq!(record(|| crate::debugf!("{n}"))["level"], "DEBUG");
+    }
+}
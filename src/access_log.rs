@@ -0,0 +1,207 @@
+//! HTTP access log records in the schema of the edge proxies, so one index
+//! pattern covers nginx, envoy and Rust services
+//!
+//! ```
+//! use std::time::Duration;
+//! use vinted_logger::access_log::{self, AccessLogEntry};
+//!
+//! let mut entry = AccessLogEntry::new("GET", "/api/v2/items?page=2", 200);
+//! entry.remote_addr = "10.0.0.1".to_owned();
+//! entry.body_bytes_sent = 512;
+//! entry.request_time = Duration::from_millis(31);
+//! access_log::record(entry);
+//! ```
+
+use std::{cell::RefCell, collections::BTreeMap, time::Duration};
+
+/// Target of access log records
+pub const TARGET: &str = "vinted::access";
+
+thread_local! {
+    /// Extensions of the entry being recorded, picked up by the JSON
+    /// formatter while it formats the record on the same thread
+    static EXTENSIONS: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// A served request, fields are named after the proxies' log format
+///
+/// Fields the proxies add to their format may be added here too, build it
+/// with [`AccessLogEntry::new`] and set the others.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct AccessLogEntry {
+    /// Address of the client
+    pub remote_addr: String,
+    /// e.g. `GET`
+    pub request_method: String,
+    /// Path and query string
+    pub request_uri: String,
+    /// Response status code
+    pub status: u16,
+    /// Bytes of the response body
+    pub body_bytes_sent: u64,
+    /// Time from the first byte read to the last byte sent, logged in seconds
+    /// with millisecond resolution
+    pub request_time: Duration,
+    /// Time spent waiting for the upstream, left out if `None`
+    pub upstream_response_time: Option<Duration>,
+    /// `User-Agent` header, left out if `None`
+    pub http_user_agent: Option<String>,
+    /// `Referer` header, left out if `None`
+    pub http_referer: Option<String>,
+    /// Additional string fields of JSON records, e.g. `request_id`. Keys
+    /// taken by the fields above or by the record envelope are left out.
+    pub extensions: BTreeMap<String, String>,
+}
+
+impl AccessLogEntry {
+    /// Entry of a request answered with `status`, the other fields empty
+    pub fn new(
+        request_method: impl Into<String>,
+        request_uri: impl Into<String>,
+        status: u16,
+    ) -> Self {
+        Self {
+            request_method: request_method.into(),
+            request_uri: request_uri.into(),
+            status,
+            ..Self::default()
+        }
+    }
+}
+
+/// Logs the entry at INFO with target [`TARGET`]
+///
+/// JSON targets write the fields with exactly these key names, whatever the
+/// escaping profile. `Console` leaves out the extensions.
+pub fn record(mut entry: AccessLogEntry) {
+    if !tracing::enabled!(target: TARGET, tracing::Level::INFO) {
+        return;
+    }
+
+    EXTENSIONS.with(|extensions| *extensions.borrow_mut() = std::mem::take(&mut entry.extensions));
+    tracing::info!(
+        target: TARGET,
+        remote_addr = entry.remote_addr.as_str(),
+        request_method = entry.request_method.as_str(),
+        request_uri = entry.request_uri.as_str(),
+        status = entry.status,
+        body_bytes_sent = entry.body_bytes_sent,
+        request_time = seconds(entry.request_time),
+        upstream_response_time = entry.upstream_response_time.map(seconds),
+        http_user_agent = entry.http_user_agent.as_deref(),
+        http_referer = entry.http_referer.as_deref(),
+    );
+    EXTENSIONS.with(|extensions| extensions.borrow_mut().clear());
+}
+
+/// Calls `f` with every extension of the entry being recorded on this thread
+pub(crate) fn for_each_extension<E>(
+    mut f: impl FnMut(&str, &str) -> Result<(), E>,
+) -> Result<(), E> {
+    EXTENSIONS.with(|extensions| {
+        extensions
+            .borrow()
+            .iter()
+            .filter(|(key, _)| !FIELDS.contains(&key.as_str()))
+            .try_for_each(|(key, value)| f(key, value))
+    })
+}
+
+/// Keys of the typed fields of [`AccessLogEntry`]
+const FIELDS: &[&str] = &[
+    "remote_addr",
+    "request_method",
+    "request_uri",
+    "status",
+    "body_bytes_sent",
+    "request_time",
+    "upstream_response_time",
+    "http_user_agent",
+    "http_referer",
+];
+
+/// Seconds with millisecond resolution, as nginx logs `$request_time`
+fn seconds(duration: Duration) -> f64 {
+    duration.as_millis() as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record, AccessLogEntry, TARGET};
+    use crate::{
+        test_support::{with_logger, Capture},
+        EscapingProfile, LoggerBuilder, Target,
+    };
+    use std::time::Duration;
+
+    /// Fields of an nginx access log line, as the edge proxies write them
+    const NGINX_SAMPLE: &str = concat!(
+        r#""remote_addr":"10.0.0.1","request_method":"GET","#,
+        r#""request_uri":"/api/v2/items?page=2&q=\"shoes\"","status":200,"#,
+        r#""body_bytes_sent":512,"request_time":0.031,"upstream_response_time":0.029,"#,
+        r#""http_user_agent":"Mozilla/5.0 (Ąžuolas)","http_referer":"https://www.vinted.lt/""#
+    );
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            remote_addr: "10.0.0.1".to_owned(),
+            request_method: "GET".to_owned(),
+            request_uri: "/api/v2/items?page=2&q=\"shoes\"".to_owned(),
+            status: 200,
+            body_bytes_sent: 512,
+            request_time: Duration::from_millis(31),
+            upstream_response_time: Some(Duration::from_millis(29)),
+            http_user_agent: Some("Mozilla/5.0 (Ąžuolas)".to_owned()),
+            http_referer: Some("https://www.vinted.lt/".to_owned()),
+            extensions: [("request_id", "abc-123"), ("status", "overridden")]
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    fn line(builder: LoggerBuilder) -> String {
+        let capture = Capture::default();
+        with_logger(builder.capture(&capture), || record(entry()));
+        capture.lines().remove(0)
+    }
+
+    #[test]
+    fn fields_match_the_nginx_schema_byte_for_byte() {
+        let json = || crate::builder("svc-test", Target::ConsoleJson);
+        for line in &[
+            line(json()),
+            line(json().escaping_profile(EscapingProfile::ClickHouse)),
+        ] {
+            assert!(line.contains(NGINX_SAMPLE), "{}", line);
+            assert!(line.contains(r#""request_id":"abc-123""#), "{}", line);
+            assert!(
+                line.contains(&format!(r#""target":"{}""#, TARGET)),
+                "{}",
+                line
+            );
+            // Extensions don't override the typed fields
+            assert!(!line.contains("overridden"), "{}", line);
+        }
+    }
+
+    #[test]
+    fn optional_fields_are_left_out() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, || {
+            record(AccessLogEntry {
+                status: 404,
+                ..AccessLogEntry::default()
+            })
+        });
+
+        let record = &capture.records()[0];
+        assert_eq!(record["status"], 404);
+        assert_eq!(record["request_time"], 0.0);
+        for key in &["upstream_response_time", "http_user_agent", "http_referer"] {
+            assert!(record.get(*key).is_none(), "{} in {}", key, record);
+        }
+    }
+}
@@ -0,0 +1,2117 @@
+#[cfg(feature = "gelf-http")]
+use crate::vinted_gelf_http_writer;
+#[cfg(unix)]
+use crate::vinted_unix_writer;
+use crate::{
+    compat::{ConsoleFormat, EnvLoggerFormat},
+    introspect::{self, VintedIntrospection},
+    vinted_attach_point, vinted_canary, vinted_coalescing_writer, vinted_console_writer,
+    vinted_delivery, vinted_deployment,
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_drain, vinted_echo_writer, vinted_event_seq, vinted_event_time, vinted_facility_map,
+    vinted_fallback,
+    vinted_field_types::{FieldType, FieldTypes},
+    vinted_field_visitor, vinted_file_writer, vinted_filter_reload, vinted_flush, vinted_host_ip,
+    vinted_installation, vinted_json_formatter, vinted_level_stats, vinted_metric,
+    vinted_mirror_writer, vinted_own_records, vinted_panic_hook,
+    vinted_pii::{Detectors, PiiScanner},
+    vinted_rate_limit,
+    vinted_redaction::Redaction,
+    vinted_span_tracker, vinted_tcp_writer,
+    vinted_timestamp::TimestampFormat,
+    vinted_udp_writer, vinted_volume_writer, EscapingProfile, InitError, LogDestination,
+    PayloadTier, PiiDetector, Target,
+};
+use std::{
+    collections::HashSet,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    panic::Location,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tracing_core::Level;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    fmt::{
+        writer::{BoxMakeWriter, TestWriter},
+        MakeWriter,
+    },
+    registry::Extensions,
+    reload, EnvFilter, Layer, Registry,
+};
+
+/// Window used by [`LoggerBuilder::coalesce_repeats`]
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Default of [`LoggerBuilder::event_time_skew`]
+pub const DEFAULT_EVENT_TIME_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Default count limit of [`LoggerBuilder::additional_field`]
+pub const DEFAULT_MAX_ADDITIONAL_FIELDS: usize = 128;
+
+/// Default limit of the serialized size, keys included, of
+/// [`LoggerBuilder::additional_field`]
+pub const DEFAULT_MAX_ADDITIONAL_FIELDS_BYTES: usize = 16 * 1024;
+
+/// What [`LoggerBuilder::try_init`] does when a global subscriber is already installed
+///
+/// An installed global subscriber can't take new layers, unless it set aside
+/// an [`AttachPoint`](crate::AttachPoint) for the logger. Otherwise the
+/// existing subscriber keeps receiving every event. A logger of this crate
+/// installed already takes those of its own facility, see
+/// [`LoggerBuilder::try_init`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InitBehavior {
+    /// Return an error, this is the default
+    FailIfSet,
+    /// Leave the existing subscriber in place and return `Ok(())`, e.g. when
+    /// tests install their own subscriber in any order
+    NoopIfSet,
+    /// Attach the logger to the [`AttachPoint`](crate::AttachPoint) of the
+    /// existing subscriber. Same as `NoopIfSet`, with a warning on stderr,
+    /// if it has none or a logger is attached to it already.
+    AttachLayerIfPossible,
+}
+
+/// Creates a [`LoggerBuilder`] for the given facility and target
+///
+/// - `facility` - facility name, usually the name of the service, e.g. `svc-search`, `core`.
+///   [`LoggerBuilder::try_init`] rejects an empty one.
+///
+/// ```
+/// vinted_logger::builder("svc-search", vinted_logger::Target::ConsoleJson)
+///     .coalesce_repeats()
+///     .try_init()?;
+/// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+/// ```
+pub fn builder(facility: &'static str, target: Target) -> LoggerBuilder {
+    LoggerBuilder {
+        facility,
+        target,
+        coalesce_window: None,
+        level_hint: false,
+        split_streams: false,
+        console_max_line: vinted_console_writer::DEFAULT_CONSOLE_MAX_LINE,
+        console_buffer: None,
+        test_writer: false,
+        #[cfg(feature = "runtime-context")]
+        runtime_context: false,
+        live_spans_threshold: None,
+        destination: None,
+        udp_buffer: vinted_udp_writer::DEFAULT_UDP_BUFFER,
+        udp_batching: None,
+        max_datagram_size: vinted_udp_writer::DEFAULT_MAX_DATAGRAM_SIZE,
+        wire_format: vinted_udp_writer::WireFormat::Json,
+        tcp_buffer: vinted_tcp_writer::DEFAULT_TCP_BUFFER,
+        tcp_framing: vinted_tcp_writer::Framing::NewlineDelimited,
+        #[cfg(feature = "gelf-http")]
+        http_batching: None,
+        #[cfg(feature = "gelf-http")]
+        http_max_attempts: vinted_gelf_http_writer::DEFAULT_HTTP_MAX_ATTEMPTS,
+        file_path: None,
+        file_max_size: vinted_file_writer::DEFAULT_FILE_MAX_SIZE,
+        file_max_files: vinted_file_writer::DEFAULT_FILE_MAX_FILES,
+        error_destination: None,
+        error_destination_level: Level::ERROR,
+        metric_target: vinted_metric::DEFAULT_METRIC_TARGET,
+        metric_destination: None,
+        stderr_echo: None,
+        console_mirror: None,
+        stderr_echo_per_second: vinted_echo_writer::DEFAULT_ECHO_PER_SECOND,
+        fallback_per_minute: vinted_fallback::DEFAULT_FALLBACK_PER_MINUTE,
+        rate_limit: vinted_rate_limit::RateLimit::default(),
+        payload_tiers: [PayloadTier::Full; 5],
+        escaping_profile: EscapingProfile::Default,
+        init_behavior: InitBehavior::FailIfSet,
+        deny_below: Vec::new(),
+        default_level: Level::INFO,
+        host_ip: false,
+        deploy_slot: None,
+        deploy_id: None,
+        drop_empty_fields: false,
+        level_stats_every: None,
+        message_template: false,
+        diagnostics_per_minute: vinted_diagnostics::DEFAULT_DIAGNOSTICS_PER_MINUTE,
+        track_volume: false,
+        event_time_skew: DEFAULT_EVENT_TIME_SKEW,
+        debug_time_budget: None,
+        field_types: FieldTypes::default(),
+        timestamp_format: TimestampFormat::default(),
+        timestamp_key: vinted_json_formatter::DEFAULT_TIMESTAMP_KEY,
+        pii_detectors: None,
+        pii_exempt_fields: HashSet::new(),
+        redaction: Redaction::default(),
+        trace_context: None,
+        env_filter: None,
+        console_format: ConsoleFormat::Default,
+        facilities: Vec::new(),
+        additional_fields: serde_json::Map::new(),
+        additional_fields_bytes: 0,
+        max_additional_fields: DEFAULT_MAX_ADDITIONAL_FIELDS,
+        max_additional_fields_bytes: DEFAULT_MAX_ADDITIONAL_FIELDS_BYTES,
+        rejected_fields: Vec::new(),
+        rejected_count: 0,
+        ignore_rejected_fields: false,
+        allow_empty_facility: false,
+        log_panics: false,
+        flush_timeout: vinted_flush::DEFAULT_FLUSH_TIMEOUT,
+        canary: None,
+        drain: vinted_drain::DrainConfig::default(),
+        #[cfg(test)]
+        capture: None,
+    }
+}
+
+/// Configures and installs Vinted logger
+#[derive(Debug)]
+pub struct LoggerBuilder {
+    facility: &'static str,
+    target: Target,
+    coalesce_window: Option<Duration>,
+    level_hint: bool,
+    split_streams: bool,
+    console_max_line: usize,
+    console_buffer: Option<usize>,
+    test_writer: bool,
+    #[cfg(feature = "runtime-context")]
+    runtime_context: bool,
+    live_spans_threshold: Option<u64>,
+    destination: Option<LogDestination>,
+    udp_buffer: usize,
+    udp_batching: Option<vinted_udp_writer::Batching>,
+    max_datagram_size: usize,
+    wire_format: vinted_udp_writer::WireFormat,
+    tcp_buffer: usize,
+    tcp_framing: vinted_tcp_writer::Framing,
+    #[cfg(feature = "gelf-http")]
+    http_batching: Option<vinted_udp_writer::Batching>,
+    #[cfg(feature = "gelf-http")]
+    http_max_attempts: u32,
+    file_path: Option<PathBuf>,
+    file_max_size: u64,
+    file_max_files: usize,
+    error_destination: Option<LogDestination>,
+    error_destination_level: Level,
+    metric_target: &'static str,
+    metric_destination: Option<LogDestination>,
+    stderr_echo: Option<Level>,
+    console_mirror: Option<Level>,
+    stderr_echo_per_second: u32,
+    fallback_per_minute: u32,
+    rate_limit: vinted_rate_limit::RateLimit,
+    payload_tiers: vinted_json_formatter::PayloadTiers,
+    escaping_profile: EscapingProfile,
+    init_behavior: InitBehavior,
+    deny_below: Vec<(String, Level)>,
+    default_level: Level,
+    host_ip: bool,
+    deploy_slot: Option<String>,
+    deploy_id: Option<String>,
+    drop_empty_fields: bool,
+    level_stats_every: Option<u64>,
+    message_template: bool,
+    diagnostics_per_minute: u32,
+    track_volume: bool,
+    event_time_skew: Duration,
+    debug_time_budget: Option<Duration>,
+    field_types: FieldTypes,
+    timestamp_format: TimestampFormat,
+    timestamp_key: &'static str,
+    pii_detectors: Option<Detectors>,
+    pii_exempt_fields: HashSet<String>,
+    redaction: Redaction,
+    trace_context: Option<vinted_json_formatter::TraceContext>,
+    env_filter: Option<EnvFilter>,
+    console_format: ConsoleFormat,
+    facilities: Vec<(String, &'static str)>,
+    additional_fields: serde_json::Map<String, serde_json::Value>,
+    additional_fields_bytes: usize,
+    max_additional_fields: usize,
+    max_additional_fields_bytes: usize,
+    // The first MAX_REJECTED_FIELDS names of rejected_count
+    rejected_fields: Vec<String>,
+    rejected_count: usize,
+    ignore_rejected_fields: bool,
+    allow_empty_facility: bool,
+    log_panics: bool,
+    flush_timeout: Duration,
+    canary: Option<vinted_canary::CanaryConfig>,
+    drain: vinted_drain::DrainConfig,
+    #[cfg(test)]
+    capture: Option<crate::test_support::Capture>,
+}
+
+/// Rejected additional fields named in errors and warnings
+const MAX_REJECTED_FIELDS: usize = 8;
+
+impl LoggerBuilder {
+    /// Sets the facility, replacing the one given to [`builder`]
+    pub fn facility(mut self, facility: &'static str) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    /// Sets the facility of JSON records whose target is `prefix` or starts
+    /// with `prefix::`, e.g. of a plugin crate logging through the host's
+    /// logger. The longest matching prefix wins, other records keep the
+    /// [facility](Self::facility).
+    ///
+    /// ```
+    /// vinted_logger::builder("svc-host", vinted_logger::Target::ConsoleJson)
+    ///     .facility_for("payments_plugin", "svc-payments")
+    ///     .facility_for("payments_plugin::fraud", "svc-fraud")
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn facility_for(mut self, prefix: impl Into<String>, facility: &'static str) -> Self {
+        let prefix = prefix.into();
+        self.facilities.retain(|(existing, _)| *existing != prefix);
+        self.facilities.push((prefix, facility));
+        self
+    }
+
+    /// Installs the logger with an empty facility, which
+    /// [`try_init`](Self::try_init) otherwise rejects, warning loudly on
+    /// stderr instead
+    ///
+    /// Meant for the transition of services which used to log without a
+    /// facility, their records end up in the catch-all index.
+    pub fn allow_empty_facility(mut self) -> Self {
+        self.allow_empty_facility = true;
+        self
+    }
+
+    /// Sets the target, replacing the one given to [`builder`]
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Adds a field with the same value to every JSON record, after
+    /// `facility`, e.g. `.additional_field("datacenter", "eu-1")`
+    ///
+    /// Fields beyond [`additional_field_limits`](Self::additional_field_limits)
+    /// and fields named like keys of the record itself, e.g. `level`, are
+    /// rejected and make [`try_init`](Self::try_init) fail, unless
+    /// [`ignore_rejected_fields`](Self::ignore_rejected_fields) is set.
+    pub fn additional_field(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        let value = value.into();
+        let size = |value: &serde_json::Value| key.len() + value.to_string().len();
+        let replaced = self.additional_fields.get(key).map(size);
+        let bytes = self.additional_fields_bytes - replaced.unwrap_or(0) + size(&value);
+
+        let rejected = vinted_json_formatter::RECORD_KEYS.contains(&key)
+            || (replaced.is_none() && self.additional_fields.len() >= self.max_additional_fields)
+            || bytes > self.max_additional_fields_bytes;
+        if rejected {
+            if self.rejected_fields.len() < MAX_REJECTED_FIELDS {
+                self.rejected_fields.push(key.to_owned());
+            }
+            self.rejected_count += 1;
+            return self;
+        }
+
+        self.additional_fields_bytes = bytes;
+        self.additional_fields.insert(key.to_owned(), value);
+        self
+    }
+
+    /// Limits how many [`additional_field`](Self::additional_field)s are kept
+    /// and how many bytes they take serialized, keys included. Defaults to
+    /// [`DEFAULT_MAX_ADDITIONAL_FIELDS`] and
+    /// [`DEFAULT_MAX_ADDITIONAL_FIELDS_BYTES`], applies to fields added after it.
+    pub fn additional_field_limits(mut self, max_fields: usize, max_bytes: usize) -> Self {
+        self.max_additional_fields = max_fields;
+        self.max_additional_fields_bytes = max_bytes;
+        self
+    }
+
+    /// Leaves out rejected [`additional_field`](Self::additional_field)s with
+    /// a warning on stderr, instead of failing [`try_init`](Self::try_init).
+    /// JSON records then carry `fields_dropped`, the number left out.
+    pub fn ignore_rejected_fields(mut self) -> Self {
+        self.ignore_rejected_fields = true;
+        self
+    }
+
+    /// Suppresses records identical to the previous one from the same callsite
+    /// and thread (same level, message and fields) and emits a single summary
+    /// record with `repeat_count`, `first_timestamp` and `last_timestamp`
+    /// instead.
+    ///
+    /// The summary is written when the callsite logs a different record, once
+    /// [`DEFAULT_COALESCE_WINDOW`] has elapsed since the run started, by
+    /// [`flush`](crate::flush), and when the logger is dropped, e.g. with its
+    /// [guard](Self::try_init_with_guard). Repeats after a window go on
+    /// being summarized, while ERROR records are written again once per
+    /// window. Applies to JSON targets only.
+    pub fn coalesce_repeats(self) -> Self {
+        self.coalesce_window(DEFAULT_COALESCE_WINDOW)
+    }
+
+    /// Same as [`LoggerBuilder::coalesce_repeats`] with a custom window
+    pub fn coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = Some(window);
+        self
+    }
+
+    /// Prefixes every `ConsoleJson` line with a fixed-width level tag, e.g.
+    /// `E|{"@timestamp":…}`, so severities can be found without parsing.
+    ///
+    /// Lines are no longer pure JSON: consumers expecting one JSON object per
+    /// line must strip the first two bytes.
+    pub fn level_hint(mut self) -> Self {
+        self.level_hint = true;
+        self
+    }
+
+    /// Writes WARN and ERROR records of console targets to stderr and
+    /// everything else to stdout, so `2>` redirection separates severities.
+    ///
+    /// Consumers reading stdout only will no longer see WARN and ERROR records.
+    pub fn split_streams(mut self) -> Self {
+        self.split_streams = true;
+        self
+    }
+
+    /// Sets the longest record written by console targets, in bytes,
+    /// defaults to [`DEFAULT_CONSOLE_MAX_LINE`](crate::DEFAULT_CONSOLE_MAX_LINE).
+    /// Longer records are replaced by a stub with their timestamp, level,
+    /// `original_bytes` and callsite, so one huge record can't freeze a
+    /// terminal. Other targets still get the whole record.
+    ///
+    /// Lines of [`compat::init_env_logger_style`](crate::compat::init_env_logger_style)
+    /// aren't capped.
+    pub fn console_max_line(mut self, bytes: usize) -> Self {
+        self.console_max_line = bytes;
+        self
+    }
+
+    /// Writes console records from a background thread, so a slow pipe on
+    /// stdout (journald or a log collector under pressure) doesn't hold up
+    /// the threads logging them
+    ///
+    /// Up to [`DEFAULT_CONSOLE_BUFFER`](crate::DEFAULT_CONSOLE_BUFFER)
+    /// records are queued, see [`LoggerBuilder::console_buffer`]. Use
+    /// [`try_init_with_guard`](LoggerBuilder::try_init_with_guard) or
+    /// [`flush`](crate::flush) so queued records are written before the
+    /// process exits.
+    ///
+    /// Lines of [`compat::init_env_logger_style`](crate::compat::init_env_logger_style)
+    /// are still written right away.
+    pub fn non_blocking(mut self) -> Self {
+        self.console_buffer = self
+            .console_buffer
+            .or(Some(vinted_console_writer::DEFAULT_CONSOLE_BUFFER));
+        self
+    }
+
+    /// Same as [`LoggerBuilder::non_blocking`], queueing up to `records`
+    /// records. Records are dropped while the queue is full, see
+    /// [`LoggerStats::dropped`](crate::LoggerStats::dropped).
+    pub fn console_buffer(mut self, records: usize) -> Self {
+        self.console_buffer = Some(records);
+        self
+    }
+
+    /// Prints console records with `print!` and `eprint!`, so `cargo test`
+    /// captures them with the output of the test logging them and shows
+    /// them only for failing tests, or with `--nocapture`
+    ///
+    /// Console targets otherwise write to stdout and stderr directly, past
+    /// the capture. Test writers are never [non-blocking](Self::non_blocking).
+    /// Other targets send records as usual, a `ConsoleJson` logger installed
+    /// for tests is captured the same way.
+    ///
+    /// The logger is global, so records are captured with the output of
+    /// whichever test is running when they are logged. Installing one for
+    /// every test with [`InitBehavior::NoopIfSet`] keeps the first.
+    ///
+    /// ```
+    /// use vinted_logger::{InitBehavior, Target};
+    ///
+    /// vinted_logger::builder("svc-search", Target::Console)
+    ///     .test_writer()
+    ///     .init_behavior(InitBehavior::NoopIfSet)
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn test_writer(mut self) -> Self {
+        self.test_writer = true;
+        self
+    }
+
+    /// Writes console lines to `capture` instead of stdout and stderr
+    #[cfg(test)]
+    pub(crate) fn capture(mut self, capture: &crate::test_support::Capture) -> Self {
+        self.capture = Some(capture.clone());
+        self
+    }
+
+    /// Warns, at most once a minute, when more than `threshold` spans are
+    /// alive at once, listing the span names with the most live spans.
+    ///
+    /// The number of live spans is always available through
+    /// [`LoggerStats::live_spans`](crate::LoggerStats::live_spans).
+    pub fn warn_on_live_spans(mut self, threshold: u64) -> Self {
+        self.live_spans_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets where network targets send records to, defaults to
+    /// `udp://127.0.0.1:9091` for `UdpJson`, `tcp://127.0.0.1:5170`, the
+    /// fluentd `in_tcp` port, for `TcpJson` and `http://127.0.0.1:12201/gelf`
+    /// for `GelfHttp`
+    ///
+    /// ```no_run
+    /// let destination: vinted_logger::LogDestination = "fluentd.logging.svc:9091".parse()?;
+    /// vinted_logger::builder("svc-search", vinted_logger::Target::UdpJson)
+    ///     .destination(destination)
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn destination(mut self, destination: impl Into<LogDestination>) -> Self {
+        self.destination = Some(destination.into());
+        self
+    }
+
+    /// Sets how many `UdpJson`, `UnixJson` and `GelfHttp` records are queued
+    /// for a destination, defaults to
+    /// [`DEFAULT_UDP_BUFFER`](crate::DEFAULT_UDP_BUFFER). Records are
+    /// dropped while the queue is full, e.g. because sending blocks, see
+    /// [`LoggerStats::dropped`](crate::LoggerStats::dropped).
+    pub fn udp_buffer(mut self, records: usize) -> Self {
+        self.udp_buffer = records;
+        self
+    }
+
+    /// Sends `UdpJson` records in newline delimited batches, which fluentd's
+    /// `in_udp` with `format json` reads as separate records, to save a
+    /// syscall per record
+    ///
+    /// A batch is sent once another record would grow it beyond
+    /// [`DEFAULT_UDP_BATCH_BYTES`](crate::DEFAULT_UDP_BATCH_BYTES), or
+    /// [`DEFAULT_UDP_BATCH_INTERVAL`](crate::DEFAULT_UDP_BATCH_INTERVAL)
+    /// after its first record was queued, see
+    /// [`udp_batching_with`](Self::udp_batching_with). Records are never
+    /// split between datagrams: a larger one is sent on its own. Off by
+    /// default, [`try_init`](Self::try_init) fails with
+    /// [`WireFormat::Gelf`](crate::WireFormat::Gelf).
+    pub fn udp_batching(self) -> Self {
+        self.udp_batching_with(
+            vinted_udp_writer::DEFAULT_UDP_BATCH_BYTES,
+            vinted_udp_writer::DEFAULT_UDP_BATCH_INTERVAL,
+        )
+    }
+
+    /// Same as [`udp_batching`](Self::udp_batching) with batches of at most
+    /// `max_bytes`, sent at the latest `interval` after their first record
+    ///
+    /// `max_bytes` must not exceed
+    /// [`max_datagram_size`](Self::max_datagram_size), otherwise
+    /// [`try_init`](Self::try_init) fails.
+    pub fn udp_batching_with(mut self, max_bytes: usize, interval: Duration) -> Self {
+        self.udp_batching = Some(vinted_udp_writer::Batching {
+            max_bytes,
+            interval,
+            key_table: false,
+        });
+        self
+    }
+
+    /// Sets the largest `UdpJson` datagram, in bytes, defaults to
+    /// [`DEFAULT_MAX_DATAGRAM_SIZE`](crate::DEFAULT_MAX_DATAGRAM_SIZE).
+    /// Larger records are sent with their `message` shortened to fit and
+    /// `"truncated": true`, records which don't fit without a message are
+    /// dropped.
+    pub fn max_datagram_size(mut self, bytes: usize) -> Self {
+        self.max_datagram_size = bytes;
+        self
+    }
+
+    /// Sets how `UdpJson` records are encoded, defaults to
+    /// [`WireFormat::Json`](crate::WireFormat::Json) for fluentd
+    ///
+    /// [`WireFormat::Gelf`](crate::WireFormat::Gelf) sends GELF messages to a
+    /// Graylog UDP input instead, chunked when larger than
+    /// [`max_datagram_size`](Self::max_datagram_size).
+    /// [`WireFormat::JsonDict`](crate::WireFormat::JsonDict) sends
+    /// [`udp_batching`](Self::udp_batching) datagrams with their keys in a
+    /// table, for collectors decoding them with
+    /// [`validate::decode_batch`](crate::validate::decode_batch).
+    ///
+    /// ```no_run
+    /// use vinted_logger::{Target, WireFormat};
+    ///
+    /// vinted_logger::builder("svc-search", Target::UdpJson)
+    ///     .destination("udp://graylog:12201".parse::<vinted_logger::LogDestination>()?)
+    ///     .wire_format(WireFormat::Gelf)
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn wire_format(mut self, wire_format: vinted_udp_writer::WireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+
+    /// Sets how many `TcpJson` records are buffered while disconnected,
+    /// defaults to [`DEFAULT_TCP_BUFFER`](crate::DEFAULT_TCP_BUFFER). The
+    /// oldest records are dropped beyond that, see
+    /// [`LoggerStats::dropped`](crate::LoggerStats::dropped).
+    pub fn tcp_buffer(mut self, records: usize) -> Self {
+        self.tcp_buffer = records;
+        self
+    }
+
+    /// Sets how `TcpJson` records are delimited, defaults to
+    /// [`Framing::NewlineDelimited`](crate::Framing::NewlineDelimited)
+    ///
+    /// ```no_run
+    /// use vinted_logger::{Framing, LengthHeader};
+    ///
+    /// vinted_logger::builder("svc-search", vinted_logger::Target::TcpJson)
+    ///     .tcp_framing(Framing::LengthPrefixed {
+    ///         header: LengthHeader::U32BigEndian,
+    ///     })
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn tcp_framing(mut self, framing: vinted_tcp_writer::Framing) -> Self {
+        self.tcp_framing = framing;
+        self
+    }
+
+    /// Posts `GelfHttp` messages in gzipped, newline delimited batches, for
+    /// Graylog HTTP inputs with bulk receiving enabled
+    ///
+    /// A batch is posted once another message would grow it beyond
+    /// [`DEFAULT_HTTP_BATCH_BYTES`](crate::DEFAULT_HTTP_BATCH_BYTES) before
+    /// compression, or
+    /// [`DEFAULT_HTTP_BATCH_INTERVAL`](crate::DEFAULT_HTTP_BATCH_INTERVAL)
+    /// after its first message was queued, see
+    /// [`http_batching_with`](Self::http_batching_with). Off by default,
+    /// every message is posted on its own, uncompressed.
+    ///
+    /// ```no_run
+    /// use vinted_logger::{LogDestination, Target};
+    ///
+    /// vinted_logger::builder("svc-search", Target::GelfHttp)
+    ///     .destination("http://graylog:12201/gelf".parse::<LogDestination>()?)
+    ///     .http_batching()
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    #[cfg(feature = "gelf-http")]
+    pub fn http_batching(self) -> Self {
+        self.http_batching_with(
+            vinted_gelf_http_writer::DEFAULT_HTTP_BATCH_BYTES,
+            vinted_gelf_http_writer::DEFAULT_HTTP_BATCH_INTERVAL,
+        )
+    }
+
+    /// Same as [`http_batching`](Self::http_batching) with batches of at most
+    /// `max_bytes` before compression, posted at the latest `interval` after
+    /// their first message
+    #[cfg(feature = "gelf-http")]
+    pub fn http_batching_with(mut self, max_bytes: usize, interval: Duration) -> Self {
+        self.http_batching = Some(vinted_udp_writer::Batching {
+            max_bytes,
+            interval,
+            key_table: false,
+        });
+        self
+    }
+
+    /// Sets how many times a `GelfHttp` request is sent before its records
+    /// are dropped, defaults to
+    /// [`DEFAULT_HTTP_MAX_ATTEMPTS`](crate::DEFAULT_HTTP_MAX_ATTEMPTS)
+    ///
+    /// Requests answered with 429 are sent again after their `Retry-After`,
+    /// those answered with a 5xx or which couldn't connect after a backoff
+    /// doubling from 100ms up to 10s. Other statuses are never retried. Must
+    /// be at least 1, otherwise [`try_init`](Self::try_init) fails.
+    #[cfg(feature = "gelf-http")]
+    pub fn http_max_attempts(mut self, attempts: u32) -> Self {
+        self.http_max_attempts = attempts;
+        self
+    }
+
+    /// Sets the file `FileJson` records are appended to, defaults to
+    /// `/var/log/<facility>/current.json`. The file and its directory are
+    /// created by `try_init`, which fails if they can't be.
+    pub fn file_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file_path = Some(path.into());
+        self
+    }
+
+    /// Rotates the `FileJson` file once it would grow beyond `max_size_bytes`,
+    /// to `current.json.1`, `current.json.2`, … keeping `max_files` of them.
+    /// Defaults to [`DEFAULT_FILE_MAX_SIZE`](crate::DEFAULT_FILE_MAX_SIZE) and
+    /// [`DEFAULT_FILE_MAX_FILES`](crate::DEFAULT_FILE_MAX_FILES).
+    ///
+    /// ```no_run
+    /// vinted_logger::builder("svc-search", vinted_logger::Target::FileJson)
+    ///     .file_path("/var/log/svc-search/current.json")
+    ///     .file_rotation(50 * 1024 * 1024, 10)
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn file_rotation(mut self, max_size_bytes: u64, max_files: usize) -> Self {
+        self.file_max_size = max_size_bytes;
+        self.file_max_files = max_files;
+        self
+    }
+
+    /// Also sends ERROR `UdpJson` records to a second destination, e.g. the
+    /// on-call pipeline. The primary destination still receives every record.
+    ///
+    /// Records are serialized once and sent to each destination from its own
+    /// socket, so one failing destination doesn't hold back the other.
+    pub fn error_destination(mut self, destination: impl Into<LogDestination>) -> Self {
+        self.error_destination = Some(destination.into());
+        self
+    }
+
+    /// Sets the least severe level sent to the
+    /// [error destination](LoggerBuilder::error_destination), defaults to ERROR
+    pub fn error_destination_level(mut self, level: Level) -> Self {
+        self.error_destination_level = level;
+        self
+    }
+
+    /// Sets the target of metric records, defaults to
+    /// [`DEFAULT_METRIC_TARGET`](crate::DEFAULT_METRIC_TARGET)
+    ///
+    /// JSON metric records are checked before they're written, those which
+    /// don't follow the schema are still written but carry `metric_error`:
+    ///
+    /// - `name` - a string of at most 64 `a-z`, `0-9`, `_` and `.`, rejected
+    ///   past [`MAX_METRIC_NAMES`](crate::MAX_METRIC_NAMES) distinct ones
+    /// - `value` - a number, strings of integers are converted and anything
+    ///   else goes to `value_raw`, see
+    ///   [`field_type`](LoggerBuilder::field_type)
+    /// - `unit` - optional, a string of at most 16 characters without whitespace
+    /// - `tags` - optional, a string of `key:value` pairs separated by commas
+    ///
+    /// ```
+    /// vinted_logger::builder("svc-orders", vinted_logger::Target::ConsoleJson).try_init()?;
+    /// tracing::info!(target: "metric", name = "orders_created", value = 1, unit = "order");
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn metric_target(mut self, target: &'static str) -> Self {
+        self.metric_target = target;
+        self
+    }
+
+    /// Sends `UdpJson` [metric records](LoggerBuilder::metric_target) to
+    /// their own destination instead of the primary one, so they can be
+    /// routed separately
+    pub fn metric_destination(mut self, destination: impl Into<LogDestination>) -> Self {
+        self.metric_destination = Some(destination.into());
+        self
+    }
+
+    /// Also echoes `UdpJson`, `TcpJson` and `UnixJson` records of `level` and
+    /// above to stderr as compact single lines, so `kubectl logs` shows
+    /// problems while records go over the network
+    ///
+    /// INFO and below are never echoed, `level` is raised to WARN. Echoing is
+    /// limited to [`stderr_echo_per_second`](Self::stderr_echo_per_second)
+    /// lines, the next line echoed tells how many were left out.
+    pub fn stderr_echo(mut self, level: Level) -> Self {
+        self.stderr_echo = Some(level);
+        self
+    }
+
+    /// Also writes `UdpJson`, `TcpJson` and `UnixJson` records of `level` and
+    /// above to stdout, the same as [`Target::ConsoleJson`] would, so `kubectl logs`
+    /// shows them while they go over the network
+    ///
+    /// Both get a record only if the filter lets it through, the console
+    /// keeps to `level` on top, e.g. WARN while DEBUG records go to fluentd.
+    /// [`split_streams`](Self::split_streams), [`level_hint`](Self::level_hint),
+    /// [`console_max_line`](Self::console_max_line) and
+    /// [`non_blocking`](Self::non_blocking) apply to the console. It gets
+    /// every record before the network target, and neither can fail the
+    /// other.
+    ///
+    /// ```no_run
+    /// use vinted_logger::{prelude::Level, Target};
+    ///
+    /// vinted_logger::builder("svc-search", Target::UdpJson)
+    ///     .mirror_to_console(Level::WARN)
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn mirror_to_console(mut self, level: Level) -> Self {
+        self.console_mirror = Some(level);
+        self
+    }
+
+    /// Sets how many lines a second [`stderr_echo`](Self::stderr_echo) may
+    /// write, defaults to [`DEFAULT_ECHO_PER_SECOND`](crate::DEFAULT_ECHO_PER_SECOND)
+    pub fn stderr_echo_per_second(mut self, per_second: u32) -> Self {
+        self.stderr_echo_per_second = per_second;
+        self
+    }
+
+    /// Sets how many lines a minute may go to stderr for records which
+    /// couldn't be delivered, defaults to
+    /// [`DEFAULT_FALLBACK_PER_MINUTE`](crate::DEFAULT_FALLBACK_PER_MINUTE)
+    ///
+    /// `UdpJson` records which couldn't be sent, or whose socket couldn't be
+    /// bound, `TcpJson` records dropped from the buffer, `UnixJson` records
+    /// which couldn't be sent and `FileJson` records which couldn't be
+    /// written are always written to stderr as
+    /// `@timestamp LEVEL facility: message` lines, unless
+    /// [`stderr_echo`](Self::stderr_echo) wrote them already. Lines beyond
+    /// the limit are left out, the next line written is preceded by how
+    /// many. See [`LoggerStats::fallback_emissions`](crate::LoggerStats::fallback_emissions).
+    pub fn fallback_per_minute(mut self, per_minute: u32) -> Self {
+        self.fallback_per_minute = per_minute;
+        self
+    }
+
+    /// Drops records beyond `max_events_per_second` a second, e.g. so request
+    /// logging during an incident doesn't saturate the UDP pipe
+    ///
+    /// Records are dropped before they're formatted. Every 10 seconds, a WARN
+    /// record with target `vinted::rate_limit` tells how many were dropped
+    /// per callsite.
+    pub fn rate_limit(mut self, max_events_per_second: u32) -> Self {
+        self.rate_limit.per_second = Some(max_events_per_second);
+        self
+    }
+
+    /// Drops records of a single callsite beyond `max_events_per_second` a
+    /// second, so one hot log line can't starve the others of the
+    /// [`rate_limit`](Self::rate_limit)
+    pub fn rate_limit_per_callsite(mut self, max_events_per_second: u32) -> Self {
+        self.rate_limit.per_callsite = Some(max_events_per_second);
+        self
+    }
+
+    /// Never drops ERROR records because of [`rate_limit`](Self::rate_limit)
+    /// or [`rate_limit_per_callsite`](Self::rate_limit_per_callsite), they
+    /// don't count against the limits either
+    pub fn rate_limit_exempt_errors(mut self) -> Self {
+        self.rate_limit.exempt_errors = true;
+        self
+    }
+
+    /// Sets how much span context JSON records of every level carry,
+    /// defaults to [`PayloadTier::Full`]
+    pub fn payload_tier(mut self, tier: PayloadTier) -> Self {
+        self.payload_tiers = [tier; 5];
+        self
+    }
+
+    /// Overrides the payload tier of records of one level, e.g. `Full` for
+    /// ERROR records while everything else is `Minimal`. Call after
+    /// [`LoggerBuilder::payload_tier`].
+    pub fn payload_tier_for(mut self, level: Level, tier: PayloadTier) -> Self {
+        self.payload_tiers[vinted_json_formatter::level_index(level)] = tier;
+        self
+    }
+
+    /// Sets how JSON records are shaped for their consumer, defaults to
+    /// [`EscapingProfile::Default`]
+    pub fn escaping_profile(mut self, escaping_profile: EscapingProfile) -> Self {
+        self.escaping_profile = escaping_profile;
+        self
+    }
+
+    /// Sets what happens when a global subscriber is already installed,
+    /// defaults to [`InitBehavior::FailIfSet`]
+    pub fn init_behavior(mut self, init_behavior: InitBehavior) -> Self {
+        self.init_behavior = init_behavior;
+        self
+    }
+
+    /// Drops records below `level` from targets starting with `target_prefix`,
+    /// whatever `RUST_LOG` says, e.g. `.deny_below("sqlx", Level::WARN)`
+    ///
+    /// Applied after the env filter: `RUST_LOG` can still loosen other targets
+    /// or tighten this one, but never loosen it. When prefixes overlap the
+    /// longest matching one wins; repeating a prefix replaces its level.
+    pub fn deny_below(mut self, target_prefix: impl Into<String>, level: Level) -> Self {
+        self.deny_below.push((target_prefix.into(), level));
+        self
+    }
+
+    /// Adds `host_ip` and `node_ip` to JSON records, resolved once at init
+    ///
+    /// `host_ip` comes from `POD_IP` or, if it isn't set, the address of the
+    /// interface routing to the internet; `node_ip` from `NODE_IP`. Fields which
+    /// can't be resolved are left out.
+    pub fn host_ip(mut self) -> Self {
+        self.host_ip = true;
+        self
+    }
+
+    /// Sets `deploy_slot` of JSON records, e.g. `blue` or `green` to tell
+    /// apart the two sides of a deploy, instead of the `DEPLOY_SLOT` env var
+    ///
+    /// Records carry `deploy_slot` and `deploy_id` whenever these or the
+    /// `DEPLOY_SLOT` and `DEPLOY_ID` env vars are set.
+    pub fn deploy_slot(mut self, slot: impl Into<String>) -> Self {
+        self.deploy_slot = Some(slot.into());
+        self
+    }
+
+    /// Sets `deploy_id` of JSON records instead of the `DEPLOY_ID` env var,
+    /// see [`deploy_slot`](Self::deploy_slot)
+    pub fn deploy_id(mut self, id: impl Into<String>) -> Self {
+        self.deploy_id = Some(id.into());
+        self
+    }
+
+    /// Leaves event fields recorded as an empty string or as `None`, e.g.
+    /// `field = ?option`, out of JSON records
+    ///
+    /// This matches on the recorded string, so a legitimate `"None"` is dropped
+    /// too; prefer recording options with [`opt`](crate::opt).
+    pub fn drop_empty_fields(mut self) -> Self {
+        self.drop_empty_fields = true;
+        self
+    }
+
+    /// Adds a `stats` object to every
+    /// [`DEFAULT_LEVEL_STATS_EVERY`](crate::DEFAULT_LEVEL_STATS_EVERY)th JSON
+    /// record, see [`level_stats_every`](Self::level_stats_every)
+    pub fn level_stats(self) -> Self {
+        self.level_stats_every(crate::DEFAULT_LEVEL_STATS_EVERY)
+    }
+
+    /// Adds a `stats` object to every `records`th JSON record, with the
+    /// records of each level formatted so far and the seconds since the
+    /// logger was installed, e.g.
+    /// `"stats":{"error":3,"warn":12,"info":985,"debug":0,"trace":0,"uptime_secs":61}`,
+    /// so error rates can be computed from the log stream itself
+    ///
+    /// Stats are left out of records they would grow beyond the
+    /// [`max_datagram_size`](Self::max_datagram_size) of `UdpJson` and
+    /// `UnixJson`, the [`console_max_line`](Self::console_max_line) of
+    /// `ConsoleJson`, or the largest UDP datagram otherwise.
+    pub fn level_stats_every(mut self, records: u64) -> Self {
+        self.level_stats_every = Some(records);
+        self
+    }
+
+    /// Adds `runtime_context` to JSON records: `tokio-worker` for records
+    /// logged from a Tokio worker thread, `tokio-blocking` from other threads
+    /// within a runtime (`spawn_blocking`, `block_on`) and `thread` from
+    /// anywhere else, to spot logging and other blocking work on workers
+    ///
+    /// Threads are classified on their first record, which costs a lookup of
+    /// the runtime; later records only read a thread local.
+    ///
+    /// Only builds with `--cfg tokio_unstable` tell workers apart exactly.
+    /// Otherwise workers are told by the name Tokio gives its threads, which
+    /// threads of `spawn_blocking` take as well.
+    #[cfg(feature = "runtime-context")]
+    pub fn runtime_context(mut self) -> Self {
+        self.runtime_context = true;
+        self
+    }
+
+    /// Adds `message_template` and `message_class` to JSON records, to group
+    /// messages which only differ in their values
+    ///
+    /// Events of the [`infof!`](crate::infof) family carry their format
+    /// string, e.g. `failed to fetch user {user_id}`. For other events the
+    /// message is normalized: UUIDs become `{uuid}`, hex ids `{hex}` and other
+    /// numbers `{n}`. `message_class` is a stable 64-bit hash of the template.
+    pub fn message_template(mut self) -> Self {
+        self.message_template = true;
+        self
+    }
+
+    /// Sets how many lines per minute the logger may write to stderr about its
+    /// own problems, e.g. an unreachable collector, defaults to
+    /// [`DEFAULT_DIAGNOSTICS_PER_MINUTE`](crate::DEFAULT_DIAGNOSTICS_PER_MINUTE)
+    ///
+    /// Problems beyond the budget are still counted, see
+    /// [`logger_stats`](crate::logger_stats).
+    pub fn diagnostics_per_minute(mut self, per_minute: u32) -> Self {
+        self.diagnostics_per_minute = per_minute;
+        self
+    }
+
+    /// Counts the bytes of JSON records per target prefix (its first two path
+    /// segments), reported by [`top_targets_by_bytes`](crate::top_targets_by_bytes)
+    ///
+    /// At most [`TRACKED_TARGETS`](crate::TRACKED_TARGETS) prefixes are tracked.
+    /// [Canary](Self::canary) records carry the heaviest 10 as `volume_top`,
+    /// e.g. `[{"target": "svc_search::db", "bytes": 52311}]`.
+    pub fn track_volume(mut self) -> Self {
+        self.track_volume = true;
+        self
+    }
+
+    /// Sets how far ahead of now an `event_time` field may be, defaults to
+    /// [`DEFAULT_EVENT_TIME_SKEW`]
+    ///
+    /// Events with a field `event_time`, an RFC 3339 string or milliseconds
+    /// since the Unix epoch, are stamped with that time instead of now, e.g.
+    /// when backfill jobs re-emit historical events. JSON records then leave
+    /// the field out. Invalid times, or times too far in the future, keep the
+    /// current time and add `event_time_error`.
+    pub fn event_time_skew(mut self, skew: Duration) -> Self {
+        self.event_time_skew = skew;
+        self
+    }
+
+    /// Stops formatting a field of JSON records through `Debug` once it took
+    /// longer than `budget`, the field then becomes `"<debug formatting aborted>"`
+    ///
+    /// Regardless of this budget, `Debug` representations are cut off after
+    /// 64 KiB without formatting the rest.
+    pub fn debug_time_budget(mut self, budget: Duration) -> Self {
+        self.debug_time_budget = Some(budget);
+        self
+    }
+
+    /// Declares the JSON type of a field, so a field typed differently by
+    /// different callsites doesn't break the Elasticsearch mapping
+    ///
+    /// Values of another type are converted when nothing is lost, e.g. the
+    /// string `"42"` to the number `42` and back. Other values are recorded as
+    /// strings under `<field>_raw` instead, leaving out the field itself. See
+    /// [`field_coercions`](crate::field_coercions) for how often either
+    /// happened. Applies to event and span fields of JSON targets.
+    pub fn field_type(mut self, name: &str, field_type: FieldType) -> Self {
+        self.field_types.insert(name.to_owned(), field_type);
+        self
+    }
+
+    pub(crate) fn field_types(mut self, field_types: FieldTypes) -> Self {
+        self.field_types = field_types;
+        self
+    }
+
+    /// Sets how JSON records are timestamped, defaults to
+    /// [`TimestampFormat::Rfc3339`](crate::TimestampFormat::Rfc3339)
+    pub fn timestamp_format(mut self, format: TimestampFormat) -> Self {
+        self.timestamp_format = format;
+        self
+    }
+
+    /// Sets the key of the timestamp of JSON records, defaults to
+    /// `@timestamp`. It can't be one of the other keys of the record, e.g.
+    /// `level`.
+    ///
+    /// ```
+    /// use vinted_logger::{Target, TimestampFormat};
+    ///
+    /// vinted_logger::builder("svc-search", Target::ConsoleJson)
+    ///     .timestamp_format(TimestampFormat::EpochMillis)
+    ///     .timestamp_key("time")
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    ///
+    /// [Coalesced repeats](Self::coalesce_repeats) and console stubs of
+    /// records too large keep the timestamp under this key too. The echo to
+    /// stderr and GELF messages take the time from `@timestamp` only, and
+    /// use the current time with another key.
+    pub fn timestamp_key(mut self, key: &'static str) -> Self {
+        self.timestamp_key = key;
+        self
+    }
+
+    /// Adds `trace_id` and `span_id` to JSON records logged within a span,
+    /// as `extract` finds them in the extensions of the span or of its
+    /// closest parent which has them, e.g. of the `OtelData` that
+    /// `tracing-opentelemetry` keeps there
+    ///
+    /// Records outside of any span, or whose spans have no ids, are written
+    /// without them. Event fields named `trace_id` or `span_id` are renamed
+    /// `_trace_id` and `_span_id` once this is set.
+    ///
+    /// ```
+    /// use vinted_logger::Target;
+    ///
+    /// struct TraceIds(u128, u64);
+    ///
+    /// vinted_logger::builder("svc-search", Target::ConsoleJson)
+    ///     .trace_context(|extensions| {
+    ///         let ids = extensions.get::<TraceIds>()?;
+    ///         Some((format!("{:032x}", ids.0), format!("{:016x}", ids.1)))
+    ///     })
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn trace_context<F>(mut self, extract: F) -> Self
+    where
+        F: Fn(&Extensions<'_>) -> Option<(String, String)> + Send + Sync + 'static,
+    {
+        self.trace_context = Some(vinted_json_formatter::TraceContext(Arc::new(extract)));
+        self
+    }
+
+    /// Masks personal data found in string fields of JSON records, with
+    /// every [`PiiDetector`], see [`mask_pii_with`](Self::mask_pii_with)
+    pub fn mask_pii(mut self) -> Self {
+        self.pii_detectors = Some(Detectors::all());
+        self
+    }
+
+    /// Masks what `detectors` find in string fields of JSON records: event
+    /// and span fields, the message and error chains, before they are
+    /// coerced to their [declared types](Self::field_type). Records which had
+    /// something masked list the detectors in `pii_masked`, e.g.
+    /// `"pii_masked":["email","card"]`, and
+    /// [`pii_masked`](crate::pii_masked) counts the masked values.
+    ///
+    /// Numbers, booleans and strings longer than
+    /// [`PII_SCAN_MAX_LEN`](crate::PII_SCAN_MAX_LEN) are not scanned, nor are
+    /// the fields exempted with [`pii_exempt_field`](Self::pii_exempt_field).
+    ///
+    /// ```
+    /// use vinted_logger::{PiiDetector, Target};
+    ///
+    /// vinted_logger::builder("svc-payments", Target::ConsoleJson)
+    ///     .mask_pii_with([PiiDetector::Card, PiiDetector::Iban])
+    ///     .pii_exempt_field("order_id")
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn mask_pii_with(mut self, detectors: impl IntoIterator<Item = PiiDetector>) -> Self {
+        self.pii_detectors = Some(detectors.into_iter().collect());
+        self
+    }
+
+    /// Leaves the values of fields named `name` as they are, for fields
+    /// known to be safe whose values look like personal data, e.g. ids
+    /// passing the Luhn check, see [`mask_pii_with`](Self::mask_pii_with)
+    pub fn pii_exempt_field(mut self, name: &str) -> Self {
+        self.pii_exempt_fields.insert(name.to_owned());
+        self
+    }
+
+    /// Records the fields named like one of `fields`, whatever their case,
+    /// as `"[REDACTED]"` in JSON records, e.g. `password` or `Authorization`
+    ///
+    /// Applies to event and span fields. Redacted fields are kept rather than
+    /// left out so occurrences can still be counted, and are always strings
+    /// whatever their [declared type](Self::field_type).
+    ///
+    /// ```
+    /// use vinted_logger::Target;
+    ///
+    /// vinted_logger::builder("svc-auth", Target::ConsoleJson)
+    ///     .redact_fields(["password", "authorization", "email"])
+    ///     .try_init()?;
+    /// // {..,"password":"[REDACTED]",..}
+    /// tracing::info!(password = "hunter2", "signed in");
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn redact_fields<I>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        for field in fields {
+            self.redaction.insert(field.as_ref());
+        }
+        self
+    }
+
+    /// Gives every field of JSON records, but for those of
+    /// [`redact_fields`](Self::redact_fields), to `redact` with its name
+    /// before it is serialized, to scrub values in place, e.g.
+    ///
+    /// ```
+    /// use vinted_logger::Target;
+    ///
+    /// vinted_logger::builder("svc-auth", Target::ConsoleJson)
+    ///     .redact_with(|name, value| {
+    ///         if name.ends_with("_token") {
+    ///             *value = "[REDACTED]".into();
+    ///         }
+    ///     })
+    ///     .try_init()?;
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    ///
+    /// Values are converted to a `serde_json::Value` to be given to `redact`,
+    /// which costs an allocation per field.
+    pub fn redact_with<F>(mut self, redact: F) -> Self
+    where
+        F: Fn(&str, &mut serde_json::Value) + Send + Sync + 'static,
+    {
+        self.redaction.set_callback(Arc::new(redact));
+        self
+    }
+
+    /// Sets the level logged when `RUST_LOG` is not set, defaults to INFO
+    pub fn default_level(mut self, level: Level) -> Self {
+        self.default_level = level;
+        self
+    }
+
+    /// Filters records with `filter` instead of `RUST_LOG` and
+    /// [`default_level`](Self::default_level), e.g. `.env_filter("my_crate=debug,info")`
+    pub fn env_filter(mut self, filter: impl Into<EnvFilter>) -> Self {
+        self.env_filter = Some(filter.into());
+        self
+    }
+
+    pub(crate) fn console_format(mut self, console_format: ConsoleFormat) -> Self {
+        self.console_format = console_format;
+        self
+    }
+
+    /// Logs panics at ERROR with target `vinted::panic` instead of printing
+    /// them on stderr, once the logger is installed
+    ///
+    /// Records carry `panic_location` (`file:line:column`) and `span_path`,
+    /// the names of the spans the panic happened in, e.g. `request > db_query`.
+    /// JSON records also get the fields of all of those spans, so a crash can
+    /// be tied to a request. Panics filtered out by the level still go to the
+    /// previous hook.
+    pub fn log_panics(mut self) -> Self {
+        self.log_panics = true;
+        self
+    }
+
+    /// Sets how long the [`LoggerGuard`](crate::LoggerGuard) waits for
+    /// queued records to be sent, defaults to
+    /// [`DEFAULT_FLUSH_TIMEOUT`](crate::DEFAULT_FLUSH_TIMEOUT)
+    pub fn flush_timeout(mut self, timeout: Duration) -> Self {
+        self.flush_timeout = timeout;
+        self
+    }
+
+    /// Sets how long batches wait for more records at most once
+    /// [`LoggerGuard::drain`](crate::LoggerGuard::drain) was called, defaults
+    /// to [`DEFAULT_DRAIN_BATCH_INTERVAL`](crate::DEFAULT_DRAIN_BATCH_INTERVAL).
+    /// Shorter batch intervals are kept.
+    pub fn drain_batch_interval(mut self, interval: Duration) -> Self {
+        self.drain.batch_interval = interval;
+        self
+    }
+
+    /// Sets how long [`LoggerGuard::drain`](crate::LoggerGuard::drain) waits
+    /// for queued ERROR records to be sent, beyond the
+    /// [flush timeout](Self::flush_timeout) other records get, defaults to
+    /// [`DEFAULT_DRAIN_ERROR_TIMEOUT`](crate::DEFAULT_DRAIN_ERROR_TIMEOUT)
+    pub fn drain_error_timeout(mut self, timeout: Duration) -> Self {
+        self.drain.error_timeout = timeout;
+        self
+    }
+
+    /// Sends a canary record every [`DEFAULT_CANARY_INTERVAL`](crate::DEFAULT_CANARY_INTERVAL)
+    /// (5 minutes) for as long as the guard of
+    /// [`try_init_with_guard`](Self::try_init_with_guard) lives, so the
+    /// collector side can tell records still get through. Network targets
+    /// only.
+    ///
+    /// Canaries are INFO records of target
+    /// [`CANARY_TARGET`](crate::CANARY_TARGET) with message `canary` and a
+    /// `canary_id` unique to each of them, which the filter must let through.
+    pub fn canary(mut self) -> Self {
+        self.canary.get_or_insert_with(Default::default);
+        self
+    }
+
+    /// Same as [`LoggerBuilder::canary`] with a custom interval
+    pub fn canary_every(mut self, interval: Duration) -> Self {
+        self.canary.get_or_insert_with(Default::default).interval = interval;
+        self
+    }
+
+    /// Sends canaries, see [`LoggerBuilder::canary`], and waits for the
+    /// collector to reflect them back to UDP `port` on 127.0.0.1, `0` for any
+    /// free one. The socket is bound when the first canary is sent.
+    ///
+    /// Canaries then carry the address to reflect them to in
+    /// `canary_reply_to`, e.g. `127.0.0.1:9095`. The collector sends back a
+    /// datagram of either the `canary_id` alone or the whole canary record.
+    ///
+    /// [`logger_stats`](crate::logger_stats) tells when the last canary was
+    /// reflected and its round trip. A canary not reflected before the next
+    /// one is due makes [`healthy`](crate::healthy) `false` until one is.
+    pub fn canary_reflections(mut self, port: u16) -> Self {
+        self.canary
+            .get_or_insert_with(Default::default)
+            .reflect_port = Some(port);
+        self
+    }
+
+    /// Installs the logger like [`try_init`](Self::try_init) and returns a
+    /// guard flushing it when dropped, so records logged right before a
+    /// short-lived process exits aren't lost
+    ///
+    /// ```
+    /// fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ///     let _guard = vinted_logger::builder("nightly-export", vinted_logger::Target::UdpJson)
+    ///         .try_init_with_guard()?;
+    ///     tracing::info!("export done");
+    ///     Ok(())
+    /// }
+    /// ```
+    #[track_caller]
+    pub fn try_init_with_guard(self) -> Result<vinted_flush::LoggerGuard, InitError> {
+        let (timeout, drain, canary) = (self.flush_timeout, self.drain, self.canary);
+        self.try_init()?;
+        Ok(vinted_flush::LoggerGuard::new(timeout, drain, canary))
+    }
+
+    /// Installs the logger as the global default subscriber
+    ///
+    /// Fails with [`InitError::AlreadyInitialized`] when another subscriber
+    /// is installed already, unless [`InitBehavior::NoopIfSet`] is set, and
+    /// with the other variants of [`InitError`] for settings which can't be
+    /// used.
+    ///
+    /// When the installed subscriber is a logger of this crate, e.g. one a
+    /// library installed, a logger of the same facility writing to another
+    /// target is attached next to it, sharing its filter, and one of another
+    /// facility fails with [`InitError::ConflictingInit`], naming where both
+    /// were initialized.
+    #[track_caller]
+    pub fn try_init(self) -> Result<(), InitError> {
+        let location = Location::caller();
+        let init_behavior = self.init_behavior;
+        if tracing::dispatcher::has_been_set() {
+            if init_behavior == InitBehavior::NoopIfSet {
+                return Ok(());
+            }
+            match vinted_installation::existing(self.facility, self.target, location)? {
+                vinted_installation::Existing::Compatible => return self.attach_installed(),
+                vinted_installation::Existing::Same | vinted_installation::Existing::None => {}
+            }
+            if init_behavior == InitBehavior::AttachLayerIfPossible {
+                return self.attach();
+            }
+        }
+
+        let installed = self.installed();
+        let (facility, target) = (self.facility, self.target);
+        let result = self.build().and_then(|(layer, filter)| {
+            vinted_installation::install(layer, facility, target, location)?;
+            Ok(filter)
+        });
+        match result {
+            Ok(filter) => {
+                installed.done(filter);
+                Ok(())
+            }
+            // Another thread might have installed a subscriber in the meantime.
+            Err(_)
+                if init_behavior != InitBehavior::FailIfSet
+                    && tracing::dispatcher::has_been_set() =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Attaches the logger next to the installed logger of the same facility
+    fn attach_installed(self) -> Result<(), InitError> {
+        let installed = self.installed();
+        let (layer, _) = self.build()?;
+        if vinted_installation::attach(layer) {
+            installed.attached();
+        }
+        Ok(())
+    }
+
+    /// Attaches the logger to the attach point of the installed subscriber,
+    /// if it has a free one
+    fn attach(self) -> Result<(), InitError> {
+        let free = tracing::dispatcher::get_default(|dispatch| {
+            dispatch
+                .downcast_ref::<vinted_attach_point::AttachPoint>()
+                .is_some_and(|point| !point.is_attached())
+        });
+        if !free {
+            vinted_diagnostics::report(
+                Diagnostic::ConfigWarning,
+                format_args!("WARNING: vinted_logger wasn't installed, the installed subscriber has no free `AttachPoint` to attach it to."),
+            );
+            return Ok(());
+        }
+
+        let installed = self.installed();
+        let (layer, filter) = self.build()?;
+        let mut layer = Some(layer);
+        let attached = tracing::dispatcher::get_default(|dispatch| {
+            match (
+                dispatch.downcast_ref::<vinted_attach_point::AttachPoint>(),
+                layer.take(),
+            ) {
+                (Some(point), Some(layer)) => point.attach(layer, dispatch),
+                _ => false,
+            }
+        });
+        if attached {
+            installed.done(filter);
+        } else {
+            vinted_diagnostics::report(
+                Diagnostic::ConfigWarning,
+                format_args!("WARNING: vinted_logger wasn't installed, another logger was attached in the meantime."),
+            );
+        }
+        Ok(())
+    }
+
+    fn installed(&self) -> Installed {
+        Installed {
+            // Console targets deliver as soon as they're installed, network
+            // ones once the first record is sent.
+            console: !self.target.is_network(),
+            log_panics: self.log_panics,
+        }
+    }
+
+    /// Layers of the logger and the handle of its filter, validating the
+    /// settings
+    pub(crate) fn build(self) -> Result<(BoxedLayer, FilterReload), InitError> {
+        // Before the warnings below, which take from the budget
+        vinted_diagnostics::set_per_minute(self.diagnostics_per_minute);
+        if let Some(canary) = self.canary {
+            if !self.target.is_network() {
+                return Err(InitError::InvalidConfig(format!(
+                    "canaries need a network target, {:?} records don't leave the host",
+                    self.target
+                )));
+            }
+            if canary.interval.is_zero() {
+                return Err(InitError::InvalidConfig(
+                    "canary interval is zero".to_owned(),
+                ));
+            }
+        }
+        if self.facility.trim().is_empty() {
+            if !self.allow_empty_facility {
+                return Err(InitError::InvalidConfig(
+                    "facility is empty, records without one are routed to the catch-all index"
+                        .to_owned(),
+                ));
+            }
+            vinted_diagnostics::report(
+                Diagnostic::ConfigWarning,
+                format_args!("WARNING: vinted_logger was installed without a facility, every record goes to the catch-all index. Pass the service name to `builder`."),
+            );
+        }
+
+        for (prefix, facility) in &self.facilities {
+            if prefix.is_empty() {
+                return Err(InitError::InvalidConfig("target prefix of `facility_for` is empty, use `facility` to set the facility of every record".to_owned()));
+            }
+            if facility.trim().is_empty() {
+                return Err(InitError::InvalidConfig(format!(
+                    "facility of target prefix `{}` is empty",
+                    prefix
+                )));
+            }
+        }
+
+        if self.timestamp_key.is_empty()
+            || (self.timestamp_key != vinted_json_formatter::DEFAULT_TIMESTAMP_KEY
+                && vinted_json_formatter::RECORD_KEYS.contains(&self.timestamp_key))
+        {
+            return Err(InitError::InvalidConfig(format!(
+                "timestamp key `{}` is empty or taken by the record",
+                self.timestamp_key
+            )));
+        }
+
+        if self.rejected_count > 0 {
+            let mut names = self
+                .rejected_fields
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if self.rejected_count > self.rejected_fields.len() {
+                names.push_str(&format!(
+                    " and {} more",
+                    self.rejected_count - self.rejected_fields.len()
+                ));
+            }
+            let message = format!(
+                "additional fields {} were rejected: fields are limited to {} and {} bytes, and can't be named like record keys",
+                names, self.max_additional_fields, self.max_additional_fields_bytes
+            );
+            if !self.ignore_rejected_fields {
+                return Err(InitError::InvalidConfig(message));
+            }
+            vinted_diagnostics::report(
+                Diagnostic::ConfigWarning,
+                format_args!("{}, leaving them out", message),
+            );
+        }
+
+        let default_level = self.default_level;
+        let filter_layer = vinted_filter_reload::with_log_points(
+            self.env_filter
+                .map_or_else(EnvFilter::try_from_default_env, Ok)
+                .unwrap_or_else(|_| {
+                    EnvFilter::default()
+                        .add_directive(LevelFilter::from_level(default_level).into())
+                }),
+        );
+
+        let own_records = vinted_own_records::OwnRecords::default();
+        let span_tracker = vinted_span_tracker::VintedSpanTracker::new(
+            self.live_spans_threshold,
+            own_records.clone(),
+        );
+        let deny_filter = if self.deny_below.is_empty() {
+            None
+        } else {
+            Some(
+                Targets::new()
+                    .with_default(LevelFilter::TRACE)
+                    .with_targets(self.deny_below),
+            )
+        };
+
+        if let (Some(destination), false) = (
+            &self.error_destination,
+            matches!(self.target, Target::UdpJson),
+        ) {
+            return Err(InitError::InvalidConfig(format!(
+                "error destination `{}` can only be used with the UdpJson target",
+                destination
+            )));
+        }
+
+        if let (Some(destination), false) = (
+            &self.metric_destination,
+            matches!(self.target, Target::UdpJson),
+        ) {
+            return Err(InitError::InvalidConfig(format!(
+                "metric destination `{}` can only be used with the UdpJson target",
+                destination
+            )));
+        }
+
+        if self.wire_format == vinted_udp_writer::WireFormat::JsonDict
+            && self.udp_batching.is_none()
+        {
+            return Err(InitError::InvalidConfig(
+                "the JsonDict wire format needs UDP batching".to_owned(),
+            ));
+        }
+        if let Some(batching) = self.udp_batching {
+            if batching.max_bytes == 0 || batching.max_bytes > self.max_datagram_size {
+                return Err(InitError::InvalidConfig(format!(
+                    "UDP batches of {} bytes don't fit datagrams of {} bytes",
+                    batching.max_bytes, self.max_datagram_size
+                )));
+            }
+            if self.wire_format == vinted_udp_writer::WireFormat::Gelf {
+                return Err(InitError::InvalidConfig(
+                    "UDP batching can't be used with the GELF wire format".to_owned(),
+                ));
+            }
+        }
+
+        #[cfg(feature = "gelf-http")]
+        {
+            if self
+                .http_batching
+                .is_some_and(|batching| batching.max_bytes == 0)
+            {
+                return Err(InitError::InvalidConfig(
+                    "HTTP batches must allow at least 1 byte".to_owned(),
+                ));
+            }
+            if self.http_max_attempts == 0 {
+                return Err(InitError::InvalidConfig(
+                    "HTTP requests need at least 1 attempt".to_owned(),
+                ));
+            }
+        }
+
+        vinted_drain::configure(self.drain);
+        vinted_fallback::configure(
+            self.fallback_per_minute,
+            if self.target.is_network() {
+                self.stderr_echo
+            } else {
+                None
+            },
+        );
+        let rate_limiter = if self.rate_limit.is_enabled() {
+            Some(vinted_rate_limit::VintedRateLimiter::new(self.rate_limit))
+        } else {
+            None
+        };
+
+        let destination = match (self.destination, self.target) {
+            (Some(destination), target) if target.is_network() => Some(destination),
+            (None, Target::UdpJson) => Some(LogDestination::default()),
+            (None, Target::TcpJson) => Some(LogDestination::Tcp("127.0.0.1:5170".to_owned())),
+            #[cfg(unix)]
+            (None, Target::UnixJson) => Some(LogDestination::Unix(
+                vinted_unix_writer::DEFAULT_UNIX_SOCKET.into(),
+            )),
+            #[cfg(feature = "gelf-http")]
+            (None, Target::GelfHttp) => {
+                Some(LogDestination::Http("127.0.0.1:12201/gelf".to_owned()))
+            }
+            _ => None,
+        };
+        let json = !matches!(self.target, Target::Console);
+        let introspection = VintedIntrospection(introspect::Config {
+            facility: self.facility,
+            target: self.target,
+            destination: destination.clone(),
+            error_destination: self.error_destination.clone(),
+            metric_destination: self.metric_destination.clone(),
+            coalesce_window: if json { self.coalesce_window } else { None },
+        });
+
+        let field_types = if self.field_types.is_empty() {
+            None
+        } else {
+            Some(Arc::new(self.field_types))
+        };
+        let deployment = vinted_deployment::Deployment::resolve(self.deploy_slot, self.deploy_id);
+        let host_ips = if self.host_ip {
+            vinted_host_ip::resolve()
+        } else {
+            Default::default()
+        };
+
+        let pii_exempt_fields = self.pii_exempt_fields;
+        let pii = self
+            .pii_detectors
+            .map(|detectors| Arc::new(PiiScanner::new(detectors, pii_exempt_fields)));
+        let redaction = if self.redaction.is_empty() {
+            None
+        } else {
+            Some(Arc::new(self.redaction))
+        };
+
+        // Largest record the target delivers whole, records carrying stats
+        // stay within it
+        let max_record_len = match self.target {
+            Target::UdpJson => self.max_datagram_size,
+            #[cfg(unix)]
+            Target::UnixJson => self.max_datagram_size,
+            Target::ConsoleJson => self.console_max_line,
+            _ => vinted_level_stats::MAX_RECORD_LEN,
+        };
+        let json_fields = vinted_field_visitor::VintedJsonFields::new(self.debug_time_budget)
+            .with_field_types(field_types.clone())
+            .with_pii(pii.clone())
+            .with_redaction(redaction.clone());
+        let json_format = vinted_json_formatter::VintedJson::new(self.facility)
+            .with_timestamp(self.timestamp_format, self.timestamp_key)
+            .with_payload_tiers(self.payload_tiers)
+            .with_escaping_profile(self.escaping_profile)
+            .with_host_ips(host_ips)
+            .with_deployment(deployment)
+            .with_drop_empty_fields(self.drop_empty_fields)
+            .with_volume_top(self.track_volume)
+            .with_level_stats(
+                self.level_stats_every
+                    .map(|every| vinted_level_stats::LevelStats::new(every, max_record_len)),
+            )
+            .with_message_template(self.message_template)
+            .with_event_time_skew(self.event_time_skew)
+            .with_debug_budget(self.debug_time_budget)
+            .with_metrics(vinted_metric::Metrics::new(
+                self.metric_target,
+                field_types.as_deref(),
+            ))
+            .with_field_types(field_types)
+            .with_facility_map(vinted_facility_map::FacilityMap::new(self.facilities))
+            .with_additional_fields(self.additional_fields, self.rejected_count)
+            .with_pii(pii)
+            .with_redaction(redaction)
+            .with_trace_context(self.trace_context)
+            .with_own_records(own_records.clone());
+        #[cfg(feature = "runtime-context")]
+        let json_format = json_format.with_runtime_context(self.runtime_context);
+
+        let console = ConsoleOutput {
+            level_hint: self.level_hint,
+            split_streams: self.split_streams,
+            max_line: self.console_max_line,
+            timestamp_key: self.timestamp_key,
+            timestamp_format: self.timestamp_format,
+            test_writer: self.test_writer,
+            buffer: self.console_buffer,
+            #[cfg(test)]
+            capture: self.capture.clone(),
+        };
+        let network = NetworkOutput {
+            stderr_echo: self.stderr_echo,
+            stderr_echo_per_second: self.stderr_echo_per_second,
+            #[cfg(test)]
+            capture: self.capture,
+            console_mirror: match self.console_mirror {
+                Some(level) if self.target.is_network() => Some((level, console.writer(true))),
+                _ => None,
+            },
+        };
+        let json = JsonOutput {
+            format: json_format,
+            fields: json_fields,
+            coalesce_window: self.coalesce_window,
+            timestamp_key: self.timestamp_key,
+            track_volume: self.track_volume,
+        };
+
+        let layer = match (self.target, destination) {
+            (Target::UdpJson, Some(destination)) => {
+                let wire_format = self.wire_format;
+                let mut writer = vinted_udp_writer::VintedUdpWriter::new(
+                    udp_addr(destination)?,
+                    self.udp_buffer,
+                    self.max_datagram_size,
+                    self.udp_batching
+                        .map(|batching| vinted_udp_writer::Batching {
+                            key_table: wire_format == vinted_udp_writer::WireFormat::JsonDict,
+                            ..batching
+                        }),
+                )
+                .with_wire_format(wire_format);
+                if let Some(destination) = self.error_destination {
+                    writer = writer.with_error_destination(
+                        udp_addr(destination)?,
+                        self.error_destination_level,
+                    );
+                }
+                if let Some(destination) = self.metric_destination {
+                    writer =
+                        writer.with_metric_destination(udp_addr(destination)?, self.metric_target);
+                }
+                json.layer(network.writer(writer))
+            }
+            (Target::TcpJson, Some(destination)) => {
+                json.layer(network.writer(vinted_tcp_writer::VintedTcpWriter::new(
+                    tcp_addr(destination)?,
+                    self.tcp_buffer,
+                    self.tcp_framing,
+                )))
+            }
+            #[cfg(unix)]
+            (Target::UnixJson, Some(destination)) => {
+                json.layer(network.writer(vinted_unix_writer::VintedUnixWriter::new(
+                    unix_path(destination)?,
+                    self.udp_buffer,
+                    self.max_datagram_size,
+                )))
+            }
+            #[cfg(feature = "gelf-http")]
+            (Target::GelfHttp, Some(destination)) => json.layer(network.writer(
+                vinted_gelf_http_writer::VintedGelfHttpWriter::new(
+                    http_endpoint(destination)?,
+                    self.udp_buffer,
+                    self.http_batching,
+                    self.http_max_attempts,
+                ),
+            )),
+            (Target::FileJson, _) => {
+                let facility = self.facility;
+                let path = self
+                    .file_path
+                    .unwrap_or_else(|| format!("/var/log/{}/current.json", facility).into());
+                let writer = vinted_file_writer::VintedFileWriter::new(
+                    path.clone(),
+                    self.file_max_size,
+                    self.file_max_files,
+                )
+                .map_err(|source| InitError::Io { path, source })?;
+                json.layer(writer)
+            }
+            (Target::ConsoleJson, _) => json.layer(console.writer(true)),
+            // Like env_logger, everything goes to stderr
+            (Target::Console, _) if self.console_format == ConsoleFormat::EnvLogger => Box::new(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(console.stderr_writer())
+                    .with_ansi(false)
+                    .event_format(EnvLoggerFormat),
+            ),
+            _ => {
+                let console = tracing_subscriber::fmt::layer().with_writer(console.writer(false));
+                // Same local wall-clock format tracing-subscriber 0.2 used with chrono
+                #[cfg(feature = "chrono")]
+                let timer = tracing_subscriber::fmt::time::ChronoLocal::new(
+                    "%b %d %H:%M:%S%.3f".to_owned(),
+                );
+                #[cfg(not(feature = "chrono"))]
+                let timer = tracing_subscriber::fmt::time::SystemTime;
+                Box::new(
+                    console.event_format(vinted_event_time::VintedEventTimeFormat::new(
+                        tracing_subscriber::fmt::format()
+                            .with_timer(vinted_event_seq::VintedSeqTimer(timer)),
+                        self.event_time_skew,
+                    )),
+                )
+            }
+        };
+
+        let (filter, filter_handle) = reload::Layer::new(filter_layer);
+        let layer = layer
+            .and_then(filter)
+            .and_then(span_tracker)
+            .and_then(deny_filter)
+            .and_then(rate_limiter)
+            .and_then(introspection)
+            .and_then(own_records);
+        Ok((Box::new(layer), filter_handle))
+    }
+}
+
+/// What is left to do once the layers of a logger are installed
+struct Installed {
+    console: bool,
+    log_panics: bool,
+}
+
+impl Installed {
+    fn done(self, filter: FilterReload) {
+        vinted_filter_reload::installed(filter);
+        self.attached();
+    }
+
+    /// Same as `done` for a logger attached next to another one, whose
+    /// filter stays the one changed by the filter handle
+    fn attached(self) {
+        if self.log_panics {
+            vinted_panic_hook::install();
+        }
+        if self.console {
+            vinted_delivery::delivered();
+        }
+    }
+}
+
+/// Every layer of a logger, on top of a `Registry`
+pub(crate) type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+type FilterReload = reload::Handle<EnvFilter, Registry>;
+
+/// Layers of the JSON targets, whatever they write to
+struct JsonOutput {
+    format: vinted_json_formatter::VintedJson,
+    fields: vinted_field_visitor::VintedJsonFields,
+    coalesce_window: Option<Duration>,
+    timestamp_key: &'static str,
+    track_volume: bool,
+}
+
+impl JsonOutput {
+    fn layer<W>(self, writer: W) -> BoxedLayer
+    where
+        W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+    {
+        let writer = vinted_volume_writer::VintedVolumeWriter::new(
+            vinted_coalescing_writer::VintedCoalescingWriter::new(
+                writer,
+                self.coalesce_window,
+                self.timestamp_key,
+            ),
+            self.track_volume,
+        );
+        Box::new(
+            tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .fmt_fields(vinted_field_visitor::SkipFields)
+                .event_format(self.format)
+                .and_then(self.fields),
+        )
+    }
+}
+
+/// Settings of the console writers
+struct ConsoleOutput {
+    level_hint: bool,
+    split_streams: bool,
+    max_line: usize,
+    timestamp_key: &'static str,
+    timestamp_format: TimestampFormat,
+    test_writer: bool,
+    buffer: Option<usize>,
+    #[cfg(test)]
+    capture: Option<crate::test_support::Capture>,
+}
+
+impl ConsoleOutput {
+    /// Writer of `VintedJson` records if `json`, of text ones otherwise,
+    /// which never get a level hint
+    fn writer(&self, json: bool) -> vinted_console_writer::VintedConsoleWriter {
+        let writer = vinted_console_writer::VintedConsoleWriter::new(
+            self.level_hint && json,
+            self.split_streams,
+            self.max_line,
+            json,
+        )
+        .timestamp(self.timestamp_key, self.timestamp_format)
+        .test_writer(self.test_writer)
+        .non_blocking(self.buffer);
+        #[cfg(test)]
+        let writer = writer.capture(self.capture.clone());
+        writer
+    }
+
+    /// Writer of lines which all go to stderr
+    fn stderr_writer(&self) -> BoxMakeWriter {
+        #[cfg(test)]
+        if let Some(ref capture) = self.capture {
+            return BoxMakeWriter::new(crate::test_support::CaptureStderr(capture.clone()));
+        }
+        if self.test_writer {
+            BoxMakeWriter::new(TestWriter::with_stderr())
+        } else {
+            BoxMakeWriter::new(io::stderr)
+        }
+    }
+}
+
+/// Writers the network targets send records through, besides their own
+struct NetworkOutput {
+    stderr_echo: Option<Level>,
+    stderr_echo_per_second: u32,
+    console_mirror: Option<(Level, vinted_console_writer::VintedConsoleWriter)>,
+    #[cfg(test)]
+    capture: Option<crate::test_support::Capture>,
+}
+
+impl NetworkOutput {
+    fn writer<W>(
+        self,
+        writer: W,
+    ) -> vinted_mirror_writer::VintedMirrorWriter<vinted_echo_writer::VintedEchoWriter<W>> {
+        let echo = vinted_echo_writer::VintedEchoWriter::new(
+            writer,
+            self.stderr_echo,
+            self.stderr_echo_per_second,
+        );
+        #[cfg(test)]
+        let echo = echo.capture(self.capture);
+        vinted_mirror_writer::VintedMirrorWriter::new(echo, self.console_mirror)
+    }
+}
+
+/// Resolves the destination once, so a hostname which doesn't resolve fails
+/// `try_init` instead of every send
+fn udp_addr(destination: LogDestination) -> Result<SocketAddr, InitError> {
+    match destination {
+        LogDestination::Udp(ref addr) => resolve(addr, &destination),
+        destination => Err(InitError::InvalidConfig(format!(
+            "`{}` can't be used with the UdpJson target, only udp:// destinations are supported",
+            destination
+        ))),
+    }
+}
+
+/// Path of the socket of `UnixJson`
+#[cfg(unix)]
+fn unix_path(destination: LogDestination) -> Result<PathBuf, InitError> {
+    match destination {
+        LogDestination::Unix(path) => Ok(path),
+        destination => Err(InitError::InvalidConfig(format!(
+            "`{}` can't be used with the UnixJson target, only unix:// destinations are supported",
+            destination
+        ))),
+    }
+}
+
+/// Same as [`udp_addr`], for `TcpJson`
+fn tcp_addr(destination: LogDestination) -> Result<SocketAddr, InitError> {
+    match destination {
+        LogDestination::Tcp(ref addr) => resolve(addr, &destination),
+        destination => Err(InitError::InvalidConfig(format!(
+            "`{}` can't be used with the TcpJson target, only tcp:// destinations are supported",
+            destination
+        ))),
+    }
+}
+
+/// Same as [`udp_addr`], for `GelfHttp`
+#[cfg(feature = "gelf-http")]
+fn http_endpoint(
+    destination: LogDestination,
+) -> Result<vinted_gelf_http_writer::Endpoint, InitError> {
+    let (scheme, url) = match destination {
+        LogDestination::Http(ref url) => ("http", url),
+        #[cfg(feature = "tls")]
+        LogDestination::Https(ref url) => ("https", url),
+        destination => {
+            return Err(InitError::InvalidConfig(format!(
+                "`{}` can't be used with the GelfHttp target, only http:// and https:// destinations are supported",
+                destination
+            )))
+        }
+    };
+    // Resolved here to fail early, the agent resolves it again per connection
+    let host = &url[..url.find('/').unwrap_or(url.len())];
+    Ok(vinted_gelf_http_writer::Endpoint {
+        addr: resolve(host, &destination)?,
+        url: format!("{}://{}", scheme, url),
+    })
+}
+
+fn resolve(addr: &str, destination: &LogDestination) -> Result<SocketAddr, InitError> {
+    let error = |source| InitError::InvalidAddress {
+        destination: destination.clone(),
+        source,
+    };
+
+    addr.to_socket_addrs()
+        .map_err(error)?
+        .next()
+        .ok_or_else(|| {
+            error(io::Error::new(
+                io::ErrorKind::NotFound,
+                "resolved to no address",
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        vinted_diagnostics::{self, Diagnostic},
+        InitError, LogDestination, Target,
+    };
+    use serde_json::json;
+    use std::{net::UdpSocket, time::Duration};
+
+    fn collector() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    /// Destination of the logger of `builder`, as introspection reports it
+    fn destination(builder: crate::LoggerBuilder) -> Option<LogDestination> {
+        with_logger(builder, || {
+            tracing::dispatcher::get_default(|dispatch| {
+                crate::introspect(dispatch)
+                    .expect("logger is installed")
+                    .destination
+            })
+        })
+    }
+
+    #[test]
+    fn udp_records_go_to_the_local_agent_by_default() {
+        assert_eq!(
+            destination(crate::builder("svc-test", Target::UdpJson)),
+            Some(LogDestination::Udp("127.0.0.1:9091".to_owned()))
+        );
+    }
+
+    #[test]
+    fn hostnames_of_destinations_are_resolved() {
+        let collector = collector();
+        let port = collector.local_addr().unwrap().port();
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(LogDestination::Udp(format!("localhost:{}", port)));
+
+        with_logger(builder, || {
+            tracing::info!("resolved");
+            crate::flush(Duration::from_secs(5));
+        });
+
+        let mut datagram = vec![0; 65_536];
+        let len = collector.recv(&mut datagram).expect("record is received");
+        let record: serde_json::Value = serde_json::from_slice(&datagram[..len]).unwrap();
+        assert_eq!(record["message"], "resolved");
+    }
+
+    #[test]
+    fn destinations_which_dont_resolve_fail_the_init() {
+        let destination = LogDestination::Udp("localhost".to_owned());
+        let result = crate::builder("svc-test", Target::UdpJson)
+            .destination(destination.clone())
+            .build();
+
+        match result {
+            Err(InitError::InvalidAddress {
+                destination: failed,
+                ..
+            }) => assert_eq!(failed, destination),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("init succeeded"),
+        }
+    }
+
+    #[test]
+    fn additional_fields_are_in_every_json_record() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .additional_field("datacenter", "eu-1")
+            .additional_field("team", "search")
+            .additional_field("shard", 3)
+            .capture(&capture);
+
+        with_logger(builder, || {
+            tracing::info!("first");
+            tracing::warn!(user_id = 7, "second");
+        });
+
+        let records = capture.records();
+        assert_eq!(records.len(), 2);
+        for record in &records {
+            assert_eq!(record["facility"], "svc-test");
+            assert_eq!(record["datacenter"], "eu-1");
+            assert_eq!(record["team"], "search");
+            assert_eq!(record["shard"], json!(3));
+        }
+    }
+
+    #[test]
+    fn facility_and_target_can_be_replaced() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-old", Target::Console)
+            .facility("svc-new")
+            .target(Target::ConsoleJson)
+            .capture(&capture);
+
+        with_logger(builder, || tracing::info!("replaced"));
+
+        assert_eq!(capture.records()[0]["facility"], "svc-new");
+    }
+
+    #[test]
+    fn empty_facilities_are_rejected() {
+        for facility in &["", "  "] {
+            let result = crate::builder(facility, Target::ConsoleJson).build();
+
+            assert!(
+                matches!(
+                    result,
+                    Err(InitError::InvalidConfig(ref message)) if message.starts_with("facility is empty")
+                ),
+                "{:?}",
+                facility
+            );
+        }
+    }
+
+    #[test]
+    fn empty_facilities_can_be_allowed_for_the_transition() {
+        let capture = Capture::default();
+        let builder = crate::builder("", Target::ConsoleJson)
+            .allow_empty_facility()
+            .capture(&capture);
+
+        // Warns on stderr, and logs anyway
+        let warnings = vinted_diagnostics::count(Diagnostic::ConfigWarning);
+        with_logger(builder, || tracing::info!("without a facility"));
+
+        assert_eq!(capture.records()[0]["facility"], "");
+        // Within the budget of the logger's own reports
+        assert!(vinted_diagnostics::count(Diagnostic::ConfigWarning) > warnings);
+    }
+}
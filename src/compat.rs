@@ -0,0 +1,308 @@
+//! Migration path for services initializing `env_logger` or `log4rs`
+//!
+//! ```
+//! // Was `env_logger::init()`
+//! vinted_logger::compat::init_env_logger_style()?;
+//! tracing::warn!("still shows up as `[… WARN  target] message`");
+//! # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+//! ```
+
+use crate::{builder, vinted_timestamp, InitError, Target};
+use std::{error::Error, fmt, time::SystemTime};
+use tracing_core::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::{
+    fmt::{
+        format::{FormatEvent, FormatFields, Writer},
+        FmtContext,
+    },
+    registry::LookupSpan,
+};
+
+/// Facility of loggers installed by [`init_env_logger_style`], console lines
+/// don't carry it
+const FACILITY: &str = "env_logger";
+
+/// Installs the [`Target::Console`] logger writing lines laid out like
+/// `env_logger`'s default format, so alerts matching them keep working while
+/// a service migrates
+///
+/// `[2021-04-20T12:42:57Z WARN  app::db] pool exhausted size=10`
+///
+/// Lines go to stderr and levels come from `RUST_LOG`, defaulting to ERROR,
+/// the same as `env_logger`. Records of the `log` crate are logged too.
+#[track_caller]
+pub fn init_env_logger_style() -> Result<(), InitError> {
+    builder(FACILITY, Target::Console)
+        .default_level(Level::ERROR)
+        .console_format(ConsoleFormat::EnvLogger)
+        .try_init()
+}
+
+/// Line layout of the `Console` target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConsoleFormat {
+    Default,
+    EnvLogger,
+}
+
+/// `env_logger`'s default layout: `[{timestamp} {level:<5} {target}] {message}`,
+/// with the time in whole UTC seconds and fields after the message
+#[derive(Debug)]
+pub(crate) struct EnvLoggerFormat;
+
+impl<S, N> FormatEvent<S, N> for EnvLoggerFormat
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let timestamp = vinted_timestamp::rfc3339(SystemTime::now());
+        let normalized = event.normalized_metadata();
+        let meta = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        write!(
+            writer,
+            "[{}Z {:<5} {}] ",
+            &timestamp[..19],
+            meta.level(),
+            meta.target()
+        )?;
+        ctx.format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Error of [`parse_log4rs_levels`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLog4rsError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ParseLog4rsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseLog4rsError {}
+
+/// Turns the levels of a log4rs YAML config into filter directives for
+/// [`LoggerBuilder::env_filter`](crate::LoggerBuilder::env_filter), e.g.
+/// `warn,app::db=debug`
+///
+/// Only `root.level` and `loggers.<name>.level` are read, appenders and
+/// everything else are ignored. Log4rs names loggers after module paths,
+/// which are the default targets of `tracing` too.
+///
+/// ```
+/// let directives = vinted_logger::compat::parse_log4rs_levels(
+///     "
+/// root:
+///   level: warn
+/// loggers:
+///   app::db:
+///     level: debug
+/// ",
+/// )?;
+/// assert_eq!(directives, "warn,app::db=debug");
+///
+/// vinted_logger::builder("svc-search", vinted_logger::Target::Console)
+///     .env_filter(directives.as_str())
+///     .try_init()?;
+/// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+/// ```
+pub fn parse_log4rs_levels(yaml: &str) -> Result<String, ParseLog4rsError> {
+    enum Section {
+        Root,
+        Loggers,
+        Other,
+    }
+
+    let mut root = None;
+    let mut loggers = Vec::new();
+    let mut section = Section::Other;
+    // Indentation of the keys directly below the current section
+    let mut child_indent = None;
+    let mut logger = None;
+
+    for (index, line) in yaml.lines().enumerate() {
+        let error = |message: String| ParseLog4rsError {
+            line: index + 1,
+            message,
+        };
+
+        let line = match line.find(" #") {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+        // Logger names hold `::`, keys end at `: ` or at the end of the line
+        let (key, value) = match trimmed.find(": ") {
+            Some(colon) => (unquote(&trimmed[..colon]), unquote(&trimmed[colon + 2..])),
+            None => match trimmed.strip_suffix(':') {
+                Some(key) => (unquote(key), ""),
+                None => continue,
+            },
+        };
+
+        let indent = line.len() - line.trim_start().len();
+        if indent == 0 {
+            section = match key {
+                "root" => Section::Root,
+                "loggers" => Section::Loggers,
+                _ => Section::Other,
+            };
+            child_indent = None;
+            logger = None;
+            continue;
+        }
+        let child_indent = *child_indent.get_or_insert(indent);
+
+        match section {
+            Section::Root if indent == child_indent && key == "level" => {
+                root = Some(level(value).map_err(error)?);
+            }
+            Section::Loggers if indent == child_indent => logger = Some(key),
+            Section::Loggers if key == "level" => {
+                if let Some(name) = logger.take() {
+                    loggers.push(format!("{}={}", name, level(value).map_err(error)?));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(root
+        .map(str::to_owned)
+        .into_iter()
+        .chain(loggers)
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .or_else(|| {
+            value
+                .strip_prefix('\'')
+                .and_then(|value| value.strip_suffix('\''))
+        })
+        .unwrap_or(value)
+}
+
+fn level(value: &str) -> Result<&'static str, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "off" => Ok("off"),
+        "error" => Ok("error"),
+        "warn" => Ok("warn"),
+        "info" => Ok("info"),
+        "debug" => Ok("debug"),
+        "trace" => Ok("trace"),
+        _ => Err(format!(
+            "invalid level `{}`, expected `off`, `error`, `warn`, `info`, `debug` or `trace`",
+            value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_log4rs_levels, ConsoleFormat};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use tracing::Level;
+
+    /// Lines `env_logger` 0.9 writes with its default format and no colors,
+    /// the time aside
+    const ENV_LOGGER_SAMPLES: &[&str] = &[
+        "[2021-04-20T12:42:57Z ERROR app::db] connection refused\n",
+        "[2021-04-20T12:42:57Z WARN  app::db] pool exhausted\n",
+        "[2021-04-20T12:42:57Z INFO  app] started\n",
+        "[2021-04-20T12:42:57Z DEBUG app::http] GET /items\n",
+        "[2021-04-20T12:42:57Z TRACE app::http] polled\n",
+    ];
+
+    /// The line with its time replaced by the one of the samples
+    fn without_time(line: &str) -> String {
+        assert_eq!(&line[..1], "[", "{:?}", line);
+        assert_eq!(&line[20..22], "Z ", "{:?}", line);
+        format!("[2021-04-20T12:42:57Z {}", &line[22..])
+    }
+
+    #[test]
+    fn lines_are_laid_out_like_env_logger() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console)
+            .default_level(Level::TRACE)
+            .console_format(ConsoleFormat::EnvLogger)
+            .capture(&capture);
+        with_logger(builder, || {
+            tracing::error!(target: "app::db", "connection refused");
+            tracing::warn!(target: "app::db", "pool exhausted");
+            tracing::info!(target: "app", "started");
+            tracing::debug!(target: "app::http", "GET /items");
+            tracing::trace!(target: "app::http", "polled");
+        });
+
+        let lines: Vec<_> = capture
+            .lines()
+            .iter()
+            .map(|line| without_time(line))
+            .collect();
+        assert_eq!(lines, ENV_LOGGER_SAMPLES);
+    }
+
+    #[test]
+    fn fields_follow_the_message() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console)
+            .console_format(ConsoleFormat::EnvLogger)
+            .capture(&capture);
+        with_logger(
+            builder,
+            || tracing::warn!(target: "app::db", size = 10, "pool exhausted"),
+        );
+
+        assert_eq!(
+            without_time(&capture.stderr()[0]),
+            "[2021-04-20T12:42:57Z WARN  app::db] pool exhausted size=10\n"
+        );
+    }
+
+    #[test]
+    fn levels_of_a_log4rs_config_become_directives() {
+        let yaml = include_str!("../tests/fixtures/log4rs.yaml");
+
+        assert_eq!(
+            parse_log4rs_levels(yaml).unwrap(),
+            "warn,app::backend::db=info,app::requests=debug,hyper::client=error"
+        );
+    }
+
+    #[test]
+    fn invalid_levels_are_rejected_with_their_line() {
+        let error =
+            parse_log4rs_levels("root:\n  level: warn\nloggers:\n  app:\n    level: loud\n")
+                .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "line 5: invalid level `loud`, expected `off`, `error`, `warn`, `info`, `debug` or `trace`"
+        );
+        assert_eq!(parse_log4rs_levels("appenders: {}\n").unwrap(), "");
+    }
+}
@@ -0,0 +1,201 @@
+//! Telemetry about usage of deprecated APIs
+//!
+//! ```
+//! #[track_caller]
+//! fn old_search() {
+//!     vinted_logger::deprecation::report("old_search", Some("search"));
+//! }
+//! ```
+
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    panic::Location,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+/// How often the same callsite may report
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Upper bound of callsites remembered by the rate limiter
+const MAX_CALLSITES: usize = 4096;
+
+/// Reports, at WARN with target `vinted::deprecation`, that `api` was used,
+/// with fields `deprecated_api`, `replacement` (empty if there is none) and
+/// `callsite` (`file:line` of the caller).
+///
+/// Every callsite reports at most once per [`REPORT_INTERVAL`]. Wrappers
+/// annotated with `#[track_caller]` report their own caller's location.
+#[track_caller]
+pub fn report(api: &str, replacement: Option<&str>) {
+    let location = Location::caller();
+
+    if !tracing::enabled!(target: "vinted::deprecation", tracing::Level::WARN)
+        || !should_report(location)
+    {
+        return;
+    }
+
+    let callsite = format!("{}:{}", location.file(), location.line());
+    tracing::warn!(
+        target: "vinted::deprecation",
+        deprecated_api = api,
+        replacement = replacement.unwrap_or_default(),
+        callsite = callsite.as_str(),
+        "deprecated API used"
+    );
+}
+
+fn should_report(location: &'static Location<'static>) -> bool {
+    static REPORTED: OnceLock<Mutex<Reported>> = OnceLock::new();
+
+    should_report_at(
+        &mut REPORTED.get_or_init(Default::default).lock(),
+        location,
+        Instant::now(),
+    )
+}
+
+type Reported = HashMap<&'static Location<'static>, Instant>;
+
+/// Callsites are `Location`s, other keys are only there for tests
+fn should_report_at<K: Hash + Eq>(
+    reported: &mut HashMap<K, Instant>,
+    callsite: K,
+    now: Instant,
+) -> bool {
+    if let Some(at) = reported.get_mut(&callsite) {
+        if now.duration_since(*at) < REPORT_INTERVAL {
+            return false;
+        }
+        *at = now;
+        return true;
+    }
+
+    if reported.len() >= MAX_CALLSITES {
+        reported.retain(|_, at| now.duration_since(*at) < REPORT_INTERVAL);
+        if reported.len() >= MAX_CALLSITES {
+            return false;
+        }
+    }
+    reported.insert(callsite, now);
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{report, should_report_at, Reported, MAX_CALLSITES, REPORT_INTERVAL};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use std::{
+        panic::Location,
+        time::{Duration, Instant},
+    };
+
+    #[track_caller]
+    fn old_search() {
+        report("old_search", Some("search"));
+    }
+
+    #[test]
+    fn reports_have_a_fixed_schema() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        let (line, other_line) = with_logger(builder, || {
+            let line = line!() + 1;
+            old_search();
+            report("old_index", None);
+            (line, line + 1)
+        });
+
+        let records = capture.records();
+        assert_eq!(records.len(), 2, "{:?}", records);
+        let (record, other) = (&records[0], &records[1]);
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["target"], "vinted::deprecation");
+        assert_eq!(record["message"], "deprecated API used");
+        assert_eq!(record["deprecated_api"], "old_search");
+        assert_eq!(record["replacement"], "search");
+        // The caller of the `#[track_caller]` helper, not the helper
+        assert_eq!(record["callsite"], format!("src/deprecation.rs:{}", line));
+        assert_eq!(other["deprecated_api"], "old_index");
+        assert_eq!(other["replacement"], "");
+        assert_eq!(
+            other["callsite"],
+            format!("src/deprecation.rs:{}", other_line)
+        );
+    }
+
+    #[test]
+    fn console_lines_carry_the_fields_unmodified() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console).capture(&capture);
+        let line = with_logger(builder, || {
+            let line = line!() + 1;
+            report("old_search", Some("search"));
+            line
+        });
+
+        // Without the styling, `\u{1b}[3mkey\u{1b}[0m\u{1b}[2m=\u{1b}[0m"value"`
+        let console = capture.lines().concat();
+        let mut plain = String::new();
+        for (i, part) in console.split('\u{1b}').enumerate() {
+            plain.push_str(if i == 0 {
+                part
+            } else {
+                &part[part.find('m').unwrap() + 1..]
+            });
+        }
+        for field in &[
+            "deprecated_api=\"old_search\"".to_owned(),
+            "replacement=\"search\"".to_owned(),
+            format!("callsite=\"src/deprecation.rs:{}\"", line),
+        ] {
+            assert!(plain.contains(field.as_str()), "{:?} in {:?}", field, plain);
+        }
+    }
+
+    #[test]
+    fn callsites_report_once_per_interval() {
+        let mut reported = Reported::default();
+        let (first, second) = (Location::caller(), Location::caller());
+        let start = Instant::now();
+
+        assert!(should_report_at(&mut reported, first, start));
+        assert!(!should_report_at(&mut reported, first, start));
+        assert!(should_report_at(&mut reported, second, start));
+        let almost = start + REPORT_INTERVAL - Duration::from_millis(1);
+        assert!(!should_report_at(&mut reported, first, almost));
+        let after = start + REPORT_INTERVAL;
+        assert!(should_report_at(&mut reported, first, after));
+        // The window starts over from the last report
+        assert!(!should_report_at(
+            &mut reported,
+            first,
+            almost + REPORT_INTERVAL
+        ));
+        assert!(should_report_at(&mut reported, second, after));
+    }
+
+    #[test]
+    fn remembered_callsites_are_bounded() {
+        let mut reported = std::collections::HashMap::new();
+        let start = Instant::now();
+
+        for callsite in 0..MAX_CALLSITES {
+            assert!(should_report_at(&mut reported, callsite, start));
+        }
+        // Unknown callsites are dropped while every remembered one is recent
+        assert!(!should_report_at(&mut reported, MAX_CALLSITES, start));
+        assert_eq!(reported.len(), MAX_CALLSITES);
+        // and make room once they aren't
+        let later = start + REPORT_INTERVAL;
+        assert!(should_report_at(&mut reported, MAX_CALLSITES, later));
+        assert_eq!(reported.len(), 1);
+    }
+}
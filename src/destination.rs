@@ -0,0 +1,366 @@
+use std::{error::Error, fmt, net::SocketAddr, path::PathBuf, str::FromStr};
+
+/// Where network targets send records to
+///
+/// Parsed from `udp://host:port`, `tcp://host:port`, `unix:///path`,
+/// `http://host:port/path` with the `gelf-http` feature,
+/// `https://host:port/path` with the `tls` feature, or a bare
+/// `host:port`, which defaults to UDP. IPv6 hosts must be enclosed in brackets,
+/// e.g. `udp://[::1]:9091`. Hostnames are kept as is, `UdpJson` and
+/// `TcpJson` resolve them once when the logger is installed, `GelfHttp`
+/// again for every new connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LogDestination {
+    /// `host:port` to send UDP datagrams to
+    Udp(String),
+
+    /// `host:port` to connect to over TCP
+    Tcp(String),
+
+    /// Path of a unix domain socket
+    Unix(PathBuf),
+
+    /// `host:port/path` to post GELF messages to, over plain HTTP. The path
+    /// defaults to `/gelf`.
+    #[cfg(feature = "gelf-http")]
+    Http(String),
+
+    /// Same as `Http`, over HTTPS
+    #[cfg(feature = "tls")]
+    Https(String),
+}
+
+impl Default for LogDestination {
+    /// Local fluentd agent, `udp://127.0.0.1:9091`
+    fn default() -> Self {
+        Self::Udp("127.0.0.1:9091".to_owned())
+    }
+}
+
+impl fmt::Display for LogDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Udp(addr) => write!(f, "udp://{}", addr),
+            Self::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+            #[cfg(feature = "gelf-http")]
+            Self::Http(url) => write!(f, "http://{}", url),
+            #[cfg(feature = "tls")]
+            Self::Https(url) => write!(f, "https://{}", url),
+        }
+    }
+}
+
+impl FromStr for LogDestination {
+    type Err = ParseDestinationError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = |reason| ParseDestinationError {
+            input: input.to_owned(),
+            reason,
+        };
+
+        let (scheme, rest) = match input.find("://") {
+            Some(at) => (&input[..at], &input[at + 3..]),
+            None => ("udp", input),
+        };
+
+        match scheme {
+            "udp" => Ok(Self::Udp(parse_host_port(rest).map_err(error)?)),
+            "tcp" => Ok(Self::Tcp(parse_host_port(rest).map_err(error)?)),
+            "unix" if rest.is_empty() => Err(error("socket path is empty")),
+            "unix" => Ok(Self::Unix(PathBuf::from(rest))),
+            #[cfg(feature = "gelf-http")]
+            "http" => Ok(Self::Http(parse_url(rest).map_err(error)?)),
+            #[cfg(not(feature = "gelf-http"))]
+            "http" => Err(error("http destinations need the `gelf-http` feature")),
+            #[cfg(feature = "tls")]
+            "https" => Ok(Self::Https(parse_url(rest).map_err(error)?)),
+            #[cfg(not(feature = "tls"))]
+            "https" => Err(error("https destinations need the `tls` feature")),
+            _ => Err(error("unknown scheme")),
+        }
+    }
+}
+
+/// Validates `host:port/path`, the path defaulting to `/gelf`
+#[cfg(feature = "gelf-http")]
+fn parse_url(input: &str) -> Result<String, &'static str> {
+    let (host_port, path) = match input.find('/') {
+        Some(at) => input.split_at(at),
+        None => (input, "/gelf"),
+    };
+    Ok(format!("{}{}", parse_host_port(host_port)?, path))
+}
+
+/// Validates `host:port`, returning it unchanged.
+fn parse_host_port(input: &str) -> Result<String, &'static str> {
+    let (host, port) = if let Some(rest) = input.strip_prefix('[') {
+        let end = rest.find(']').ok_or("IPv6 address is missing `]`")?;
+        let port = match &rest[end + 1..] {
+            "" => return Err("port is missing"),
+            after => after
+                .strip_prefix(':')
+                .ok_or("expected `:port` after the IPv6 address")?,
+        };
+        (&rest[..end], port)
+    } else {
+        let at = input.rfind(':').ok_or("port is missing")?;
+        if input[..at].contains(':') {
+            return Err("IPv6 addresses must be enclosed in brackets");
+        }
+        (&input[..at], &input[at + 1..])
+    };
+
+    if host.is_empty() {
+        return Err("host is empty");
+    }
+    if port.is_empty() {
+        return Err("port is missing");
+    }
+    port.parse::<u16>()
+        .map_err(|_| "port is not a number between 0 and 65535")?;
+
+    Ok(input.to_owned())
+}
+
+impl From<SocketAddr> for LogDestination {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Udp(addr.to_string())
+    }
+}
+
+impl From<(&str, u16)> for LogDestination {
+    fn from((host, port): (&str, u16)) -> Self {
+        if host.contains(':') && !host.starts_with('[') {
+            Self::Udp(format!("[{}]:{}", host, port))
+        } else {
+            Self::Udp(format!("{}:{}", host, port))
+        }
+    }
+}
+
+/// Error returned when a string is not a valid [`LogDestination`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDestinationError {
+    input: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ParseDestinationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid log destination `{}`: {}; expected {}",
+            self.input, self.reason, EXPECTED
+        )
+    }
+}
+
+impl Error for ParseDestinationError {}
+
+#[cfg(feature = "tls")]
+const EXPECTED: &str = "`udp://host:port`, `tcp://host:port`, `unix:///path`, `http://host:port/path`, `https://host:port/path` or `host:port`";
+#[cfg(all(feature = "gelf-http", not(feature = "tls")))]
+const EXPECTED: &str =
+    "`udp://host:port`, `tcp://host:port`, `unix:///path`, `http://host:port/path` or `host:port`";
+#[cfg(not(feature = "gelf-http"))]
+const EXPECTED: &str = "`udp://host:port`, `tcp://host:port`, `unix:///path` or `host:port`";
+
+#[cfg(test)]
+mod tests {
+    use super::LogDestination::{self, Tcp, Udp, Unix};
+    use std::{
+        net::{Ipv6Addr, SocketAddr},
+        path::PathBuf,
+    };
+
+    fn parse(input: &str) -> LogDestination {
+        input
+            .parse()
+            .unwrap_or_else(|e| panic!("`{}` doesn't parse: {}", input, e))
+    }
+
+    fn reason(input: &str) -> String {
+        let err = input.parse::<LogDestination>().unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.starts_with(&format!("invalid log destination `{}`: ", input)),
+            "{}",
+            message
+        );
+        assert!(message.ends_with(&format!("expected {}", super::EXPECTED)));
+        err.reason.to_owned()
+    }
+
+    #[test]
+    fn schemes_select_the_transport() {
+        assert_eq!(parse("udp://127.0.0.1:9091"), Udp("127.0.0.1:9091".into()));
+        assert_eq!(parse("tcp://fluentd:5170"), Tcp("fluentd:5170".into()));
+        assert_eq!(
+            parse("unix:///run/fluentd.sock"),
+            Unix(PathBuf::from("/run/fluentd.sock"))
+        );
+    }
+
+    #[test]
+    fn bare_host_port_defaults_to_udp() {
+        assert_eq!(
+            parse("fluentd.logging.svc:9091"),
+            Udp("fluentd.logging.svc:9091".into())
+        );
+        assert_eq!(parse("10.0.0.1:0"), Udp("10.0.0.1:0".into()));
+        assert_eq!(parse("localhost:65535"), Udp("localhost:65535".into()));
+    }
+
+    #[test]
+    fn ipv6_literals_need_brackets() {
+        assert_eq!(parse("[::1]:9091"), Udp("[::1]:9091".into()));
+        assert_eq!(
+            parse("tcp://[fe80::1%eth0]:5170"),
+            Tcp("[fe80::1%eth0]:5170".into())
+        );
+        assert_eq!(
+            parse("udp://[2001:db8::8a2e:370:7334]:9091"),
+            Udp("[2001:db8::8a2e:370:7334]:9091".into())
+        );
+
+        assert_eq!(
+            reason("::1:9091"),
+            "IPv6 addresses must be enclosed in brackets"
+        );
+        assert_eq!(
+            reason("udp://2001:db8::1:9091"),
+            "IPv6 addresses must be enclosed in brackets"
+        );
+        assert_eq!(reason("[::1:9091"), "IPv6 address is missing `]`");
+        assert_eq!(
+            reason("[::1]9091"),
+            "expected `:port` after the IPv6 address"
+        );
+        assert_eq!(reason("[]:9091"), "host is empty");
+    }
+
+    #[test]
+    fn missing_and_invalid_ports_are_rejected() {
+        assert_eq!(reason("localhost"), "port is missing");
+        assert_eq!(reason("localhost:"), "port is missing");
+        assert_eq!(reason("[::1]"), "port is missing");
+        assert_eq!(reason("[::1]:"), "port is missing");
+        assert_eq!(reason("udp://"), "port is missing");
+        assert_eq!(reason("tcp://fluentd"), "port is missing");
+        assert_eq!(
+            reason("localhost:65536"),
+            "port is not a number between 0 and 65535"
+        );
+        assert_eq!(
+            reason("localhost:http"),
+            "port is not a number between 0 and 65535"
+        );
+        assert_eq!(
+            reason("localhost:-1"),
+            "port is not a number between 0 and 65535"
+        );
+        assert_eq!(reason(":9091"), "host is empty");
+    }
+
+    #[test]
+    fn unknown_schemes_and_empty_paths_are_rejected() {
+        #[cfg(not(feature = "gelf-http"))]
+        assert_eq!(
+            reason("http://graylog:12201"),
+            "http destinations need the `gelf-http` feature"
+        );
+        #[cfg(not(feature = "tls"))]
+        assert_eq!(
+            reason("https://graylog:12201"),
+            "https destinations need the `tls` feature"
+        );
+        assert_eq!(reason("ftp://fluentd:9091"), "unknown scheme");
+        assert_eq!(reason("UDP://fluentd:9091"), "unknown scheme");
+        assert_eq!(reason("://fluentd:9091"), "unknown scheme");
+        assert_eq!(reason("unix://"), "socket path is empty");
+    }
+
+    #[cfg(feature = "gelf-http")]
+    #[test]
+    fn http_destinations_default_to_the_gelf_path() {
+        use super::LogDestination::Http;
+
+        assert_eq!(
+            parse("http://graylog:12201"),
+            Http("graylog:12201/gelf".into())
+        );
+        assert_eq!(
+            parse("http://[::1]:8080/ingest/gelf"),
+            Http("[::1]:8080/ingest/gelf".into())
+        );
+        assert_eq!(
+            parse("http://graylog:12201/"),
+            Http("graylog:12201/".into())
+        );
+        assert_eq!(reason("http://graylog/gelf"), "port is missing");
+        assert_eq!(reason("http://:12201/gelf"), "host is empty");
+        assert_eq!(
+            parse("http://graylog:12201").to_string(),
+            "http://graylog:12201/gelf"
+        );
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn https_destinations_are_parsed_like_http_ones() {
+        use super::LogDestination::Https;
+
+        assert_eq!(
+            parse("https://graylog:443"),
+            Https("graylog:443/gelf".into())
+        );
+        assert_eq!(reason("https://graylog/gelf"), "port is missing");
+        assert_eq!(
+            parse("https://graylog:443/ingest").to_string(),
+            "https://graylog:443/ingest"
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for input in [
+            "udp://127.0.0.1:9091",
+            "udp://[::1]:9091",
+            "tcp://fluentd:5170",
+            "unix:///run/fluentd.sock",
+        ] {
+            assert_eq!(parse(input).to_string(), input);
+            assert_eq!(parse(&parse(input).to_string()), parse(input));
+        }
+        assert_eq!(
+            LogDestination::default().to_string(),
+            "udp://127.0.0.1:9091"
+        );
+    }
+
+    #[test]
+    fn conversions_produce_udp_destinations() {
+        let v4: SocketAddr = "127.0.0.1:9091".parse().unwrap();
+        assert_eq!(LogDestination::from(v4), Udp("127.0.0.1:9091".into()));
+        let v6 = SocketAddr::from((Ipv6Addr::LOCALHOST, 9091));
+        assert_eq!(LogDestination::from(v6), Udp("[::1]:9091".into()));
+
+        assert_eq!(
+            LogDestination::from(("fluentd", 9091)),
+            Udp("fluentd:9091".into())
+        );
+        assert_eq!(
+            LogDestination::from(("::1", 9091)),
+            Udp("[::1]:9091".into())
+        );
+        assert_eq!(
+            LogDestination::from(("[::1]", 9091)),
+            Udp("[::1]:9091".into())
+        );
+        // Conversions agree with parsing
+        assert_eq!(LogDestination::from(("::1", 9091)), parse("[::1]:9091"));
+    }
+}
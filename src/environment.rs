@@ -0,0 +1,605 @@
+use crate::{
+    builder,
+    vinted_field_types::{FieldType, FieldTypes},
+    InitError, LogDestination, LoggerBuilder, LoggerGuard, Target,
+};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::Value;
+use std::{collections::BTreeMap, env, fmt};
+use tracing_core::Level;
+
+/// Environment variable overriding the target [`init_for_environment`]
+/// picks: `udp_json`, `tcp_json`, `unix_json`, `gelf_http` (with the
+/// `gelf-http` feature), `file_json`, `console_json` or `console`
+pub const TARGET_ENV: &str = "VINTED_LOGGER_TARGET";
+
+/// Environment variable making [`init_for_environment`] echo network
+/// records to stderr, see [`LoggerBuilder::stderr_echo`](crate::LoggerBuilder::stderr_echo):
+/// `warn`, `error` or `off`
+pub const ECHO_ENV: &str = "VINTED_LOGGER_ECHO";
+
+/// Environment variable declaring field types for [`init_for_environment`],
+/// see [`LoggerBuilder::field_type`](crate::LoggerBuilder::field_type):
+/// `name=type` pairs separated by commas, e.g. `user_id=number,order_id=string`
+pub const FIELD_TYPES_ENV: &str = "VINTED_LOGGER_FIELD_TYPES";
+
+/// Installs the logger with the target standard for the environment
+///
+/// - `production` and `staging` - [`Target::UdpJson`] to the local fluentd agent
+/// - `development` - [`Target::ConsoleJson`]
+/// - `test` - [`Target::Console`] at WARN
+///
+/// The target can be overridden with [`TARGET_ENV`], e.g.
+/// `VINTED_LOGGER_TARGET=console` to read production logs locally. `RUST_LOG`
+/// still takes precedence over the environment's default level.
+/// [`ECHO_ENV`] echoes WARN or ERROR records to stderr too and
+/// [`FIELD_TYPES_ENV`] declares field types.
+///
+/// Same as [`init_from_config`] with no other settings, failing with
+/// [`InitError::AlreadyInitialized`] when a global subscriber is installed
+/// already. The returned guard flushes the logger when dropped, see
+/// [`LoggerBuilder::try_init_with_guard`].
+///
+/// ```
+/// let _guard = vinted_logger::init_for_environment("test", "svc-search")?;
+/// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+/// ```
+#[track_caller]
+pub fn init_for_environment(
+    environment: &str,
+    facility: &'static str,
+) -> Result<LoggerGuard, InitError> {
+    let config = LoggerConfig {
+        environment: environment.to_owned(),
+        ..LoggerConfig::default()
+    };
+    environment_builder(&config, facility)?.try_init_with_guard()
+}
+
+/// Installs the logger for the environment of `config`, see
+/// [`LoggerConfig`]
+///
+/// Fails with [`InitError::AlreadyInitialized`] when a global subscriber is
+/// installed already, e.g. by a test harness, like [`init_for_environment`].
+/// [`LoggerConfig::builder`] with
+/// [`InitBehavior::NoopIfSet`](crate::InitBehavior::NoopIfSet) leaves that
+/// one in place instead.
+///
+/// ```
+/// let config: vinted_logger::LoggerConfig = serde_json::from_str(
+///     r#"{ "environment": "test", "facility": "svc-search", "target": "console" }"#,
+/// )?;
+/// let _guard = vinted_logger::init_from_config(&config)?;
+/// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+/// ```
+#[track_caller]
+pub fn init_from_config(config: &LoggerConfig) -> Result<LoggerGuard, InitError> {
+    config.builder()?.try_init_with_guard()
+}
+
+/// Settings of [`init_from_config`], e.g. a section of the service's YAML or
+/// TOML config
+///
+/// ```toml
+/// [logger]
+/// environment = "production"
+/// facility = "svc-search"
+/// target = "tcp_json"
+/// address = "tcp://fluentd.logging.svc:5170"
+/// level = "debug"
+/// additional_fields = { datacenter = "eu-1" }
+/// field_types = { user_id = "number", order_id = "string" }
+/// facilities = { payments_plugin = "svc-payments" }
+/// ```
+///
+/// Only `environment` and `facility` are required, the other settings
+/// default to those of the environment, see [`init_for_environment`].
+/// Unknown keys and invalid values fail deserialization, naming the valid
+/// ones.
+///
+/// More settings may be added, build it with [`LoggerConfig::new`] or by
+/// deserializing it.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct LoggerConfig {
+    /// `production`, `staging`, `development` or `test`
+    pub environment: String,
+    /// Facility name, see [`builder`](crate::builder)
+    pub facility: String,
+    /// `udp_json`, `tcp_json`, `unix_json`, `gelf_http`, `file_json`,
+    /// `console_json` or `console`.
+    /// [`TARGET_ENV`] still takes precedence.
+    pub target: Option<Target>,
+    /// Where network targets send records to, see
+    /// [`LoggerBuilder::destination`]
+    pub address: Option<LogDestination>,
+    /// Default level, `RUST_LOG` still takes precedence
+    pub level: Option<Level>,
+    /// Static fields of every JSON record, see
+    /// [`LoggerBuilder::additional_field`]
+    pub additional_fields: BTreeMap<String, Value>,
+    /// Declared types of fields, `number`, `string` or `bool`, see
+    /// [`LoggerBuilder::field_type`]. [`FIELD_TYPES_ENV`] adds to them.
+    pub field_types: BTreeMap<String, FieldType>,
+    /// Facilities of records by target prefix, see
+    /// [`LoggerBuilder::facility_for`]
+    pub facilities: BTreeMap<String, String>,
+}
+
+impl LoggerConfig {
+    /// Settings of `facility` in `environment`, the others left to the
+    /// environment's defaults
+    ///
+    /// ```
+    /// let mut config = vinted_logger::LoggerConfig::new("test", "svc-search");
+    /// config.level = Some(tracing::Level::DEBUG);
+    /// ```
+    pub fn new(environment: impl Into<String>, facility: impl Into<String>) -> Self {
+        Self {
+            environment: environment.into(),
+            facility: facility.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Builder for these settings, to customize the logger further before
+    /// installing it
+    ///
+    /// The facilities are leaked, as loggers keep a `&'static str`; build
+    /// loggers from a config once per process.
+    pub fn builder(&self) -> Result<LoggerBuilder, InitError> {
+        let facility: &'static str = Box::leak(self.facility.clone().into_boxed_str());
+        environment_builder(self, facility)
+    }
+}
+
+const CONFIG_KEYS: &[&str] = &[
+    "environment",
+    "facility",
+    "target",
+    "address",
+    "level",
+    "additional_fields",
+    "field_types",
+    "facilities",
+];
+
+impl<'de> Deserialize<'de> for LoggerConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ConfigVisitor;
+
+        impl<'de> Visitor<'de> for ConfigVisitor {
+            type Value = LoggerConfig;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("logger settings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<LoggerConfig, A::Error> {
+                let mut environment = None;
+                let mut facility = None;
+                let mut config = LoggerConfig::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "environment" => environment = Some(map.next_value()?),
+                        "facility" => facility = Some(map.next_value()?),
+                        "target" => {
+                            config.target = match map.next_value::<Option<String>>()? {
+                                Some(name) => Some(parse_target(&name).ok_or_else(|| {
+                                    de::Error::custom(format_args!(
+                                        "invalid target `{}`, expected {}",
+                                        name,
+                                        target_names()
+                                    ))
+                                })?),
+                                None => None,
+                            }
+                        }
+                        "address" => {
+                            config.address = match map.next_value::<Option<String>>()? {
+                                Some(address) => {
+                                    Some(address.parse().map_err(de::Error::custom)?)
+                                }
+                                None => None,
+                            }
+                        }
+                        "level" => {
+                            config.level = match map.next_value::<Option<String>>()? {
+                                Some(level) => Some(level.parse().map_err(|_| {
+                                    de::Error::custom(format_args!(
+                                        "invalid level `{}`, expected `error`, `warn`, `info`, `debug` or `trace`",
+                                        level
+                                    ))
+                                })?),
+                                None => None,
+                            }
+                        }
+                        "additional_fields" => {
+                            config.additional_fields = map
+                                .next_value::<Option<BTreeMap<String, Value>>>()?
+                                .unwrap_or_default()
+                        }
+                        "field_types" => {
+                            let names = map
+                                .next_value::<Option<BTreeMap<String, String>>>()?
+                                .unwrap_or_default();
+                            for (field, name) in names {
+                                let field_type = name.parse().map_err(de::Error::custom)?;
+                                config.field_types.insert(field, field_type);
+                            }
+                        }
+                        "facilities" => {
+                            config.facilities = map
+                                .next_value::<Option<BTreeMap<String, String>>>()?
+                                .unwrap_or_default()
+                        }
+                        _ => return Err(de::Error::unknown_field(&key, CONFIG_KEYS)),
+                    }
+                }
+                config.environment =
+                    environment.ok_or_else(|| de::Error::missing_field("environment"))?;
+                config.facility = facility.ok_or_else(|| de::Error::missing_field("facility"))?;
+                Ok(config)
+            }
+        }
+
+        deserializer.deserialize_map(ConfigVisitor)
+    }
+}
+
+/// Targets by their name in configs and [`TARGET_ENV`]
+const TARGETS: &[(&str, Target)] = &[
+    ("udp_json", Target::UdpJson),
+    ("tcp_json", Target::TcpJson),
+    #[cfg(unix)]
+    ("unix_json", Target::UnixJson),
+    #[cfg(feature = "gelf-http")]
+    ("gelf_http", Target::GelfHttp),
+    ("file_json", Target::FileJson),
+    ("console_json", Target::ConsoleJson),
+    ("console", Target::Console),
+];
+
+fn parse_target(name: &str) -> Option<Target> {
+    TARGETS
+        .iter()
+        .find(|&&(target_name, _)| target_name == name)
+        .map(|&(_, target)| target)
+}
+
+/// Names of [`TARGETS`], e.g. `` `udp_json`, `file_json` or `console` ``
+fn target_names() -> String {
+    let names: Vec<_> = TARGETS
+        .iter()
+        .map(|(name, _)| format!("`{}`", name))
+        .collect();
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+fn environment_builder(
+    config: &LoggerConfig,
+    facility: &'static str,
+) -> Result<LoggerBuilder, InitError> {
+    let environment = config.environment.as_str();
+    let (target, default_level) = match environment {
+        "production" | "staging" => (Target::UdpJson, Level::INFO),
+        "development" => (Target::ConsoleJson, Level::INFO),
+        "test" => (Target::Console, Level::WARN),
+        _ => {
+            return Err(InitError::InvalidConfig(format!(
+            "unknown environment `{}`, expected `production`, `staging`, `development` or `test`",
+            environment
+        )))
+        }
+    };
+
+    let target = match env::var(TARGET_ENV) {
+        Ok(name) => parse_target(&name).ok_or_else(|| {
+            InitError::InvalidConfig(format!(
+                "invalid {} `{}`, expected {}",
+                TARGET_ENV,
+                name,
+                target_names()
+            ))
+        })?,
+        Err(_) => config.target.unwrap_or(target),
+    };
+
+    let echo = match env::var(ECHO_ENV) {
+        Ok(name) => match name.as_str() {
+            "warn" => Some(Level::WARN),
+            "error" => Some(Level::ERROR),
+            "off" => None,
+            _ => {
+                return Err(InitError::InvalidConfig(format!(
+                    "invalid {} `{}`, expected `warn`, `error` or `off`",
+                    ECHO_ENV, name
+                )))
+            }
+        },
+        Err(_) => None,
+    };
+
+    let mut field_types = FieldTypes::default();
+    for (name, &field_type) in &config.field_types {
+        field_types.insert(name.clone(), field_type);
+    }
+    if let Ok(pairs) = env::var(FIELD_TYPES_ENV) {
+        let declared = FieldTypes::parse(&pairs).map_err(|e| {
+            InitError::InvalidConfig(format!("invalid {} `{}`: {}", FIELD_TYPES_ENV, pairs, e))
+        })?;
+        field_types.extend(declared);
+    }
+
+    let mut builder = builder(facility, target)
+        .default_level(config.level.unwrap_or(default_level))
+        .field_types(field_types);
+    if let Some(ref address) = config.address {
+        builder = builder.destination(address.clone());
+    }
+    for (key, value) in &config.additional_fields {
+        builder = builder.additional_field(key, value.clone());
+    }
+    for (prefix, facility) in &config.facilities {
+        let facility: &'static str = Box::leak(facility.clone().into_boxed_str());
+        builder = builder.facility_for(prefix.clone(), facility);
+    }
+    if let Some(level) = echo {
+        builder = builder.stderr_echo(level);
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{environment_builder, LoggerConfig, TARGET_ENV};
+    use crate::{
+        test_support::{with_logger, Capture},
+        FieldType, InitError, LogDestination, Target,
+    };
+    use std::env;
+    use tracing::Level;
+
+    /// Target and destination the logger of `environment` is built with
+    fn resolved(environment: &str) -> Result<(Target, Option<LogDestination>), InitError> {
+        resolved_config(&LoggerConfig {
+            environment: environment.to_owned(),
+            ..LoggerConfig::default()
+        })
+    }
+
+    /// Target and destination the logger of `config` is built with
+    fn resolved_config(
+        config: &LoggerConfig,
+    ) -> Result<(Target, Option<LogDestination>), InitError> {
+        let builder = environment_builder(config, "svc-test")?;
+        Ok(with_logger(builder, || {
+            tracing::dispatcher::get_default(|dispatch| {
+                let report = crate::introspect(dispatch).expect("logger is installed");
+                (report.target, report.destination)
+            })
+        }))
+    }
+
+    // A single test, the override is an environment variable of the process
+    #[test]
+    fn environments_pick_their_target_unless_overridden() {
+        env::remove_var(TARGET_ENV);
+        let fluentd = Some(LogDestination::Udp("127.0.0.1:9091".to_owned()));
+        assert_eq!(
+            resolved("production").unwrap(),
+            (Target::UdpJson, fluentd.clone())
+        );
+        assert_eq!(
+            resolved("staging").unwrap(),
+            (Target::UdpJson, fluentd.clone())
+        );
+        assert_eq!(
+            resolved("development").unwrap(),
+            (Target::ConsoleJson, None)
+        );
+        assert_eq!(resolved("test").unwrap(), (Target::Console, None));
+        assert!(matches!(
+            resolved("prod"),
+            Err(InitError::InvalidConfig(ref message)) if message.contains("unknown environment `prod`")
+        ));
+
+        // `test` is at WARN unless `RUST_LOG` says otherwise
+        if env::var("RUST_LOG").is_err() {
+            let config = LoggerConfig {
+                environment: "test".to_owned(),
+                ..LoggerConfig::default()
+            };
+            let builder = environment_builder(&config, "svc-test").unwrap();
+            with_logger(builder, || {
+                assert!(tracing::enabled!(Level::WARN));
+                assert!(!tracing::enabled!(Level::INFO));
+            });
+        }
+
+        // Settings of the config replace those of the environment
+        let config: LoggerConfig = serde_json::from_str(
+            r#"{
+                "environment": "test",
+                "facility": "svc-test",
+                "target": "udp_json",
+                "address": "udp://127.0.0.1:5005"
+            }"#,
+        )
+        .unwrap();
+        let collector = Some(LogDestination::Udp("127.0.0.1:5005".to_owned()));
+        assert_eq!(
+            resolved_config(&config).unwrap(),
+            (Target::UdpJson, collector)
+        );
+        let config: LoggerConfig = serde_json::from_str(
+            r#"{ "environment": "production", "facility": "svc-test", "target": "console" }"#,
+        )
+        .unwrap();
+        assert_eq!(resolved_config(&config).unwrap(), (Target::Console, None));
+
+        env::set_var(TARGET_ENV, "console_json");
+        let overridden = resolved("production");
+        let overridden_config = resolved_config(&config);
+        env::set_var(TARGET_ENV, "syslog");
+        let invalid = resolved("production");
+        env::remove_var(TARGET_ENV);
+        assert_eq!(overridden.unwrap(), (Target::ConsoleJson, None));
+        assert_eq!(overridden_config.unwrap(), (Target::ConsoleJson, None));
+        assert!(matches!(
+            invalid,
+            Err(InitError::InvalidConfig(ref message)) if message.starts_with("invalid VINTED_LOGGER_TARGET `syslog`")
+        ));
+    }
+
+    #[test]
+    fn configs_declare_field_types() {
+        let config: LoggerConfig = serde_json::from_str(
+            r#"{
+                "environment": "development",
+                "facility": "svc-test",
+                "field_types": { "user_id": "number" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.field_types["user_id"], FieldType::Number);
+
+        let capture = Capture::default();
+        let builder = environment_builder(&config, "svc-test").unwrap();
+        with_logger(builder.capture(&capture), || {
+            tracing::warn!(user_id = "42", "typed")
+        });
+        assert_eq!(capture.records()[0]["user_id"], 42);
+
+        let invalid = serde_json::from_str::<LoggerConfig>(
+            r#"{ "environment": "test", "facility": "svc-test", "field_types": { "user_id": "int" } }"#,
+        )
+        .unwrap_err();
+        assert!(
+            invalid.to_string().contains("unknown field type `int`"),
+            "{}",
+            invalid
+        );
+    }
+
+    #[test]
+    fn configs_set_the_level_and_fields_of_records() {
+        let config: LoggerConfig = serde_json::from_str(
+            r#"{
+                "environment": "development",
+                "facility": "svc-search",
+                "target": "console_json",
+                "level": "debug",
+                "additional_fields": { "datacenter": "eu-1", "shard": 3 }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.facility, "svc-search");
+        assert_eq!(config.target, Some(Target::ConsoleJson));
+        assert_eq!(config.level, Some(Level::DEBUG));
+        assert_eq!(config.address, None);
+
+        let capture = Capture::default();
+        let builder = config.builder().unwrap();
+        with_logger(builder.capture(&capture), || {
+            tracing::debug!("searched");
+            tracing::trace!("skipped");
+        });
+        let records = capture.records();
+        assert_eq!(records.len(), 1, "{:?}", records);
+        assert_eq!(records[0]["facility"], "svc-search");
+        assert_eq!(records[0]["datacenter"], "eu-1");
+        assert_eq!(records[0]["shard"], 3);
+    }
+
+    #[test]
+    fn configs_map_target_prefixes_to_facilities() {
+        let config: LoggerConfig = serde_json::from_str(
+            r#"{
+                "environment": "development",
+                "facility": "svc-host",
+                "facilities": { "payments": "svc-payments", "payments::fraud": "svc-fraud" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.facilities["payments"], "svc-payments");
+
+        let capture = Capture::default();
+        let builder = config.builder().unwrap();
+        with_logger(builder.capture(&capture), || {
+            tracing::info!(target: "payments::refunds", "refunded");
+            tracing::info!(target: "payments::fraud", "flagged");
+            tracing::info!(target: "host", "served");
+        });
+        let facilities: Vec<_> = capture
+            .records()
+            .iter()
+            .map(|record| record["facility"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(facilities, ["svc-payments", "svc-fraud", "svc-host"]);
+    }
+
+    #[cfg(feature = "gelf-http")]
+    #[test]
+    fn configs_name_the_gelf_http_target() {
+        let config: LoggerConfig = serde_json::from_str(
+            r#"{ "environment": "production", "facility": "svc-test", "target": "gelf_http" }"#,
+        )
+        .unwrap();
+        assert_eq!(config.target, Some(Target::GelfHttp));
+
+        let invalid = serde_json::from_str::<LoggerConfig>(
+            r#"{ "environment": "test", "facility": "svc", "target": "gelf" }"#,
+        )
+        .unwrap_err();
+        assert!(invalid.to_string().contains("`gelf_http`"), "{}", invalid);
+    }
+
+    #[test]
+    fn invalid_configs_name_the_valid_values() {
+        let error = |config: &str| serde_json::from_str::<LoggerConfig>(config).unwrap_err();
+
+        let invalid = error(r#"{ "environment": "test", "facility": "svc", "target": "syslog" }"#);
+        assert!(
+            invalid
+                .to_string()
+                .starts_with("invalid target `syslog`, expected `udp_json`, `tcp_json`"),
+            "{}",
+            invalid
+        );
+        assert!(invalid.to_string().contains("or `console`"), "{}", invalid);
+
+        let invalid = error(r#"{ "environment": "test", "facility": "svc", "level": "loud" }"#);
+        assert!(
+            invalid.to_string().starts_with(
+                "invalid level `loud`, expected `error`, `warn`, `info`, `debug` or `trace`"
+            ),
+            "{}",
+            invalid
+        );
+
+        let invalid =
+            error(r#"{ "environment": "test", "facility": "svc", "address": "ftp://x:1" }"#);
+        assert!(invalid.to_string().contains("ftp://x:1"), "{}", invalid);
+
+        let invalid = error(r#"{ "environment": "test", "facility": "svc", "host": "x" }"#);
+        assert!(
+            invalid
+                .to_string()
+                .starts_with("unknown field `host`, expected one of"),
+            "{}",
+            invalid
+        );
+
+        let invalid = error(r#"{ "environment": "test" }"#);
+        assert!(
+            invalid.to_string().starts_with("missing field `facility`"),
+            "{}",
+            invalid
+        );
+    }
+}
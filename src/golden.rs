@@ -0,0 +1,261 @@
+//! Golden records of the canonical events in `tests/golden/`, one file per
+//! target and profile, so that every change of the output collectors parse
+//! shows up in review.
+//!
+//! `cargo xtask regen-golden` rewrites the files after an intended change.
+//! The clock, the host and the thread are replaced by placeholders, the
+//! rest of every record is compared byte for byte.
+
+use crate::{
+    test_support::{with_logger, Capture},
+    EscapingProfile, LoggerBuilder, PayloadTier, Target, TimestampFormat, WireFormat,
+};
+use std::{env, fmt::Write, fs, net::UdpSocket, path::PathBuf, time::Duration};
+use tracing::Level;
+
+/// Set by `cargo xtask regen-golden`, the test then writes the files
+const REGEN_ENV: &str = "VINTED_REGEN_GOLDEN";
+
+/// Time of the events overriding theirs with `event_time`, kept as is
+const EVENT_TIME: &str = "2021-04-20T12:42:57.353Z";
+
+/// Events every profile renders, in this order
+fn log_canonical_events() {
+    tracing::trace!(attempt = 1, "trace");
+    tracing::debug!(attempt = 2, "debug");
+    tracing::info!(attempt = 3, "info");
+    tracing::warn!(attempt = 4, "warn");
+    tracing::error!(attempt = 5, "error");
+
+    {
+        let _outer = tracing::info_span!("outer", user_id = 7).entered();
+        let _request = tracing::info_span!("request", path = "/items").entered();
+        tracing::info!(items = 3, ok = true, ratio = 0.5, "listed");
+    }
+
+    tracing::info!(
+        greeting = "Labas, pasauli! Привет 🌍",
+        control = "tab\tcr\rnul\0quote\"backslash\\",
+        "unicode ąčęėįšųūž ✓"
+    );
+
+    let big = "0123456789".repeat(300);
+    tracing::info!(
+        big = big.as_str(),
+        wide = u128::MAX,
+        list = ?[1, 2, 3],
+        "big fields"
+    );
+
+    tracing::warn!(
+        target: "svc_test::overridden",
+        event_time = EVENT_TIME,
+        level = "custom",
+        message_class = "client",
+        "overrides"
+    );
+}
+
+/// Targets and profiles, with the name of their file
+fn profiles() -> Vec<(&'static str, Target, LoggerBuilder)> {
+    let json = || crate::builder("svc-test", Target::ConsoleJson);
+    let udp = || crate::builder("svc-test", Target::UdpJson);
+    vec![
+        ("console_json.jsonl", Target::ConsoleJson, json()),
+        (
+            "console_json_standard.jsonl",
+            Target::ConsoleJson,
+            json().payload_tier(PayloadTier::Standard),
+        ),
+        (
+            "console_json_minimal.jsonl",
+            Target::ConsoleJson,
+            json().payload_tier(PayloadTier::Minimal),
+        ),
+        (
+            "console_json_clickhouse.jsonl",
+            Target::ConsoleJson,
+            json().escaping_profile(EscapingProfile::ClickHouse),
+        ),
+        (
+            "console_json_epoch_millis.jsonl",
+            Target::ConsoleJson,
+            json().timestamp_format(TimestampFormat::EpochMillis),
+        ),
+        (
+            "console.txt",
+            Target::Console,
+            crate::builder("svc-test", Target::Console),
+        ),
+        ("udp_json.jsonl", Target::UdpJson, udp()),
+        (
+            "udp_gelf.jsonl",
+            Target::UdpJson,
+            udp().wire_format(WireFormat::Gelf),
+        ),
+    ]
+}
+
+/// Lines of the canonical events as logged by `builder`
+fn render(target: Target, builder: LoggerBuilder) -> Vec<String> {
+    let builder = builder.default_level(Level::TRACE);
+    if target != Target::UdpJson {
+        let capture = Capture::default();
+        with_logger(builder.capture(&capture), log_canonical_events);
+        return capture.lines();
+    }
+
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    with_logger(builder.destination(collector.local_addr().unwrap()), || {
+        log_canonical_events();
+        crate::flush(Duration::from_secs(5));
+    });
+    collector
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    let mut datagram = vec![0; 65_536];
+    std::iter::from_fn(|| {
+        let len = collector.recv(&mut datagram).ok()?;
+        let mut line = String::from_utf8(datagram[..len].to_vec()).unwrap();
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        Some(line)
+    })
+    .collect()
+}
+
+/// Records the logger adds about the events, e.g. the notice of untyped
+/// fields, which only the first logger of the process gets
+fn is_own_record(line: &str) -> bool {
+    line.contains("\"target\":\"vinted_logger\",")
+        || line.contains("\"_target\":\"vinted_logger\",")
+        || line.contains("\u{1b}[2mvinted_logger\u{1b}[0m")
+}
+
+/// Replaces the value of the first `"key":`, up to the next `,` or `}`,
+/// unless it is the overridden [`EVENT_TIME`]
+fn normalize(line: &str, key: &str, with: &str) -> String {
+    let start = match line.find(&format!("\"{}\":", key)) {
+        Some(start) => start + key.len() + 3,
+        None => return line.to_owned(),
+    };
+    let end = start + line[start..].find([',', '}']).unwrap();
+    if is_event_time(line[start..end].trim_start_matches('"')) {
+        return line.to_owned();
+    }
+    format!("{}{}{}", &line[..start], with, &line[end..])
+}
+
+/// `EVENT_TIME` in any of the timestamp formats
+fn is_event_time(value: &str) -> bool {
+    ["2021-04-20T12:42:57.353", "1618922577353", "1618922577.353"]
+        .iter()
+        .any(|time| value.starts_with(time))
+}
+
+fn normalize_json(line: &str) -> String {
+    let mut line = line.to_owned();
+    for (key, with) in &[
+        ("@timestamp", "\"TIMESTAMP\""),
+        ("timestamp", "\"TIMESTAMP\""),
+        ("event_seq", "0"),
+        ("_event_seq", "0"),
+        ("thread_id", "\"THREAD\""),
+        ("_thread_id", "\"THREAD\""),
+        ("thread_name", "\"THREAD\""),
+        ("_thread_name", "\"THREAD\""),
+        ("line", "0"),
+        ("_line", "0"),
+        ("host", "\"HOST\""),
+    ] {
+        line = normalize(&line, key, with);
+    }
+    line
+}
+
+/// Console lines start with the time and event_seq, e.g.
+/// `\u{1b}[2m2021-04-20T12:42:57.353+00:00 #0\u{1b}[0m `. Escapes are
+/// written as `\e` to keep the file readable.
+fn normalize_console(line: &str) -> String {
+    let line = match (line.strip_prefix("\u{1b}[2m"), line.find("\u{1b}[0m ")) {
+        (Some(rest), Some(end)) => {
+            let time = rest.split(' ').next().unwrap_or_default();
+            let time = if is_event_time(time) {
+                time
+            } else {
+                "TIMESTAMP"
+            };
+            format!("\u{1b}[2m{} #0{}", time, &line[end..])
+        }
+        _ => line.to_owned(),
+    };
+    line.replace('\u{1b}', "\\e")
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// The lines which differ, prefixed with `-` for expected and `+` for actual
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<_>, Vec<_>) =
+        (expected.lines().collect(), actual.lines().collect());
+    let mut diff = String::new();
+    for n in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(n), actual.get(n));
+        if old != new {
+            writeln!(diff, "line {}:", n + 1).unwrap();
+            if let Some(old) = old {
+                writeln!(diff, "- {}", old).unwrap();
+            }
+            if let Some(new) = new {
+                writeln!(diff, "+ {}", new).unwrap();
+            }
+        }
+    }
+    diff
+}
+
+#[test]
+fn records_match_the_golden_files() {
+    let regen = env::var_os(REGEN_ENV).is_some();
+    let mut failures = String::new();
+    for (file, target, builder) in profiles() {
+        let lines = render(target, builder);
+        let actual: String = lines
+            .iter()
+            .filter(|line| !is_own_record(line))
+            .map(|line| match target {
+                Target::Console => normalize_console(line),
+                _ => normalize_json(line),
+            })
+            .collect();
+
+        let path = golden_dir().join(file);
+        if regen {
+            fs::create_dir_all(golden_dir()).unwrap();
+            fs::write(&path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&path).unwrap_or_default();
+        if expected != actual {
+            writeln!(
+                failures,
+                "{}:\n{}",
+                path.display(),
+                diff(&expected, &actual)
+            )
+            .unwrap();
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "records differ from the golden files, run `cargo xtask regen-golden` if \
+         the change is intended\n\n{}",
+        failures
+    );
+}
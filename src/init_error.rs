@@ -0,0 +1,163 @@
+use crate::LogDestination;
+use std::{error::Error, fmt, io, panic::Location, path::PathBuf};
+use tracing_subscriber::util::TryInitError;
+
+/// Error returned when the logger can't be installed, see
+/// [`LoggerBuilder::try_init`](crate::LoggerBuilder::try_init)
+///
+/// Converts into `Box<dyn Error + Send + Sync>` like any other error, so `?`
+/// keeps working in functions returning one.
+///
+/// ```
+/// use vinted_logger::{InitError, Target};
+///
+/// match vinted_logger::builder("svc-search", Target::Console).try_init() {
+///     Ok(()) | Err(InitError::AlreadyInitialized(_)) => {}
+///     Err(e) => panic!("logger can't be installed: {}", e),
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum InitError {
+    /// A global subscriber is already set, e.g. by another test, see
+    /// [`InitBehavior::NoopIfSet`](crate::InitBehavior::NoopIfSet)
+    AlreadyInitialized(TryInitError),
+
+    /// A logger of another facility was installed already, e.g. by a
+    /// library, and records can't carry both
+    ///
+    /// Loggers of the same facility are attached next to the installed one
+    /// instead.
+    ConflictingInit {
+        /// Facility of the installed logger
+        installed_facility: &'static str,
+        /// Where the installed logger was initialized
+        installed_at: &'static Location<'static>,
+        /// Facility of the logger which wasn't installed
+        facility: &'static str,
+        /// Where it was initialized
+        location: &'static Location<'static>,
+    },
+
+    /// Settings of the builder, or of the environment, are invalid or don't
+    /// go together
+    InvalidConfig(String),
+
+    /// The host of a destination can't be resolved
+    InvalidAddress {
+        /// Destination which can't be resolved
+        destination: LogDestination,
+        /// Why it can't be resolved
+        source: io::Error,
+    },
+
+    /// The `FileJson` file can't be opened
+    Io {
+        /// Path of the file
+        path: PathBuf,
+        /// Why it can't be opened
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyInitialized(e) => e.fmt(f),
+            Self::ConflictingInit {
+                installed_facility,
+                installed_at,
+                facility,
+                location,
+            } => write!(
+                f,
+                "logger of facility `{}` initialized at {} can't be installed, the logger of facility `{}` was installed at {}",
+                facility, location, installed_facility, installed_at
+            ),
+            Self::InvalidConfig(message) => f.write_str(message),
+            Self::InvalidAddress {
+                destination,
+                source,
+            } => write!(f, "can't resolve destination `{}`: {}", destination, source),
+            Self::Io { path, source } => {
+                write!(f, "can't open log file `{}`: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::AlreadyInitialized(e) => Some(e),
+            Self::ConflictingInit { .. } | Self::InvalidConfig(_) => None,
+            Self::InvalidAddress { source, .. } | Self::Io { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<TryInitError> for InitError {
+    fn from(e: TryInitError) -> Self {
+        Self::AlreadyInitialized(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InitError;
+    use crate::Target;
+    use std::{error::Error, io, path::Path};
+
+    #[test]
+    fn files_which_cant_be_opened_fail_with_io() {
+        // Its directory is a file
+        let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml/current.json");
+        let result = crate::builder("svc-test", Target::FileJson)
+            .file_path(&file)
+            .build();
+
+        match result {
+            Err(
+                ref e @ InitError::Io {
+                    ref path,
+                    ref source,
+                },
+            ) => {
+                assert_eq!(path, &file);
+                assert_ne!(source.kind(), io::ErrorKind::NotFound);
+                assert!(e.to_string().contains("Cargo.toml/current.json"), "{}", e);
+                assert!(e.source().is_some());
+            }
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("init succeeded"),
+        }
+    }
+
+    #[test]
+    fn invalid_settings_fail_with_invalid_config() {
+        let result = crate::builder("", Target::ConsoleJson).try_init();
+
+        match result {
+            Err(ref e @ InitError::InvalidConfig(ref message)) => {
+                assert_eq!(e.to_string(), *message);
+                assert!(e.source().is_none());
+            }
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(()) => panic!("init succeeded"),
+        }
+    }
+
+    #[test]
+    fn init_errors_are_boxed_by_the_question_mark() {
+        fn init() -> Result<(), Box<dyn Error + Send + Sync>> {
+            crate::builder("", Target::ConsoleJson).try_init()?;
+            Ok(())
+        }
+
+        let e = init().expect_err("facility is empty");
+        assert!(matches!(
+            e.downcast_ref::<InitError>(),
+            Some(InitError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+#[allow(deprecated)]
+use crate::{internal_diagnostics, InternalDiagnostics};
+use crate::{vinted_span_tracker::live_spans, LogDestination, Target};
+use std::time::Duration;
+use tracing::Dispatch;
+use tracing_core::Subscriber;
+use tracing_subscriber::Layer;
+
+/// Configuration and state of a logger installed by
+/// [`LoggerBuilder::try_init`](crate::LoggerBuilder::try_init)
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct IntrospectionReport {
+    /// Facility of every record
+    pub facility: &'static str,
+    /// Target records are written to
+    pub target: Target,
+    /// Where `UdpJson` and `TcpJson` records are sent to, `None` for console
+    /// targets
+    pub destination: Option<LogDestination>,
+    /// Where ERROR `UdpJson` records are sent to as well
+    pub error_destination: Option<LogDestination>,
+    /// Where `UdpJson` metric records are sent to instead
+    pub metric_destination: Option<LogDestination>,
+    /// Window of repeated record coalescing, `None` if disabled
+    pub coalesce_window: Option<Duration>,
+    /// Spans created but not closed yet, see
+    /// [`LoggerStats::live_spans`](crate::LoggerStats::live_spans)
+    pub live_spans: u64,
+    /// The logger's own problems, see [`internal_diagnostics`]
+    #[deprecated(note = "use `logger_stats`, which has the same counters")]
+    #[allow(deprecated)]
+    pub internal_diagnostics: InternalDiagnostics,
+}
+
+/// Returns the report of the logger installed in `dispatch`, or `None` if it
+/// isn't a Vinted logger
+///
+/// ```
+/// tracing::dispatcher::get_default(|dispatch| {
+///     if let Some(report) = vinted_logger::introspect(dispatch) {
+///         println!("logging to {:?}", report.target);
+///     }
+/// });
+/// ```
+#[allow(deprecated)]
+pub fn introspect(dispatch: &Dispatch) -> Option<IntrospectionReport> {
+    let config = &dispatch.downcast_ref::<VintedIntrospection>()?.0;
+
+    Some(IntrospectionReport {
+        facility: config.facility,
+        target: config.target,
+        destination: config.destination.clone(),
+        error_destination: config.error_destination.clone(),
+        metric_destination: config.metric_destination.clone(),
+        coalesce_window: config.coalesce_window,
+        live_spans: live_spans(),
+        internal_diagnostics: internal_diagnostics(),
+    })
+}
+
+/// Resolved configuration of an installed logger
+#[derive(Debug)]
+pub(crate) struct Config {
+    pub(crate) facility: &'static str,
+    pub(crate) target: Target,
+    pub(crate) destination: Option<LogDestination>,
+    pub(crate) error_destination: Option<LogDestination>,
+    pub(crate) metric_destination: Option<LogDestination>,
+    pub(crate) coalesce_window: Option<Duration>,
+}
+
+/// Layer doing nothing but carrying the configuration, found by
+/// [`introspect`] through `Dispatch::downcast_ref`
+#[derive(Debug)]
+pub(crate) struct VintedIntrospection(pub(crate) Config);
+
+impl<S: Subscriber> Layer<S> for VintedIntrospection {}
+
+#[cfg(test)]
+mod tests {
+    use super::introspect;
+    use crate::{test_support::with_logger, LogDestination, Target};
+    use std::time::Duration;
+    use tracing::Dispatch;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn installed_loggers_report_their_config() {
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(LogDestination::Udp("127.0.0.1:9095".to_owned()))
+            .error_destination(LogDestination::Udp("127.0.0.1:9096".to_owned()))
+            .coalesce_window(Duration::from_secs(2));
+        let report = with_logger(builder, || {
+            let _span = tracing::info_span!("live").entered();
+            tracing::dispatcher::get_default(introspect)
+        })
+        .expect("a Vinted logger is installed");
+
+        assert_eq!(report.facility, "svc-test");
+        assert_eq!(report.target, Target::UdpJson);
+        assert_eq!(
+            report.destination,
+            Some(LogDestination::Udp("127.0.0.1:9095".to_owned()))
+        );
+        assert_eq!(
+            report.error_destination,
+            Some(LogDestination::Udp("127.0.0.1:9096".to_owned()))
+        );
+        assert_eq!(report.metric_destination, None);
+        assert_eq!(report.coalesce_window, Some(Duration::from_secs(2)));
+        assert!(report.live_spans >= 1, "{:?}", report);
+
+        let report = with_logger(crate::builder("svc-test", Target::Console), || {
+            tracing::dispatcher::get_default(introspect)
+        })
+        .expect("a Vinted logger is installed");
+        assert_eq!(report.target, Target::Console);
+        assert_eq!(report.destination, None);
+    }
+
+    #[test]
+    fn foreign_subscribers_report_nothing() {
+        assert!(introspect(&Dispatch::new(Registry::default())).is_none());
+        assert!(introspect(&Dispatch::new(tracing_subscriber::fmt().finish())).is_none());
+        assert!(introspect(&Dispatch::none()).is_none());
+    }
+}
@@ -1,15 +1,180 @@
-use std::error::Error;
-use tracing_subscriber::EnvFilter;
+//! Structured logger for Vinted Rust applications, built on `tracing`
+//!
+//! Records are sent as JSON to the local fluentd agent over UDP
+//! ([`Target::UdpJson`]), TCP ([`Target::TcpJson`]) or, on unix, a unix
+//! datagram socket (`Target::UnixJson`), written as JSON to
+//! stdout ([`Target::ConsoleJson`]) or as plain text for local development
+//! ([`Target::Console`]).
+//!
+//! ```
+//! use vinted_logger::prelude::*;
+//!
+//! vinted_logger::builder("svc-search", Target::Console).try_init()?;
+//! info!(user_id = 42, "signed in");
+//! # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+//! ```
+#![deny(missing_docs)]
 
+// The `*f!` macros expand to `::vinted_logger` paths, unit tests use them too
+#[cfg(test)]
+extern crate self as vinted_logger;
+
+#[doc(hidden)]
+pub mod __private;
+pub mod access_log;
+mod builder;
+pub mod compat;
+pub mod deprecation;
+mod destination;
+mod environment;
+#[cfg(test)]
+mod golden;
+mod init_error;
+mod introspect;
+mod log_point;
+pub mod prelude;
+mod result_ext;
+#[cfg(feature = "retry")]
+pub mod retry;
+#[cfg(test)]
+mod test_support;
+pub mod validate;
+pub(crate) mod vinted_attach_point;
+pub(crate) mod vinted_canary;
+pub(crate) mod vinted_coalescing_writer;
+pub(crate) mod vinted_console_writer;
+pub(crate) mod vinted_delivery;
+pub(crate) mod vinted_deployment;
+pub(crate) mod vinted_diagnostics;
+pub(crate) mod vinted_dict;
+pub(crate) mod vinted_drain;
+pub(crate) mod vinted_echo_writer;
+pub(crate) mod vinted_escaping;
+pub(crate) mod vinted_event_seq;
+pub(crate) mod vinted_event_time;
+pub(crate) mod vinted_events;
+pub(crate) mod vinted_facility_map;
+pub(crate) mod vinted_fallback;
+pub(crate) mod vinted_field_types;
+pub(crate) mod vinted_field_visitor;
+pub(crate) mod vinted_file_writer;
+pub(crate) mod vinted_filter_reload;
+pub(crate) mod vinted_flush;
+pub(crate) mod vinted_gelf;
+#[cfg(feature = "gelf-http")]
+pub(crate) mod vinted_gelf_http_writer;
+pub(crate) mod vinted_host_ip;
+pub(crate) mod vinted_installation;
 pub(crate) mod vinted_json_formatter;
+pub(crate) mod vinted_level_stats;
+pub(crate) mod vinted_message_template;
+pub(crate) mod vinted_metric;
+pub(crate) mod vinted_mirror_writer;
+pub(crate) mod vinted_own_records;
+pub(crate) mod vinted_panic_hook;
+pub(crate) mod vinted_pii;
+pub(crate) mod vinted_rate_limit;
+pub(crate) mod vinted_redaction;
+#[cfg(feature = "runtime-context")]
+pub(crate) mod vinted_runtime_context;
+#[cfg(all(feature = "sendmmsg", target_os = "linux"))]
+pub(crate) mod vinted_sendmmsg;
+#[cfg(feature = "shutdown")]
+pub(crate) mod vinted_shutdown;
+pub(crate) mod vinted_span_tracker;
+pub(crate) mod vinted_stats;
+pub(crate) mod vinted_tcp_writer;
+pub(crate) mod vinted_timestamp;
 pub(crate) mod vinted_udp_writer;
+#[cfg(unix)]
+pub(crate) mod vinted_unix_writer;
+pub(crate) mod vinted_volume_writer;
+
+pub use builder::{
+    builder, InitBehavior, LoggerBuilder, DEFAULT_COALESCE_WINDOW, DEFAULT_EVENT_TIME_SKEW,
+    DEFAULT_MAX_ADDITIONAL_FIELDS, DEFAULT_MAX_ADDITIONAL_FIELDS_BYTES,
+};
+pub use destination::{LogDestination, ParseDestinationError};
+pub use environment::{
+    init_for_environment, init_from_config, LoggerConfig, ECHO_ENV, FIELD_TYPES_ENV, TARGET_ENV,
+};
+pub use init_error::InitError;
+pub use introspect::{introspect, IntrospectionReport};
+pub use log_point::{disable_log_point, enable_log_point, MAX_LOG_POINTS};
+pub use result_ext::{ResultExt, RESULT_TARGET};
+pub use vinted_attach_point::AttachPoint;
+pub use vinted_canary::{CANARY_TARGET, DEFAULT_CANARY_INTERVAL};
+pub use vinted_console_writer::{DEFAULT_CONSOLE_BUFFER, DEFAULT_CONSOLE_MAX_LINE};
+pub use vinted_delivery::{first_delivery, wait_first_delivery, FirstDelivery, FirstDeliveryError};
+#[allow(deprecated)]
+pub use vinted_diagnostics::{
+    internal_diagnostics, InternalDiagnostics, DEFAULT_DIAGNOSTICS_PER_MINUTE,
+};
+pub use vinted_drain::{DEFAULT_DRAIN_BATCH_INTERVAL, DEFAULT_DRAIN_ERROR_TIMEOUT, DRAIN_TARGET};
+pub use vinted_echo_writer::DEFAULT_ECHO_PER_SECOND;
+pub use vinted_escaping::{EscapingProfile, CLICKHOUSE_MAX_DEPTH};
+pub use vinted_events::{
+    logger_events, LoggerEvent, LoggerEvents, NextLoggerEvent, LOGGER_EVENTS_CAPACITY,
+};
+pub use vinted_fallback::DEFAULT_FALLBACK_PER_MINUTE;
+pub use vinted_field_types::{field_coercions, FieldCoercions, FieldType};
+pub use vinted_field_visitor::{opt, OptDisplay};
+pub use vinted_file_writer::{DEFAULT_FILE_MAX_FILES, DEFAULT_FILE_MAX_SIZE};
+pub use vinted_filter_reload::{filter_handle, FilterError, FilterHandle};
+pub use vinted_flush::{flush, LoggerGuard, DEFAULT_FLUSH_TIMEOUT};
+pub use vinted_gelf::GELF_MAX_CHUNKS;
+#[cfg(feature = "gelf-http")]
+pub use vinted_gelf_http_writer::{
+    DEFAULT_HTTP_BATCH_BYTES, DEFAULT_HTTP_BATCH_INTERVAL, DEFAULT_HTTP_MAX_ATTEMPTS,
+};
+pub use vinted_json_formatter::PayloadTier;
+pub use vinted_level_stats::DEFAULT_LEVEL_STATS_EVERY;
+pub use vinted_logger_macros::{debugf, errorf, infof, tracef, warnf};
+pub use vinted_metric::{DEFAULT_METRIC_TARGET, MAX_METRIC_NAMES};
+pub use vinted_pii::{pii_masked, PiiDetector, PII_SCAN_MAX_LEN};
+#[cfg(all(feature = "sendmmsg", target_os = "linux"))]
+pub use vinted_sendmmsg::{sendmmsg_stats, SendmmsgStats};
+#[cfg(feature = "shutdown")]
+pub use vinted_shutdown::run_until_shutdown;
+pub use vinted_stats::{
+    destination_stats, healthy, logger_stats, DestinationRole, DestinationStats, LoggerStats,
+};
+pub use vinted_tcp_writer::{Framing, LengthHeader, DEFAULT_TCP_BUFFER};
+pub use vinted_timestamp::TimestampFormat;
+pub use vinted_udp_writer::{
+    WireFormat, DEFAULT_MAX_DATAGRAM_SIZE, DEFAULT_UDP_BATCH_BYTES, DEFAULT_UDP_BATCH_INTERVAL,
+    DEFAULT_UDP_BUFFER,
+};
+#[cfg(unix)]
+pub use vinted_unix_writer::DEFAULT_UNIX_SOCKET;
+pub use vinted_volume_writer::{top_targets_by_bytes, TRACKED_TARGETS};
 
 /// Logging target
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Target {
     /// Messages will be logged as JSON and sent to a UDP socket
     UdpJson,
 
+    /// Messages will be logged as JSON and sent over TCP, newline delimited
+    /// unless [`LoggerBuilder::tcp_framing`] says otherwise
+    TcpJson,
+
+    /// Messages will be logged as JSON and sent as datagrams to a unix
+    /// socket, [`DEFAULT_UNIX_SOCKET`] unless a `unix://` destination is set
+    #[cfg(unix)]
+    UnixJson,
+
+    /// Messages will be logged as GELF and posted to a Graylog HTTP input,
+    /// see [`LoggerBuilder::http_batching`]. `https://` destinations need
+    /// the `tls` feature.
+    #[cfg(feature = "gelf-http")]
+    GelfHttp,
+
+    /// Messages will be logged as JSON to a file rotated by size, see
+    /// [`LoggerBuilder::file_path`] and [`LoggerBuilder::file_rotation`]
+    FileJson,
+
     /// Messages will be logged as JSON to stdout
     ConsoleJson,
 
@@ -17,29 +182,30 @@ pub enum Target {
     Console,
 }
 
+impl Target {
+    /// Whether records are sent from a background thread, to a socket
+    pub(crate) fn is_network(self) -> bool {
+        match self {
+            Self::UdpJson | Self::TcpJson => true,
+            #[cfg(unix)]
+            Self::UnixJson => true,
+            #[cfg(feature = "gelf-http")]
+            Self::GelfHttp => true,
+            Self::FileJson | Self::ConsoleJson | Self::Console => false,
+        }
+    }
+}
+
 /// Creates an instance of Vinted logger
 ///
-/// - `facility` - facility name, usually the name of the service, e.g. `svc-search`, `core`
-pub fn try_init(
-    facility: &'static str,
-    target: Target,
-) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    let filter_layer = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
-
-    match target {
-        Target::UdpJson => tracing_subscriber::fmt()
-            .with_env_filter(filter_layer)
-            .with_writer(vinted_udp_writer::VintedUdpWriter::new("127.0.0.1:9091"))
-            .fmt_fields(tracing_subscriber::fmt::format::JsonFields::new())
-            .event_format(vinted_json_formatter::VintedJson::new(facility))
-            .try_init(),
-        Target::ConsoleJson => tracing_subscriber::fmt()
-            .with_env_filter(filter_layer)
-            .fmt_fields(tracing_subscriber::fmt::format::JsonFields::new())
-            .event_format(vinted_json_formatter::VintedJson::new(facility))
-            .try_init(),
-        Target::Console => tracing_subscriber::fmt()
-            .with_env_filter(filter_layer)
-            .try_init(),
-    }
+/// - `facility` - facility name, usually the name of the service, e.g. `svc-search`, `core`,
+///   must not be empty
+///
+/// ```
+/// vinted_logger::try_init("svc-search", vinted_logger::Target::Console)?;
+/// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+/// ```
+#[track_caller]
+pub fn try_init(facility: &'static str, target: Target) -> Result<(), InitError> {
+    builder(facility, target).try_init()
 }
@@ -0,0 +1,199 @@
+use parking_lot::{const_mutex, Mutex};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Upper bound of log point names remembered, as registered by
+/// [`gated_debug!`](crate::gated_debug) and as enabled
+pub const MAX_LOG_POINTS: usize = 1024;
+
+/// Target of the records of [`gated_debug!`](crate::gated_debug), always let
+/// through by the level filter
+pub(crate) const TARGET: &str = "vinted::log_point";
+
+const UNREGISTERED: u8 = 0;
+const DISABLED: u8 = 1;
+const ENABLED: u8 = 2;
+
+static REGISTRY: Mutex<Registry> = const_mutex(Registry {
+    enabled: None,
+    points: None,
+});
+
+struct Registry {
+    enabled: Option<HashSet<String>>,
+    points: Option<HashMap<&'static str, Vec<&'static LogPoint>>>,
+}
+
+/// Switches on the [`gated_debug!`](crate::gated_debug) statements named
+/// `name`, including ones which haven't run yet
+///
+/// Returns `false` if [`MAX_LOG_POINTS`] names are enabled already.
+pub fn enable_log_point(name: &str) -> bool {
+    let mut registry = REGISTRY.lock();
+    let enabled = registry.enabled.get_or_insert_with(HashSet::new);
+    if !enabled.contains(name) {
+        if enabled.len() >= MAX_LOG_POINTS {
+            return false;
+        }
+        enabled.insert(name.to_owned());
+    }
+    registry.set(name, ENABLED);
+    true
+}
+
+/// Switches off the [`gated_debug!`](crate::gated_debug) statements named `name`
+pub fn disable_log_point(name: &str) {
+    let mut registry = REGISTRY.lock();
+    if let Some(ref mut enabled) = registry.enabled {
+        enabled.remove(name);
+    }
+    registry.set(name, DISABLED);
+}
+
+impl Registry {
+    fn set(&self, name: &str, state: u8) {
+        let points = self.points.as_ref().and_then(|points| points.get(name));
+        for point in points.into_iter().flatten() {
+            point.state.store(state, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Logs at DEBUG with target `vinted::log_point` and a `log_point` field
+/// holding the name, but only while the name is switched on with
+/// [`enable_log_point`], regardless of the level filter
+///
+/// Disabled statements don't evaluate their fields, which costs a single
+/// relaxed atomic load.
+///
+/// ```
+/// # let key = "item:42";
+/// vinted_logger::gated_debug!("cache_miss_details", key, "cache miss");
+/// ```
+#[macro_export]
+macro_rules! gated_debug {
+    ($name:literal $(,)?) => {
+        $crate::gated_debug!($name, "")
+    };
+    ($name:literal, $($rest:tt)+) => {{
+        static POINT: $crate::__private::LogPoint = $crate::__private::LogPoint::new($name);
+        if POINT.enabled() {
+            $crate::__private::tracing::debug!(target: "vinted::log_point", log_point = $name, $($rest)+);
+        }
+    }};
+}
+
+/// A [`gated_debug!`](crate::gated_debug) statement, not part of the public API
+#[derive(Debug)]
+pub struct LogPoint {
+    name: &'static str,
+    state: AtomicU8,
+}
+
+impl LogPoint {
+    /// Point of the given name, registered when it first runs
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            state: AtomicU8::new(UNREGISTERED),
+        }
+    }
+
+    /// A single relaxed load once registered
+    #[inline]
+    pub fn enabled(&'static self) -> bool {
+        match self.state.load(Ordering::Relaxed) {
+            ENABLED => true,
+            DISABLED => false,
+            _ => self.register(),
+        }
+    }
+
+    /// Points beyond [`MAX_LOG_POINTS`] names stay disabled for good
+    #[cold]
+    fn register(&'static self) -> bool {
+        let mut registry = REGISTRY.lock();
+        let registry = &mut *registry;
+        let points = registry.points.get_or_insert_with(HashMap::new);
+        let registered = points.len() < MAX_LOG_POINTS || points.contains_key(self.name);
+        if registered {
+            points.entry(self.name).or_default().push(self);
+        }
+
+        let enabled = registered
+            && registry
+                .enabled
+                .as_ref()
+                .is_some_and(|enabled| enabled.contains(self.name));
+        self.state
+            .store(if enabled { ENABLED } else { DISABLED }, Ordering::Relaxed);
+        enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disable_log_point, enable_log_point, TARGET};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use std::cell::Cell;
+
+    // Names are unique to each test, the registry is global
+
+    #[test]
+    fn points_log_only_while_enabled() {
+        let capture = Capture::default();
+        let evaluated = Cell::new(0);
+        let value = || {
+            evaluated.set(evaluated.get() + 1);
+            evaluated.get()
+        };
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, || {
+            let log =
+                |n: u32| crate::gated_debug!("test_toggled", n, evaluated = value(), "cache miss");
+            log(0);
+            assert!(enable_log_point("test_toggled"));
+            log(1);
+            log(2);
+            disable_log_point("test_toggled");
+            log(3);
+            assert!(enable_log_point("test_toggled"));
+            log(4);
+        });
+
+        let records = capture.records();
+        let ns: Vec<_> = records
+            .iter()
+            .map(|record| record["n"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ns, [1, 2, 4]);
+        // Disabled points don't evaluate their fields
+        assert_eq!(evaluated.get(), 3);
+        for record in &records {
+            assert_eq!(record["level"], "DEBUG");
+            assert_eq!(record["target"], TARGET);
+            assert_eq!(record["log_point"], "test_toggled");
+            assert_eq!(record["message"], "cache miss");
+        }
+    }
+
+    #[test]
+    fn names_enabled_before_their_point_runs_apply_to_it() {
+        assert!(enable_log_point("test_enabled_early"));
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, || {
+            crate::gated_debug!("test_enabled_early", "first run");
+            crate::gated_debug!("test_never_enabled", "first run");
+        });
+
+        let records = capture.records();
+        assert_eq!(records.len(), 1, "{:?}", records);
+        assert_eq!(records[0]["log_point"], "test_enabled_early");
+    }
+}
@@ -0,0 +1,24 @@
+//! Everything needed to log with `vinted_logger` without depending on
+//! `tracing` directly, so its version always matches the one the logger is
+//! registered with
+//!
+//! ```
+//! use vinted_logger::prelude::*;
+//!
+//! let _ = vinted_logger::try_init("svc-search", Target::Console);
+//! let span = info_span!("search", query = "shoes");
+//! let _entered = span.enter();
+//! info!(results = 42, "search completed");
+//! ```
+//!
+//! `#[instrument]` expands to `::tracing` paths and still needs a `tracing`
+//! dependency.
+
+pub use crate::{
+    builder, debugf, errorf, infof, tracef, try_init, warnf, LogDestination, ResultExt, Target,
+};
+pub use tracing::{
+    self, debug, debug_span, error, error_span, event, info, info_span, instrument, span, trace,
+    trace_span, warn, warn_span, Instrument, Level, Span,
+};
+pub use tracing_subscriber::filter::LevelFilter;
@@ -0,0 +1,184 @@
+use std::{error::Error, panic::Location};
+use tracing::Level;
+
+/// Target of the records of [`ResultExt`]
+pub const RESULT_TARGET: &str = "vinted::error";
+
+/// Logs errors where they're propagated, returning the `Result` unchanged so
+/// it still chains with `?`
+///
+/// Records carry `error` with the message and the source chain of the error
+/// (see [`Visit::record_error`](tracing::field::Visit::record_error)) and
+/// `callsite` (`file:line` of the caller), with target [`RESULT_TARGET`].
+/// Wrappers annotated with `#[track_caller]` report their own caller's
+/// location. `Ok` results log nothing.
+///
+/// ```
+/// use vinted_logger::ResultExt;
+///
+/// fn read_config() -> std::io::Result<String> {
+///     let config = std::fs::read_to_string("config.toml").log_err("can't read config")?;
+///     Ok(config)
+/// }
+/// ```
+pub trait ResultExt<E> {
+    /// Logs an error at ERROR with `message`
+    #[track_caller]
+    fn log_err(self, message: &str) -> Self;
+
+    /// Logs an error at `level` with the message `message` makes of it,
+    /// which is only called for errors
+    #[track_caller]
+    fn log_err_with(self, level: Level, message: impl FnOnce(&E) -> String) -> Self;
+}
+
+impl<T, E: Error + 'static> ResultExt<E> for Result<T, E> {
+    #[inline]
+    #[track_caller]
+    fn log_err(self, message: &str) -> Self {
+        if let Err(ref error) = self {
+            log(Level::ERROR, error, message, Location::caller());
+        }
+        self
+    }
+
+    #[inline]
+    #[track_caller]
+    fn log_err_with(self, level: Level, message: impl FnOnce(&E) -> String) -> Self {
+        if let Err(ref error) = self {
+            log(level, error, &message(error), Location::caller());
+        }
+        self
+    }
+}
+
+#[cold]
+fn log(level: Level, error: &(dyn Error + 'static), message: &str, location: &Location<'_>) {
+    let callsite = format!("{}:{}", location.file(), location.line());
+
+    // Levels of callsites are static, every level needs its own
+    macro_rules! log {
+        ($level:expr) => {
+            tracing::event!(
+                target: RESULT_TARGET,
+                $level,
+                error,
+                callsite = callsite.as_str(),
+                "{}",
+                message
+            )
+        };
+    }
+    match level {
+        Level::ERROR => log!(Level::ERROR),
+        Level::WARN => log!(Level::WARN),
+        Level::INFO => log!(Level::INFO),
+        Level::DEBUG => log!(Level::DEBUG),
+        Level::TRACE => log!(Level::TRACE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResultExt, RESULT_TARGET};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use serde_json::{json, Value};
+    use std::{fmt, io};
+    use tracing::Level;
+
+    #[derive(Debug)]
+    struct QueryFailed(io::Error);
+
+    impl fmt::Display for QueryFailed {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("query failed")
+        }
+    }
+
+    impl std::error::Error for QueryFailed {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn failed() -> Result<(), QueryFailed> {
+        Err(QueryFailed(io::Error::other("connection reset")))
+    }
+
+    fn records(log: impl FnOnce()) -> Vec<Value> {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .default_level(Level::TRACE)
+            .capture(&capture);
+        with_logger(builder, log);
+        capture.records()
+    }
+
+    /// Helper logging on behalf of its caller
+    #[track_caller]
+    fn load_items() -> Result<(), QueryFailed> {
+        failed().log_err("can't load items")
+    }
+
+    #[test]
+    fn errors_are_logged_with_their_chain() {
+        let mut line = 0;
+        let records = records(|| {
+            line = line!() + 1;
+            let result = failed().log_err("can't list items");
+            assert!(result.is_err(), "returned unchanged");
+        });
+
+        let record = &records[0];
+        assert_eq!(record["level"], "ERROR");
+        assert_eq!(record["target"], RESULT_TARGET);
+        assert_eq!(record["message"], "can't list items");
+        assert_eq!(
+            record["error"],
+            json!({"message": "query failed", "chain": ["connection reset"]})
+        );
+        assert_eq!(record["callsite"], format!("src/result_ext.rs:{}", line));
+    }
+
+    #[test]
+    fn track_caller_helpers_report_their_callers() {
+        let mut line = 0;
+        let records = records(|| {
+            line = line!() + 1;
+            let _ = load_items();
+        });
+
+        assert_eq!(
+            records[0]["callsite"],
+            format!("src/result_ext.rs:{}", line)
+        );
+    }
+
+    #[test]
+    fn levels_and_messages_can_be_chosen() {
+        let records = records(|| {
+            for level in [Level::WARN, Level::DEBUG] {
+                let _ = failed().log_err_with(level, |e| format!("retrying after {}", e));
+            }
+        });
+
+        assert_eq!(records[0]["level"], "WARN");
+        assert_eq!(records[1]["level"], "DEBUG");
+        assert_eq!(records[0]["message"], "retrying after query failed");
+    }
+
+    #[test]
+    fn ok_results_log_nothing() {
+        let records = records(|| {
+            let ok: Result<u8, QueryFailed> = Ok(1);
+            assert_eq!(ok.log_err("unused").unwrap(), 1);
+            let ok: Result<u8, QueryFailed> = Ok(2);
+            let _ = ok.log_err_with(Level::ERROR, |_| unreachable!("only called for errors"));
+        });
+
+        assert!(records.is_empty(), "{:?}", records);
+    }
+}
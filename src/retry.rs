@@ -0,0 +1,289 @@
+//! Retries with consistently logged attempts
+//!
+//! ```no_run
+//! # async fn fetch_user() -> Result<(), std::io::Error> { Ok(()) }
+//! # async fn run() -> Result<(), std::io::Error> {
+//! use std::time::Duration;
+//! use vinted_logger::retry::{retry, RetryPolicy};
+//!
+//! let policy = RetryPolicy::new("fetch_user")
+//!     .max_attempts(5)
+//!     .backoff(Duration::from_millis(50), Duration::from_secs(2));
+//! retry(&policy, fetch_user).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    error::Error,
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::Instrument;
+
+/// How many times and how slowly an operation is retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    operation: &'static str,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Policy of 3 attempts, backoff doubling from 100ms up to 10s and 10%
+    /// jitter.
+    ///
+    /// - `operation` - label of the retried operation, recorded as the
+    ///   `operation` field of the `retry` span
+    pub fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: 0.1,
+        }
+    }
+
+    /// Sets how many times the operation is run at most, including the
+    /// first attempt
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the backoff after the first failure, doubled after every further
+    /// failure up to `max`
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Sets the fraction, between 0 and 1, by which every backoff is randomly
+    /// shortened or lengthened
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    fn backoff_after(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .checked_mul(1 << (attempt - 1).min(31))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+
+        // [-1, 1), good enough to spread retries of concurrent callers
+        let random = f64::from(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .subsec_nanos()
+                .wrapping_mul(2_654_435_761),
+        ) / f64::from(u32::MAX)
+            * 2.0
+            - 1.0;
+
+        backoff.mul_f64(1.0 + self.jitter * random)
+    }
+}
+
+/// Runs `op` until it succeeds or `policy` runs out of attempts, returning the
+/// last error in that case.
+///
+/// Attempts run within a `retry` span carrying the policy's `operation`. Every
+/// failure followed by another attempt is logged as WARN with `attempt`,
+/// `max_attempts`, `next_backoff_ms` and `error`, the error and its sources
+/// joined by `: `. The last failure is logged as ERROR with
+/// `attempts_exhausted = true`.
+pub async fn retry<T, E, F, Fut>(policy: &RetryPolicy, mut op: F) -> Result<T, E>
+where
+    E: Error,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let span = tracing::info_span!("retry", operation = policy.operation);
+
+    async move {
+        let mut attempt = 1;
+        loop {
+            let error = match op().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            if attempt >= policy.max_attempts {
+                tracing::error!(
+                    attempt,
+                    max_attempts = policy.max_attempts,
+                    attempts_exhausted = true,
+                    error = %error_chain(&error),
+                    "{} failed, no attempts left",
+                    policy.operation
+                );
+                return Err(error);
+            }
+
+            let backoff = policy.backoff_after(attempt);
+            tracing::warn!(
+                attempt,
+                max_attempts = policy.max_attempts,
+                next_backoff_ms = backoff.as_millis() as u64,
+                error = %error_chain(&error),
+                "{} failed, retrying",
+                policy.operation
+            );
+
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+fn error_chain(error: &dyn Error) -> String {
+    let mut chain = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        chain.push_str(": ");
+        chain.push_str(&error.to_string());
+        source = error.source();
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry, RetryPolicy};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use serde_json::Value;
+    use std::{cell::Cell, fmt, time::Duration};
+
+    #[derive(Debug)]
+    struct Failure(&'static str, Option<Box<Failure>>);
+
+    impl fmt::Display for Failure {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl std::error::Error for Failure {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.1.as_deref().map(|source| source as _)
+        }
+    }
+
+    fn failure() -> Failure {
+        Failure(
+            "request failed",
+            Some(Box::new(Failure("connection reset", None))),
+        )
+    }
+
+    /// Records of running `op` with `max_attempts` and its result
+    fn run(max_attempts: u32, fail: u32) -> (Result<u32, Failure>, Vec<Value>) {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("runtime can be built");
+        let policy = RetryPolicy::new("fetch_user")
+            .max_attempts(max_attempts)
+            .backoff(Duration::from_millis(1), Duration::from_millis(3))
+            .jitter(0.0);
+        let attempts = Cell::new(0);
+        let result = with_logger(builder, || {
+            runtime.block_on(retry(&policy, || {
+                attempts.set(attempts.get() + 1);
+                let attempt = attempts.get();
+                async move {
+                    if attempt <= fail {
+                        Err(failure())
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            }))
+        });
+        (result, capture.records())
+    }
+
+    #[test]
+    fn failed_attempts_are_logged_until_one_succeeds() {
+        let (result, records) = run(5, 3);
+        assert_eq!(result.unwrap(), 4);
+
+        assert_eq!(records.len(), 3, "{:?}", records);
+        for (record, (attempt, backoff)) in records.iter().zip(&[(1, 1), (2, 2), (3, 3)]) {
+            assert_eq!(record["level"], "WARN");
+            assert_eq!(record["message"], "fetch_user failed, retrying");
+            assert_eq!(record["attempt"], *attempt);
+            assert_eq!(record["max_attempts"], 5);
+            assert_eq!(record["next_backoff_ms"], *backoff);
+            assert_eq!(record["error"], "request failed: connection reset");
+            assert!(record.get("attempts_exhausted").is_none(), "{}", record);
+            assert_eq!(record["span"]["name"], "retry");
+            assert_eq!(record["span"]["operation"], "fetch_user");
+        }
+    }
+
+    #[test]
+    fn last_error_is_logged_and_returned() {
+        let (result, records) = run(2, u32::MAX);
+        let error = result.unwrap_err();
+        assert_eq!(error.0, "request failed");
+
+        let levels: Vec<_> = records.iter().map(|record| &record["level"]).collect();
+        assert_eq!(levels, ["WARN", "ERROR"]);
+        let last = &records[1];
+        assert_eq!(last["message"], "fetch_user failed, no attempts left");
+        assert_eq!(last["attempt"], 2);
+        assert_eq!(last["max_attempts"], 2);
+        assert_eq!(last["attempts_exhausted"], true);
+        assert_eq!(last["error"], "request failed: connection reset");
+        assert!(last.get("next_backoff_ms").is_none(), "{}", last);
+        assert_eq!(last["span"]["operation"], "fetch_user");
+    }
+
+    #[test]
+    fn successes_log_nothing() {
+        let (result, records) = run(3, 0);
+        assert_eq!(result.unwrap(), 1);
+        assert!(records.is_empty(), "{:?}", records);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let policy = RetryPolicy::new("op")
+            .backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .jitter(0.0);
+        let backoffs: Vec<_> = (1..=6)
+            .map(|attempt| policy.backoff_after(attempt))
+            .collect();
+        assert_eq!(
+            backoffs,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+        assert_eq!(policy.backoff_after(u32::MAX), Duration::from_secs(1));
+
+        let jittered = RetryPolicy::new("op")
+            .backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .jitter(0.5);
+        for _ in 0..100 {
+            let backoff = jittered.backoff_after(1);
+            assert!(
+                backoff >= Duration::from_millis(50) && backoff <= Duration::from_millis(150),
+                "{:?}",
+                backoff
+            );
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Helpers shared by the unit tests
+use crate::{builder::BoxedLayer, LoggerBuilder};
+use parking_lot::Mutex;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    fmt, io,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tracing::{dispatcher, field, Dispatch, Event, Level, Metadata, Span};
+use tracing_core::{
+    callsite::{Callsite, Identifier},
+    field::{FieldSet, Value as FieldValue, ValueSet},
+    metadata::Kind,
+    Interest,
+};
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, Registry};
+
+/// Lines written by console writers capturing them, or by anything using it
+/// as a `MakeWriter`, with whether they went to stderr
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Capture(Arc<Mutex<Lines>>);
+
+type Lines = Vec<(bool, Vec<u8>)>;
+
+impl Capture {
+    pub(crate) fn push(&self, stderr: bool, line: &[u8]) {
+        self.0.lock().push((stderr, line.to_vec()));
+    }
+
+    pub(crate) fn lines(&self) -> Vec<String> {
+        self.lines_where(|_| true)
+    }
+
+    pub(crate) fn stdout(&self) -> Vec<String> {
+        self.lines_where(|stderr| !stderr)
+    }
+
+    pub(crate) fn stderr(&self) -> Vec<String> {
+        self.lines_where(|stderr| stderr)
+    }
+
+    fn lines_where(&self, keep: impl Fn(bool) -> bool) -> Vec<String> {
+        self.0
+            .lock()
+            .iter()
+            .filter(|(stderr, _)| keep(*stderr))
+            .map(|(_, line)| String::from_utf8(line.clone()).expect("line is UTF-8"))
+            .collect()
+    }
+
+    /// Every line parsed as a JSON record
+    pub(crate) fn records(&self) -> Vec<Value> {
+        self.lines().iter().map(|line| parse(line)).collect()
+    }
+}
+
+impl<'a> MakeWriter<'a> for Capture {
+    type Writer = CaptureWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        CaptureWriter(self.clone(), false)
+    }
+}
+
+/// `MakeWriter` of stderr lines
+#[derive(Debug, Clone)]
+pub(crate) struct CaptureStderr(pub(crate) Capture);
+
+impl<'a> MakeWriter<'a> for CaptureStderr {
+    type Writer = CaptureWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        CaptureWriter(self.0.clone(), true)
+    }
+}
+
+pub(crate) struct CaptureWriter(Capture, bool);
+
+impl io::Write for CaptureWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.push(self.1, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `f` with the logger of `builder` as the default subscriber of the
+/// thread, instead of installing it globally
+pub(crate) fn with_logger<R>(builder: LoggerBuilder, f: impl FnOnce() -> R) -> R {
+    let (layer, _filter) = builder.build().expect("logger can be built");
+    with_layer(layer, f)
+}
+
+pub(crate) fn with_layer<R>(layer: BoxedLayer, f: impl FnOnce() -> R) -> R {
+    let dispatch = Dispatch::new(Registry::default().with(layer));
+    tracing::dispatcher::with_default(&dispatch, f)
+}
+
+/// A line of JSON, which must be a single object ending with a newline
+pub(crate) fn parse(line: &str) -> Value {
+    assert!(
+        line.ends_with('\n'),
+        "{:?} doesn't end with a newline",
+        line
+    );
+    assert_eq!(line.matches('\n').count(), 1, "{:?} isn't one line", line);
+    let record: Value = serde_json::from_str(line).expect("line is JSON");
+    assert!(record.is_object(), "{} isn't an object", record);
+    record
+}
+
+/// Polls `condition` for up to 5s, for records logged from other threads
+pub(crate) fn wait_until(condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    true
+}
+
+/// Deterministic xorshift64 generator for randomized tests, so failures
+/// reproduce
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// In `0..n`
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Up to `max_len` chars, mostly ASCII with control characters, quotes,
+    /// backslashes and multi-byte chars mixed in
+    pub(crate) fn string(&mut self, max_len: usize) -> String {
+        const SPECIAL: &[char] = &[
+            '\t', '\r', '\n', '\0', '\u{1b}', '\u{7f}', '"', '\\', '/', '.', 'é', '€', '😀',
+            '\u{2028}', '\u{feff}',
+        ];
+        (0..self.below(max_len + 1))
+            .map(|_| match self.below(4) {
+                0 => SPECIAL[self.below(SPECIAL.len())],
+                _ => (b'a' + self.below(26) as u8) as char,
+            })
+            .collect()
+    }
+}
+
+/// Fields of random events and spans, not all of them with a value
+const RANDOM_FIELDS: usize = 6;
+
+/// Callsite made up at runtime, so that random events and spans can have
+/// field names the macros would need at compile time
+struct DynamicCallsite(OnceLock<Metadata<'static>>);
+
+impl Callsite for DynamicCallsite {
+    fn set_interest(&self, _: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.0.get().expect("metadata is set once registered")
+    }
+}
+
+/// Leaks a callsite of `kind` with [`RANDOM_FIELDS`] random field names
+fn random_metadata(rng: &mut Rng, kind: Kind, level: Level) -> &'static Metadata<'static> {
+    const RESERVED: &[&str] = &[
+        "message",
+        "level",
+        "@timestamp",
+        "target",
+        "span",
+        "name",
+        "_name",
+        "host",
+        "a.b",
+    ];
+    let leak = |name: String| -> &'static str { Box::leak(name.into_boxed_str()) };
+    let names: Vec<&'static str> = (0..RANDOM_FIELDS)
+        .map(|_| match rng.below(6) {
+            0 => "",
+            1 => RESERVED[rng.below(RESERVED.len())],
+            2 => leak(rng.string(300)),
+            _ => leak(rng.string(8)),
+        })
+        .collect();
+    let name = leak(rng.string(12));
+
+    let callsite: &'static DynamicCallsite = Box::leak(Box::new(DynamicCallsite(OnceLock::new())));
+    let fields = FieldSet::new(Box::leak(names.into_boxed_slice()), Identifier(callsite));
+    let meta = Metadata::new(
+        name,
+        "svc_test::fuzz",
+        level,
+        Some(file!()),
+        Some(line!()),
+        Some(module_path!()),
+        fields,
+        kind,
+    );
+    let _ = callsite.0.set(meta);
+    tracing_core::callsite::register(callsite);
+    callsite.metadata()
+}
+
+// Only ever formatted with `Debug`
+#[allow(dead_code)]
+#[derive(Debug)]
+enum Tree {
+    Leaf(String),
+    Node(Vec<Tree>),
+}
+
+/// Mostly a chain, so that deep trees stay small
+fn random_tree(rng: &mut Rng, depth: usize) -> Tree {
+    if depth == 0 || rng.below(8) == 0 {
+        return Tree::Leaf(rng.string(6));
+    }
+    let children = if rng.below(8) == 0 { 2 } else { 1 };
+    Tree::Node((0..children).map(|_| random_tree(rng, depth - 1)).collect())
+}
+
+fn random_value(rng: &mut Rng) -> Box<dyn FieldValue> {
+    match rng.below(10) {
+        0 => Box::new(rng.string(40)),
+        1 => Box::new(rng.string(5_000)),
+        2 => Box::new(rng.next() as i64),
+        3 => Box::new([u128::MAX, u128::from(rng.next()) << 64][rng.below(2)]),
+        4 => Box::new([i128::MIN, i64::MIN.into()][rng.below(2)]),
+        // NaN and infinities included
+        5 => Box::new(f64::from_bits(rng.next())),
+        6 => Box::new(field::debug(random_tree(rng, 200))),
+        #[cfg(unix)]
+        7 => {
+            use std::os::unix::ffi::OsStringExt;
+            // Not UTF-8, e.g. lone surrogates encoded as WTF-8
+            let bytes: Vec<u8> = (0..rng.below(20)).map(|_| rng.next() as u8).collect();
+            Box::new(field::debug(std::ffi::OsString::from_vec(bytes)))
+        }
+        8 => Box::new(field::display(rng.string(40))),
+        _ => Box::new(rng.below(2) == 0),
+    }
+}
+
+/// Random values of the fields of `meta`, some left out
+fn with_random_values<R>(
+    rng: &mut Rng,
+    meta: &'static Metadata<'static>,
+    f: impl FnOnce(&ValueSet<'_>) -> R,
+) -> R {
+    let values: Vec<Option<Box<dyn FieldValue>>> = (0..RANDOM_FIELDS)
+        .map(|_| match rng.below(4) {
+            0 => None,
+            _ => Some(random_value(rng)),
+        })
+        .collect();
+    let fields: Vec<_> = meta.fields().iter().collect();
+    let values: [(&field::Field, Option<&dyn FieldValue>); RANDOM_FIELDS] =
+        std::array::from_fn(|i| (&fields[i], values[i].as_deref()));
+    f(&meta.fields().value_set(&values))
+}
+
+/// Logs an event of random level, field names and values, within up to 3
+/// random spans
+pub(crate) fn log_random_event(rng: &mut Rng) {
+    const LEVELS: &[Level] = &[
+        Level::TRACE,
+        Level::DEBUG,
+        Level::INFO,
+        Level::WARN,
+        Level::ERROR,
+    ];
+    let enabled = |meta| dispatcher::get_default(|dispatch| dispatch.enabled(meta));
+
+    let mut spans = Vec::new();
+    for _ in 0..rng.below(4) {
+        let meta = random_metadata(rng, Kind::SPAN, Level::INFO);
+        let span = with_random_values(rng, meta, |values| match enabled(meta) {
+            true => Span::new(meta, values),
+            false => Span::none(),
+        });
+        spans.push(span.entered());
+    }
+    let level = LEVELS[rng.below(LEVELS.len())];
+    let meta = random_metadata(rng, Kind::EVENT, level);
+    with_random_values(rng, meta, |values| {
+        if enabled(meta) {
+            dispatcher::get_default(|dispatch| dispatch.event(&Event::new(meta, values)));
+        }
+    });
+    // Exited innermost first
+    while let Some(span) = spans.pop() {
+        drop(span);
+    }
+}
+
+/// Deserializes any JSON, failing on objects with a key twice
+struct UniqueKeys;
+
+impl<'de> Deserialize<'de> for UniqueKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(UniqueKeys)
+    }
+}
+
+impl<'de> Visitor<'de> for UniqueKeys {
+    type Value = UniqueKeys;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JSON without duplicate keys")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Self, E> {
+        Ok(self)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<Self, E> {
+        Ok(self)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<Self, E> {
+        Ok(self)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<Self, E> {
+        Ok(self)
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<Self, E> {
+        Ok(self)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self, E> {
+        Ok(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self, A::Error> {
+        while seq.next_element::<UniqueKeys>()?.is_some() {}
+        Ok(self)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self, A::Error> {
+        let mut keys = HashSet::new();
+        while let Some(key) = map.next_key::<String>()? {
+            map.next_value::<UniqueKeys>()?;
+            if !keys.insert(key.clone()) {
+                return Err(serde::de::Error::custom(format!("`{}` is duplicated", key)));
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Checks what every record must be to a collector: UTF-8, a single JSON
+/// object on one line ending with a newline, without a key twice at any
+/// depth
+pub(crate) fn check_record(line: &[u8]) -> Result<Value, String> {
+    let line = std::str::from_utf8(line).map_err(|e| e.to_string())?;
+    let record = line.strip_suffix('\n').ok_or("no trailing newline")?;
+    if record.contains('\n') {
+        return Err("more than one line".to_owned());
+    }
+    serde_json::from_str::<UniqueKeys>(record).map_err(|e| e.to_string())?;
+    match serde_json::from_str(record).map_err(|e| e.to_string())? {
+        record @ Value::Object(_) => Ok(record),
+        _ => Err("not an object".to_owned()),
+    }
+}
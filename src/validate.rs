@@ -0,0 +1,168 @@
+//! Decoding of what the logger sends, for collectors
+//!
+//! Datagrams of [`WireFormat::Json`](crate::WireFormat::Json) are newline
+//! delimited `VintedJson` records, several of them with
+//! [`LoggerBuilder::udp_batching`](crate::LoggerBuilder::udp_batching).
+//!
+//! [`WireFormat::JsonDict`](crate::WireFormat::JsonDict) batches carry their
+//! keys only once, in a container of newline terminated lines:
+//!
+//! ```text
+//! {"vinted_dict":1,"keys":["@timestamp","level","message"]}
+//! [0,"2021-04-20T12:42:57.123Z",1,"INFO",2,"signed in"]
+//! [0,"2021-04-20T12:42:57.125Z",1,"WARN",2,"slow query"]
+//! ```
+//!
+//! The header is an object whose first key is `vinted_dict`, the version of
+//! the container, and `keys` the table of top level keys. Every other line
+//! is a record, an array of key indexes alternating with their values in the
+//! order of the record. Nested objects keep their keys. A batch the table
+//! wouldn't make smaller is sent as plain records instead, which start with
+//! another key. Version 1 is the only one so far.
+//!
+//! ```
+//! let datagram = b"{\"vinted_dict\":1,\"keys\":[\"level\",\"message\"]}\n[0,\"INFO\",1,\"hi\"]\n";
+//! let records = vinted_logger::validate::decode_batch(datagram)?;
+//! assert_eq!(records, ["{\"level\":\"INFO\",\"message\":\"hi\"}\n"]);
+//! # Ok::<(), vinted_logger::validate::DecodeError>(())
+//! ```
+
+use crate::vinted_dict::{HEADER_KEY, VERSION};
+use serde_json::{value::RawValue, Value};
+use std::{error::Error, fmt};
+
+/// Why a datagram couldn't be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    line: usize,
+    reason: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid batch, line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Error for DecodeError {}
+
+/// The records of a datagram, each as its `VintedJson` line ending with a
+/// newline, whether it's a key table container or plain records
+///
+/// Records of a container are given back byte for byte as the logger wrote
+/// them. Plain records aren't checked beyond ending with a newline.
+pub fn decode_batch(datagram: &[u8]) -> Result<Vec<String>, DecodeError> {
+    let error = |line: usize, reason: String| DecodeError {
+        line: line + 1,
+        reason,
+    };
+    let datagram = std::str::from_utf8(datagram).map_err(|e| error(0, e.to_string()))?;
+    let lines = datagram
+        .strip_suffix('\n')
+        .ok_or_else(|| error(0, "doesn't end with a newline".to_owned()))?
+        .split('\n');
+
+    let header = format!("{{\"{}\":", HEADER_KEY);
+    if !datagram.starts_with(&header) {
+        return Ok(lines.map(|line| format!("{}\n", line)).collect());
+    }
+
+    let mut lines = lines.enumerate();
+    let keys = match lines.next() {
+        Some((n, line)) => header_keys(line).map_err(|reason| error(n, reason))?,
+        None => Vec::new(),
+    };
+    lines
+        .map(|(n, line)| record(line, &keys).map_err(|reason| error(n, reason)))
+        .collect()
+}
+
+/// The key table of a header, serialized
+fn header_keys(line: &str) -> Result<Vec<String>, String> {
+    let header: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    match header[HEADER_KEY].as_u64() {
+        Some(VERSION) => {}
+        Some(version) => {
+            return Err(format!(
+                "version {} isn't supported, only {}",
+                version, VERSION
+            ))
+        }
+        None => return Err(format!("`{}` isn't a version", HEADER_KEY)),
+    }
+    match header["keys"].as_array() {
+        Some(keys) if keys.iter().all(Value::is_string) => {
+            Ok(keys.iter().map(Value::to_string).collect())
+        }
+        _ => Err("`keys` isn't an array of strings".to_owned()),
+    }
+}
+
+fn record(line: &str, keys: &[String]) -> Result<String, String> {
+    let entries: Vec<&RawValue> = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    if !entries.len().is_multiple_of(2) {
+        return Err("a key index has no value".to_owned());
+    }
+
+    let mut record = String::with_capacity(line.len() * 2);
+    record.push('{');
+    for (n, entry) in entries.chunks(2).enumerate() {
+        let key = entry[0]
+            .get()
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| keys.get(index))
+            .ok_or_else(|| format!("`{}` isn't an index of the key table", entry[0].get()))?;
+        if n > 0 {
+            record.push(',');
+        }
+        record.push_str(key);
+        record.push(':');
+        record.push_str(entry[1].get());
+    }
+    record.push_str("}\n");
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_batch;
+
+    fn reason(datagram: &str) -> String {
+        decode_batch(datagram.as_bytes()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn plain_records_are_split() {
+        assert_eq!(
+            decode_batch(b"{\"a\":1}\n{\"a\":2}\n").unwrap(),
+            ["{\"a\":1}\n", "{\"a\":2}\n"]
+        );
+        assert_eq!(
+            reason("{\"a\":1}"),
+            "invalid batch, line 1: doesn't end with a newline"
+        );
+    }
+
+    #[test]
+    fn invalid_containers_are_rejected() {
+        let header = "{\"vinted_dict\":1,\"keys\":[\"a\",\"b\"]}\n";
+        assert_eq!(
+            decode_batch(format!("{}[1,{{\"a\":1}},0,\"\\n\"]\n", header).as_bytes()).unwrap(),
+            ["{\"b\":{\"a\":1},\"a\":\"\\n\"}\n"]
+        );
+        assert_eq!(
+            reason("{\"vinted_dict\":2,\"keys\":[]}\n"),
+            "invalid batch, line 1: version 2 isn't supported, only 1"
+        );
+        assert_eq!(
+            reason(&format!("{}[0,1]\n[2,1]\n", header)),
+            "invalid batch, line 3: `2` isn't an index of the key table"
+        );
+        assert_eq!(
+            reason(&format!("{}[0,1,1]\n", header)),
+            "invalid batch, line 2: a key index has no value"
+        );
+        assert!(reason(&format!("{}{{\"a\":1}}\n", header)).starts_with("invalid batch, line 2: "));
+    }
+}
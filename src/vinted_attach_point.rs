@@ -0,0 +1,149 @@
+use crate::builder::BoxedLayer;
+use std::any::TypeId;
+use tracing_core::{
+    span::{Attributes, Id, Record},
+    Dispatch, Event, Interest, LevelFilter, Metadata,
+};
+use tracing_subscriber::{
+    layer::{Context, Layer},
+    reload, Registry,
+};
+
+/// Where [`InitBehavior::AttachLayerIfPossible`](crate::InitBehavior::AttachLayerIfPossible)
+/// attaches the logger to a subscriber installed before it
+///
+/// A subscriber can't take new layers once it's installed, so the place of
+/// the logger has to be set aside up front: layer the attach point right
+/// onto the `Registry`, before the other layers, and install the logger
+/// later, e.g. once its configuration is loaded. The attach point does
+/// nothing until then, and takes a single logger.
+///
+/// ```
+/// use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+/// use vinted_logger::{AttachPoint, InitBehavior, Target};
+///
+/// tracing_subscriber::registry()
+///     .with(AttachPoint::default())
+///     .with(tracing_subscriber::fmt::layer())
+///     .init();
+///
+/// vinted_logger::builder("svc-search", Target::ConsoleJson)
+///     .init_behavior(InitBehavior::AttachLayerIfPossible)
+///     .try_init()?;
+/// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+/// ```
+///
+/// The filter of the logger, `RUST_LOG` by default, applies to the layers
+/// after the attach point as well once it's attached.
+pub struct AttachPoint {
+    layer: reload::Layer<Option<BoxedLayer>, Registry>,
+    handle: reload::Handle<Option<BoxedLayer>, Registry>,
+}
+
+impl Default for AttachPoint {
+    fn default() -> Self {
+        let (layer, handle) = reload::Layer::new(None);
+        Self { layer, handle }
+    }
+}
+
+impl std::fmt::Debug for AttachPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AttachPoint")
+            .field("attached", &self.is_attached())
+            .finish()
+    }
+}
+
+impl AttachPoint {
+    pub(crate) fn is_attached(&self) -> bool {
+        self.handle.with_current(Option::is_some).unwrap_or(true)
+    }
+
+    /// Attaches the layers of a logger to `dispatch`, the subscriber of the
+    /// attach point, returns `false` if a logger is attached already
+    pub(crate) fn attach(&self, layer: BoxedLayer, dispatch: &Dispatch) -> bool {
+        if self.is_attached() {
+            return false;
+        }
+        // The subscriber is registered already, the layers learn about it
+        // from here instead
+        layer.on_register_dispatch(dispatch);
+        self.handle.reload(Some(layer)).is_ok()
+    }
+}
+
+impl Layer<Registry> for AttachPoint {
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.layer.on_register_dispatch(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.layer.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, Registry>) -> bool {
+        self.layer.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, Registry>) {
+        self.layer.on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, Registry>) {
+        self.layer.on_record(span, values, ctx);
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, Registry>) {
+        self.layer.on_follows_from(span, follows, ctx);
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, Registry>) -> bool {
+        self.layer.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, Registry>) {
+        self.layer.on_event(event, ctx);
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, Registry>) {
+        self.layer.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, Registry>) {
+        self.layer.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, Registry>) {
+        self.layer.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, Registry>) {
+        self.layer.on_id_change(old, new, ctx);
+    }
+
+    /// Whatever the logger lets through, the subscriber can't be capped to
+    /// what it let through before it was attached
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        None
+    }
+
+    /// Finds the attach point itself and, once attached, the layers of the
+    /// logger, e.g. for [`introspect`](crate::introspect)
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            return Some(self as *const Self as *const ());
+        }
+        // Safety: the layers are never replaced once attached, so pointers
+        // into them stay valid as long as the attach point, after the lock
+        // is released
+        self.handle
+            .with_current(|layer| {
+                layer
+                    .as_ref()
+                    .and_then(|layer| unsafe { layer.downcast_raw(id) })
+            })
+            .ok()
+            .flatten()
+    }
+}
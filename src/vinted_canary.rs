@@ -0,0 +1,251 @@
+use crate::vinted_stats;
+use std::{
+    io,
+    net::UdpSocket,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Default of [`LoggerBuilder::canary_every`](crate::LoggerBuilder::canary_every)
+pub const DEFAULT_CANARY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Target of canary records
+pub const CANARY_TARGET: &str = "vinted_logger::canary";
+
+/// Longest wait for a reflection between checks whether the guard is gone
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Canary settings of a logger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CanaryConfig {
+    pub(crate) interval: Duration,
+    /// Local port reflections are received on, `None` if the collector
+    /// doesn't reflect canaries
+    pub(crate) reflect_port: Option<u16>,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_CANARY_INTERVAL,
+            reflect_port: None,
+        }
+    }
+}
+
+/// Thread sending canaries, stopped when dropped
+#[derive(Debug)]
+pub(crate) struct Canary {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Canary {
+    pub(crate) fn start(config: CanaryConfig) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let _ = ::std::thread::Builder::new()
+            .name("vinted-logger-canary".to_owned())
+            .spawn(move || run(config, &thread_stopped));
+        Self { stopped }
+    }
+}
+
+impl Drop for Canary {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Id unique to the process and the canary, e.g. `4242-7-1718000000000`
+fn canary_id() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    format!(
+        "{}-{}-{}",
+        process::id(),
+        SEQ.fetch_add(1, Ordering::Relaxed),
+        millis
+    )
+}
+
+fn run(config: CanaryConfig, stopped: &AtomicBool) {
+    // Bound once the first canary is due, the port stays the same afterwards
+    let mut socket: Option<UdpSocket> = None;
+
+    while !stopped.load(Ordering::Relaxed) {
+        let id = canary_id();
+        let tick = Instant::now();
+
+        let reply_to = match config.reflect_port {
+            Some(port) => {
+                if socket.is_none() {
+                    socket = bind(port);
+                }
+                socket
+                    .as_ref()
+                    .and_then(|socket| socket.local_addr().ok())
+                    .map(|addr| addr.to_string())
+            }
+            None => None,
+        };
+        tracing::info!(
+            target: CANARY_TARGET,
+            canary_id = id.as_str(),
+            canary_reply_to = reply_to.as_deref(),
+            "canary"
+        );
+
+        let deadline = tick + config.interval;
+        match socket {
+            Some(ref socket) if reply_to.is_some() => {
+                match wait_reflection(socket, &id, deadline, stopped) {
+                    Some(at) => vinted_stats::canary_reflected(at.duration_since(tick)),
+                    None if stopped.load(Ordering::Relaxed) => return,
+                    None => vinted_stats::canary_missed(),
+                }
+            }
+            _ => {}
+        }
+        sleep_until(deadline, stopped);
+    }
+}
+
+fn bind(port: u16) -> Option<UdpSocket> {
+    let socket = UdpSocket::bind(("127.0.0.1", port))
+        .and_then(|socket| socket.set_read_timeout(Some(POLL_INTERVAL)).map(|_| socket));
+    match socket {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            crate::vinted_diagnostics::report(
+                crate::vinted_diagnostics::Diagnostic::BindFailed,
+                format_args!(
+                    "Canary reflections can't be received on 127.0.0.1:{}: {}",
+                    port, e
+                ),
+            );
+            None
+        }
+    }
+}
+
+/// Waits for the reflection of canary `id` until `deadline`, returns when it
+/// was received
+fn wait_reflection(
+    socket: &UdpSocket,
+    id: &str,
+    deadline: Instant,
+    stopped: &AtomicBool,
+) -> Option<Instant> {
+    let mut buf = [0; 2048];
+    while Instant::now() < deadline && !stopped.load(Ordering::Relaxed) {
+        match socket.recv(&mut buf) {
+            Ok(len) if is_reflection(&buf[..len], id) => return Some(Instant::now()),
+            // Reflections of earlier canaries, once they missed their window
+            Ok(_) => {}
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            }
+            Err(_) => ::std::thread::sleep(POLL_INTERVAL),
+        }
+    }
+    None
+}
+
+/// Whether `datagram` reflects canary `id`: either the id itself or the
+/// canary record, a JSON object with the id in `canary_id`
+fn is_reflection(datagram: &[u8], id: &str) -> bool {
+    let datagram = match std::str::from_utf8(datagram) {
+        Ok(datagram) => datagram.trim(),
+        Err(_) => return false,
+    };
+    if datagram == id {
+        return true;
+    }
+    match serde_json::from_str::<serde_json::Value>(datagram) {
+        Ok(record) => record.get("canary_id").and_then(|id| id.as_str()) == Some(id),
+        Err(_) => false,
+    }
+}
+
+fn sleep_until(deadline: Instant, stopped: &AtomicBool) {
+    loop {
+        let now = Instant::now();
+        if now >= deadline || stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        ::std::thread::sleep((deadline - now).min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_reflection, CANARY_TARGET};
+    use crate::{
+        test_support::{with_logger, Capture},
+        vinted_stats, InitError, Target,
+    };
+
+    #[test]
+    fn reflections_are_the_id_or_the_record() {
+        assert!(is_reflection(b"42-1-1000", "42-1-1000"));
+        assert!(is_reflection(b"42-1-1000\n", "42-1-1000"));
+        assert!(is_reflection(
+            br#"{"message":"canary","canary_id":"42-1-1000"}"#,
+            "42-1-1000"
+        ));
+        assert!(!is_reflection(b"42-1-999", "42-1-1000"));
+        assert!(!is_reflection(br#"{"canary_id":"42-0-1000"}"#, "42-1-1000"));
+        assert!(!is_reflection(br#"{"message":"42-1-1000"}"#, "42-1-1000"));
+        assert!(!is_reflection(b"\xff42-1-1000", "42-1-1000"));
+    }
+
+    #[test]
+    fn canaries_need_a_network_target() {
+        let result = crate::builder("svc-test", Target::ConsoleJson)
+            .canary()
+            .build();
+        assert!(matches!(result, Err(InitError::InvalidConfig(_))));
+        let result = crate::builder("svc-test", Target::UdpJson)
+            .canary_every(std::time::Duration::from_secs(0))
+            .build();
+        assert!(matches!(result, Err(InitError::InvalidConfig(_))));
+        assert!(crate::builder("svc-test", Target::UdpJson)
+            .canary_reflections(0)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn canary_records_carry_the_minute_buckets() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, || {
+            tracing::info!(target: CANARY_TARGET, canary_id = "test", "canary");
+            tracing::info!("after the canary");
+        });
+
+        let records = capture.records();
+        let buckets = records[0][vinted_stats::BUCKETS_KEY]
+            .as_array()
+            .expect("minute_buckets is an array");
+        assert!(
+            (1..=vinted_stats::BUCKET_MINUTES).contains(&buckets.len()),
+            "{:?}",
+            buckets
+        );
+        for bucket in buckets {
+            assert!(bucket["start"].as_str().unwrap().ends_with(":00+00:00"));
+            for counter in ["emitted", "delivered", "dropped_client", "send_errors"] {
+                assert!(bucket[counter].is_u64(), "{}", bucket);
+            }
+        }
+        assert!(records[1].get(vinted_stats::BUCKETS_KEY).is_none());
+    }
+}
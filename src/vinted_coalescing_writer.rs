@@ -0,0 +1,636 @@
+use crate::vinted_flush::{self, Pending};
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io,
+    sync::{Arc, Weak},
+    thread::{self, Thread, ThreadId},
+    time::{Duration, Instant},
+};
+use tracing_core::{callsite::Identifier, Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Every record produced by `VintedJson` starts with the timestamp entry,
+/// followed by the event sequence number.
+const EVENT_SEQ_PREFIX: &[u8] = b",\"event_seq\":";
+
+/// Wraps a `MakeWriter` and suppresses records identical to the previous one
+/// from the same callsite and thread, replacing them with a single summary
+/// record.
+///
+/// Summaries are written when the callsite logs a different record, from a
+/// background thread once the window of the run is over, by
+/// [`flush`](crate::flush) and when the writer is dropped.
+pub(crate) struct VintedCoalescingWriter<W> {
+    shared: Arc<Shared<W>>,
+    // Declared last so it ends the runs once the writer is gone
+    _timer: Option<Timer>,
+}
+
+struct Shared<W> {
+    inner: W,
+    coalescer: Option<Coalescer>,
+}
+
+impl<W> VintedCoalescingWriter<W>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    /// `window` of `None` disables coalescing and passes every record through.
+    /// Records are timestamped under `timestamp_key`, in any
+    /// [`TimestampFormat`](crate::TimestampFormat).
+    pub(crate) fn new(inner: W, window: Option<Duration>, timestamp_key: &str) -> Self {
+        let shared = Arc::new(Shared {
+            inner,
+            coalescer: window.map(|window| {
+                let mut timestamp_prefix = b"{".to_vec();
+                let _ = serde_json::to_writer(&mut timestamp_prefix, timestamp_key);
+                timestamp_prefix.push(b':');
+                Coalescer {
+                    window,
+                    timestamp_prefix,
+                    runs: Mutex::new(HashMap::new()),
+                }
+            }),
+        });
+
+        let timer = window.map(|window| {
+            let pending: Arc<dyn Pending> = shared.clone();
+            vinted_flush::register_pending(Arc::downgrade(&pending));
+            let weak = Arc::downgrade(&shared);
+            let thread = ::std::thread::Builder::new()
+                .name("vinted-logger-coalescer".to_owned())
+                .spawn(move || expire_runs(&weak, window));
+            Timer {
+                // Summaries are still written by the next records and flushes
+                thread: thread.ok().map(|thread| thread.thread().clone()),
+                shared: pending,
+            }
+        });
+
+        Self {
+            shared,
+            _timer: timer,
+        }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for VintedCoalescingWriter<W> {
+    type Writer = CoalescingWriterImpl<'a, W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        CoalescingWriterImpl {
+            inner: self.shared.inner.make_writer(),
+            source: None,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        CoalescingWriterImpl {
+            inner: self.shared.inner.make_writer_for(meta),
+            source: self
+                .shared
+                .coalescer
+                .as_ref()
+                .map(|coalescer| (coalescer, meta.callsite(), *meta.level())),
+        }
+    }
+}
+
+pub(crate) struct CoalescingWriterImpl<'a, W> {
+    inner: W,
+    source: Option<(&'a Coalescer, Identifier, Level)>,
+}
+
+impl<W: io::Write> io::Write for CoalescingWriterImpl<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.source {
+            Some((coalescer, ref callsite, level)) => {
+                coalescer.write(&mut self.inner, callsite, level, buf)?;
+                Ok(buf.len())
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> Pending for Shared<W>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync,
+{
+    fn write_pending(&self) {
+        if let Some(ref coalescer) = self.coalescer {
+            coalescer.write_all_summaries(&self.inner);
+        }
+    }
+}
+
+/// Writes the pending summaries when the writer is dropped, and wakes the
+/// thread expiring runs so it stops
+struct Timer {
+    thread: Option<Thread>,
+    shared: Arc<dyn Pending>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.write_pending();
+        if let Some(ref thread) = self.thread {
+            thread.unpark();
+        }
+    }
+}
+
+/// Writes the summaries of runs whose window is over, until the writer is
+/// dropped
+fn expire_runs<W>(shared: &Weak<Shared<W>>, window: Duration)
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync,
+{
+    loop {
+        let next = match shared.upgrade() {
+            Some(shared) => match shared.coalescer {
+                Some(ref coalescer) => coalescer.expire(&shared.inner, Instant::now()),
+                None => return,
+            },
+            None => return,
+        };
+        let now = Instant::now();
+        thread::park_timeout(next.map_or(window, |next| next.saturating_duration_since(now)));
+    }
+}
+
+struct Coalescer {
+    window: Duration,
+    /// `{"<timestamp key>":`
+    timestamp_prefix: Vec<u8>,
+    /// The current run of each callsite, in each thread since records carry
+    /// their thread
+    runs: Mutex<HashMap<(Identifier, ThreadId), Run>>,
+}
+
+/// A sequence of identical records, of which only the first one was written.
+/// Timestamps are kept as JSON, strings with their quotes or numbers.
+struct Run {
+    level: Level,
+    hash: u64,
+    record: Vec<u8>,
+    started: Instant,
+    repeat_count: u64,
+    first_timestamp: String,
+    last_timestamp: String,
+    last_event_seq: String,
+}
+
+impl Coalescer {
+    fn write(
+        &self,
+        writer: &mut dyn io::Write,
+        callsite: &Identifier,
+        level: Level,
+        record: &[u8],
+    ) -> io::Result<()> {
+        let (envelope, hash) = match fingerprint(&self.timestamp_prefix, record) {
+            Some(fingerprint) => fingerprint,
+            None => return writer.write_all(record),
+        };
+
+        let key = (callsite.clone(), thread::current().id());
+        let mut runs = self.runs.lock();
+
+        if let Some(run) = runs.get_mut(&key) {
+            if run.hash == hash {
+                if run.started.elapsed() < self.window {
+                    run.suppress(&envelope);
+                    return Ok(());
+                }
+
+                // The window is over: report what was suppressed so far and
+                // start a new one. ERROR records are written at least once per
+                // window, everything else keeps being summarized.
+                run.write_summary(&self.timestamp_prefix, writer)?;
+                run.started = Instant::now();
+                if level == Level::ERROR {
+                    writer.write_all(record)?;
+                } else {
+                    run.suppress(&envelope);
+                }
+                return Ok(());
+            }
+
+            run.write_summary(&self.timestamp_prefix, writer)?;
+        }
+
+        writer.write_all(record)?;
+        runs.insert(
+            key,
+            Run {
+                level,
+                hash,
+                record: record.to_vec(),
+                started: Instant::now(),
+                repeat_count: 0,
+                first_timestamp: String::new(),
+                last_timestamp: String::new(),
+                last_event_seq: String::new(),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Writes the summaries of the runs whose window is over through the
+    /// writer of their callsite. Runs of ERROR records end there, so the
+    /// next repeat is written again; other runs go on being summarized, and
+    /// are forgotten once a window passes without repeats. Returns when the
+    /// next window ends.
+    fn expire<W>(&self, inner: &W, now: Instant) -> Option<Instant>
+    where
+        W: for<'writer> MakeWriter<'writer>,
+    {
+        let mut next: Option<Instant> = None;
+        self.runs.lock().retain(|(callsite, _), run| {
+            let ends = run.started + self.window;
+            if ends > now {
+                next = Some(next.map_or(ends, |next| next.min(ends)));
+                return true;
+            }
+            if run.repeat_count == 0 {
+                return false;
+            }
+
+            let mut writer = inner.make_writer_for(callsite.0.metadata());
+            let _ = run.write_summary(&self.timestamp_prefix, &mut writer);
+            if run.level == Level::ERROR {
+                return false;
+            }
+            run.started = now;
+            let ends = now + self.window;
+            next = Some(next.map_or(ends, |next| next.min(ends)));
+            true
+        });
+        next
+    }
+
+    /// Writes the summary of every run, and forgets them
+    fn write_all_summaries<W>(&self, inner: &W)
+    where
+        W: for<'writer> MakeWriter<'writer>,
+    {
+        for ((callsite, _), mut run) in self.runs.lock().drain() {
+            if run.repeat_count > 0 {
+                let mut writer = inner.make_writer_for(callsite.0.metadata());
+                let _ = run.write_summary(&self.timestamp_prefix, &mut writer);
+            }
+        }
+    }
+}
+
+impl Run {
+    fn suppress(&mut self, envelope: &Envelope<'_>) {
+        if self.repeat_count == 0 {
+            self.first_timestamp = envelope.timestamp.to_owned();
+        }
+        self.repeat_count += 1;
+        self.last_timestamp = envelope.timestamp.to_owned();
+        self.last_event_seq = envelope.event_seq.to_owned();
+    }
+
+    /// Writes the first record of the run again, stamped with the time and
+    /// sequence number of the last suppressed repeat and carrying
+    /// `repeat_count`, `first_timestamp` and `last_timestamp`.
+    fn write_summary(
+        &mut self,
+        timestamp_prefix: &[u8],
+        writer: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        if self.repeat_count == 0 {
+            return Ok(());
+        }
+
+        let body = match split(timestamp_prefix, &self.record) {
+            Some(envelope) => envelope.body,
+            None => return Ok(()),
+        };
+        let body = match body.iter().rposition(|&byte| byte == b'}') {
+            Some(end) => &body[..end],
+            None => return Ok(()),
+        };
+
+        let mut summary = Vec::with_capacity(self.record.len() + 128);
+        summary.extend_from_slice(timestamp_prefix);
+        summary.extend_from_slice(self.last_timestamp.as_bytes());
+        summary.extend_from_slice(EVENT_SEQ_PREFIX);
+        summary.extend_from_slice(self.last_event_seq.as_bytes());
+        summary.extend_from_slice(body);
+        summary.extend_from_slice(
+            format!(
+                ",\"repeat_count\":{},\"first_timestamp\":{},\"last_timestamp\":{}}}\n",
+                self.repeat_count, self.first_timestamp, self.last_timestamp
+            )
+            .as_bytes(),
+        );
+
+        self.repeat_count = 0;
+        writer.write_all(&summary)
+    }
+}
+
+/// Entries every record starts with, and everything after them
+struct Envelope<'a> {
+    timestamp: &'a str,
+    event_seq: &'a str,
+    body: &'a [u8],
+}
+
+/// Returns `None` if the record doesn't look like a `VintedJson` one, which
+/// starts with `timestamp_prefix`.
+fn split<'a>(timestamp_prefix: &[u8], record: &'a [u8]) -> Option<Envelope<'a>> {
+    let rest = record.strip_prefix(timestamp_prefix)?;
+    // A string, timestamps have nothing to escape, or a number
+    let end = match rest.first()? {
+        b'"' => rest[1..].iter().position(|&byte| byte == b'"')? + 2,
+        _ => rest.iter().position(|byte| !byte.is_ascii_digit())?,
+    };
+    let timestamp = std::str::from_utf8(&rest[..end]).ok()?;
+    if timestamp.is_empty() {
+        return None;
+    }
+
+    let rest = rest[end..].strip_prefix(EVENT_SEQ_PREFIX)?;
+    let end = rest.iter().position(|byte| !byte.is_ascii_digit())?;
+    let event_seq = std::str::from_utf8(&rest[..end]).ok()?;
+
+    Some(Envelope {
+        timestamp,
+        event_seq,
+        body: &rest[end..],
+    })
+}
+
+/// Returns the record's envelope and a hash of everything but the envelope,
+/// or `None` if the record doesn't look like a `VintedJson` one.
+fn fingerprint<'a>(timestamp_prefix: &[u8], record: &'a [u8]) -> Option<(Envelope<'a>, u64)> {
+    let envelope = split(timestamp_prefix, record)?;
+
+    let mut hasher = DefaultHasher::new();
+    envelope.body.hash(&mut hasher);
+
+    Some((envelope, hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{wait_until, with_logger, Capture},
+        LoggerBuilder, Target, TimestampFormat,
+    };
+    use serde_json::Value;
+    use std::{thread, time::Duration};
+    use tracing::{error, info};
+
+    fn logger(capture: &Capture, window: Duration) -> LoggerBuilder {
+        crate::builder("svc-test", Target::ConsoleJson)
+            .coalesce_window(window)
+            .capture(capture)
+    }
+
+    /// Logs `message` from a single callsite
+    fn log(message: &str) {
+        info!(user_id = 7, "{}", message);
+    }
+
+    fn repeat_counts(records: &[Value]) -> Vec<Option<u64>> {
+        records
+            .iter()
+            .map(|record| record.get("repeat_count").and_then(|count| count.as_u64()))
+            .collect()
+    }
+
+    #[test]
+    fn burst_is_written_once_then_summarized() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_secs(60)), || {
+            for _ in 0..5 {
+                log("same");
+            }
+            log("different");
+        });
+
+        let records = capture.records();
+        assert_eq!(records.len(), 3, "{:?}", records);
+        assert_eq!(records[0]["message"], "same");
+        assert!(records[0].get("repeat_count").is_none());
+
+        let summary = &records[1];
+        assert_eq!(summary["message"], "same");
+        assert_eq!(summary["user_id"], 7);
+        assert_eq!(summary["repeat_count"], 4);
+        assert_eq!(summary["last_timestamp"], summary["@timestamp"]);
+        assert!(summary["first_timestamp"].as_str() <= summary["last_timestamp"].as_str());
+        assert!(summary["event_seq"].as_u64() > records[0]["event_seq"].as_u64());
+
+        assert_eq!(records[2]["message"], "different");
+    }
+
+    #[test]
+    fn interleaved_records_are_all_written() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_secs(60)), || {
+            for _ in 0..3 {
+                log("a");
+                log("b");
+            }
+        });
+
+        let records = capture.records();
+        assert_eq!(repeat_counts(&records), [None; 6]);
+        let messages: Vec<_> = records.iter().map(|record| &record["message"]).collect();
+        assert_eq!(messages, ["a", "b", "a", "b", "a", "b"]);
+    }
+
+    #[test]
+    fn runs_are_kept_per_callsite_and_thread() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_secs(60)), || {
+            let dispatch = tracing::dispatcher::get_default(Clone::clone);
+            let other = thread::spawn(move || {
+                tracing::dispatcher::with_default(&dispatch, || {
+                    for _ in 0..100 {
+                        log("same");
+                    }
+                })
+            });
+            for _ in 0..100 {
+                log("same");
+                info!("other callsite");
+            }
+            other.join().unwrap();
+        });
+
+        let records = capture.records();
+        let summaries: Vec<_> = records
+            .iter()
+            .filter_map(|record| Some((record["message"].as_str()?, record.get("repeat_count")?)))
+            .collect();
+        assert_eq!(records.len(), 6, "{:?}", records);
+        assert_eq!(summaries.len(), 3, "{:?}", summaries);
+        for (_, count) in summaries {
+            assert_eq!(count, 99);
+        }
+    }
+
+    #[test]
+    fn different_field_values_are_not_repeats() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_secs(60)), || {
+            for attempt in 0..3 {
+                info!(attempt, "retrying");
+            }
+        });
+
+        let records = capture.records();
+        assert_eq!(records.len(), 3);
+        for (attempt, record) in records.iter().enumerate() {
+            assert_eq!(record["attempt"], attempt);
+            assert!(record.get("repeat_count").is_none());
+        }
+    }
+
+    #[test]
+    fn a_burst_followed_by_silence_is_summarized_once_the_window_ends() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_millis(50)), || {
+            for _ in 0..3 {
+                log("same");
+            }
+            assert!(wait_until(|| capture.records().len() == 2));
+            assert_eq!(repeat_counts(&capture.records()), [None, Some(2)]);
+        });
+        assert_eq!(capture.records().len(), 2, "nothing pending is left");
+    }
+
+    #[test]
+    fn flushing_writes_pending_summaries() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_secs(60)), || {
+            for _ in 0..3 {
+                log("same");
+            }
+            assert_eq!(capture.records().len(), 1);
+            crate::flush(Duration::from_secs(1));
+            assert_eq!(repeat_counts(&capture.records()), [None, Some(2)]);
+
+            // The run starts over
+            log("same");
+            log("same");
+        });
+        assert_eq!(
+            repeat_counts(&capture.records()),
+            [None, Some(2), None, Some(1)]
+        );
+    }
+
+    #[test]
+    fn dropping_the_logger_writes_pending_summaries() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_secs(60)), || {
+            for _ in 0..4 {
+                log("same");
+            }
+        });
+        assert_eq!(repeat_counts(&capture.records()), [None, Some(3)]);
+    }
+
+    #[test]
+    fn repeats_after_a_window_keep_being_summarized() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_millis(50)), || {
+            for round in 0..2 {
+                if round == 1 {
+                    thread::sleep(Duration::from_millis(80));
+                }
+                for _ in 0..3 {
+                    log("same");
+                }
+            }
+        });
+
+        // The first window ends with its summary, the second one with the
+        // logger
+        assert_eq!(repeat_counts(&capture.records()), [None, Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn errors_are_written_once_per_window() {
+        let capture = Capture::default();
+        with_logger(logger(&capture, Duration::from_millis(50)), || {
+            for round in 0..2 {
+                if round == 1 {
+                    thread::sleep(Duration::from_millis(80));
+                }
+                for _ in 0..3 {
+                    error!("failing");
+                }
+            }
+        });
+
+        assert_eq!(
+            repeat_counts(&capture.records()),
+            [None, Some(2), None, Some(2)]
+        );
+    }
+
+    #[test]
+    fn disabled_coalescing_writes_everything() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, || {
+            for _ in 0..3 {
+                log("same");
+            }
+        });
+
+        assert_eq!(capture.records().len(), 3);
+    }
+
+    #[test]
+    fn repeats_are_summarized_with_any_timestamp_key_and_format() {
+        for &format in &[
+            TimestampFormat::Rfc3339,
+            TimestampFormat::Rfc3339Millis,
+            TimestampFormat::EpochMillis,
+        ] {
+            let capture = Capture::default();
+            let builder = logger(&capture, Duration::from_secs(60))
+                .timestamp_key("time")
+                .timestamp_format(format);
+            with_logger(builder, || {
+                for _ in 0..3 {
+                    log("same");
+                }
+                log("different");
+            });
+
+            let records = capture.records();
+            assert_eq!(records.len(), 3, "{:?}: {:?}", format, records);
+            let summary = &records[1];
+            assert_eq!(summary["repeat_count"], 2, "{:?}", format);
+            assert!(summary.get("@timestamp").is_none(), "{}", summary);
+            assert_eq!(summary["last_timestamp"], summary["time"]);
+            // Timestamps keep their type: numbers for EpochMillis
+            assert_eq!(
+                summary["first_timestamp"].is_number(),
+                format == TimestampFormat::EpochMillis,
+                "{}",
+                summary
+            );
+            assert_eq!(summary["time"].is_number(), records[0]["time"].is_number());
+        }
+    }
+}
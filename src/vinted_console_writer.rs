@@ -0,0 +1,581 @@
+use crate::{
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_escaping::Entries,
+    vinted_flush::Progress,
+    vinted_json_formatter::DEFAULT_TIMESTAMP_KEY,
+    vinted_stats,
+    vinted_timestamp::{self, TimestampFormat},
+};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc,
+    },
+    time::SystemTime,
+};
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Longest line written atomically to a pipe, `PIPE_BUF` on Linux. Lines of
+/// other processes writing to the same pipe may end up within longer ones.
+pub(crate) const PIPE_BUF: usize = 4096;
+
+/// Default of [`LoggerBuilder::console_max_line`](crate::LoggerBuilder::console_max_line)
+pub const DEFAULT_CONSOLE_MAX_LINE: usize = 32 * 1024;
+
+/// Default of [`LoggerBuilder::console_buffer`](crate::LoggerBuilder::console_buffer)
+pub const DEFAULT_CONSOLE_BUFFER: usize = 4_096;
+
+/// Message of the records written instead of lines beyond the cap
+const TOO_LARGE: &str = "<record too large for console>";
+
+static OVERSIZED: AtomicU64 = AtomicU64::new(0);
+
+/// Lines written to stdout or stderr longer than [`PIPE_BUF`]
+pub(crate) fn oversized_lines() -> u64 {
+    OVERSIZED.load(Ordering::Relaxed)
+}
+
+/// Writes records to stdout, optionally prefixed with a level tag and with
+/// WARN and ERROR records split off to stderr.
+///
+/// Every line, tag included, is written with a single `write` under the lock
+/// of the stream, so lines of different threads, and of processes sharing
+/// the pipe as long as lines fit in [`PIPE_BUF`], never interleave.
+///
+/// Records longer than a cap are replaced by a stub keeping their timestamp,
+/// level and callsite, see [`stub`], so a single huge record can't freeze a
+/// terminal or bury `kubectl logs --tail`.
+///
+/// Non-blocking writers queue lines for a background thread writing them
+/// instead, see [`VintedConsoleWriter::non_blocking`]. Test writers print
+/// them, see [`VintedConsoleWriter::test_writer`].
+#[derive(Debug)]
+pub(crate) struct VintedConsoleWriter {
+    level_hint: bool,
+    split_streams: bool,
+    max_line: usize,
+    json: bool,
+    timestamp: (&'static str, TimestampFormat),
+    test_writer: bool,
+    queue: Option<Queue>,
+    #[cfg(test)]
+    capture: Option<crate::test_support::Capture>,
+}
+
+impl VintedConsoleWriter {
+    /// `json` tells whether records are `VintedJson` ones, whose stubs are
+    /// JSON as well
+    pub(crate) fn new(level_hint: bool, split_streams: bool, max_line: usize, json: bool) -> Self {
+        Self {
+            level_hint,
+            split_streams,
+            max_line,
+            json,
+            timestamp: (DEFAULT_TIMESTAMP_KEY, TimestampFormat::Rfc3339),
+            test_writer: false,
+            queue: None,
+            #[cfg(test)]
+            capture: None,
+        }
+    }
+
+    /// Stubs of JSON records keep the timestamp under `key`, or are
+    /// timestamped with `format` when it's missing
+    pub(crate) fn timestamp(self, key: &'static str, format: TimestampFormat) -> Self {
+        Self {
+            timestamp: (key, format),
+            ..self
+        }
+    }
+
+    /// Writes lines to `capture` instead, if any
+    #[cfg(test)]
+    pub(crate) fn capture(self, capture: Option<crate::test_support::Capture>) -> Self {
+        Self { capture, ..self }
+    }
+
+    /// Writes lines with `print!` and `eprint!`, which the test harness
+    /// captures, instead of writing to the streams themselves, which it
+    /// doesn't. Test writers are never non-blocking.
+    pub(crate) fn test_writer(self, test_writer: bool) -> Self {
+        Self {
+            test_writer,
+            ..self
+        }
+    }
+
+    /// With a `capacity`, queues up to that many lines for a background
+    /// thread writing them, so a slow or blocked stdout doesn't hold up
+    /// logging threads. Lines are dropped while the queue is full, the same
+    /// as `UdpJson` records.
+    ///
+    /// The thread lives as long as the writer, [`flush`](crate::flush)
+    /// waits for it.
+    pub(crate) fn non_blocking(mut self, capacity: Option<usize>) -> Self {
+        let capacity = match capacity {
+            Some(capacity) if !self.test_writer => capacity,
+            _ => return self,
+        };
+        // A capacity of 0 would make every write wait for the thread
+        let (sender, receiver) = sync_channel::<(bool, Vec<u8>)>(capacity.max(1));
+        let progress = Progress::register();
+
+        let thread_progress = progress.clone();
+        let _ = ::std::thread::Builder::new()
+            .name("vinted-logger-console".to_owned())
+            .spawn(move || {
+                // Ends once the sender is dropped, after the queue is drained
+                for (stderr, line) in receiver {
+                    let _ = if stderr {
+                        write_line(&mut io::stderr().lock(), &line)
+                    } else {
+                        write_line(&mut io::stdout().lock(), &line)
+                    };
+                    thread_progress.done(1);
+                }
+                thread_progress.stopped();
+            });
+
+        self.queue = Some(Queue {
+            sender: Mutex::new(sender),
+            progress,
+        });
+        self
+    }
+}
+
+/// Queue of the thread writing lines of a non-blocking writer
+#[derive(Debug)]
+struct Queue {
+    sender: Mutex<SyncSender<(bool, Vec<u8>)>>,
+    progress: Arc<Progress>,
+}
+
+impl Queue {
+    /// Drops the line when the queue is full
+    fn queue(&self, stderr: bool, error: bool, line: Vec<u8>) {
+        self.progress.queued(error);
+        let result = self.sender.lock().try_send((stderr, line));
+        if result.is_err() {
+            self.progress.not_queued();
+        }
+
+        match result {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                vinted_stats::console_dropped();
+                vinted_diagnostics::report(
+                    Diagnostic::Dropped,
+                    format_args!("Console queue is full, dropped a log record"),
+                )
+            }
+            Err(TrySendError::Disconnected(_)) => vinted_diagnostics::report(
+                Diagnostic::ChannelClosed,
+                format_args!("Log record can't be queued, the console writer thread stopped"),
+            ),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for VintedConsoleWriter {
+    type Writer = ConsoleWriterImpl<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ConsoleWriterImpl {
+            stderr: false,
+            hint: None,
+            level: None,
+            max_line: self.max_line,
+            json: self.json,
+            timestamp: self.timestamp,
+            test_writer: self.test_writer,
+            queue: self.queue.as_ref(),
+            #[cfg(test)]
+            capture: self.capture.as_ref(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let level = *meta.level();
+
+        ConsoleWriterImpl {
+            stderr: self.split_streams && level <= Level::WARN,
+            hint: if self.level_hint {
+                Some(level_hint(level))
+            } else {
+                None
+            },
+            level: Some(level),
+            max_line: self.max_line,
+            json: self.json,
+            timestamp: self.timestamp,
+            test_writer: self.test_writer,
+            queue: self.queue.as_ref(),
+            #[cfg(test)]
+            capture: self.capture.as_ref(),
+        }
+    }
+}
+
+fn level_hint(level: Level) -> &'static [u8] {
+    match level {
+        Level::TRACE => b"T|",
+        Level::DEBUG => b"D|",
+        Level::INFO => b"I|",
+        Level::WARN => b"W|",
+        Level::ERROR => b"E|",
+    }
+}
+
+pub(crate) struct ConsoleWriterImpl<'a> {
+    stderr: bool,
+    hint: Option<&'static [u8]>,
+    level: Option<Level>,
+    max_line: usize,
+    json: bool,
+    timestamp: (&'static str, TimestampFormat),
+    test_writer: bool,
+    queue: Option<&'a Queue>,
+    #[cfg(test)]
+    capture: Option<&'a crate::test_support::Capture>,
+}
+
+fn write_line(writer: &mut dyn Write, line: &[u8]) -> io::Result<()> {
+    // Whatever was printed before goes out first, so the line is a write of
+    // its own
+    writer.flush()?;
+    writer.write_all(line)?;
+    writer.flush()
+}
+
+impl Write for ConsoleWriterImpl<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let record = if buf.len() > self.max_line {
+            Cow::Owned(stub(buf, self.level, self.json, self.timestamp))
+        } else {
+            Cow::Borrowed(buf)
+        };
+        let line = match self.hint {
+            Some(hint) => Cow::Owned([hint, &record].concat()),
+            None => record,
+        };
+        if line.len() > PIPE_BUF {
+            OVERSIZED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        #[cfg(test)]
+        if let Some(capture) = self.capture {
+            capture.push(self.stderr, &line);
+            return Ok(buf.len());
+        }
+
+        if let Some(queue) = self.queue {
+            queue.queue(
+                self.stderr,
+                self.level == Some(Level::ERROR),
+                line.into_owned(),
+            );
+        } else if self.test_writer {
+            let line = String::from_utf8_lossy(&line);
+            if self.stderr {
+                eprint!("{}", line);
+            } else {
+                print!("{}", line);
+            }
+        } else if self.stderr {
+            write_line(&mut io::stderr().lock(), &line)?;
+        } else {
+            write_line(&mut io::stdout().lock(), &line)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // The thread may be blocked on the stream, holding its lock
+        if self.queue.is_some() {
+            Ok(())
+        } else if self.stderr {
+            io::stderr().flush()
+        } else {
+            io::stdout().flush()
+        }
+    }
+}
+
+/// Record standing in for `record`, which is too large for the console:
+///
+/// - `{"@timestamp":…,"level":…,"facility":…,"message":"<record too large
+///   for console>","original_bytes":N,"callsite":"file:line"}` for JSON
+///   records, with the timestamp, level, facility and callsite (`file:line`,
+///   or the target) of the record itself. The timestamp key is the one of
+///   `timestamp`, whose format stamps the stub with the current time when
+///   the record has no timestamp;
+/// - `<timestamp> <LEVEL> <record too large for console> original_bytes=N`
+///   for text records, timestamped when it's written.
+fn stub(
+    record: &[u8],
+    level: Option<Level>,
+    json: bool,
+    timestamp: (&'static str, TimestampFormat),
+) -> Vec<u8> {
+    let level = level.map(|level| level.as_str()).unwrap_or("INFO");
+    if !json {
+        return format!(
+            "{} {:>5} {} original_bytes={}\n",
+            vinted_timestamp::rfc3339(SystemTime::now()),
+            level,
+            TOO_LARGE,
+            record.len()
+        )
+        .into_bytes();
+    }
+
+    let entries = match serde_json::from_slice(record) {
+        Ok(Entries(entries)) => entries,
+        Err(_) => Vec::new(),
+    };
+    let get = |key: &str| {
+        entries
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value)
+    };
+    let (timestamp_key, timestamp_format) = timestamp;
+    let timestamp = match get(timestamp_key) {
+        Some(timestamp @ (Value::String(_) | Value::Number(_))) => timestamp.clone(),
+        _ => timestamp_format.format(SystemTime::now()),
+    };
+    let callsite = match (get("file"), get("line")) {
+        (Some(Value::String(file)), Some(line)) => {
+            Some(Value::String(format!("{}:{}", file, line)))
+        }
+        _ => get("target").cloned(),
+    };
+
+    let mut stub = vec![
+        (timestamp_key.to_owned(), timestamp),
+        (
+            "level".to_owned(),
+            get("level")
+                .cloned()
+                .unwrap_or_else(|| Value::String(level.to_owned())),
+        ),
+    ];
+    if let Some(facility) = get("facility") {
+        stub.push(("facility".to_owned(), facility.clone()));
+    }
+    stub.push(("message".to_owned(), Value::String(TOO_LARGE.to_owned())));
+    stub.push(("original_bytes".to_owned(), Value::from(record.len())));
+    if let Some(callsite) = callsite {
+        stub.push(("callsite".to_owned(), callsite));
+    }
+
+    let mut stub = serde_json::to_vec(&Entries(stub)).unwrap_or_default();
+    stub.push(b'\n');
+    stub
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{parse, with_logger, Capture},
+        Target, TimestampFormat,
+    };
+    use tracing::{debug, error, info, trace, warn};
+
+    fn log_every_level() {
+        trace!("t");
+        debug!("d");
+        info!("i");
+        warn!("w");
+        error!("e");
+    }
+
+    #[test]
+    fn level_hint_prefixes_json_lines() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .default_level(tracing::Level::TRACE)
+            .level_hint()
+            .capture(&capture);
+        with_logger(builder, log_every_level);
+
+        let lines = capture.lines();
+        let hints: Vec<_> = lines.iter().map(|line| &line[..2]).collect();
+        assert_eq!(hints, ["T|", "D|", "I|", "W|", "E|"]);
+        for line in &lines {
+            let record = parse(&line[2..]);
+            assert_eq!(record["message"], line[..1].to_lowercase());
+        }
+        assert!(capture.stderr().is_empty());
+    }
+
+    #[test]
+    fn lines_are_pure_json_by_default() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, log_every_level);
+
+        let messages: Vec<_> = capture
+            .records()
+            .iter()
+            .map(|r| r["message"].clone())
+            .collect();
+        assert_eq!(messages, ["i", "w", "e"]);
+        assert_eq!(capture.stdout().len(), 3);
+    }
+
+    #[test]
+    fn split_streams_sends_warn_and_error_to_stderr() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .default_level(tracing::Level::TRACE)
+            .split_streams()
+            .capture(&capture);
+        with_logger(builder, log_every_level);
+
+        let messages = |lines: Vec<String>| -> Vec<_> {
+            lines
+                .iter()
+                .map(|line| parse(line)["message"].clone())
+                .collect()
+        };
+        assert_eq!(messages(capture.stdout()), ["t", "d", "i"]);
+        assert_eq!(messages(capture.stderr()), ["w", "e"]);
+    }
+
+    #[test]
+    fn split_streams_applies_to_the_text_console() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console)
+            .split_streams()
+            .capture(&capture);
+        with_logger(builder, log_every_level);
+
+        assert_eq!(capture.stdout().len(), 1);
+        assert!(
+            capture.stdout()[0].ends_with(" i\n"),
+            "{:?}",
+            capture.stdout()
+        );
+        let stderr = capture.stderr();
+        assert_eq!(stderr.len(), 2);
+        assert!(stderr[0].contains("WARN") && stderr[1].contains("ERROR"));
+    }
+
+    #[test]
+    fn lines_past_pipe_buf_are_counted() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        let before = crate::logger_stats().oversized_console_lines;
+
+        with_logger(builder, || {
+            info!(pad = "x".repeat(super::PIPE_BUF).as_str(), "large");
+            info!("small");
+        });
+
+        let lines = capture.lines();
+        assert!(lines[0].len() > super::PIPE_BUF && lines[1].len() < super::PIPE_BUF);
+        // Other tests may log large lines meanwhile
+        assert!(crate::logger_stats().oversized_console_lines > before);
+    }
+
+    #[test]
+    fn oversized_records_are_stubbed_on_the_console_only() {
+        use std::{net::UdpSocket, time::Duration};
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(collector.local_addr().unwrap())
+            .mirror_to_console(tracing::Level::INFO)
+            .console_max_line(1_024)
+            .capture(&capture);
+
+        let pad = "x".repeat(4_000);
+        let line = with_logger(builder, || {
+            let line = line!() + 1;
+            warn!(pad = pad.as_str(), "huge");
+            info!("small");
+            crate::flush(Duration::from_secs(5));
+            line
+        });
+
+        let console = capture.records();
+        assert_eq!(console.len(), 2);
+        let stub = &console[0];
+        assert_eq!(stub["message"], super::TOO_LARGE);
+        assert_eq!(stub["level"], "WARN");
+        assert_eq!(stub["facility"], "svc-test");
+        assert!(stub["@timestamp"].is_string());
+        assert!(stub["original_bytes"].as_u64().unwrap() > 4_000);
+        assert_eq!(
+            stub["callsite"],
+            format!("src/vinted_console_writer.rs:{}", line)
+        );
+        assert_eq!(console[1]["message"], "small");
+
+        let mut datagram = vec![0; 65_536];
+        let len = collector.recv(&mut datagram).expect("record is received");
+        let record: serde_json::Value = serde_json::from_slice(&datagram[..len]).unwrap();
+        assert_eq!(record["message"], "huge");
+        assert_eq!(record["pad"], pad.as_str());
+        assert_eq!(len as u64, stub["original_bytes"].as_u64().unwrap());
+    }
+
+    #[test]
+    fn oversized_text_lines_are_stubbed() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console)
+            .console_max_line(256)
+            .capture(&capture);
+
+        with_logger(builder, || error!(pad = "x".repeat(1_000).as_str(), "huge"));
+
+        let line = &capture.lines()[0];
+        assert!(
+            line.contains("ERROR <record too large for console> original_bytes="),
+            "{}",
+            line
+        );
+        assert!(line.len() < 256);
+    }
+
+    #[test]
+    fn stubs_keep_the_timestamp_key_and_format() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .timestamp_key("time")
+            .timestamp_format(TimestampFormat::EpochMillis)
+            .console_max_line(512)
+            .capture(&capture);
+
+        with_logger(builder, || info!(pad = "x".repeat(1_000).as_str(), "huge"));
+
+        let stub = &capture.records()[0];
+        assert_eq!(stub["message"], super::TOO_LARGE);
+        assert!(stub["time"].is_u64(), "{}", stub);
+        assert!(stub.get("@timestamp").is_none(), "{}", stub);
+
+        // Records without a timestamp are stamped in the configured format
+        let record = br#"{"level":"WARN","message":"huge"}"#;
+        let stub = super::stub(record, None, true, ("ts", TimestampFormat::Rfc3339Millis));
+        let stub = parse(std::str::from_utf8(&stub).unwrap());
+        let ts = stub["ts"].as_str().unwrap();
+        assert!(
+            ts.ends_with('Z') && ts.len() == "2021-04-20T12:42:57.353Z".len(),
+            "{}",
+            ts
+        );
+        assert_eq!(stub["level"], "WARN");
+    }
+}
@@ -0,0 +1,142 @@
+use parking_lot::{const_mutex, Condvar, Mutex};
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+static DELIVERY: Mutex<Delivery> = const_mutex(Delivery {
+    state: State::Pending,
+    wakers: Vec::new(),
+});
+static DELIVERED: Condvar = Condvar::new();
+
+struct Delivery {
+    state: State,
+    wakers: Vec<Waker>,
+}
+
+enum State {
+    Pending,
+    Delivered,
+    Failed(String),
+}
+
+/// Why no record has been delivered
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FirstDeliveryError {
+    /// Nothing was delivered within the timeout
+    Timeout,
+    /// Records can never be delivered, e.g. the socket couldn't be bound
+    Failed(String),
+}
+
+impl fmt::Display for FirstDeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("no log record was delivered in time"),
+            Self::Failed(reason) => write!(f, "log records can't be delivered: {}", reason),
+        }
+    }
+}
+
+impl Error for FirstDeliveryError {}
+
+/// Resolves once the logger has delivered its first record, e.g. to hold back
+/// a readiness probe until a misconfigured destination would have shown up.
+///
+/// `UdpJson` resolves after the first datagram is sent, `TcpJson` after the
+/// first record is written to the connection, console targets as soon as
+/// they are installed. Fails right away when records can never be
+/// delivered. Wrap it in a timeout of your runtime, or use
+/// [`wait_first_delivery`] to block.
+///
+/// ```
+/// # async fn ready() -> Result<(), vinted_logger::FirstDeliveryError> {
+/// vinted_logger::first_delivery().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn first_delivery() -> FirstDelivery {
+    FirstDelivery(())
+}
+
+/// Blocks until the logger has delivered its first record, see [`first_delivery`]
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// vinted_logger::wait_first_delivery(Duration::from_secs(5))?;
+/// # Ok::<(), vinted_logger::FirstDeliveryError>(())
+/// ```
+pub fn wait_first_delivery(timeout: Duration) -> Result<(), FirstDeliveryError> {
+    let deadline = Instant::now() + timeout;
+    let mut delivery = DELIVERY.lock();
+    loop {
+        match delivery.state {
+            State::Delivered => return Ok(()),
+            State::Failed(ref reason) => return Err(FirstDeliveryError::Failed(reason.clone())),
+            State::Pending => {
+                if DELIVERED.wait_until(&mut delivery, deadline).timed_out() {
+                    return Err(FirstDeliveryError::Timeout);
+                }
+            }
+        }
+    }
+}
+
+/// Future returned by [`first_delivery`]
+#[derive(Debug)]
+pub struct FirstDelivery(());
+
+impl Future for FirstDelivery {
+    type Output = Result<(), FirstDeliveryError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut delivery = DELIVERY.lock();
+        match delivery.state {
+            State::Delivered => Poll::Ready(Ok(())),
+            State::Failed(ref reason) => {
+                Poll::Ready(Err(FirstDeliveryError::Failed(reason.clone())))
+            }
+            State::Pending => {
+                if !delivery
+                    .wakers
+                    .iter()
+                    .any(|waker| waker.will_wake(cx.waker()))
+                {
+                    delivery.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub(crate) fn delivered() {
+    settle(State::Delivered);
+}
+
+pub(crate) fn failed(reason: String) {
+    settle(State::Failed(reason));
+}
+
+/// The first outcome sticks, later ones are ignored.
+fn settle(state: State) {
+    let wakers = {
+        let mut delivery = DELIVERY.lock();
+        if !matches!(delivery.state, State::Pending) {
+            return;
+        }
+        delivery.state = state;
+        std::mem::take(&mut delivery.wakers)
+    };
+
+    DELIVERED.notify_all();
+    for waker in wakers {
+        waker.wake();
+    }
+}
@@ -0,0 +1,31 @@
+use std::env;
+
+/// Environment variable holding the deployment slot, e.g. `blue` or `green`
+pub(crate) const SLOT_ENV: &str = "DEPLOY_SLOT";
+
+/// Environment variable holding the deployment identifier
+pub(crate) const ID_ENV: &str = "DEPLOY_ID";
+
+/// Deployment recorded as `deploy_slot` and `deploy_id`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Deployment {
+    pub(crate) slot: Option<String>,
+    pub(crate) id: Option<String>,
+}
+
+impl Deployment {
+    /// Explicit values, or else [`SLOT_ENV`] and [`ID_ENV`]. Empty values are
+    /// left out.
+    pub(crate) fn resolve(slot: Option<String>, id: Option<String>) -> Self {
+        let value = |explicit: Option<String>, name: &str| {
+            explicit
+                .or_else(|| env::var(name).ok())
+                .filter(|value| !value.is_empty())
+        };
+
+        Self {
+            slot: value(slot, SLOT_ENV),
+            id: value(id, ID_ENV),
+        }
+    }
+}
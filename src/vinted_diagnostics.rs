@@ -0,0 +1,300 @@
+use crate::vinted_stats;
+use parking_lot::{const_mutex, Mutex};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Default of [`LoggerBuilder::diagnostics_per_minute`](crate::LoggerBuilder::diagnostics_per_minute)
+pub const DEFAULT_DIAGNOSTICS_PER_MINUTE: u32 = 10;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+static GLOBAL_PER_MINUTE: AtomicU32 = AtomicU32::new(DEFAULT_DIAGNOSTICS_PER_MINUTE);
+/// Kinds of [`Diagnostic`]
+const KINDS: usize = 6;
+
+static COUNTS: [AtomicU64; KINDS] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static SUPPRESSED: AtomicU64 = AtomicU64::new(0);
+static BUCKETS: Mutex<Option<Buckets>> = const_mutex(None);
+
+/// Kinds of problems the logger reports about itself on stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Diagnostic {
+    SendFailed,
+    BindFailed,
+    ChannelClosed,
+    Dropped,
+    /// Not a problem, but reported whatever the filter says
+    ConfigChanged,
+    /// Settings the logger was installed with anyway, or an init which
+    /// installed nothing
+    ConfigWarning,
+}
+
+impl Diagnostic {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Lines per minute a single kind may take out of the global budget
+    fn per_minute(self) -> f64 {
+        match self {
+            Self::SendFailed => 5.0,
+            Self::BindFailed | Self::ChannelClosed | Self::Dropped => 2.0,
+            Self::ConfigChanged | Self::ConfigWarning => 5.0,
+        }
+    }
+}
+
+/// How often the logger ran into problems with itself, whether or not they
+/// were reported on stderr
+#[deprecated(note = "the same counters are in `LoggerStats`, see `logger_stats`")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InternalDiagnostics {
+    /// Records which couldn't be sent, see [`LoggerStats::send_errors`](crate::LoggerStats::send_errors)
+    pub send_failures: u64,
+    /// Sockets which couldn't be bound
+    pub bind_failures: u64,
+    /// Records which couldn't be queued because their writer thread stopped
+    pub channel_closures: u64,
+    /// Records dropped because the `UdpJson` queue, the `TcpJson` buffer or
+    /// the queue of a non-blocking console was full
+    pub dropped: u64,
+    /// Reports left out of stderr to stay within the budget
+    pub suppressed: u64,
+    /// Console lines longer than `PIPE_BUF` (4096 bytes), which other
+    /// processes writing to the same pipe may interleave with
+    pub oversized_console_lines: u64,
+}
+
+/// Counts of the logger's own problems since the process started, taken
+/// from [`logger_stats`](crate::logger_stats)
+#[deprecated(note = "use `logger_stats`, which has the same counters")]
+#[allow(deprecated)]
+pub fn internal_diagnostics() -> InternalDiagnostics {
+    let stats = vinted_stats::logger_stats();
+
+    InternalDiagnostics {
+        send_failures: stats.send_errors,
+        bind_failures: stats.bind_failures,
+        channel_closures: stats.channel_closures,
+        dropped: stats.dropped,
+        suppressed: stats.diagnostics_suppressed,
+        oversized_console_lines: stats.oversized_console_lines,
+    }
+}
+
+/// Reports of `diagnostic` since the process started
+pub(crate) fn count(diagnostic: Diagnostic) -> u64 {
+    COUNTS[diagnostic.index()].load(Ordering::Relaxed)
+}
+
+/// Reports left out of stderr since the process started
+pub(crate) fn suppressed() -> u64 {
+    SUPPRESSED.load(Ordering::Relaxed)
+}
+
+/// Sets the budget, starting over with a full one if reports were written
+/// already
+pub(crate) fn set_per_minute(per_minute: u32) {
+    GLOBAL_PER_MINUTE.store(per_minute, Ordering::Relaxed);
+    *BUCKETS.lock() = None;
+}
+
+/// Counts the problem and writes it to stderr unless its kind, or all kinds
+/// together, are over budget. The next line of a kind mentions how many of
+/// its reports were left out.
+pub(crate) fn report(diagnostic: Diagnostic, message: fmt::Arguments<'_>) {
+    COUNTS[diagnostic.index()].fetch_add(1, Ordering::Relaxed);
+
+    let suppressed = {
+        let mut buckets = BUCKETS.lock();
+        let buckets = buckets.get_or_insert_with(|| {
+            Buckets::new(f64::from(GLOBAL_PER_MINUTE.load(Ordering::Relaxed)))
+        });
+        match buckets.take(diagnostic, Instant::now()) {
+            Some(suppressed) => suppressed,
+            None => {
+                SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+    };
+
+    if suppressed == 0 {
+        eprintln!("{}", message);
+    } else {
+        eprintln!("{} ({} similar reports suppressed)", message, suppressed);
+    }
+}
+
+struct Buckets {
+    global: Bucket,
+    by_kind: [Bucket; KINDS],
+    suppressed: [u64; KINDS],
+}
+
+impl Buckets {
+    fn new(global: f64) -> Self {
+        let bucket = |diagnostic: Diagnostic| Bucket::new(diagnostic.per_minute().min(global));
+
+        Self {
+            global: Bucket::new(global),
+            by_kind: [
+                bucket(Diagnostic::SendFailed),
+                bucket(Diagnostic::BindFailed),
+                bucket(Diagnostic::ChannelClosed),
+                bucket(Diagnostic::Dropped),
+                bucket(Diagnostic::ConfigChanged),
+                bucket(Diagnostic::ConfigWarning),
+            ],
+            suppressed: [0; KINDS],
+        }
+    }
+
+    /// Returns how many reports of the kind were suppressed since the last
+    /// one written, or `None` if this one has to be suppressed too.
+    fn take(&mut self, diagnostic: Diagnostic, now: Instant) -> Option<u64> {
+        let index = diagnostic.index();
+
+        if !self.by_kind[index].has_token(now) || !self.global.has_token(now) {
+            self.suppressed[index] += 1;
+            return None;
+        }
+        self.by_kind[index].tokens -= 1.0;
+        self.global.tokens -= 1.0;
+
+        Some(std::mem::take(&mut self.suppressed[index]))
+    }
+}
+
+/// Token bucket holding up to a minute worth of tokens
+struct Bucket {
+    per_minute: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: f64) -> Self {
+        Self {
+            per_minute,
+            tokens: per_minute,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn has_token(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64() / MINUTE.as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_minute).min(self.per_minute);
+        self.refilled = now;
+        self.tokens >= 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count, report, suppressed, Buckets, Diagnostic, MINUTE};
+    use parking_lot::Mutex;
+    use std::{sync::Arc, thread, time::Instant};
+
+    const FAILING: [Diagnostic; 4] = [
+        Diagnostic::SendFailed,
+        Diagnostic::BindFailed,
+        Diagnostic::ChannelClosed,
+        Diagnostic::Dropped,
+    ];
+
+    #[test]
+    fn kinds_together_stay_within_the_global_budget() {
+        let buckets = Arc::new(Mutex::new(Buckets::new(10.0)));
+        let start = Instant::now();
+
+        // Every failing subsystem at once, well over its own rate
+        let threads: Vec<_> = FAILING
+            .iter()
+            .map(|&diagnostic| {
+                let buckets = Arc::clone(&buckets);
+                thread::spawn(move || {
+                    (0..500)
+                        .filter(|_| buckets.lock().take(diagnostic, start).is_some())
+                        .count()
+                })
+            })
+            .collect();
+        let written: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        assert!(written.iter().sum::<usize>() <= 10, "{:?}", written);
+        let buckets = buckets.lock();
+        for (&diagnostic, &written) in FAILING.iter().zip(&written) {
+            assert!(
+                written as f64 <= diagnostic.per_minute(),
+                "{:?}",
+                diagnostic
+            );
+            // Every report is either written or counted as suppressed
+            assert_eq!(
+                written as u64 + buckets.suppressed[diagnostic.index()],
+                500,
+                "{:?}",
+                diagnostic
+            );
+        }
+    }
+
+    #[test]
+    fn budget_refills_over_a_minute() {
+        let mut buckets = Buckets::new(3.0);
+        let start = Instant::now();
+        let written = |buckets: &mut Buckets, now| {
+            (0..20)
+                .filter(|_| buckets.take(Diagnostic::SendFailed, now).is_some())
+                .count()
+        };
+
+        assert_eq!(written(&mut buckets, start), 3);
+        assert_eq!(written(&mut buckets, start + MINUTE / 3), 1);
+        // The first line after a pause tells how many were left out
+        assert_eq!(
+            buckets.take(Diagnostic::SendFailed, start + MINUTE * 2),
+            Some(19)
+        );
+        assert_eq!(written(&mut buckets, start + MINUTE * 2), 2);
+    }
+
+    #[test]
+    fn counters_are_exact_when_lines_are_suppressed() {
+        let counts = || FAILING.map(count);
+        let (before, suppressed_before) = (counts(), suppressed());
+        let threads: Vec<_> = FAILING
+            .iter()
+            .map(|&diagnostic| {
+                thread::spawn(move || {
+                    for n in 0..200 {
+                        report(diagnostic, format_args!("test diagnostic {}", n));
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        let after = counts();
+
+        // Other tests may report at the same time, never fewer
+        for (before, after) in before.iter().zip(&after) {
+            assert!(after - before >= 200, "{:?} {:?}", before, after);
+        }
+        // At most a minute worth of the budget was left to write lines with
+        let suppressed = suppressed() - suppressed_before;
+        assert!(suppressed >= 800 - 10, "{}", suppressed);
+    }
+}
@@ -0,0 +1,188 @@
+//! Key table containers of [`WireFormat::JsonDict`](crate::WireFormat::JsonDict)
+//! batches, see [`validate`](crate::validate) for the format
+
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::value::RawValue;
+use std::{collections::HashMap, fmt, io::Write};
+
+/// Version of the container written
+pub(crate) const VERSION: u64 = 1;
+
+/// Key of the header holding the version
+pub(crate) const HEADER_KEY: &str = "vinted_dict";
+
+/// Top level entries of a JSON object with their values as written
+struct RawEntries<'a>(Vec<(String, &'a RawValue)>);
+
+impl<'de: 'a, 'a> Deserialize<'de> for RawEntries<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RawEntriesVisitor<'a>(std::marker::PhantomData<&'a ()>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for RawEntriesVisitor<'a> {
+            type Value = RawEntries<'a>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RawEntries<'a>, A::Error> {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(32));
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(RawEntries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(RawEntriesVisitor(std::marker::PhantomData))
+    }
+}
+
+/// The newline delimited records of `batch` in a container, if it's
+/// smaller than the batch itself
+///
+/// Values are copied as written, so decoding gives the records back byte
+/// for byte.
+pub(crate) fn encode(batch: &[u8]) -> Option<Vec<u8>> {
+    let mut keys: Vec<String> = Vec::new();
+    let mut indexes: HashMap<String, usize> = HashMap::new();
+    let mut records = Vec::with_capacity(batch.len());
+
+    for record in batch.split(|&byte| byte == b'\n') {
+        if record.is_empty() {
+            continue;
+        }
+        let RawEntries(entries) = serde_json::from_slice(record).ok()?;
+        records.push(b'[');
+        for (n, (key, value)) in entries.into_iter().enumerate() {
+            if n > 0 {
+                records.push(b',');
+            }
+            let index = match indexes.get(&key) {
+                Some(&index) => index,
+                None => {
+                    keys.push(key.clone());
+                    indexes.insert(key, keys.len() - 1);
+                    keys.len() - 1
+                }
+            };
+            write!(records, "{},", index).ok()?;
+            records.extend_from_slice(value.get().as_bytes());
+        }
+        records.extend_from_slice(b"]\n");
+    }
+
+    let mut container = format!("{{\"{}\":{},\"keys\":", HEADER_KEY, VERSION).into_bytes();
+    serde_json::to_writer(&mut container, &keys).ok()?;
+    container.extend_from_slice(b"}\n");
+    container.extend_from_slice(&records);
+    if container.len() < batch.len() {
+        Some(container)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode;
+    use crate::{
+        test_support::{check_record, log_random_event, with_logger, Capture, Rng},
+        validate::decode_batch,
+        InitError, Target, WireFormat,
+    };
+    use tracing::Level;
+
+    fn batch(lines: &[String]) -> Vec<u8> {
+        lines.concat().into_bytes()
+    }
+
+    #[test]
+    fn batches_decode_to_the_records_byte_for_byte() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .default_level(Level::TRACE)
+            .capture(&capture);
+        let mut rng = Rng::new(726);
+        with_logger(builder, || {
+            for n in 0..200 {
+                tracing::info!(user_id = n, path = "/items", "listed");
+                log_random_event(&mut rng);
+            }
+        });
+        let lines = capture.lines();
+
+        for records in lines.chunks(7) {
+            let batch = batch(records);
+            let encoded = encode(&batch).unwrap_or_else(|| batch.clone());
+            assert_eq!(decode_batch(&encoded).unwrap(), records);
+        }
+    }
+
+    #[test]
+    fn batches_of_similar_records_shrink() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .additional_field("datacenter", "eu-1")
+            .capture(&capture);
+        with_logger(builder, || {
+            let _span = tracing::info_span!("request", request_id = "6f1c").entered();
+            for n in 0..20 {
+                tracing::info!(user_id = n, status = 200, duration_ms = 12.5, "served");
+            }
+        });
+        let batch = batch(&capture.lines());
+        let encoded = encode(&batch).expect("batch shrinks");
+        // Keys are a good part of the bytes of short records, about a quarter
+        assert!(
+            encoded.len() * 10 < batch.len() * 8,
+            "{} of {} bytes",
+            encoded.len(),
+            batch.len()
+        );
+        assert_eq!(decode_batch(&encoded).unwrap(), capture.lines());
+    }
+
+    #[test]
+    fn udp_batches_are_sent_in_containers() {
+        use std::{net::UdpSocket, time::Duration};
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(socket.local_addr().unwrap())
+            .udp_batching_with(4_000, Duration::from_millis(200))
+            .wire_format(WireFormat::JsonDict);
+        with_logger(builder, || {
+            for n in 0..5 {
+                tracing::info!(user_id = n, "listed");
+            }
+            crate::flush(Duration::from_secs(5));
+        });
+
+        let mut datagram = vec![0; 65_536];
+        let len = socket.recv(&mut datagram).unwrap();
+        assert!(datagram.starts_with(b"{\"vinted_dict\":1,\"keys\":[\"@timestamp\","));
+        let records = decode_batch(&datagram[..len]).unwrap();
+        assert_eq!(records.len(), 5);
+        for (n, record) in records.iter().enumerate() {
+            let record = check_record(record.as_bytes()).unwrap();
+            assert_eq!(record["user_id"], n);
+            assert_eq!(record["message"], "listed");
+        }
+
+        let result = crate::builder("svc-test", Target::UdpJson)
+            .wire_format(WireFormat::JsonDict)
+            .build();
+        assert!(matches!(result, Err(InitError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn batches_which_dont_shrink_are_left_alone() {
+        let record = "{\"a\":1}\n".to_owned();
+        assert_eq!(encode(record.as_bytes()), None);
+        assert_eq!(decode_batch(record.as_bytes()).unwrap(), [record]);
+        assert_eq!(encode(b"not json\n"), None);
+    }
+}
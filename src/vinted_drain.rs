@@ -0,0 +1,68 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Default of [`LoggerBuilder::drain_batch_interval`](crate::LoggerBuilder::drain_batch_interval)
+pub const DEFAULT_DRAIN_BATCH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Default of [`LoggerBuilder::drain_error_timeout`](crate::LoggerBuilder::drain_error_timeout)
+pub const DEFAULT_DRAIN_ERROR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Target of the record logged when draining starts
+pub const DRAIN_TARGET: &str = "vinted_logger::drain";
+
+/// Milliseconds since the epoch draining started at, 0 until it does
+static DRAINING_SINCE: AtomicU64 = AtomicU64::new(0);
+static BATCH_INTERVAL_MS: AtomicU64 =
+    AtomicU64::new(DEFAULT_DRAIN_BATCH_INTERVAL.as_millis() as u64);
+
+/// Drain settings of a logger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DrainConfig {
+    pub(crate) batch_interval: Duration,
+    pub(crate) error_timeout: Duration,
+}
+
+impl Default for DrainConfig {
+    fn default() -> Self {
+        Self {
+            batch_interval: DEFAULT_DRAIN_BATCH_INTERVAL,
+            error_timeout: DEFAULT_DRAIN_ERROR_TIMEOUT,
+        }
+    }
+}
+
+pub(crate) fn configure(config: DrainConfig) {
+    BATCH_INTERVAL_MS.store(config.batch_interval.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Switches every writer to draining, for good. Returns `false` if it was
+/// draining already.
+pub(crate) fn start() -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.as_millis() as u64);
+    DRAINING_SINCE
+        .compare_exchange(0, now.max(1), Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+}
+
+/// When draining started, see [`LoggerGuard::drain`](crate::LoggerGuard::drain)
+pub(crate) fn draining_since() -> Option<SystemTime> {
+    match DRAINING_SINCE.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+    }
+}
+
+/// How long the first record of a batch waits for others, `interval` or
+/// the shorter drain one while draining
+pub(crate) fn batch_interval(interval: Duration) -> Duration {
+    if DRAINING_SINCE.load(Ordering::Relaxed) == 0 {
+        return interval;
+    }
+    interval.min(Duration::from_millis(
+        BATCH_INTERVAL_MS.load(Ordering::Relaxed),
+    ))
+}
@@ -0,0 +1,257 @@
+use crate::vinted_json_formatter::RECORD_KEYS;
+use parking_lot::Mutex;
+use std::{fmt::Write as _, io, time::Instant};
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Default of [`LoggerBuilder::stderr_echo_per_second`](crate::LoggerBuilder::stderr_echo_per_second)
+pub const DEFAULT_ECHO_PER_SECOND: u32 = 10;
+
+/// Wraps a `MakeWriter` of JSON records and echoes WARN and ERROR ones to
+/// stderr as compact single lines, e.g. for `kubectl logs` during incidents
+/// when records otherwise only go over UDP.
+pub(crate) struct VintedEchoWriter<W> {
+    inner: W,
+    level: Option<Level>,
+    budget: Mutex<Budget>,
+    #[cfg(test)]
+    capture: Option<crate::test_support::Capture>,
+}
+
+impl<W> VintedEchoWriter<W> {
+    /// Echoes records of `level` and above, at most `per_second` lines a
+    /// second. `level` of `None` echoes nothing, levels below WARN count as
+    /// WARN.
+    pub(crate) fn new(inner: W, level: Option<Level>, per_second: u32) -> Self {
+        Self {
+            inner,
+            level: level.map(|level| level.min(Level::WARN)),
+            budget: Mutex::new(Budget {
+                per_second: f64::from(per_second),
+                tokens: f64::from(per_second),
+                refilled: Instant::now(),
+                suppressed: 0,
+            }),
+            #[cfg(test)]
+            capture: None,
+        }
+    }
+
+    /// Echoes lines to `capture` instead, as stderr ones, if any
+    #[cfg(test)]
+    pub(crate) fn capture(self, capture: Option<crate::test_support::Capture>) -> Self {
+        Self { capture, ..self }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for VintedEchoWriter<W> {
+    type Writer = EchoWriterImpl<'a, W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        EchoWriterImpl {
+            inner: self.inner.make_writer(),
+            budget: None,
+            #[cfg(test)]
+            capture: None,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        EchoWriterImpl {
+            inner: self.inner.make_writer_for(meta),
+            budget: match self.level {
+                Some(level) if *meta.level() <= level => Some(&self.budget),
+                _ => None,
+            },
+            #[cfg(test)]
+            capture: self.capture.as_ref(),
+        }
+    }
+}
+
+pub(crate) struct EchoWriterImpl<'a, W> {
+    inner: W,
+    budget: Option<&'a Mutex<Budget>>,
+    #[cfg(test)]
+    capture: Option<&'a crate::test_support::Capture>,
+}
+
+impl<'a, W: io::Write> io::Write for EchoWriterImpl<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(budget) = self.budget {
+            let suppressed = budget.lock().take();
+            if let Some(suppressed) = suppressed {
+                if let Some(mut line) = compact(&buf[..written]) {
+                    if suppressed > 0 {
+                        let _ = write!(line, " ({} lines suppressed)", suppressed);
+                    }
+                    line.push('\n');
+                    #[cfg(test)]
+                    if let Some(capture) = self.capture {
+                        capture.push(true, line.as_bytes());
+                        return Ok(written);
+                    }
+                    let _ = io::stderr().lock().write_all(line.as_bytes());
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `@timestamp LEVEL target: message key=value ...` out of a serialized
+/// record, with the event fields only
+fn compact(record: &[u8]) -> Option<String> {
+    let record: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(record).ok()?;
+    let text = |key: &str| {
+        record
+            .get(key)
+            .and_then(|value| value.as_str())
+            .unwrap_or("")
+    };
+
+    let mut line = format!(
+        "{} {} {}: {}",
+        text("@timestamp"),
+        text("level"),
+        text("target"),
+        text("message")
+    );
+    for (key, value) in &record {
+        if RECORD_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let _ = match value {
+            serde_json::Value::String(value) => write!(line, " {}={:?}", key, value),
+            value => write!(line, " {}={}", key, value),
+        };
+    }
+
+    Some(line)
+}
+
+/// Token bucket holding up to a second worth of lines
+struct Budget {
+    per_second: f64,
+    tokens: f64,
+    refilled: Instant,
+    suppressed: u64,
+}
+
+impl Budget {
+    /// Returns how many lines were suppressed since the last one echoed, or
+    /// `None` if this one has to be suppressed too.
+    fn take(&mut self) -> Option<u64> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second);
+        self.refilled = now;
+
+        if self.tokens < 1.0 {
+            self.suppressed += 1;
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        LoggerBuilder, Target,
+    };
+    use std::{net::UdpSocket, thread, time::Duration};
+    use tracing::Level;
+
+    /// Lines echoed to stderr while `log` runs
+    fn echoed(
+        configure: impl FnOnce(LoggerBuilder) -> LoggerBuilder,
+        log: impl FnOnce(),
+    ) -> Vec<String> {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(collector.local_addr().unwrap())
+            .capture(&capture);
+        with_logger(configure(builder), log);
+        capture.stderr()
+    }
+
+    #[test]
+    fn records_from_the_level_up_are_echoed() {
+        let lines = echoed(
+            |builder| builder.stderr_echo(Level::ERROR),
+            || {
+                tracing::warn!("warned");
+                tracing::error!(user_id = 7, path = "/items", "failed");
+            },
+        );
+
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        let line = &lines[0];
+        assert!(
+            line.ends_with(" ERROR vinted_logger::vinted_echo_writer::tests: failed path=\"/items\" user_id=7\n"),
+            "{:?}",
+            line
+        );
+    }
+
+    #[test]
+    fn info_is_never_echoed() {
+        let lines = echoed(
+            |builder| {
+                builder
+                    .default_level(Level::TRACE)
+                    .stderr_echo(Level::TRACE)
+            },
+            || {
+                tracing::trace!("traced");
+                tracing::debug!("debugged");
+                tracing::info!("informed");
+                tracing::warn!("warned");
+            },
+        );
+
+        assert_eq!(lines.len(), 1, "{:?}", lines);
+        assert!(lines[0].contains(" WARN "), "{:?}", lines);
+    }
+
+    #[test]
+    fn nothing_is_echoed_unless_enabled() {
+        let lines = echoed(|builder| builder, || tracing::error!("failed"));
+
+        assert!(lines.is_empty(), "{:?}", lines);
+    }
+
+    #[test]
+    fn lines_beyond_the_rate_are_suppressed_and_counted() {
+        let lines = echoed(
+            |builder| builder.stderr_echo(Level::WARN).stderr_echo_per_second(3),
+            || {
+                for n in 0..10 {
+                    tracing::warn!(n, "storm");
+                }
+                // Enough for one more line
+                thread::sleep(Duration::from_millis(400));
+                tracing::warn!("after the storm");
+            },
+        );
+
+        assert_eq!(lines.len(), 4, "{:?}", lines);
+        for (n, line) in lines[..3].iter().enumerate() {
+            assert!(line.ends_with(&format!("storm n={}\n", n)), "{:?}", line);
+        }
+        assert!(
+            lines[3].ends_with("after the storm (7 lines suppressed)\n"),
+            "{:?}",
+            lines[3]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{
+    de::{Deserialize, Deserializer, MapAccess, Visitor},
+    ser::{Serialize, SerializeMap, Serializer},
+};
+use serde_json::Value;
+use std::{collections::HashSet, fmt};
+
+/// Objects nested deeper than this are kept as JSON strings by
+/// [`EscapingProfile::ClickHouse`]
+pub const CLICKHOUSE_MAX_DEPTH: usize = 4;
+
+/// How JSON records are shaped for their consumer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EscapingProfile {
+    /// Records as produced by the formatter, this is the default
+    Default,
+
+    /// Records safe for ClickHouse `JSONEachRow` ingestion: nested objects are
+    /// flattened into dotted keys (`span.name`) up to [`CLICKHOUSE_MAX_DEPTH`]
+    /// levels, deeper objects become JSON strings, and duplicate keys are
+    /// renamed `key_2`, `key_3`, … in order of appearance. Tabs and carriage
+    /// returns only ever appear escaped inside JSON strings.
+    ClickHouse,
+}
+
+/// Rewrites a serialized record for ClickHouse, see [`EscapingProfile::ClickHouse`].
+pub(crate) fn clickhouse(record: &str) -> serde_json::Result<String> {
+    let Entries(entries) = serde_json::from_str(record)?;
+
+    let mut flattened = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        flatten(key, value, 1, &mut flattened);
+    }
+
+    let mut seen = HashSet::with_capacity(flattened.len());
+    let mut output = Vec::with_capacity(record.len());
+    let mut serializer = serde_json::Serializer::new(&mut output);
+    let mut map = serializer.serialize_map(Some(flattened.len()))?;
+    for (key, value) in flattened {
+        let key = if seen.contains(&key) {
+            (2..)
+                .map(|n| format!("{}_{}", key, n))
+                .find(|renamed| !seen.contains(renamed))
+                .unwrap_or_default()
+        } else {
+            key
+        };
+        map.serialize_entry(&key, &value)?;
+        seen.insert(key);
+    }
+    map.end()?;
+
+    Ok(String::from_utf8(output).unwrap_or_default())
+}
+
+fn flatten(key: String, value: Value, depth: usize, flattened: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(object) if depth <= CLICKHOUSE_MAX_DEPTH && !object.is_empty() => {
+            for (nested_key, nested_value) in object {
+                flatten(
+                    format!("{}.{}", key, nested_key),
+                    nested_value,
+                    depth + 1,
+                    flattened,
+                );
+            }
+        }
+        Value::Object(object) => {
+            flattened.push((key, Value::String(Value::Object(object).to_string())))
+        }
+        value => flattened.push((key, value)),
+    }
+}
+
+/// Top level entries of a JSON object in their original order, duplicates
+/// included, serialized in that order too
+pub(crate) struct Entries(pub(crate) Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = Entries;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entries, A::Error> {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(16));
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+impl Serialize for Entries {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clickhouse, Entries, EscapingProfile, CLICKHOUSE_MAX_DEPTH};
+    use crate::{
+        test_support::{with_logger, Capture, Rng},
+        Target,
+    };
+    use serde_json::Value;
+    use std::collections::HashSet;
+
+    /// A random JSON object as text, possibly with duplicate keys, and its
+    /// entries with nested keys joined by dots
+    fn object(rng: &mut Rng, depth: usize, out: &mut String) {
+        const KEYS: &[&str] = &["a", "b", "span", "level", "a.b", "a_2"];
+        out.push('{');
+        for i in 0..rng.below(5) {
+            if i > 0 {
+                out.push(',');
+            }
+            let key = match rng.below(3) {
+                0 => rng.string(6),
+                _ => KEYS[rng.below(KEYS.len())].to_owned(),
+            };
+            out.push_str(&Value::from(key).to_string());
+            out.push(':');
+            match rng.below(6) {
+                0 if depth < CLICKHOUSE_MAX_DEPTH + 2 => object(rng, depth + 1, out),
+                0 | 1 => out.push_str(&Value::from(rng.string(12)).to_string()),
+                2 => out.push_str(&rng.next().to_string()),
+                3 => out.push_str("[1,\"\\t\",{\"x\":null}]"),
+                4 => out.push_str("true"),
+                _ => out.push_str("null"),
+            }
+        }
+        out.push('}');
+    }
+
+    /// What a strict `JSONEachRow` reader accepts: a single line of a flat
+    /// JSON object, with unique keys and no raw tabs or carriage returns
+    fn check_json_each_row(line: &str) -> Result<(), String> {
+        if line.bytes().any(|b| b == b'\t' || b == b'\r' || b == b'\n') {
+            return Err("raw tab, carriage return or newline".to_owned());
+        }
+        if line.bytes().any(|b| b < 0x20) {
+            return Err("raw control character".to_owned());
+        }
+        let Entries(entries) = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let mut keys = HashSet::new();
+        for (key, value) in entries {
+            if value.is_object() {
+                return Err(format!("`{}` is a nested object", key));
+            }
+            if !keys.insert(key.clone()) {
+                return Err(format!("`{}` is duplicated", key));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn random_records_are_valid_json_each_rows() {
+        let mut rng = Rng::new(717);
+        for _ in 0..20_000 {
+            let mut record = String::new();
+            object(&mut rng, 1, &mut record);
+            let rewritten = clickhouse(&record).expect("record is rewritten");
+            if let Err(e) = check_json_each_row(&rewritten) {
+                panic!("{} from {}: {}", e, record, rewritten);
+            }
+            // Rewriting is deterministic and idempotent
+            assert_eq!(clickhouse(&record).unwrap(), rewritten);
+            assert_eq!(clickhouse(&rewritten).unwrap(), rewritten);
+        }
+    }
+
+    #[test]
+    fn objects_are_flattened_up_to_the_depth_limit() {
+        let rewritten = clickhouse(
+            r#"{"a":{"b":{"c":{"d":{"e":{"f":1}}}}},"e":{},"s":{"t":"\t"},"a.b":2,"a.b":3,"a.b_2":4}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rewritten,
+            r#"{"a.b.c.d.e":"{\"f\":1}","e":"{}","s.t":"\t","a.b":2,"a.b_2":3,"a.b_2_2":4}"#
+        );
+    }
+
+    #[test]
+    fn records_of_the_profile_are_valid_json_each_rows() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .escaping_profile(EscapingProfile::ClickHouse)
+            .capture(&capture);
+        with_logger(builder, || {
+            let _span = tracing::info_span!("request", path = "/tab\there").entered();
+            tracing::info!(
+                level_name = "a\r\tb",
+                message_extra = "\u{1}",
+                "tab\tand\rcr"
+            );
+        });
+
+        let line = &capture.lines()[0];
+        let row = line.strip_suffix('\n').expect("line ends with a newline");
+        check_json_each_row(row).unwrap();
+        let record: Value = serde_json::from_str(row).unwrap();
+        assert_eq!(record["message"], "tab\tand\rcr");
+        assert_eq!(record["span.path"], "/tab\there");
+        assert_eq!(record["span.name"], "request");
+    }
+}
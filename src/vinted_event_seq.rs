@@ -0,0 +1,118 @@
+use crate::{vinted_event_time, vinted_timestamp};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing_subscriber::fmt::{format::Writer, time::FormatTime};
+
+static EVENT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Number of the event being formatted, strictly increasing in the order
+/// events are emitted. Events are formatted on the thread emitting them, so
+/// the order within a thread is always preserved.
+pub(crate) fn next() -> u64 {
+    EVENT_SEQ.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Prints the inner timer as is followed by `#event_seq`, as the timestamp is
+/// the only part of tracing-subscriber's text format written for every event.
+/// Events with a valid `event_time` get their own time in RFC 3339 instead.
+#[derive(Debug)]
+pub(crate) struct VintedSeqTimer<T>(pub(crate) T);
+
+impl<T: FormatTime> FormatTime for VintedSeqTimer<T> {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        match vinted_event_time::current_override() {
+            Some(time) => w.write_str(&vinted_timestamp::rfc3339(time))?,
+            None => self.0.format_time(w)?,
+        }
+        write!(w, " #{}", next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use std::{collections::HashSet, thread};
+    use tracing::Dispatch;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    #[test]
+    fn bursts_within_a_millisecond_keep_their_order() {
+        let capture = Capture::default();
+        let (layer, _filter) = crate::builder("svc-test", Target::ConsoleJson)
+            .capture(&capture)
+            .build()
+            .unwrap();
+        let dispatch = Dispatch::new(Registry::default().with(layer));
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let dispatch = dispatch.clone();
+                thread::spawn(move || {
+                    tracing::dispatcher::with_default(&dispatch, || {
+                        for n in 0..100 {
+                            // The same time for every event of the burst
+                            tracing::info!(
+                                event_time = "2021-04-20T12:42:57.353Z",
+                                thread,
+                                n,
+                                "burst"
+                            );
+                        }
+                    })
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        let records = capture.records();
+        assert_eq!(records.len(), 400);
+        assert!(records
+            .iter()
+            .all(|record| record["@timestamp"] == "2021-04-20T12:42:57.353+00:00"));
+        let seqs: HashSet<_> = records
+            .iter()
+            .map(|record| record["event_seq"].as_u64().unwrap())
+            .collect();
+        assert_eq!(seqs.len(), 400, "every event has its own event_seq");
+        for thread in 0..4 {
+            let ns: Vec<_> = {
+                let mut burst: Vec<_> = records
+                    .iter()
+                    .filter(|record| record["thread"] == thread)
+                    .map(|record| (record["event_seq"].as_u64(), record["n"].as_u64()))
+                    .collect();
+                burst.sort_unstable();
+                burst.into_iter().map(|(_, n)| n.unwrap()).collect()
+            };
+            // Sorting by event_seq gives back the emission order of the thread
+            assert_eq!(ns, (0..100).collect::<Vec<_>>(), "thread {}", thread);
+        }
+    }
+
+    #[test]
+    fn console_lines_carry_increasing_event_seqs() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console).capture(&capture);
+        with_logger(builder, || {
+            for n in 0..10 {
+                tracing::info!(n, "burst");
+            }
+        });
+
+        let seqs: Vec<u64> = capture
+            .lines()
+            .iter()
+            .map(|line| {
+                let seq = &line[line.find(" #").unwrap() + 2..];
+                seq[..seq.find('\u{1b}').unwrap()].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(seqs.len(), 10);
+        assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", seqs);
+    }
+}
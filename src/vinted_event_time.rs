@@ -0,0 +1,270 @@
+use crate::vinted_timestamp;
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing_core::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::{FormatEvent, FormatFields, Writer},
+        FmtContext,
+    },
+    registry::LookupSpan,
+};
+
+/// Field overriding the timestamp of a record
+pub(crate) const FIELD: &str = "event_time";
+
+/// Field explaining why `event_time` was ignored
+pub(crate) const ERROR_FIELD: &str = "event_time_error";
+
+thread_local! {
+    /// Time of the event being formatted by `VintedEventTimeFormat`
+    static OVERRIDE: Cell<Option<SystemTime>> = const { Cell::new(None) };
+}
+
+/// Outcome of looking for [`FIELD`] in an event
+#[derive(Debug)]
+pub(crate) enum EventTime {
+    Absent,
+    Valid(SystemTime),
+    Invalid(String),
+}
+
+impl EventTime {
+    /// Reads `event_time` of the event, an RFC 3339 string or milliseconds
+    /// since the Unix epoch. Times more than `max_skew` ahead of now are
+    /// invalid.
+    pub(crate) fn of(event: &Event<'_>, max_skew: Duration) -> Self {
+        if event.metadata().fields().field(FIELD).is_none() {
+            return Self::Absent;
+        }
+
+        let mut visitor = Visitor(Self::Absent);
+        event.record(&mut visitor);
+        match visitor.0 {
+            Self::Valid(time) if time > SystemTime::now() + max_skew => Self::Invalid(format!(
+                "{} is more than {}s in the future",
+                vinted_timestamp::rfc3339(time),
+                max_skew.as_secs()
+            )),
+            event_time => event_time,
+        }
+    }
+
+    pub(crate) fn valid(&self) -> Option<SystemTime> {
+        match *self {
+            Self::Valid(time) => Some(time),
+            _ => None,
+        }
+    }
+}
+
+struct Visitor(EventTime);
+
+impl Visitor {
+    fn record_millis(&mut self, millis: i128) {
+        let time = u64::try_from(millis.unsigned_abs()).ok().and_then(|abs| {
+            if millis >= 0 {
+                UNIX_EPOCH.checked_add(Duration::from_millis(abs))
+            } else {
+                UNIX_EPOCH.checked_sub(Duration::from_millis(abs))
+            }
+        });
+        self.0 = match time {
+            Some(time) => EventTime::Valid(time),
+            None => EventTime::Invalid(format!("{} milliseconds is out of range", millis)),
+        };
+    }
+}
+
+impl Visit for Visitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == FIELD {
+            if value.is_finite() {
+                self.record_millis(value as i128);
+            } else {
+                self.0 = EventTime::Invalid(format!("{} is not a number of milliseconds", value));
+            }
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == FIELD {
+            self.record_millis(i128::from(value));
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == FIELD {
+            self.record_millis(i128::from(value));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == FIELD {
+            self.0 = match vinted_timestamp::parse_rfc3339(value) {
+                Some(time) => EventTime::Valid(time),
+                None => EventTime::Invalid(format!("{:?} is not an RFC 3339 timestamp", value)),
+            };
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == FIELD {
+            self.0 = EventTime::Invalid(format!(
+                "{:?} is neither an RFC 3339 string nor epoch milliseconds",
+                value
+            ));
+        }
+    }
+}
+
+/// Time of the event being formatted, if it overrides the current time
+pub(crate) fn current_override() -> Option<SystemTime> {
+    OVERRIDE.with(Cell::get)
+}
+
+/// Wraps a text format, so its timer can print the time of events carrying
+/// a valid `event_time`
+#[derive(Debug)]
+pub(crate) struct VintedEventTimeFormat<F> {
+    inner: F,
+    max_skew: Duration,
+}
+
+impl<F> VintedEventTimeFormat<F> {
+    pub(crate) fn new(inner: F, max_skew: Duration) -> Self {
+        Self { inner, max_skew }
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for VintedEventTimeFormat<F>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let event_time = EventTime::of(event, self.max_skew).valid();
+        OVERRIDE.with(|current| current.set(event_time));
+        let result = self.inner.format_event(ctx, writer, event);
+        OVERRIDE.with(|current| current.set(None));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ERROR_FIELD, FIELD};
+    use crate::{
+        test_support::{with_logger, Capture},
+        vinted_timestamp, LoggerBuilder, Target,
+    };
+    use serde_json::Value;
+    use std::time::{Duration, SystemTime};
+
+    fn record(builder: LoggerBuilder, log: impl FnOnce()) -> Value {
+        let capture = Capture::default();
+        with_logger(builder.capture(&capture), log);
+        capture.records().remove(0)
+    }
+
+    fn json_builder() -> LoggerBuilder {
+        crate::builder("svc-test", Target::ConsoleJson)
+    }
+
+    #[test]
+    fn valid_times_replace_the_timestamp() {
+        let string = record(json_builder(), || {
+            tracing::info!(event_time = "2021-04-20T15:42:57.353+03:00", "backfilled")
+        });
+        let millis = record(json_builder(), || {
+            tracing::info!(event_time = 1_618_922_577_353_u64, "backfilled")
+        });
+        let signed = record(json_builder(), || {
+            tracing::info!(event_time = 1_618_922_577_353_i64, "backfilled")
+        });
+
+        for record in &[string, millis, signed] {
+            assert_eq!(record["@timestamp"], "2021-04-20T12:42:57.353+00:00");
+            assert!(record.get(FIELD).is_none(), "{}", record);
+            assert!(record.get(ERROR_FIELD).is_none(), "{}", record);
+        }
+    }
+
+    #[test]
+    fn invalid_times_keep_the_timestamp() {
+        let before = SystemTime::now() - Duration::from_secs(1);
+        for (log, error) in [
+            (
+                (|| tracing::info!(event_time = "yesterday", "backfilled")) as fn(),
+                "\"yesterday\" is not an RFC 3339 timestamp",
+            ),
+            (
+                || tracing::info!(event_time = f64::NAN, "backfilled"),
+                "NaN is not a number of milliseconds",
+            ),
+            (|| tracing::info!(event_time = ?Some(1), "backfilled"), ""),
+        ] {
+            let record = record(json_builder(), log);
+            let timestamp = record["@timestamp"].as_str().unwrap();
+            let timestamp = vinted_timestamp::parse_rfc3339(timestamp).unwrap();
+            assert!(timestamp > before, "{}", record);
+            assert!(
+                record[ERROR_FIELD].as_str().unwrap().contains(error),
+                "{}",
+                record
+            );
+        }
+    }
+
+    #[test]
+    fn times_too_far_ahead_are_rejected() {
+        let ahead = SystemTime::now() + Duration::from_secs(60 * 60);
+        let ahead = vinted_timestamp::rfc3339(ahead);
+        let log = || tracing::info!(event_time = ahead.as_str(), "from the future");
+
+        let rejected = record(json_builder(), log);
+        assert_ne!(rejected["@timestamp"], ahead.as_str());
+        assert!(
+            rejected[ERROR_FIELD]
+                .as_str()
+                .unwrap()
+                .ends_with("is more than 300s in the future"),
+            "{}",
+            rejected
+        );
+
+        let skewed = json_builder().event_time_skew(Duration::from_secs(2 * 60 * 60));
+        let accepted = record(skewed, log);
+        assert_eq!(accepted["@timestamp"], ahead.as_str());
+        assert!(accepted.get(ERROR_FIELD).is_none(), "{}", accepted);
+    }
+
+    #[test]
+    fn console_lines_start_with_the_event_time() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console).capture(&capture);
+        with_logger(builder, || {
+            tracing::info!(event_time = 1_618_922_577_353_u64, "backfilled")
+        });
+
+        let line = &capture.lines()[0];
+        assert!(
+            line.starts_with("\u{1b}[2m2021-04-20T12:42:57.353+00:00 #"),
+            "{:?}",
+            line
+        );
+    }
+}
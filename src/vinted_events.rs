@@ -0,0 +1,322 @@
+use parking_lot::{const_mutex, Condvar, Mutex};
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// Events kept for a [`LoggerEvents`] which doesn't take them, beyond which
+/// the oldest ones are dropped
+pub const LOGGER_EVENTS_CAPACITY: usize = 64;
+
+static SUBSCRIBERS: Mutex<Vec<Weak<Subscriber>>> = const_mutex(Vec::new());
+
+/// Change in how the logger delivers records, see [`logger_events`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LoggerEvent {
+    /// `TcpJson` connected to its destination
+    Connected {
+        /// Address of the destination
+        addr: SocketAddr,
+    },
+    /// `TcpJson` lost or couldn't open its connection, records are buffered
+    /// until it reconnects; or the `UdpJson` socket couldn't be bound, and
+    /// records will never be sent
+    Disconnected {
+        /// Address of the destination
+        addr: SocketAddr,
+        /// What went wrong
+        error: String,
+    },
+}
+
+impl fmt::Display for LoggerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connected { addr } => write!(f, "connected to {}", addr),
+            Self::Disconnected { addr, error } => {
+                write!(f, "disconnected from {}: {}", addr, error)
+            }
+        }
+    }
+}
+
+/// Subscribes to [`LoggerEvent`]s, e.g. to flip a status flag or shed
+/// debug work while logging is degraded
+///
+/// Every subscription gets every event emitted from then on. Events are
+/// emitted by the threads sending records, which never wait for a
+/// subscription: up to [`LOGGER_EVENTS_CAPACITY`] events are kept for one
+/// which doesn't take them, the oldest ones are dropped beyond, see
+/// [`LoggerEvents::missed`].
+///
+/// Don't log what's received from the threads taking the events while
+/// logging is degraded, it would be buffered or lost with the rest.
+///
+/// ```no_run
+/// let events = vinted_logger::logger_events();
+/// std::thread::spawn(move || {
+///     for event in events {
+///         eprintln!("logger {}", event);
+///     }
+/// });
+/// ```
+pub fn logger_events() -> LoggerEvents {
+    let subscriber = Arc::new(Subscriber {
+        queue: Mutex::new(Queue {
+            events: VecDeque::new(),
+            missed: 0,
+            waker: None,
+        }),
+        ready: Condvar::new(),
+    });
+
+    let mut subscribers = SUBSCRIBERS.lock();
+    subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+    subscribers.push(Arc::downgrade(&subscriber));
+    LoggerEvents(subscriber)
+}
+
+/// Sends `event` to every subscription
+pub(crate) fn emit(event: LoggerEvent) {
+    let subscribers: Vec<_> = SUBSCRIBERS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+
+    for subscriber in subscribers {
+        let waker = {
+            let mut queue = subscriber.queue.lock();
+            if queue.events.len() >= LOGGER_EVENTS_CAPACITY {
+                queue.events.pop_front();
+                queue.missed += 1;
+            }
+            queue.events.push_back(event.clone());
+            queue.waker.take()
+        };
+        subscriber.ready.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Subscription returned by [`logger_events`], iterating blocks until the
+/// next event
+pub struct LoggerEvents(Arc<Subscriber>);
+
+struct Subscriber {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+struct Queue {
+    events: VecDeque<LoggerEvent>,
+    missed: u64,
+    waker: Option<Waker>,
+}
+
+impl LoggerEvents {
+    /// Takes the oldest event, if any
+    pub fn try_recv(&self) -> Option<LoggerEvent> {
+        self.0.queue.lock().events.pop_front()
+    }
+
+    /// Takes the oldest event, waiting up to `timeout` for one
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<LoggerEvent> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.0.queue.lock();
+        loop {
+            if let Some(event) = queue.events.pop_front() {
+                return Some(event);
+            }
+            if self.0.ready.wait_until(&mut queue, deadline).timed_out() {
+                return queue.events.pop_front();
+            }
+        }
+    }
+
+    /// Resolves with the oldest event once there is one
+    ///
+    /// ```
+    /// # async fn watch() {
+    /// let mut events = vinted_logger::logger_events();
+    /// loop {
+    ///     let event = events.next_event().await;
+    ///     eprintln!("logger {}", event);
+    /// }
+    /// # }
+    /// ```
+    pub fn next_event(&mut self) -> NextLoggerEvent<'_> {
+        NextLoggerEvent(self)
+    }
+
+    /// Events dropped so far because they weren't taken in time
+    pub fn missed(&self) -> u64 {
+        self.0.queue.lock().missed
+    }
+}
+
+impl Iterator for LoggerEvents {
+    type Item = LoggerEvent;
+
+    fn next(&mut self) -> Option<LoggerEvent> {
+        let mut queue = self.0.queue.lock();
+        loop {
+            if let Some(event) = queue.events.pop_front() {
+                return Some(event);
+            }
+            self.0.ready.wait(&mut queue);
+        }
+    }
+}
+
+impl fmt::Debug for LoggerEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let queue = self.0.queue.lock();
+        f.debug_struct("LoggerEvents")
+            .field("pending", &queue.events.len())
+            .field("missed", &queue.missed)
+            .finish()
+    }
+}
+
+/// Future returned by [`LoggerEvents::next_event`]
+#[derive(Debug)]
+pub struct NextLoggerEvent<'a>(&'a mut LoggerEvents);
+
+impl Future for NextLoggerEvent<'_> {
+    type Output = LoggerEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<LoggerEvent> {
+        let mut queue = (self.0).0.queue.lock();
+        match queue.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{emit, logger_events, LoggerEvent, LoggerEvents, LOGGER_EVENTS_CAPACITY};
+    use crate::vinted_tcp_writer::{Framing, VintedTcpWriter};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{SocketAddr, TcpListener},
+        time::{Duration, Instant},
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// Next event about `addr`, skipping those of writers of other tests
+    fn next_about(events: &LoggerEvents, addr: SocketAddr) -> LoggerEvent {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while let Some(event) = events.recv_timeout(deadline - Instant::now()) {
+            match event {
+                LoggerEvent::Connected { addr: to }
+                | LoggerEvent::Disconnected { addr: to, .. }
+                    if to == addr =>
+                {
+                    return event
+                }
+                _ => {}
+            }
+        }
+        panic!("no event about {}", addr);
+    }
+
+    #[test]
+    fn tcp_connections_are_followed() {
+        let events = logger_events();
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let writer = VintedTcpWriter::new(addr, 16, Framing::NewlineDelimited);
+        let write = |record: &str| writer.make_writer().write_all(record.as_bytes()).unwrap();
+
+        // Nothing listens yet
+        write("{\"n\":1}\n");
+        assert!(matches!(
+            next_about(&events, addr),
+            LoggerEvent::Disconnected { .. }
+        ));
+
+        let listener = TcpListener::bind(addr).unwrap();
+        let (first, _) = listener.accept().unwrap();
+        assert_eq!(next_about(&events, addr), LoggerEvent::Connected { addr });
+        first
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(&first).read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"n\":1}\n");
+
+        // Writes to the closed connection fail sooner or later
+        drop(first);
+        loop {
+            write("{\"n\":2}\n");
+            match events.recv_timeout(Duration::from_millis(20)) {
+                Some(LoggerEvent::Disconnected { addr: from, .. }) if from == addr => break,
+                _ => {}
+            }
+        }
+        let _second = listener.accept().unwrap();
+        assert_eq!(next_about(&events, addr), LoggerEvent::Connected { addr });
+    }
+
+    #[test]
+    fn the_oldest_events_are_dropped_for_slow_subscriptions() {
+        let events = logger_events();
+        let addrs: Vec<SocketAddr> = (0..LOGGER_EVENTS_CAPACITY as u16 + 6)
+            .map(|port| SocketAddr::from(([192, 0, 2, 1], port + 1)))
+            .collect();
+        for &addr in &addrs {
+            emit(LoggerEvent::Connected { addr });
+        }
+
+        assert!(events.missed() >= 6, "{:?}", events);
+        let kept: Vec<_> = std::iter::from_fn(|| events.try_recv())
+            .filter_map(|event| match event {
+                LoggerEvent::Connected { addr } if addrs.contains(&addr) => Some(addr),
+                _ => None,
+            })
+            .collect();
+        assert!(kept.len() <= LOGGER_EVENTS_CAPACITY);
+        assert_eq!(kept.last(), addrs.last());
+        assert!(addrs.ends_with(&kept));
+    }
+
+    #[test]
+    fn events_can_be_awaited() {
+        let mut events = logger_events();
+        let addr = SocketAddr::from(([192, 0, 2, 2], 24224));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let event = runtime.block_on(async {
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                emit(LoggerEvent::Connected { addr });
+            });
+            loop {
+                let event = events.next_event().await;
+                if event == (LoggerEvent::Connected { addr }) {
+                    break event;
+                }
+            }
+        });
+        assert_eq!(event.to_string(), "connected to 192.0.2.2:24224");
+    }
+}
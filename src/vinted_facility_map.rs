@@ -0,0 +1,169 @@
+use parking_lot::RwLock;
+use std::{cmp::Reverse, collections::HashMap};
+use tracing_core::{callsite::Identifier, Metadata};
+
+/// Facilities of records by target prefix, see
+/// [`LoggerBuilder::facility_for`](crate::LoggerBuilder::facility_for)
+#[derive(Debug, Default)]
+pub(crate) struct FacilityMap {
+    /// Longest prefixes first, so the first match wins
+    prefixes: Vec<(String, &'static str)>,
+    /// The target of a callsite never changes, neither does its facility
+    by_callsite: RwLock<HashMap<Identifier, Option<&'static str>>>,
+}
+
+impl FacilityMap {
+    pub(crate) fn new(mut prefixes: Vec<(String, &'static str)>) -> Self {
+        prefixes.sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+
+        Self {
+            prefixes,
+            by_callsite: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Facility mapped to the longest prefix of the record's target, `None`
+    /// if none is
+    ///
+    /// Records `bridged` from the `log` crate share the callsite of their
+    /// level whatever their target, they're resolved without the cache.
+    pub(crate) fn resolve(&self, meta: &Metadata<'_>, bridged: bool) -> Option<&'static str> {
+        if self.prefixes.is_empty() {
+            return None;
+        }
+        if bridged {
+            return self.lookup(meta.target());
+        }
+
+        let callsite = meta.callsite();
+        if let Some(facility) = self.by_callsite.read().get(&callsite) {
+            return *facility;
+        }
+
+        let facility = self.lookup(meta.target());
+        self.by_callsite.write().insert(callsite, facility);
+        facility
+    }
+
+    fn lookup(&self, target: &str) -> Option<&'static str> {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| matches(prefix, target))
+            .map(|&(_, facility)| facility)
+    }
+}
+
+/// Whether `prefix` is `target` or one of its parent modules, `app::db`
+/// matches `app::db::pool` but not `app::dbx`
+fn matches(prefix: &str, target: &str) -> bool {
+    match target.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+
+    fn facilities(log: impl FnOnce()) -> Vec<String> {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-host", Target::ConsoleJson)
+            .facility_for("payments", "svc-payments")
+            .facility_for("payments::fraud", "svc-fraud")
+            .facility_for("search", "svc-search")
+            .capture(&capture);
+        with_logger(builder, log);
+        capture
+            .records()
+            .iter()
+            .map(|record| record["facility"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn the_longest_prefix_wins() {
+        let facilities = facilities(|| {
+            tracing::info!(target: "payments", "charged");
+            tracing::info!(target: "payments::refunds", "refunded");
+            tracing::info!(target: "payments::fraud::rules", "flagged");
+            tracing::info!(target: "search::index", "indexed");
+            tracing::info!(target: "searchd", "not a module of search");
+            tracing::info!(target: "host::http", "served");
+        });
+
+        assert_eq!(
+            facilities,
+            [
+                "svc-payments",
+                "svc-payments",
+                "svc-fraud",
+                "svc-search",
+                "svc-host",
+                "svc-host",
+            ]
+        );
+    }
+
+    #[test]
+    fn callsites_keep_their_facility_across_interest_rebuilds() {
+        let facilities = facilities(|| {
+            for _ in 0..3 {
+                tracing::info!(target: "payments::fraud", "flagged");
+                tracing::info!(target: "host", "served");
+                // What reloading the filter does
+                tracing::callsite::rebuild_interest_cache();
+            }
+        });
+
+        assert_eq!(
+            facilities,
+            ["svc-fraud", "svc-host"].repeat(3),
+            "resolved from the cache after the first record"
+        );
+    }
+
+    /// Sends a `log` record through the bridge, as `LogTracer` does
+    fn log_bridged(target: &str) {
+        tracing_log::format_trace(
+            &log::Record::builder()
+                .args(format_args!("bridged"))
+                .level(log::Level::Info)
+                .target(target)
+                .build(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn bridged_records_are_resolved_by_target() {
+        let facilities = facilities(|| {
+            log_bridged("payments::refunds");
+            log_bridged("search::index");
+            log_bridged("host::http");
+            log_bridged("payments::fraud");
+        });
+
+        assert_eq!(
+            facilities,
+            ["svc-payments", "svc-search", "svc-host", "svc-fraud"],
+            "the callsite of a level is shared by every bridged target"
+        );
+    }
+
+    #[test]
+    fn prefixes_can_be_remapped() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-host", Target::ConsoleJson)
+            .facility_for("payments", "svc-old")
+            .facility_for("payments", "svc-payments")
+            .capture(&capture);
+
+        with_logger(builder, || tracing::info!(target: "payments", "charged"));
+
+        assert_eq!(capture.records()[0]["facility"], "svc-payments");
+    }
+}
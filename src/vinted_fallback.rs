@@ -0,0 +1,195 @@
+use parking_lot::{const_mutex, Mutex};
+use serde_json::{Map, Value};
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing_core::Level;
+
+/// Default of [`LoggerBuilder::fallback_per_minute`](crate::LoggerBuilder::fallback_per_minute)
+pub const DEFAULT_FALLBACK_PER_MINUTE: u32 = 100;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+static PER_MINUTE: AtomicU32 = AtomicU32::new(DEFAULT_FALLBACK_PER_MINUTE);
+/// Least severe level echoed to stderr already, as `level_rank`, 0 for none
+static ECHOED: AtomicU32 = AtomicU32::new(0);
+static EMISSIONS: AtomicU64 = AtomicU64::new(0);
+static BUDGET: Mutex<Option<Budget>> = const_mutex(None);
+
+pub(crate) fn configure(per_minute: u32, echoed: Option<Level>) {
+    PER_MINUTE.store(per_minute, Ordering::Relaxed);
+    // Levels below WARN are echoed as WARN
+    let echoed = echoed.map(|level| level.min(Level::WARN));
+    ECHOED.store(echoed.map_or(0, level_rank), Ordering::Relaxed);
+}
+
+/// Lines written to stderr for records no sink delivered
+pub(crate) fn emissions() -> u64 {
+    EMISSIONS.load(Ordering::Relaxed)
+}
+
+/// Writes newline delimited JSON records which couldn't be delivered to
+/// stderr as `@timestamp LEVEL facility: message` lines, within the budget.
+/// Records [`VintedEchoWriter`](crate::vinted_echo_writer::VintedEchoWriter)
+/// put on stderr already, and bytes which aren't JSON records, e.g. GELF
+/// chunks, are left out.
+///
+/// Once lines were left out, the next line written is preceded by how many.
+pub(crate) fn undelivered(records: &[u8]) {
+    for record in records.split(|&byte| byte == b'\n') {
+        if let Some(line) = compact(record) {
+            write(line);
+        }
+    }
+}
+
+fn write(line: String) {
+    let suppressed = {
+        let mut budget = BUDGET.lock();
+        let budget = budget.get_or_insert_with(Budget::new);
+        match budget.take() {
+            Some(suppressed) => suppressed,
+            None => return,
+        }
+    };
+
+    EMISSIONS.fetch_add(1, Ordering::Relaxed);
+    let mut stderr = io::stderr().lock();
+    if suppressed > 0 {
+        let _ = writeln!(
+            stderr,
+            "{} undelivered log records left out of stderr",
+            suppressed
+        );
+    }
+    let _ = writeln!(stderr, "{}", line);
+}
+
+/// `@timestamp LEVEL facility: message` out of a `VintedJson` record or a
+/// GELF message, `None` for records echoed already
+fn compact(record: &[u8]) -> Option<String> {
+    let record: Map<String, Value> = serde_json::from_slice(record).ok()?;
+    let field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|&key| record.get(key))
+            .map(|value| match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            })
+            .unwrap_or_default()
+    };
+
+    let level = match record.get("level") {
+        Some(Value::String(level)) => level.parse().ok(),
+        // GELF syslog severities
+        Some(Value::Number(severity)) => match severity.as_u64() {
+            Some(0..=3) => Some(Level::ERROR),
+            Some(4) => Some(Level::WARN),
+            Some(5 | 6) => Some(Level::INFO),
+            _ => Some(Level::DEBUG),
+        },
+        _ => None,
+    };
+    if let Some(level) = level {
+        if level_rank(level) <= ECHOED.load(Ordering::Relaxed) {
+            return None;
+        }
+    }
+
+    Some(format!(
+        "{} {} {}: {}",
+        field(&["@timestamp", "timestamp"]),
+        level.map_or_else(|| field(&["level"]), |level| level.to_string()),
+        field(&["facility", "_facility"]),
+        field(&["message", "short_message"])
+    ))
+}
+
+/// 1 for ERROR up to 5 for TRACE
+fn level_rank(level: Level) -> u32 {
+    match level {
+        Level::ERROR => 1,
+        Level::WARN => 2,
+        Level::INFO => 3,
+        Level::DEBUG => 4,
+        Level::TRACE => 5,
+    }
+}
+
+/// Token bucket holding up to a minute worth of lines
+struct Budget {
+    per_minute: f64,
+    tokens: f64,
+    refilled: Instant,
+    suppressed: u64,
+}
+
+impl Budget {
+    fn new() -> Self {
+        let per_minute = f64::from(PER_MINUTE.load(Ordering::Relaxed));
+        Self {
+            per_minute,
+            tokens: per_minute,
+            refilled: Instant::now(),
+            suppressed: 0,
+        }
+    }
+
+    /// Returns how many lines were left out since the last one written, or
+    /// `None` if this one has to be left out too.
+    fn take(&mut self) -> Option<u64> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64() / MINUTE.as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_minute).min(self.per_minute);
+        self.refilled = now;
+
+        if self.tokens < 1.0 {
+            self.suppressed += 1;
+            return None;
+        }
+        self.tokens -= 1.0;
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact, Budget};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn records_are_compacted_to_one_line() {
+        // INFO records are never echoed, whatever other tests configured
+        let record = br#"{"@timestamp":"2021-04-20T12:42:57.123Z","level":"INFO","facility":"svc-test","message":"signed in","user_id":7}"#;
+        assert_eq!(
+            compact(record).unwrap(),
+            "2021-04-20T12:42:57.123Z INFO svc-test: signed in"
+        );
+
+        let gelf = br#"{"version":"1.1","timestamp":1618922577.123,"level":6,"_facility":"svc-test","short_message":"slow"}"#;
+        assert_eq!(compact(gelf).unwrap(), "1618922577.123 INFO svc-test: slow");
+
+        assert_eq!(compact(b"\x1e\x0f chunk"), None);
+    }
+
+    #[test]
+    fn lines_beyond_the_budget_are_counted() {
+        let mut budget = Budget {
+            per_minute: 2.0,
+            tokens: 2.0,
+            refilled: Instant::now(),
+            suppressed: 0,
+        };
+        assert_eq!(budget.take(), Some(0));
+        assert_eq!(budget.take(), Some(0));
+        assert_eq!(budget.take(), None);
+        assert_eq!(budget.take(), None);
+
+        // Half a minute later, a line is back
+        budget.refilled -= Duration::from_secs(30);
+        assert_eq!(budget.take(), Some(2));
+        assert_eq!(budget.take(), None);
+    }
+}
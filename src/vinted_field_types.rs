@@ -0,0 +1,270 @@
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static COERCED: AtomicU64 = AtomicU64::new(0);
+static MOVED_TO_RAW: AtomicU64 = AtomicU64::new(0);
+
+/// JSON type a field is declared to have, see
+/// [`LoggerBuilder::field_type`](crate::LoggerBuilder::field_type)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FieldType {
+    /// JSON number
+    Number,
+    /// JSON string
+    String,
+    /// JSON boolean
+    Bool,
+}
+
+impl FromStr for FieldType {
+    type Err = String;
+
+    /// Parses `number`, `string` or `bool`, as used in configuration
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "number" => Ok(Self::Number),
+            "string" => Ok(Self::String),
+            "bool" => Ok(Self::Bool),
+            _ => Err(format!(
+                "unknown field type `{}`, expected `number`, `string` or `bool`",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Number => "number",
+            Self::String => "string",
+            Self::Bool => "bool",
+        })
+    }
+}
+
+/// How many field values didn't have their declared type, since the process
+/// started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FieldCoercions {
+    /// Values converted to the declared type without losing anything
+    pub coerced: u64,
+    /// Values which couldn't be converted and were moved to `<field>_raw`
+    pub moved_to_raw: u64,
+}
+
+/// Counts of values which didn't have the type declared with
+/// [`LoggerBuilder::field_type`](crate::LoggerBuilder::field_type)
+///
+/// ```
+/// let coercions = vinted_logger::field_coercions();
+/// if coercions.moved_to_raw > 0 {
+///     println!("{} values were moved to `_raw` fields", coercions.moved_to_raw);
+/// }
+/// ```
+pub fn field_coercions() -> FieldCoercions {
+    FieldCoercions {
+        coerced: COERCED.load(Ordering::Relaxed),
+        moved_to_raw: MOVED_TO_RAW.load(Ordering::Relaxed),
+    }
+}
+
+/// Declared types of fields by name
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FieldTypes(HashMap<String, FieldType>);
+
+impl FieldTypes {
+    pub(crate) fn insert(&mut self, name: String, field_type: FieldType) {
+        self.0.insert(name, field_type);
+    }
+
+    /// Adds the types of `other`, which win over those of the same fields
+    pub(crate) fn extend(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<FieldType> {
+        self.0.get(name).copied()
+    }
+
+    /// Parses `name=type` pairs separated by commas, e.g.
+    /// `user_id=number,order_id=string`
+    pub(crate) fn parse(pairs: &str) -> Result<Self, String> {
+        let mut field_types = Self::default();
+        for pair in pairs
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(field_type)) if !name.trim().is_empty() => {
+                    field_types.insert(name.trim().to_owned(), field_type.trim().parse()?)
+                }
+                _ => {
+                    return Err(format!(
+                        "invalid field type `{}`, expected `name=type`",
+                        pair
+                    ))
+                }
+            }
+        }
+        Ok(field_types)
+    }
+}
+
+/// Converts `value` to `expected` if that loses nothing, or else returns it
+/// as a string to be recorded under `<field>_raw`
+pub(crate) fn coerce(expected: FieldType, value: Value) -> Result<Value, String> {
+    let coerced = match (expected, value) {
+        (FieldType::Number, value @ Value::Number(_))
+        | (FieldType::String, value @ Value::String(_))
+        | (FieldType::Bool, value @ Value::Bool(_)) => return Ok(value),
+        (FieldType::Number, Value::String(value)) => integer(&value).ok_or(value),
+        (FieldType::String, Value::Number(value)) => Ok(Value::String(value.to_string())),
+        (FieldType::String, Value::Bool(value)) => Ok(Value::String(value.to_string())),
+        (FieldType::Bool, Value::String(value)) => match value.as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(value),
+        },
+        (_, value) => Err(value.to_string()),
+    };
+
+    match coerced {
+        Ok(_) => COERCED.fetch_add(1, Ordering::Relaxed),
+        Err(_) => MOVED_TO_RAW.fetch_add(1, Ordering::Relaxed),
+    };
+    coerced
+}
+
+/// Strings of digits which turn back into the same string, so neither `+1`,
+/// `007`, `-0` nor numbers out of the `i64`/`u64` range
+pub(crate) fn integer(value: &str) -> Option<Value> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    if digits.is_empty()
+        || !digits.bytes().all(|byte| byte.is_ascii_digit())
+        || (digits.starts_with('0') && value != "0")
+    {
+        return None;
+    }
+
+    match value.parse::<i64>() {
+        Ok(number) => Some(Value::from(number)),
+        Err(_) => value.parse::<u64>().ok().map(Value::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{coerce, field_coercions, FieldType, FieldTypes};
+    use crate::{
+        test_support::{with_logger, Capture},
+        LoggerBuilder, Target,
+    };
+    use serde_json::{json, Value};
+
+    fn record(builder: LoggerBuilder, log: impl FnOnce()) -> Value {
+        let capture = Capture::default();
+        with_logger(builder.capture(&capture), log);
+        capture.records().remove(0)
+    }
+
+    fn typed_builder() -> LoggerBuilder {
+        crate::builder("svc-test", Target::ConsoleJson)
+            .field_type("user_id", FieldType::Number)
+            .field_type("order_id", FieldType::String)
+            .field_type("paid", FieldType::Bool)
+    }
+
+    #[test]
+    fn values_are_coerced_both_ways_when_nothing_is_lost() {
+        let before = field_coercions();
+        let record = record(typed_builder(), || {
+            tracing::info!(user_id = "12345", order_id = 42, paid = "true", "ordered")
+        });
+
+        assert_eq!(record["user_id"], 12_345);
+        assert_eq!(record["order_id"], "42");
+        assert_eq!(record["paid"], true);
+        // Other tests may coerce at the same time, never fewer
+        assert!(field_coercions().coerced - before.coerced >= 3);
+    }
+
+    #[test]
+    fn lossy_values_move_to_raw_fields() {
+        let before = field_coercions();
+        let record = record(typed_builder(), || {
+            tracing::info!(user_id = "007", paid = "yes", "ordered")
+        });
+
+        for (field, raw) in &[("user_id", "007"), ("paid", "yes")] {
+            assert!(record.get(*field).is_none(), "{}", record);
+            assert_eq!(record[format!("{}_raw", field)], *raw, "{}", record);
+        }
+        assert!(field_coercions().moved_to_raw - before.moved_to_raw >= 2);
+    }
+
+    #[test]
+    fn unregistered_fields_are_left_alone() {
+        let record = record(typed_builder(), || {
+            tracing::info!(item_id = "12345", count = 3, "listed")
+        });
+
+        assert_eq!(record["item_id"], "12345");
+        assert_eq!(record["count"], 3);
+        assert!(record.get("item_id_raw").is_none(), "{}", record);
+    }
+
+    #[test]
+    fn only_exact_integers_become_numbers() {
+        for (value, expected) in &[
+            ("0", Ok(json!(0))),
+            ("-17", Ok(json!(-17))),
+            ("18446744073709551615", Ok(json!(u64::MAX))),
+            ("+1", Err("+1")),
+            ("-0", Err("-0")),
+            ("1.5", Err("1.5")),
+            ("18446744073709551616", Err("18446744073709551616")),
+            ("", Err("")),
+        ] {
+            let coerced = coerce(FieldType::Number, json!(value));
+            assert_eq!(
+                coerced,
+                expected.clone().map_err(str::to_owned),
+                "{:?}",
+                value
+            );
+        }
+        assert_eq!(coerce(FieldType::String, json!(1.5)), Ok(json!("1.5")));
+        assert_eq!(
+            coerce(FieldType::Bool, json!([true])),
+            Err("[true]".to_owned())
+        );
+    }
+
+    #[test]
+    fn pairs_are_parsed_or_rejected() {
+        let types = FieldTypes::parse(" user_id=number, order_id = string,,paid=bool").unwrap();
+        assert_eq!(types.get("user_id"), Some(FieldType::Number));
+        assert_eq!(types.get("order_id"), Some(FieldType::String));
+        assert_eq!(types.get("paid"), Some(FieldType::Bool));
+
+        assert!(FieldTypes::parse("user_id").is_err());
+        assert!(FieldTypes::parse("=number").is_err());
+        assert!(FieldTypes::parse("user_id=integer")
+            .unwrap_err()
+            .contains("unknown field type `integer`"));
+    }
+}
@@ -0,0 +1,1060 @@
+use crate::{
+    vinted_event_time,
+    vinted_field_types::{self, FieldTypes},
+    vinted_own_records::OwnRecords,
+    vinted_pii::{Detectors, PiiScanner},
+    vinted_redaction::Redaction,
+};
+use parking_lot::Mutex;
+use serde::ser::SerializeMap;
+use serde::ser::Serializer as _;
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::HashSet,
+    convert::TryFrom,
+    error::Error,
+    fmt::{self, Write as _},
+    iter,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tracing::field::{display, DisplayValue};
+use tracing_core::{
+    callsite::Identifier,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    LevelFilter, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::format::{FormatFields, Writer},
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+/// Upper bound of callsites remembered by [`notice_untyped_fields`]
+const MAX_NOTICED_CALLSITES: usize = 1024;
+
+/// Longest `Debug` representation of a field kept, longer ones are cut off
+/// and end with [`TRUNCATED`]
+pub(crate) const MAX_DEBUG_LEN: usize = 64 * 1024;
+
+const TRUNCATED: &str = "...(truncated)";
+
+/// Sources of an error recorded at most, in case they loop
+const MAX_ERROR_SOURCES: usize = 32;
+
+/// Value of fields whose `Debug` formatting ran out of time
+const ABORTED: &str = "<debug formatting aborted>";
+
+/// Bytes formatted between checks of the time budget
+const BUDGET_CHECK_INTERVAL: usize = 4 * 1024;
+
+thread_local! {
+    /// Set while an [`OptDisplay`] is formatted, so its values are never
+    /// mistaken for a `None` left by `?option`
+    static FORMATTING_OPT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Records an optional value through `Display`, or nothing at all for `None`
+///
+/// ```
+/// # let user_id: Option<String> = None;
+/// tracing::info!(user_id = vinted_logger::opt(&user_id), "signed in");
+/// ```
+///
+/// Options of numbers and booleans keep their type when recorded as is, e.g.
+/// `count = option_u64`.
+pub fn opt<T: fmt::Display>(value: &Option<T>) -> Option<DisplayValue<OptDisplay<'_, T>>> {
+    value
+        .as_ref()
+        .map(|value| tracing::field::display(OptDisplay(value)))
+}
+
+/// Value of an option recorded with [`opt`]
+#[derive(Debug)]
+pub struct OptDisplay<'a, T>(&'a T);
+
+impl<'a, T: fmt::Display> fmt::Display for OptDisplay<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        FORMATTING_OPT.with(|formatting| formatting.set(true));
+        self.0.fmt(f)
+    }
+}
+
+/// Keys of a record: those reserved by the formatter, shared by every
+/// record, and those taken by its fields so far
+#[derive(Debug, Default)]
+pub(crate) struct RecordKeys {
+    reserved: Arc<HashSet<Cow<'static, str>>>,
+    taken: HashSet<Cow<'static, str>>,
+}
+
+impl RecordKeys {
+    pub(crate) fn new(reserved: Arc<HashSet<Cow<'static, str>>>) -> Self {
+        Self {
+            reserved,
+            taken: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.reserved.contains(key) || self.taken.contains(key)
+    }
+
+    /// Takes `key`, returns `false` if it was taken already
+    pub(crate) fn insert(&mut self, key: Cow<'static, str>) -> bool {
+        !self.reserved.contains(&key) && self.taken.insert(key)
+    }
+}
+
+/// Serializes event fields into a JSON map keeping their types: integers and
+/// floats become numbers, booleans stay booleans, byte slices are base64
+/// encoded and errors become objects with their source chain. Everything
+/// recorded through `Debug` or `Display` becomes a string.
+pub(crate) struct VintedFieldVisitor<S: SerializeMap> {
+    serializer: S,
+    state: Result<(), S::Error>,
+    untyped_fields: Vec<&'static str>,
+    drop_empty: bool,
+    skip_event_time: bool,
+    skip_log_fields: bool,
+    debug_budget: Option<Duration>,
+    message: Option<String>,
+    field_types: Option<Arc<FieldTypes>>,
+    keys: Option<RecordKeys>,
+    pii: Option<Arc<PiiScanner>>,
+    pii_found: Detectors,
+    redaction: Option<Arc<Redaction>>,
+}
+
+impl<S: SerializeMap> VintedFieldVisitor<S> {
+    pub(crate) fn new(serializer: S) -> Self {
+        Self {
+            serializer,
+            state: Ok(()),
+            untyped_fields: Vec::new(),
+            drop_empty: false,
+            skip_event_time: false,
+            skip_log_fields: false,
+            debug_budget: None,
+            message: None,
+            field_types: None,
+            keys: None,
+            pii: None,
+            pii_found: Detectors::default(),
+            redaction: None,
+        }
+    }
+
+    /// Keeps a copy of `message`, see [`message`](Self::message)
+    pub(crate) fn capture_message(self, capture_message: bool) -> Self {
+        Self {
+            message: if capture_message {
+                Some(String::new())
+            } else {
+                None
+            },
+            ..self
+        }
+    }
+
+    /// The recorded `message`, if captured and not empty
+    pub(crate) fn message(&self) -> Option<&str> {
+        self.message
+            .as_deref()
+            .filter(|message| !message.is_empty())
+    }
+
+    fn keep_message(&mut self, field: &Field, value: &str) {
+        if let Some(ref mut message) = self.message {
+            if field.name() == "message" {
+                message.push_str(value);
+            }
+        }
+    }
+
+    /// Aborts `Debug` formatting of a field after `budget`
+    pub(crate) fn debug_budget(self, debug_budget: Option<Duration>) -> Self {
+        Self {
+            debug_budget,
+            ..self
+        }
+    }
+
+    /// Skips fields, other than `message`, recorded as an empty string or as
+    /// the `Debug` string `None`
+    pub(crate) fn drop_empty(self, drop_empty: bool) -> Self {
+        Self { drop_empty, ..self }
+    }
+
+    /// Coerces fields other than `message` to their declared types
+    pub(crate) fn field_types(self, field_types: Option<Arc<FieldTypes>>) -> Self {
+        Self {
+            field_types,
+            ..self
+        }
+    }
+
+    /// Masks personal data in string values, before they are coerced
+    pub(crate) fn pii(self, pii: Option<Arc<PiiScanner>>) -> Self {
+        Self { pii, ..self }
+    }
+
+    /// Detectors which masked something so far, see [`pii`](Self::pii)
+    pub(crate) fn pii_found(&self) -> Detectors {
+        self.pii_found
+    }
+
+    fn mask<'v>(&mut self, field: &Field, value: &'v str) -> Cow<'v, str> {
+        match self.pii {
+            Some(ref pii) => pii.mask(field.name(), value, &mut self.pii_found),
+            None => Cow::Borrowed(value),
+        }
+    }
+
+    /// Redacts fields right before they are serialized
+    pub(crate) fn redaction(self, redaction: Option<Arc<Redaction>>) -> Self {
+        Self { redaction, ..self }
+    }
+
+    /// Keeps the keys of the record unique: fields named like one of `keys`
+    /// or like a field recorded before are renamed `_<field>`, or left out
+    /// if that's taken too
+    pub(crate) fn unique_keys(self, keys: RecordKeys) -> Self {
+        Self {
+            keys: Some(keys),
+            ..self
+        }
+    }
+
+    /// Keys taken so far, see [`unique_keys`](Self::unique_keys)
+    pub(crate) fn take_keys(&mut self) -> RecordKeys {
+        self.keys.take().unwrap_or_default()
+    }
+
+    fn key(&mut self, name: Cow<'static, str>) -> Option<Cow<'static, str>> {
+        let keys = match self.keys {
+            Some(ref mut keys) => keys,
+            None => return Some(name),
+        };
+        let key = if keys.contains(&name) {
+            Cow::Owned(format!("_{}", name))
+        } else {
+            name
+        };
+        if !keys.insert(key.clone()) {
+            return None;
+        }
+        Some(key)
+    }
+
+    /// Skips `event_time`, once it became the record's timestamp
+    pub(crate) fn skip_event_time(self, skip_event_time: bool) -> Self {
+        Self {
+            skip_event_time,
+            ..self
+        }
+    }
+
+    /// Skips the `log.target`, `log.module_path`, `log.file` and `log.line`
+    /// fields of records bridged from the `log` crate, once they became the
+    /// record's own `target`, `module`, `file` and `line`
+    pub(crate) fn skip_log_fields(self, skip_log_fields: bool) -> Self {
+        Self {
+            skip_log_fields,
+            ..self
+        }
+    }
+
+    fn is_skipped(&self, field: &Field) -> bool {
+        (self.skip_event_time && field.name() == vinted_event_time::FIELD)
+            || (self.skip_log_fields && field.name().starts_with("log."))
+    }
+
+    fn is_dropped(&self, field: &Field, value: &str) -> bool {
+        self.drop_empty && field.name() != "message" && (value.is_empty() || value == "None")
+    }
+
+    /// Fields, other than `message`, which were recorded as strings through
+    /// `Debug` or `Display`
+    pub(crate) fn untyped_fields(&self) -> &[&'static str] {
+        &self.untyped_fields
+    }
+
+    pub(crate) fn take_serializer(self) -> Result<S, S::Error> {
+        self.state?;
+        Ok(self.serializer)
+    }
+
+    fn serialize_entry<V: serde::Serialize + ?Sized>(&mut self, field: &Field, value: &V) {
+        if self.is_skipped(field) {
+            return;
+        }
+        if let Some(key) = self.key(Cow::Borrowed(field.name())) {
+            self.write_entry(field, &key, value);
+        }
+    }
+
+    fn write_entry<V: serde::Serialize + ?Sized>(&mut self, field: &Field, key: &str, value: &V) {
+        if self.state.is_err() {
+            return;
+        }
+        let redacted = match self.redaction {
+            Some(ref redaction) => redaction.redact(field.name(), value),
+            None => None,
+        };
+        self.state = match redacted {
+            Some(ref redacted) => self.serializer.serialize_entry(key, redacted),
+            None => self.serializer.serialize_entry(key, value),
+        };
+    }
+
+    /// Serializes a field of a declared type, returns `false` for the others.
+    /// Values which can't be coerced go to `<field>_raw` as strings instead.
+    fn serialize_declared(&mut self, field: &Field, value: impl FnOnce() -> Value) -> bool {
+        let redacted = match self.redaction {
+            Some(ref redaction) => redaction.redacts(field.name()),
+            None => false,
+        };
+        if self.is_skipped(field) {
+            return true;
+        }
+        let expected = match self.field_types {
+            Some(ref field_types) if field.name() != "message" && !redacted => {
+                field_types.get(field.name())
+            }
+            _ => None,
+        };
+        let expected = match expected {
+            Some(expected) => expected,
+            None => return false,
+        };
+
+        match vinted_field_types::coerce(expected, value()) {
+            Ok(value) => self.serialize_entry(field, &value),
+            Err(raw) => {
+                if let Some(key) = self.key(Cow::Owned(format!("{}_raw", field.name()))) {
+                    self.write_entry(field, &key, &raw);
+                }
+            }
+        }
+        true
+    }
+}
+
+impl<S: SerializeMap> Visit for VintedFieldVisitor<S> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if !self.serialize_declared(field, || Value::from(value)) {
+            self.serialize_entry(field, &value);
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if !self.serialize_declared(field, || Value::from(value)) {
+            self.serialize_entry(field, &value);
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if !self.serialize_declared(field, || Value::from(value)) {
+            self.serialize_entry(field, &value);
+        }
+    }
+
+    /// Numbers outside of the `i64` range are recorded as strings, as most
+    /// consumers can't index them as numbers.
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        match i64::try_from(value) {
+            Ok(value) => self.record_i64(field, value),
+            Err(_) => self.record_str(field, &value.to_string()),
+        }
+    }
+
+    /// Numbers outside of the `i64` range are recorded as strings, as most
+    /// consumers can't index them as numbers.
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        match i64::try_from(value) {
+            Ok(value) => self.record_i64(field, value),
+            Err(_) => self.record_str(field, &value.to_string()),
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if !self.serialize_declared(field, || Value::from(value)) {
+            self.serialize_entry(field, &value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = &*self.mask(field, value);
+        self.keep_message(field, value);
+        if !self.is_dropped(field, value) && !self.serialize_declared(field, || Value::from(value))
+        {
+            self.serialize_entry(field, value);
+        }
+    }
+
+    fn record_bytes(&mut self, field: &Field, value: &[u8]) {
+        let value = base64(value);
+        if !self.serialize_declared(field, || Value::from(value.as_str())) {
+            self.serialize_entry(field, &value);
+        }
+    }
+
+    /// Errors become an object of their `message` and the messages of their
+    /// sources in `chain`, outermost first. A field declared as a string gets
+    /// the message only.
+    fn record_error(&mut self, field: &Field, value: &(dyn Error + 'static)) {
+        let message = capture_debug(&display(value), self.debug_budget);
+        let message = self.mask(field, &message).into_owned();
+        if self.serialize_declared(field, || Value::from(message.as_str())) {
+            return;
+        }
+
+        let debug_budget = self.debug_budget;
+        let chain: Vec<String> = iter::successors(value.source(), |&source| source.source())
+            .take(MAX_ERROR_SOURCES)
+            .map(|source| capture_debug(&display(source), debug_budget))
+            .map(|source| self.mask(field, &source).into_owned())
+            .collect();
+        self.serialize_entry(
+            field,
+            &SerializableError {
+                message: &message,
+                chain: &chain,
+            },
+        );
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        FORMATTING_OPT.with(|formatting| formatting.set(false));
+        let value = capture_debug(value, self.debug_budget);
+        let value = self.mask(field, &value).into_owned();
+        self.keep_message(field, &value);
+        if !FORMATTING_OPT.with(Cell::get) && self.is_dropped(field, &value) {
+            return;
+        }
+        if field.name() != "message" {
+            self.untyped_fields.push(field.name());
+        }
+        if !self.serialize_declared(field, || Value::from(value.as_str())) {
+            self.serialize_entry(field, &value);
+        }
+    }
+}
+
+struct SerializableError<'a> {
+    message: &'a str,
+    chain: &'a [String],
+}
+
+impl serde::Serialize for SerializableError<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("message", self.message)?;
+        map.serialize_entry("chain", self.chain)?;
+        map.end()
+    }
+}
+
+/// Formats a value through `Debug` without ever holding more than
+/// [`MAX_DEBUG_LEN`] bytes of it, so runaway impls (e.g. of cyclic pointer
+/// graphs) are stopped early instead of exhausting memory. Formatting taking
+/// longer than `budget` is stopped too.
+fn capture_debug(value: &dyn fmt::Debug, budget: Option<Duration>) -> String {
+    let mut writer = CappedWriter {
+        value: String::new(),
+        budget: budget.map(|budget| (Instant::now(), budget)),
+        checked_at: 0,
+        stopped: None,
+    };
+    // An error without a stop is the impl's own, keep what it wrote
+    let _ = write!(writer, "{:?}", value);
+
+    match writer.stopped {
+        Some(Stop::Full) => {
+            writer.value.push_str(TRUNCATED);
+            writer.value
+        }
+        Some(Stop::OutOfTime) => ABORTED.to_owned(),
+        None => writer.value,
+    }
+}
+
+enum Stop {
+    Full,
+    OutOfTime,
+}
+
+/// Fails every write once it stopped, which well-behaved `Debug` impls pass
+/// on right away
+struct CappedWriter {
+    value: String,
+    budget: Option<(Instant, Duration)>,
+    checked_at: usize,
+    stopped: Option<Stop>,
+}
+
+impl fmt::Write for CappedWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.stopped.is_some() {
+            return Err(fmt::Error);
+        }
+
+        let room = MAX_DEBUG_LEN - self.value.len();
+        if s.len() > room {
+            let mut end = room;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.value.push_str(&s[..end]);
+            self.stopped = Some(Stop::Full);
+            return Err(fmt::Error);
+        }
+        self.value.push_str(s);
+
+        if let Some((started, budget)) = self.budget {
+            if self.value.len() - self.checked_at >= BUDGET_CHECK_INTERVAL {
+                self.checked_at = self.value.len();
+                if started.elapsed() > budget {
+                    self.stopped = Some(Stop::OutOfTime);
+                    return Err(fmt::Error);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fields of a span as JSON targets record them, kept in the span's
+/// extensions by [`VintedJsonFields`]
+#[derive(Debug, Default)]
+pub(crate) struct SpanFields(pub(crate) serde_json::Map<String, Value>);
+
+/// Detectors which masked something in the fields of a span, kept next to
+/// its [`SpanFields`]
+#[derive(Debug, Default)]
+pub(crate) struct SpanPii(pub(crate) Detectors);
+
+/// Records span fields with [`VintedFieldVisitor`] into [`SpanFields`], so
+/// they get the same types and `Debug` limits as event fields
+#[derive(Debug, Default)]
+pub(crate) struct VintedJsonFields {
+    debug_budget: Option<Duration>,
+    field_types: Option<Arc<FieldTypes>>,
+    pii: Option<Arc<PiiScanner>>,
+    redaction: Option<Arc<Redaction>>,
+}
+
+impl VintedJsonFields {
+    pub(crate) fn new(debug_budget: Option<Duration>) -> Self {
+        Self {
+            debug_budget,
+            field_types: None,
+            pii: None,
+            redaction: None,
+        }
+    }
+
+    pub(crate) fn with_field_types(self, field_types: Option<Arc<FieldTypes>>) -> Self {
+        Self {
+            field_types,
+            ..self
+        }
+    }
+
+    pub(crate) fn with_pii(self, pii: Option<Arc<PiiScanner>>) -> Self {
+        Self { pii, ..self }
+    }
+
+    pub(crate) fn with_redaction(self, redaction: Option<Arc<Redaction>>) -> Self {
+        Self { redaction, ..self }
+    }
+
+    fn to_json<R: RecordFields>(
+        &self,
+        fields: R,
+    ) -> Result<(serde_json::Map<String, Value>, Detectors), serde_json::Error> {
+        let mut visitor =
+            VintedFieldVisitor::new(serde_json::value::Serializer.serialize_map(None)?)
+                .debug_budget(self.debug_budget)
+                .field_types(self.field_types.clone())
+                .pii(self.pii.clone())
+                .redaction(self.redaction.clone());
+        fields.record(&mut visitor);
+        let pii_found = visitor.pii_found();
+        match visitor.take_serializer()?.end()? {
+            Value::Object(fields) => Ok((fields, pii_found)),
+            _ => Ok((serde_json::Map::new(), pii_found)),
+        }
+    }
+}
+
+impl<S> Layer<S> for VintedJsonFields
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let (fields, pii_found) = self.to_json(attrs).unwrap_or_default();
+        let mut extensions = span.extensions_mut();
+        extensions.insert(SpanFields(fields));
+        if !pii_found.is_empty() {
+            extensions.insert(SpanPii(pii_found));
+        }
+    }
+
+    /// Values recorded later replace the earlier ones of the same field
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let (added, pii_found) = self.to_json(values).unwrap_or_default();
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanFields>() {
+            Some(SpanFields(fields)) => fields.extend(added),
+            None => extensions.insert(SpanFields(added)),
+        }
+        if pii_found.is_empty() {
+            return;
+        }
+        match extensions.get_mut::<SpanPii>() {
+            Some(SpanPii(found)) => found.extend(pii_found),
+            None => extensions.insert(SpanPii(pii_found)),
+        }
+    }
+}
+
+/// Formats no span fields at all, JSON targets take them from [`SpanFields`]
+/// instead
+#[derive(Debug, Default)]
+pub(crate) struct SkipFields;
+
+impl<'writer> FormatFields<'writer> for SkipFields {
+    fn format_fields<R: RecordFields>(&self, _: Writer<'writer>, _: R) -> fmt::Result {
+        Ok(())
+    }
+}
+
+/// Emits a DEBUG notice, once per callsite, about fields which lost their
+/// type so developers can discover typed support gaps.
+pub(crate) fn notice_untyped_fields(
+    meta: &Metadata<'_>,
+    fields: &[&'static str],
+    own_records: &OwnRecords,
+) {
+    static NOTICED: OnceLock<Mutex<HashSet<Identifier>>> = OnceLock::new();
+
+    // Whether the notice itself is enabled can't be told from here, the most
+    // verbose level of every subscriber can
+    if fields.is_empty() || LevelFilter::current() < LevelFilter::DEBUG {
+        return;
+    }
+
+    {
+        let mut noticed = NOTICED.get_or_init(Default::default).lock();
+        if noticed.len() >= MAX_NOTICED_CALLSITES || !noticed.insert(meta.callsite()) {
+            return;
+        }
+    }
+
+    let callsite = format!(
+        "{}:{}",
+        meta.file().unwrap_or_else(|| meta.target()),
+        meta.line().unwrap_or_default()
+    );
+    let fields = fields.join(", ");
+    own_records.log(move || {
+        tracing::debug!(
+            target: "vinted_logger",
+            callsite = callsite.as_str(),
+            fields = fields.as_str(),
+            "fields were recorded through Debug or Display and lost their type"
+        )
+    });
+}
+
+/// Standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+
+        for (i, shift) in [18, 12, 6, 0].iter().enumerate() {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> shift) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, ABORTED, MAX_DEBUG_LEN, TRUNCATED};
+    use crate::{
+        test_support::{wait_until, with_logger, Capture},
+        LoggerBuilder, Target,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        cell::Cell,
+        error::Error,
+        fmt,
+        time::{Duration, Instant},
+    };
+
+    fn record(log: impl FnOnce()) -> Value {
+        record_of(crate::builder("svc-test", Target::ConsoleJson), log)
+    }
+
+    fn record_of(builder: LoggerBuilder, log: impl FnOnce()) -> Value {
+        let capture = Capture::default();
+        with_logger(builder.capture(&capture), log);
+        capture.records().remove(0)
+    }
+
+    #[test]
+    fn primitive_values_keep_their_type() {
+        let record = record(|| {
+            tracing::info!(
+                float = 1.5_f64,
+                negative = i64::MIN,
+                unsigned = u64::MAX,
+                past_i64 = i64::MAX as u64 + 1,
+                flag = false,
+                text = "a \"quoted\" text",
+                "msg"
+            )
+        });
+
+        assert_eq!(record["float"], 1.5);
+        assert_eq!(record["negative"], i64::MIN);
+        assert_eq!(record["unsigned"], u64::MAX);
+        assert_eq!(record["past_i64"], json!(9_223_372_036_854_775_808_u64));
+        assert_eq!(record["flag"], false);
+        assert_eq!(record["text"], "a \"quoted\" text");
+    }
+
+    #[test]
+    fn wide_integers_are_strings_outside_of_the_i64_range() {
+        let record = record(|| {
+            tracing::info!(
+                i128_max_i64 = i128::from(i64::MAX),
+                i128_min_i64 = i128::from(i64::MIN),
+                i128_past_max = i128::from(i64::MAX) + 1,
+                i128_past_min = i128::from(i64::MIN) - 1,
+                i128_min = i128::MIN,
+                u128_small = 5_u128,
+                u128_max_i64 = i64::MAX as u128,
+                u128_past_i64 = i64::MAX as u128 + 1,
+                u128_max = u128::MAX,
+                "msg"
+            )
+        });
+
+        assert_eq!(record["i128_max_i64"], i64::MAX);
+        assert_eq!(record["i128_min_i64"], i64::MIN);
+        assert_eq!(record["i128_past_max"], "9223372036854775808");
+        assert_eq!(record["i128_past_min"], "-9223372036854775809");
+        assert_eq!(record["i128_min"], i128::MIN.to_string());
+        assert_eq!(record["u128_small"], 5);
+        assert_eq!(record["u128_max_i64"], i64::MAX);
+        assert_eq!(record["u128_past_i64"], "9223372036854775808");
+        assert_eq!(record["u128_max"], u128::MAX.to_string());
+    }
+
+    #[test]
+    fn bytes_are_base64() {
+        let record = record(|| {
+            tracing::info!(
+                empty = &b""[..],
+                one = &b"h"[..],
+                two = &b"he"[..],
+                three = &b"hel"[..],
+                binary = &[0_u8, 0xff, 0x10][..],
+                "msg"
+            )
+        });
+
+        assert_eq!(record["empty"], "");
+        assert_eq!(record["one"], "aA==");
+        assert_eq!(record["two"], "aGU=");
+        assert_eq!(record["three"], "aGVs");
+        assert_eq!(record["binary"], "AP8Q");
+        assert_eq!(base64(b"hello world"), "aGVsbG8gd29ybGQ=");
+    }
+
+    #[derive(Debug)]
+    struct Failure(&'static str, Option<Box<Failure>>);
+
+    impl fmt::Display for Failure {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for Failure {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            self.1.as_deref().map(|source| source as _)
+        }
+    }
+
+    #[test]
+    fn errors_carry_their_chain() {
+        let error = Failure(
+            "request failed",
+            Some(Box::new(Failure("connection reset", None))),
+        );
+        let error: &(dyn Error + 'static) = &error;
+        let record = record(|| tracing::error!(error, "msg"));
+
+        assert_eq!(
+            record["error"],
+            json!({"message": "request failed", "chain": ["connection reset"]})
+        );
+    }
+
+    #[test]
+    fn every_source_of_an_error_is_in_its_chain() {
+        let error = Failure(
+            "checkout failed",
+            Some(Box::new(Failure(
+                "payment declined",
+                Some(Box::new(Failure("card expired", None))),
+            ))),
+        );
+        let error: &(dyn Error + 'static) = &error;
+        let record = record(|| tracing::error!(error, "msg"));
+
+        assert_eq!(record["error"]["message"], "checkout failed");
+        assert_eq!(
+            record["error"]["chain"],
+            json!(["payment declined", "card expired"])
+        );
+    }
+
+    #[test]
+    fn errors_recorded_as_strings_stay_strings() {
+        let error = Failure("request failed", None);
+        let record = record(|| tracing::error!(error = %error, cause = "timeout", "msg"));
+
+        assert_eq!(record["error"], "request failed");
+        assert_eq!(record["cause"], "timeout");
+    }
+
+    #[test]
+    fn debug_and_display_values_are_strings() {
+        let record = record(|| {
+            tracing::info!(
+                list = ?vec![1, 2],
+                shown = %std::net::Ipv4Addr::LOCALHOST,
+                text = ?"debug",
+                "msg"
+            )
+        });
+
+        assert_eq!(record["list"], "[1, 2]");
+        assert_eq!(record["shown"], "127.0.0.1");
+        assert_eq!(record["text"], "\"debug\"");
+    }
+
+    #[test]
+    fn debug_values_stay_on_one_line() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Order {
+            id: u64,
+            items: Vec<&'static str>,
+        }
+        let order = Order {
+            id: 7,
+            items: vec!["book", "pen"],
+        };
+        let record = record(|| tracing::info!(order = ?order, "msg"));
+
+        assert_eq!(
+            record["order"],
+            r#"Order { id: 7, items: ["book", "pen"] }"#
+        );
+    }
+
+    #[test]
+    fn options_are_recorded_through_opt() {
+        let (some, none): (Option<u64>, Option<u64>) = (Some(7), None);
+        let record =
+            record(|| tracing::info!(some = crate::opt(&some), none = crate::opt(&none), "msg"));
+
+        assert_eq!(record["some"], "7");
+        assert!(record.get("none").is_none(), "{}", record);
+    }
+
+    #[test]
+    fn none_and_empty_strings_are_kept_unless_dropped() {
+        let log = || {
+            let none: Option<u64> = None;
+            tracing::info!(none = ?none, empty = "", some = ?Some(7), "")
+        };
+
+        let record = record(log);
+        assert_eq!(record["none"], "None");
+        assert_eq!(record["empty"], "");
+        assert_eq!(record["some"], "Some(7)");
+
+        let builder = crate::builder("svc-test", Target::ConsoleJson).drop_empty_fields();
+        let record = record_of(builder, log);
+        assert!(record.get("none").is_none(), "{}", record);
+        assert!(record.get("empty").is_none(), "{}", record);
+        assert_eq!(record["some"], "Some(7)");
+        // Even an empty message is kept
+        assert_eq!(record["message"], "");
+    }
+
+    #[test]
+    fn none_strings_recorded_through_opt_are_kept() {
+        let builder = crate::builder("svc-test", Target::ConsoleJson).drop_empty_fields();
+        let record = record_of(builder, || {
+            let name = Some("None");
+            tracing::info!(name = crate::opt(&name), raw = "None", "msg")
+        });
+
+        assert_eq!(record["name"], "None");
+        // The heuristic can't tell other values apart
+        assert!(record.get("raw").is_none(), "{}", record);
+    }
+
+    #[test]
+    fn untyped_fields_are_noticed_once_per_callsite() {
+        const NOTICE: &str = "fields were recorded through Debug or Display and lost their type";
+
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .default_level(tracing::Level::DEBUG)
+            .capture(&capture);
+        let notices = || {
+            capture
+                .records()
+                .into_iter()
+                .filter(|record| record["message"] == NOTICE)
+                .collect::<Vec<_>>()
+        };
+        with_logger(builder, || {
+            for _ in 0..3 {
+                tracing::info!(list = ?vec![1], shown = %1, typed = 1, "untyped");
+            }
+            // Logged from a thread of its own
+            assert!(wait_until(|| !notices().is_empty()));
+            tracing::info!(typed = 1, "typed");
+        });
+
+        let notices = notices();
+        assert_eq!(notices.len(), 1, "{:?}", notices);
+        assert_eq!(notices[0]["level"], "DEBUG");
+        assert_eq!(notices[0]["target"], "vinted_logger");
+        assert_eq!(notices[0]["fields"], "list, shown");
+        let callsite = notices[0]["callsite"].as_str().unwrap();
+        assert!(
+            callsite.starts_with("src/vinted_field_visitor.rs:"),
+            "{}",
+            callsite
+        );
+    }
+
+    #[test]
+    fn untyped_fields_are_not_noticed_above_debug() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, || {
+            tracing::info!(list = ?vec![1], "untyped");
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        });
+
+        assert_eq!(capture.records().len(), 1, "{:?}", capture.lines());
+    }
+
+    /// Never ends on its own, like `Debug` of a cyclic pointer graph, and
+    /// counts how often it was written to
+    struct Endless<'a> {
+        writes: &'a Cell<usize>,
+        pause: Duration,
+    }
+
+    impl fmt::Debug for Endless<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let chunk = "node -> ".repeat(128);
+            loop {
+                self.writes.set(self.writes.get() + 1);
+                f.write_str(&chunk)?;
+                std::thread::sleep(self.pause);
+            }
+        }
+    }
+
+    #[test]
+    fn endless_debug_values_are_cut_off() {
+        let writes = Cell::new(0);
+        let endless = Endless {
+            writes: &writes,
+            pause: Duration::ZERO,
+        };
+        // Room for both values in one line
+        let builder = crate::builder("svc-test", Target::ConsoleJson).console_max_line(1 << 20);
+        let record = record_of(builder, || {
+            let _span = tracing::info_span!("graph", root = ?endless).entered();
+            tracing::info!(value = ?endless, "msg")
+        });
+
+        for value in &[&record["value"], &record["span"]["root"]] {
+            let value = value.as_str().unwrap();
+            assert_eq!(value.len(), MAX_DEBUG_LEN + TRUNCATED.len());
+            assert!(value.ends_with(TRUNCATED), "{}", &value[value.len() - 40..]);
+        }
+        // Formatting stopped at the cap, twice, instead of going on
+        let chunks = MAX_DEBUG_LEN / 1024 + 1;
+        assert!(writes.get() <= 2 * chunks, "{} writes", writes.get());
+    }
+
+    #[test]
+    fn slow_debug_values_are_aborted_after_the_budget() {
+        let writes = Cell::new(0);
+        let slow = Endless {
+            writes: &writes,
+            pause: Duration::from_millis(5),
+        };
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .debug_time_budget(Duration::from_millis(30));
+        let start = Instant::now();
+        let record = record_of(builder, || tracing::info!(value = ?slow, "msg"));
+
+        assert_eq!(record["value"], ABORTED);
+        // Long before the cap would have stopped it
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+        assert!(
+            writes.get() < MAX_DEBUG_LEN / 1024,
+            "{} writes",
+            writes.get()
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use crate::{
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_fallback, vinted_stats,
+};
+use parking_lot::Mutex;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Default size of [`LoggerBuilder::file_rotation`](crate::LoggerBuilder::file_rotation)
+pub const DEFAULT_FILE_MAX_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Default count of rotated files kept by
+/// [`LoggerBuilder::file_rotation`](crate::LoggerBuilder::file_rotation)
+pub const DEFAULT_FILE_MAX_FILES: usize = 5;
+
+/// Appends records to a file, rotating it to `<path>.1`, `<path>.2`, … once
+/// it would grow beyond a size, and deleting the oldest beyond a count.
+///
+/// Only complete lines are written, each with a single write, so the file
+/// never holds a partial record followed by another one on the same line.
+/// Writers of every thread share the file through a lock, which rotation
+/// holds as well.
+#[derive(Debug)]
+pub(crate) struct VintedFileWriter {
+    file: Mutex<LogFile>,
+}
+
+impl VintedFileWriter {
+    /// Opens `path` for appending, creating it and its directory if needed
+    pub(crate) fn new(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let (file, size) = open(&path)?;
+
+        Ok(Self {
+            file: Mutex::new(LogFile {
+                path,
+                file: Some(file),
+                size,
+                max_size: max_size.max(1),
+                max_files,
+            }),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    /// `None` after rotation failed to open a new file, retried on the next
+    /// write
+    file: Option<File>,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl LogFile {
+    fn write_lines(&mut self, lines: &[u8]) -> io::Result<()> {
+        if self.file.is_none() || (self.size > 0 && self.size + lines.len() as u64 > self.max_size)
+        {
+            self.rotate()?;
+        }
+
+        let file = match self.file {
+            Some(ref mut file) => file,
+            None => return Err(io::Error::other("log file isn't open")),
+        };
+        file.write_all(lines)?;
+        self.size += lines.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, deletes the ones beyond
+    /// `max_files` and starts a new file at `path`
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.file.take().is_some() {
+            if self.max_files == 0 {
+                fs::remove_file(&self.path)?;
+            } else {
+                let _ = fs::remove_file(rotated(&self.path, self.max_files));
+                for n in (1..self.max_files).rev() {
+                    let from = rotated(&self.path, n);
+                    if from.exists() {
+                        fs::rename(&from, rotated(&self.path, n + 1))?;
+                    }
+                }
+                fs::rename(&self.path, rotated(&self.path, 1))?;
+            }
+        }
+
+        let (file, size) = open(&self.path)?;
+        self.file = Some(file);
+        self.size = size;
+        Ok(())
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", n));
+    PathBuf::from(rotated)
+}
+
+/// Opens `path` for appending, ending a partial last line left by a crash
+/// so the next record starts on its own line
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+
+    let mut size = file.metadata()?.len();
+    if size > 0 {
+        let mut last = [0; 1];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last != *b"\n" {
+            file.write_all(b"\n")?;
+            size += 1;
+        }
+    }
+    Ok((file, size))
+}
+
+impl<'a> MakeWriter<'a> for VintedFileWriter {
+    type Writer = FileWriterImpl<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        FileWriterImpl {
+            file: &self.file,
+            pending: Vec::new(),
+        }
+    }
+}
+
+/// Holds back what follows the last newline written until the line is
+/// complete, or until it's dropped
+pub(crate) struct FileWriterImpl<'a> {
+    file: &'a Mutex<LogFile>,
+    pending: Vec<u8>,
+}
+
+impl FileWriterImpl<'_> {
+    fn write_lines(&self, lines: &[u8]) {
+        let mut file = self.file.lock();
+        if let Err(e) = file.write_lines(lines) {
+            vinted_stats::file_write_failed();
+            vinted_diagnostics::report(
+                Diagnostic::SendFailed,
+                format_args!(
+                    "Log record can't be written to {}: {}",
+                    file.path.display(),
+                    e
+                ),
+            );
+            vinted_fallback::undelivered(lines);
+        }
+    }
+}
+
+impl io::Write for FileWriterImpl<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = match buf.iter().rposition(|&byte| byte == b'\n') {
+            Some(end) => end + 1,
+            None => {
+                self.pending.extend_from_slice(buf);
+                return Ok(buf.len());
+            }
+        };
+
+        if self.pending.is_empty() {
+            self.write_lines(&buf[..end]);
+        } else {
+            let mut lines = std::mem::take(&mut self.pending);
+            lines.extend_from_slice(&buf[..end]);
+            self.write_lines(&lines);
+        }
+        self.pending.extend_from_slice(&buf[end..]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for FileWriterImpl<'_> {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let mut line = std::mem::take(&mut self.pending);
+            line.push(b'\n');
+            self.write_lines(&line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rotated, VintedFileWriter};
+    use crate::{
+        test_support::{check_record, with_logger},
+        Target,
+    };
+    use std::{
+        fs,
+        io::Write,
+        path::{Path, PathBuf},
+        thread,
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// Empty directory of its own for `test`, under the system's
+    fn dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("vinted-logger-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn files_rotate_past_the_size_limit() {
+        let dir = dir("rotate");
+        let path = dir.join("current.json");
+        let builder = crate::builder("svc-test", Target::FileJson)
+            .file_path(&path)
+            .file_rotation(2_048, 3);
+
+        with_logger(builder, || {
+            for n in 0..100 {
+                tracing::info!(n, "rotating");
+            }
+        });
+
+        let mut numbers = Vec::new();
+        for file in [
+            rotated(&path, 3),
+            rotated(&path, 2),
+            rotated(&path, 1),
+            path.clone(),
+        ] {
+            let size = fs::metadata(&file).unwrap().len();
+            assert!(size <= 2_048, "{} is {} bytes", file.display(), size);
+            for line in lines(&file) {
+                let record = check_record(format!("{}\n", line).as_bytes())
+                    .unwrap_or_else(|e| panic!("{}: {}", e, line));
+                numbers.push(record["n"].as_u64().unwrap());
+            }
+        }
+        assert!(!rotated(&path, 4).exists(), "only 3 rotated files are kept");
+        // The newest records, in order, the oldest were deleted
+        assert_eq!(numbers.last(), Some(&99));
+        assert!(numbers.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert!(numbers[0] > 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writers_only_write_whole_lines() {
+        let dir = dir("concurrent");
+        let path = dir.join("current.json");
+        let writer = VintedFileWriter::new(path.clone(), 4_096, 100).unwrap();
+
+        thread::scope(|scope| {
+            for thread in 0..8 {
+                let writer = &writer;
+                scope.spawn(move || {
+                    for n in 0..200 {
+                        let line = format!("{{\"thread\":{},\"n\":{}}}\n", thread, n);
+                        let (head, tail) = line.split_at(line.len() / 2);
+                        // A record written in parts still lands whole
+                        let mut writer = writer.make_writer();
+                        writer.write_all(head.as_bytes()).unwrap();
+                        writer.write_all(tail.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+
+        let mut total = 0;
+        for file in fs::read_dir(&dir).unwrap() {
+            for line in lines(&file.unwrap().path()) {
+                let record: serde_json::Value =
+                    serde_json::from_str(&line).unwrap_or_else(|e| panic!("{}: {}", e, line));
+                assert!(record["thread"].is_u64() && record["n"].is_u64());
+                total += 1;
+            }
+        }
+        assert_eq!(total, 8 * 200);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn partial_lines_left_by_a_crash_are_ended() {
+        let dir = dir("partial");
+        let path = dir.join("current.json");
+        fs::write(&path, "{\"n\":1}\n{\"n\":").unwrap();
+
+        let writer = VintedFileWriter::new(path.clone(), 4_096, 1).unwrap();
+        writer.make_writer().write_all(b"{\"n\":2}\n").unwrap();
+
+        assert_eq!(lines(&path), ["{\"n\":1}", "{\"n\":", "{\"n\":2}"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
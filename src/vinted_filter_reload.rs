@@ -0,0 +1,260 @@
+use crate::{
+    log_point,
+    vinted_diagnostics::{self, Diagnostic},
+};
+use std::{
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tracing_subscriber::{reload, EnvFilter};
+
+static HANDLE: OnceLock<FilterHandle> = OnceLock::new();
+
+/// Target of `logger_config_changed` and `logger_config_rejected` records
+pub(crate) const TARGET: &str = "vinted::config";
+
+/// Changes the level filter of the installed logger at runtime, e.g. to
+/// bump a service to DEBUG for a few minutes, see [`filter_handle`]
+///
+/// Filters apply on top of
+/// [`LoggerBuilder::deny_below`](crate::LoggerBuilder::deny_below), which
+/// they can't loosen.
+#[derive(Clone)]
+pub struct FilterHandle {
+    reload: Arc<dyn Reload>,
+}
+
+impl FilterHandle {
+    /// Replaces the filter with `directives`, in `RUST_LOG` syntax, e.g.
+    /// `debug` or `info,my_crate::db=trace`
+    ///
+    /// A filter which changes is logged at INFO with target `vinted::config`
+    /// and message `logger_config_changed`, with the `setting` (`filter`),
+    /// its `old` and `new` value and the `source` of the change, `api` here
+    /// and `file` for [`watch_file`](Self::watch_file). Directives which
+    /// can't be applied are logged at WARN as `logger_config_rejected`, with
+    /// the `value` and the `error`. Both are written to stderr too, so they
+    /// show whatever the filter lets through.
+    ///
+    /// ```
+    /// vinted_logger::builder("svc-search", vinted_logger::Target::Console).try_init()?;
+    /// if let Some(filter) = vinted_logger::filter_handle() {
+    ///     filter.set_filter("debug")?;
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn set_filter(&self, directives: &str) -> Result<(), FilterError> {
+        self.apply(directives, Source::Api)
+    }
+
+    fn apply(&self, directives: &str, source: Source<'_>) -> Result<(), FilterError> {
+        let old = self.user_directives();
+        match self.reload_filter(directives) {
+            Ok(()) => {
+                let new = self.user_directives();
+                if new != old {
+                    changed("filter", &old, &new, source);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                rejected("filter", directives, source, &e);
+                Err(e)
+            }
+        }
+    }
+
+    fn reload_filter(&self, directives: &str) -> Result<(), FilterError> {
+        let error = |reason: String| FilterError {
+            input: directives.to_owned(),
+            reason,
+        };
+
+        let filter = EnvFilter::try_new(directives).map_err(|e| error(e.to_string()))?;
+        self.reload
+            .reload(with_log_points(filter))
+            .map_err(|e| error(e.to_string()))
+    }
+
+    /// Directives of the filter in use
+    pub fn filter(&self) -> String {
+        self.reload.current().unwrap_or_default()
+    }
+
+    /// Directives of the filter in use, without the one of log points
+    fn user_directives(&self) -> String {
+        let log_points = log_points_directive();
+        self.filter()
+            .split(',')
+            .filter(|&directive| directive != log_points)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Applies the directives in the file at `path` whenever they change,
+    /// checking every `interval` from a background thread, e.g. to lower
+    /// the level by writing `debug` to `/etc/vinted-logger/level`
+    ///
+    /// A missing or empty file leaves the filter alone, invalid directives
+    /// are reported with a WARN record, see [`set_filter`](Self::set_filter).
+    /// Changed and rejected records carry the `path` of the file as well.
+    pub fn watch_file(&self, path: impl Into<PathBuf>, interval: Duration) {
+        let handle = self.clone();
+        let path = path.into();
+        let _ = ::std::thread::Builder::new()
+            .name("vinted-logger-filter-watch".to_owned())
+            .spawn(move || {
+                let mut applied = String::new();
+                loop {
+                    if let Ok(directives) = fs::read_to_string(&path) {
+                        let directives = directives.trim();
+                        if !directives.is_empty() && directives != applied {
+                            let _ = handle.apply(directives, Source::File(&path));
+                            applied = directives.to_owned();
+                        }
+                    }
+                    ::std::thread::sleep(interval);
+                }
+            });
+    }
+}
+
+impl fmt::Debug for FilterHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterHandle")
+            .field("filter", &self.filter())
+            .finish()
+    }
+}
+
+/// Returns the [`FilterHandle`] of the logger installed by
+/// [`LoggerBuilder::try_init`](crate::LoggerBuilder::try_init), `None`
+/// before it's installed
+pub fn filter_handle() -> Option<FilterHandle> {
+    HANDLE.get().cloned()
+}
+
+/// Returned by [`FilterHandle::set_filter`] for directives which can't be
+/// applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    input: String,
+    reason: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log filter `{}`: {}", self.input, self.reason)
+    }
+}
+
+impl Error for FilterError {}
+
+/// What changed a setting at runtime
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    Api,
+    File(&'a Path),
+}
+
+impl<'a> Source<'a> {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Api => "api",
+            Self::File(_) => "file",
+        }
+    }
+
+    /// Path of the file, as a string so that records keep it typed
+    fn path(self) -> Option<String> {
+        match self {
+            Self::Api => None,
+            Self::File(path) => Some(path.display().to_string()),
+        }
+    }
+}
+
+/// Logs a setting changed at runtime, to stderr too
+fn changed(setting: &str, old: &str, new: &str, source: Source<'_>) {
+    tracing::info!(
+        target: TARGET,
+        setting,
+        old,
+        new,
+        source = source.name(),
+        path = source.path().as_deref(),
+        "logger_config_changed"
+    );
+    vinted_diagnostics::report(
+        Diagnostic::ConfigChanged,
+        format_args!(
+            "Logger setting `{}` changed from `{}` to `{}` by {}",
+            setting,
+            old,
+            new,
+            source.name()
+        ),
+    );
+}
+
+/// Logs a setting which couldn't be changed at runtime, to stderr too
+fn rejected(setting: &str, value: &str, source: Source<'_>, error: &FilterError) {
+    tracing::warn!(
+        target: TARGET,
+        setting,
+        value,
+        source = source.name(),
+        path = source.path().as_deref(),
+        error = error.reason.as_str(),
+        "logger_config_rejected"
+    );
+    vinted_diagnostics::report(
+        Diagnostic::ConfigChanged,
+        format_args!(
+            "Logger setting `{}` wasn't changed by {}: {}",
+            setting,
+            source.name(),
+            error
+        ),
+    );
+}
+
+/// Log points are switched on and off by name instead, whatever the filter
+pub(crate) fn with_log_points(filter: EnvFilter) -> EnvFilter {
+    filter.add_directive(
+        log_points_directive()
+            .parse()
+            .expect("log point directive is valid"),
+    )
+}
+
+fn log_points_directive() -> String {
+    format!("{}=debug", log_point::TARGET)
+}
+
+/// Keeps the handle of an installed logger for [`filter_handle`]
+pub(crate) fn installed<S: 'static>(handle: reload::Handle<EnvFilter, S>) {
+    let _ = HANDLE.set(FilterHandle {
+        reload: Arc::new(handle),
+    });
+}
+
+/// [`reload::Handle`] without the type of the subscriber it's in
+trait Reload: Send + Sync {
+    fn reload(&self, filter: EnvFilter) -> Result<(), reload::Error>;
+
+    fn current(&self) -> Option<String>;
+}
+
+impl<S: 'static> Reload for reload::Handle<EnvFilter, S> {
+    fn reload(&self, filter: EnvFilter) -> Result<(), reload::Error> {
+        reload::Handle::reload(self, filter)
+    }
+
+    fn current(&self) -> Option<String> {
+        self.with_current(ToString::to_string).ok()
+    }
+}
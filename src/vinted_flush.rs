@@ -0,0 +1,235 @@
+use crate::{
+    vinted_canary::{Canary, CanaryConfig},
+    vinted_drain::{self, DrainConfig},
+};
+use parking_lot::{const_mutex, Condvar, Mutex};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+/// Default of [`LoggerBuilder::flush_timeout`](crate::LoggerBuilder::flush_timeout)
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+static WRITERS: Mutex<Vec<Weak<Progress>>> = const_mutex(Vec::new());
+static PENDING: Mutex<Vec<Weak<dyn Pending>>> = const_mutex(Vec::new());
+
+/// A writer holding records back, e.g. the summaries of coalesced repeats,
+/// which [`flush`] has it write first
+pub(crate) trait Pending: Send + Sync {
+    fn write_pending(&self);
+}
+
+/// Has [`flush`] write what `pending` holds back, as long as it's alive
+pub(crate) fn register_pending(pending: Weak<dyn Pending>) {
+    let mut all = PENDING.lock();
+    all.retain(|pending| pending.strong_count() > 0);
+    all.push(pending);
+}
+
+/// How far the thread of a background writer got with the records queued for
+/// it, so [`flush`] can wait for it
+#[derive(Debug)]
+pub(crate) struct Progress {
+    counts: Mutex<Counts>,
+    done: Condvar,
+}
+
+#[derive(Debug)]
+struct Counts {
+    queued: u64,
+    done: u64,
+    /// `queued` as of the last ERROR record, which is done once `done` gets
+    /// there since threads go through their records in order
+    last_error: u64,
+    stopped: bool,
+}
+
+impl Progress {
+    /// Creates the progress of a new writer, which [`flush`] waits for as
+    /// long as it's alive
+    pub(crate) fn register() -> Arc<Self> {
+        let progress = Arc::new(Self {
+            counts: Mutex::new(Counts {
+                queued: 0,
+                done: 0,
+                last_error: 0,
+                stopped: false,
+            }),
+            done: Condvar::new(),
+        });
+
+        let mut writers = WRITERS.lock();
+        writers.retain(|writer| writer.strong_count() > 0);
+        writers.push(Arc::downgrade(&progress));
+        progress
+    }
+
+    /// Counts a record about to be queued. Called before queueing, so the
+    /// thread can never be done with more records than were queued. `error`
+    /// records are waited for longer while draining, see
+    /// [`LoggerGuard::drain`].
+    pub(crate) fn queued(&self, error: bool) {
+        let mut counts = self.counts.lock();
+        counts.queued += 1;
+        if error {
+            counts.last_error = counts.queued;
+        }
+    }
+
+    /// Takes back [`queued`](Self::queued) for a record which wasn't queued
+    /// after all
+    pub(crate) fn not_queued(&self) {
+        self.counts.lock().queued -= 1;
+        self.done.notify_all();
+    }
+
+    /// Counts records the thread is done with, whether they were sent, failed
+    /// or were dropped
+    pub(crate) fn done(&self, records: u64) {
+        self.counts.lock().done += records;
+        self.done.notify_all();
+    }
+
+    /// The thread stopped, nothing it didn't send yet ever will be
+    pub(crate) fn stopped(&self) {
+        self.counts.lock().stopped = true;
+        self.done.notify_all();
+    }
+
+    /// Waits until the thread is done with every record queued so far, or
+    /// with every ERROR record if `errors`
+    fn wait(&self, deadline: Instant, errors: bool) -> bool {
+        let mut counts = self.counts.lock();
+        // An ERROR record which wasn't queued after all took its place back
+        let target = match errors {
+            true => counts.last_error.min(counts.queued),
+            false => counts.queued,
+        };
+        while counts.done < target && !counts.stopped {
+            if self.done.wait_until(&mut counts, deadline).timed_out() {
+                return counts.done >= target;
+            }
+        }
+        counts.done >= target
+    }
+}
+
+/// Waits up to `timeout` until every record logged so far has been sent,
+/// e.g. before a short-lived job exits. Returns `false` if some are still
+/// queued once it's over.
+///
+/// `UdpJson` and `TcpJson` records are sent from background threads, which
+/// die with the process, and so are console records with
+/// [`LoggerBuilder::non_blocking`](crate::LoggerBuilder::non_blocking).
+/// Otherwise console records are written right away, stdout and stderr are
+/// flushed too. The summaries of [coalesced
+/// repeats](crate::LoggerBuilder::coalesce_repeats) are written first.
+///
+/// ```
+/// vinted_logger::builder("nightly-export", vinted_logger::Target::UdpJson).try_init()?;
+/// tracing::info!("export done");
+/// vinted_logger::flush(std::time::Duration::from_secs(1));
+/// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+/// ```
+pub fn flush(timeout: Duration) -> bool {
+    wait(Instant::now() + timeout, false)
+}
+
+fn wait(deadline: Instant, errors: bool) -> bool {
+    let pending: Vec<_> = PENDING.lock().iter().filter_map(Weak::upgrade).collect();
+    for pending in pending {
+        pending.write_pending();
+    }
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+
+    let writers: Vec<_> = WRITERS.lock().iter().filter_map(Weak::upgrade).collect();
+    let mut flushed = true;
+    for writer in writers {
+        flushed &= writer.wait(deadline, errors);
+    }
+    flushed
+}
+
+/// Flushes the logger when dropped, see
+/// [`LoggerBuilder::try_init_with_guard`](crate::LoggerBuilder::try_init_with_guard)
+///
+/// Dropping it doesn't uninstall the logger, records logged afterwards are
+/// still sent, but nothing waits for them. Canaries, see
+/// [`LoggerBuilder::canary`](crate::LoggerBuilder::canary), are sent as long
+/// as the guard lives.
+#[derive(Debug)]
+#[must_use = "dropping the guard flushes the logger right away"]
+pub struct LoggerGuard {
+    timeout: Duration,
+    error_timeout: Duration,
+    _canary: Option<Canary>,
+}
+
+impl LoggerGuard {
+    pub(crate) fn new(timeout: Duration, drain: DrainConfig, canary: Option<CanaryConfig>) -> Self {
+        Self {
+            timeout,
+            error_timeout: drain.error_timeout,
+            _canary: canary.map(Canary::start),
+        }
+    }
+
+    /// Waits until every record logged so far has been sent, up to the
+    /// [flush timeout](crate::LoggerBuilder::flush_timeout). Returns `false`
+    /// if some are still queued once it's over.
+    pub fn flush(&self) -> bool {
+        flush(self.timeout)
+    }
+
+    /// Starts draining, e.g. from the SIGTERM handler of a service about to
+    /// be replaced by another deployment slot, then flushes. Returns `false`
+    /// if some records are still queued once it's over.
+    ///
+    /// From then on batches wait at most
+    /// [`drain_batch_interval`](crate::LoggerBuilder::drain_batch_interval)
+    /// for more records, for the rest of the process. The first call logs an
+    /// INFO record of target [`DRAIN_TARGET`](crate::DRAIN_TARGET) with
+    /// message `draining_started`, and [`logger_stats`](crate::logger_stats)
+    /// tells when it was in `draining_since`.
+    ///
+    /// The flush waits up to the [flush
+    /// timeout](crate::LoggerBuilder::flush_timeout) for every record, and
+    /// up to the [drain error
+    /// timeout](crate::LoggerBuilder::drain_error_timeout), counted from the
+    /// call too, for ERROR records.
+    ///
+    /// ```no_run
+    /// let guard = vinted_logger::builder("svc-search", vinted_logger::Target::UdpJson)
+    ///     .try_init_with_guard()?;
+    /// // Once SIGTERM is received
+    /// guard.drain();
+    /// # Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    /// ```
+    pub fn drain(&self) -> bool {
+        let start = Instant::now();
+        if vinted_drain::start() {
+            tracing::info!(target: vinted_drain::DRAIN_TARGET, "draining_started");
+        }
+
+        if flush(self.timeout) {
+            return true;
+        }
+        wait(start + self.error_timeout, true);
+        flush(Duration::ZERO)
+    }
+
+    /// Subscribes to changes in how records are delivered, see
+    /// [`logger_events`](crate::logger_events)
+    pub fn events(&self) -> crate::LoggerEvents {
+        crate::logger_events()
+    }
+}
+
+impl Drop for LoggerGuard {
+    fn drop(&mut self) {
+        flush(self.timeout);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{vinted_escaping::Entries, vinted_timestamp};
+use serde_json::Value;
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Most chunks of a GELF message, Graylog drops messages of more
+pub const GELF_MAX_CHUNKS: usize = 128;
+
+/// Magic bytes, message id, sequence number and count
+const CHUNK_HEADER_LEN: usize = 12;
+
+/// Turns a `VintedJson` record into a GELF 1.1 message: the message becomes
+/// `short_message`, the level its syslog severity and the other fields
+/// additional fields, prefixed with `_`
+///
+/// Objects, arrays and booleans become strings, as GELF values are strings
+/// or numbers only, and nulls are left out.
+pub(crate) fn convert(record: &[u8]) -> Option<Vec<u8>> {
+    let Entries(entries) = serde_json::from_slice(record).ok()?;
+
+    let mut message = String::new();
+    let mut host = None;
+    let mut timestamp = None;
+    let mut level = None;
+    let mut fields = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        match (key.as_str(), value) {
+            ("message", Value::String(value)) => message = value,
+            ("host", Value::String(value)) => host = Some(value),
+            ("@timestamp", Value::String(value)) => {
+                timestamp = vinted_timestamp::parse_rfc3339(&value)
+            }
+            ("@timestamp", Value::Number(millis)) => {
+                timestamp = millis
+                    .as_u64()
+                    .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+            }
+            ("level", Value::String(value)) => level = Some(severity(&value)),
+            (_, Value::Null) => {}
+            (_, value) => fields.push((additional_key(&key), additional_value(value))),
+        }
+    }
+
+    let timestamp = timestamp
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // Milliseconds, which is what Graylog keeps
+    let timestamp = timestamp.as_secs() as f64 + f64::from(timestamp.subsec_millis()) / 1_000.0;
+    let host = host.unwrap_or_else(|| gethostname::gethostname().to_string_lossy().into_owned());
+
+    let mut gelf = vec![
+        ("version".to_owned(), Value::from("1.1")),
+        ("host".to_owned(), Value::from(host)),
+        ("short_message".to_owned(), Value::from(message)),
+        ("timestamp".to_owned(), Value::from(timestamp)),
+    ];
+    gelf.extend(level.map(|level| ("level".to_owned(), Value::from(level))));
+    gelf.extend(fields);
+    serde_json::to_vec(&Entries(gelf)).ok()
+}
+
+/// Syslog severity of a `tracing` level
+fn severity(level: &str) -> u8 {
+    match level {
+        "ERROR" => 3,
+        "WARN" => 4,
+        "INFO" => 6,
+        _ => 7,
+    }
+}
+
+/// `_<key>`, with characters GELF doesn't allow in keys replaced by `_`.
+/// `_id` is reserved, so an `id` field becomes `__id`.
+fn additional_key(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if key == "id" {
+        "__id".to_owned()
+    } else {
+        format!("_{}", key)
+    }
+}
+
+fn additional_value(value: Value) -> Value {
+    match value {
+        value @ (Value::String(_) | Value::Number(_)) => value,
+        value => Value::String(value.to_string()),
+    }
+}
+
+/// Splits `message` into GELF chunks of at most `max_size` bytes each,
+/// header included. Returns `None` when it takes more than
+/// [`GELF_MAX_CHUNKS`] of them.
+pub(crate) fn chunks(message: &[u8], max_size: usize) -> Option<Vec<Vec<u8>>> {
+    let payload = max_size
+        .checked_sub(CHUNK_HEADER_LEN)
+        .filter(|&len| len > 0)?;
+    let count = message.len().div_ceil(payload);
+    if count > GELF_MAX_CHUNKS {
+        return None;
+    }
+
+    let id = message_id().to_be_bytes();
+    let chunks = message
+        .chunks(payload)
+        .enumerate()
+        .map(|(sequence, part)| {
+            let mut chunk = Vec::with_capacity(CHUNK_HEADER_LEN + part.len());
+            chunk.extend_from_slice(&[0x1e, 0x0f]);
+            chunk.extend_from_slice(&id);
+            chunk.push(sequence as u8);
+            chunk.push(count as u8);
+            chunk.extend_from_slice(part);
+            chunk
+        })
+        .collect();
+    Some(chunks)
+}
+
+/// Ids unique to the process, and unlikely to collide with those of other
+/// processes sending to the same input
+fn message_id() -> u64 {
+    static SEED: OnceLock<u64> = OnceLock::new();
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    let seed = *SEED.get_or_init(|| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (u64::from(process::id()) << 32) ^ now.as_nanos() as u64
+    });
+    seed.wrapping_add(NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunks, convert, CHUNK_HEADER_LEN, GELF_MAX_CHUNKS};
+    use crate::{test_support::with_logger, LogDestination, Target, WireFormat};
+    use serde_json::{json, Value};
+    use std::{net::UdpSocket, time::Duration};
+
+    /// Message of `chunks`, checking their headers
+    fn reassemble(mut chunks: Vec<Vec<u8>>) -> Vec<u8> {
+        let count = chunks.len();
+        assert!((2..=GELF_MAX_CHUNKS).contains(&count), "{} chunks", count);
+        chunks.sort_by_key(|chunk| chunk[10]);
+
+        let id = &chunks[0][2..10];
+        let mut message = Vec::new();
+        for (sequence, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk[..2], [0x1e, 0x0f]);
+            assert_eq!(&chunk[2..10], id);
+            assert_eq!(usize::from(chunk[10]), sequence);
+            assert_eq!(usize::from(chunk[11]), count);
+            message.extend_from_slice(&chunk[CHUNK_HEADER_LEN..]);
+        }
+        message
+    }
+
+    #[test]
+    fn records_become_gelf_messages() {
+        let record = br#"{"@timestamp":"2024-03-01T12:00:00.123456Z","level":"WARN","host":"web-1","message":"slow","id":7,"user.name":"jane","tags":["a"],"ok":true,"none":null}"#;
+        let gelf: Value = serde_json::from_slice(&convert(record).unwrap()).unwrap();
+
+        assert_eq!(
+            gelf,
+            json!({
+                "version": "1.1",
+                "host": "web-1",
+                "short_message": "slow",
+                "timestamp": 1_709_294_400.123,
+                "level": 4,
+                "__id": 7,
+                "_user.name": "jane",
+                "_tags": "[\"a\"]",
+                "_ok": "true",
+            })
+        );
+    }
+
+    #[test]
+    fn large_messages_are_chunked() {
+        let message: Vec<u8> = (0..100_000).map(|n| b'a' + (n % 26) as u8).collect();
+        let parts = chunks(&message, 8_192).unwrap();
+
+        assert!(parts.iter().all(|chunk| chunk.len() <= 8_192));
+        assert_eq!(reassemble(parts), message);
+        // Every message gets its own id
+        let first = chunks(&message, 8_192).unwrap();
+        let second = chunks(&message, 8_192).unwrap();
+        assert_ne!(first[0][2..10], second[0][2..10]);
+    }
+
+    #[test]
+    fn messages_of_more_than_128_chunks_are_not_chunked() {
+        let payload = 100 - CHUNK_HEADER_LEN;
+        assert!(chunks(&vec![b'a'; payload * GELF_MAX_CHUNKS], 100).is_some());
+        assert!(chunks(&vec![b'a'; payload * GELF_MAX_CHUNKS + 1], 100).is_none());
+        assert!(chunks(b"a", CHUNK_HEADER_LEN).is_none());
+    }
+
+    #[test]
+    fn chunks_of_a_100kb_record_are_sent_and_reassembled() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let destination = LogDestination::Udp(collector.local_addr().unwrap().to_string());
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(destination)
+            .wire_format(WireFormat::Gelf);
+        let message = "x".repeat(100 * 1024);
+
+        with_logger(builder, || {
+            // Formatted messages are cut at 64 KiB, string values are not
+            tracing::error!(user_id = 7, message = message.as_str());
+            crate::flush(Duration::from_secs(5));
+        });
+
+        let mut datagram = vec![0; 65_536];
+        let len = collector.recv(&mut datagram).expect("chunk is received");
+        let count = usize::from(datagram[11]);
+        let mut parts = vec![datagram[..len].to_vec()];
+        while parts.len() < count {
+            let len = collector.recv(&mut datagram).expect("chunk is received");
+            assert!(len <= crate::DEFAULT_MAX_DATAGRAM_SIZE);
+            parts.push(datagram[..len].to_vec());
+        }
+
+        let gelf: Value = serde_json::from_slice(&reassemble(parts)).unwrap();
+        assert_eq!(gelf["version"], "1.1");
+        assert_eq!(gelf["short_message"], message);
+        assert_eq!(gelf["level"], 3);
+        assert_eq!(gelf["_facility"], "svc-test");
+        assert_eq!(gelf["_user_id"], 7);
+        assert!(gelf["timestamp"].is_f64());
+    }
+}
@@ -0,0 +1,726 @@
+use crate::{
+    vinted_delivery,
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_drain,
+    vinted_events::{self, LoggerEvent},
+    vinted_fallback,
+    vinted_flush::Progress,
+    vinted_gelf,
+    vinted_stats::{self, Destination, DestinationRole},
+    vinted_udp_writer::Batching,
+};
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use parking_lot::Mutex;
+use std::{
+    io::{self, Read, Write},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Default of [`LoggerBuilder::http_max_attempts`](crate::LoggerBuilder::http_max_attempts)
+pub const DEFAULT_HTTP_MAX_ATTEMPTS: u32 = 5;
+
+/// Default of [`LoggerBuilder::http_batching`](crate::LoggerBuilder::http_batching),
+/// before compression
+pub const DEFAULT_HTTP_BATCH_BYTES: usize = 1024 * 1024;
+
+/// Default of [`LoggerBuilder::http_batching`](crate::LoggerBuilder::http_batching)
+pub const DEFAULT_HTTP_BATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long dropping a `VintedGelfHttpWriter` waits for queued records to be
+/// sent
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Longest `Retry-After` waited for, longer ones are cut short
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Largest response body read before the connection goes back to the pool,
+/// connections of longer ones are closed
+const MAX_RESPONSE_BODY: u64 = 64 * 1024;
+
+/// Where GELF messages are posted to
+#[derive(Debug, Clone)]
+pub(crate) struct Endpoint {
+    /// `http://` or `https://` URL, with the host as configured
+    pub(crate) url: String,
+    /// Address the host resolved to when the logger was installed, named by
+    /// events until a response tells the one connected to
+    pub(crate) addr: SocketAddr,
+}
+
+/// Posts records as GELF messages to a Graylog HTTP input, from a background
+/// thread.
+///
+/// Without [`Batching`] every message is posted on its own. With it, queued
+/// messages are posted newline delimited and gzipped, for inputs with bulk
+/// receiving enabled.
+///
+/// Requests go over the keep-alive connections of a `ureq` agent, which
+/// resolves the host again whenever it opens a new one, e.g. once Graylog
+/// restarts behind a new address.
+///
+/// Requests answered with 429 are sent again after their `Retry-After`,
+/// those answered with a 5xx or failing to connect after an exponential
+/// backoff, up to a number of attempts. Records of requests which run out of
+/// attempts, or which are rejected with another status, are dropped. Up to a
+/// number of records are queued, beyond which new ones are dropped as well.
+/// Dropping the `VintedGelfHttpWriter` stops the thread once the queue is
+/// sent, waiting up to [`SHUTDOWN_TIMEOUT`] and no longer retrying.
+pub(crate) struct VintedGelfHttpWriter {
+    writer: WriterImpl,
+    // Declared last so it's dropped after the sender above, which is what
+    // lets the thread stop
+    _stopped: Stopped,
+}
+
+impl VintedGelfHttpWriter {
+    pub(crate) fn new(
+        endpoint: Endpoint,
+        capacity: usize,
+        batching: Option<Batching>,
+        max_attempts: u32,
+    ) -> Self {
+        // A capacity of 0 would make every write wait for the thread
+        let (sender, receiver) = sync_channel::<Bytes>(capacity.max(1));
+        let (stopping, stopped) = channel::<()>();
+        let closing = Arc::new(AtomicBool::new(false));
+        let progress = Progress::register();
+        let stats = Destination::register(endpoint.url.clone(), DestinationRole::Records);
+
+        let mut poster = Poster {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(CONNECT_TIMEOUT)
+                .timeout_read(IO_TIMEOUT)
+                .timeout_write(IO_TIMEOUT)
+                // Redirected records aren't posted again, they're rejected
+                .redirects(0)
+                .user_agent(concat!("vinted-logger/", env!("CARGO_PKG_VERSION")))
+                .build(),
+            endpoint,
+            max_attempts: max_attempts.max(1),
+            closing: closing.clone(),
+            progress: progress.clone(),
+            stats: stats.clone(),
+            delivered: false,
+            connected: None,
+        };
+        let _ = thread::spawn(move || {
+            let _stopping = stopping;
+            match batching {
+                Some(batching) => poster.post_batches(receiver, batching),
+                None => {
+                    for message in receiver {
+                        poster.post(&[message], false);
+                    }
+                }
+            }
+            poster.progress.stopped();
+        });
+
+        Self {
+            writer: WriterImpl {
+                sender: Arc::new(Mutex::new(sender)),
+                progress,
+                stats,
+                error: false,
+            },
+            _stopped: Stopped {
+                closing,
+                stopped: Mutex::new(stopped),
+            },
+        }
+    }
+}
+
+/// Waits for the thread to stop when dropped, telling it to give up on
+/// retries
+struct Stopped {
+    closing: Arc<AtomicBool>,
+    stopped: Mutex<Receiver<()>>,
+}
+
+impl Drop for Stopped {
+    fn drop(&mut self) {
+        self.closing.store(true, Ordering::Relaxed);
+        let _ = self.stopped.get_mut().recv_timeout(SHUTDOWN_TIMEOUT);
+    }
+}
+
+/// What a request got back
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) retry_after: Option<Duration>,
+    /// Address the request was sent to
+    pub(crate) addr: SocketAddr,
+}
+
+/// State of the thread posting messages
+struct Poster {
+    agent: ureq::Agent,
+    endpoint: Endpoint,
+    max_attempts: u32,
+    closing: Arc<AtomicBool>,
+    progress: Arc<Progress>,
+    stats: Arc<Destination>,
+    delivered: bool,
+    // Whether the last request got a response, events are only emitted
+    // when it changes
+    connected: Option<bool>,
+}
+
+impl Poster {
+    /// Posts queued messages together, once another one would grow the
+    /// batch beyond `max_bytes` or once its first message waited for
+    /// `interval`, shortened while draining. A message larger than
+    /// `max_bytes` is posted on its own.
+    fn post_batches(&mut self, receiver: Receiver<Bytes>, batching: Batching) {
+        let mut batch: Vec<Bytes> = Vec::new();
+        let mut len = 0;
+        // When the first message of the batch was queued
+        let mut started: Option<Instant> = None;
+
+        loop {
+            // A batch started before draining gets the drain interval from
+            // its next message on
+            let next = match started {
+                Some(started) => {
+                    let deadline = started + vinted_drain::batch_interval(batching.interval);
+                    receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let message = match next {
+                Ok(message) => message,
+                Err(disconnected) => {
+                    if !batch.is_empty() {
+                        self.post(&batch, true);
+                        batch.clear();
+                        len = 0;
+                    }
+                    started = None;
+                    if disconnected == RecvTimeoutError::Disconnected {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            // Messages are newline delimited
+            if !batch.is_empty() && len + message.len() + 1 > batching.max_bytes {
+                self.post(&batch, true);
+                batch.clear();
+                len = 0;
+            }
+            if batch.is_empty() {
+                started = Some(Instant::now());
+            }
+            len += message.len() + 1;
+            batch.push(message);
+        }
+    }
+
+    /// Posts `messages`, newline delimited and gzipped if `gzip`, retrying
+    /// until they're accepted or dropped
+    fn post(&mut self, messages: &[Bytes], gzip: bool) {
+        let mut body = Vec::with_capacity(messages.iter().map(|m| m.len() + 1).sum());
+        for message in messages {
+            body.extend_from_slice(message);
+            body.push(b'\n');
+        }
+        if gzip {
+            let mut encoder =
+                GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::fast());
+            // Writing to a `Vec` doesn't fail
+            body = encoder
+                .write_all(&body)
+                .and_then(|()| encoder.finish())
+                .expect("gzip is written to memory");
+        } else {
+            body.pop();
+        }
+        let records = messages.len() as u64;
+
+        let mut backoff = MIN_BACKOFF;
+        for attempt in 1..=self.max_attempts {
+            let wait = match request(&self.agent, &self.endpoint, &body, gzip) {
+                Ok(response) if (200..300).contains(&response.status) => {
+                    self.endpoint.addr = response.addr;
+                    self.connected(true, None);
+                    self.stats.sent(records);
+                    if !self.delivered {
+                        self.delivered = true;
+                        vinted_delivery::delivered();
+                    }
+                    self.progress.done(records);
+                    return;
+                }
+                Ok(response) if response.status == 429 => {
+                    self.connected(true, None);
+                    response.retry_after.unwrap_or(backoff).min(MAX_RETRY_AFTER)
+                }
+                Ok(response) if response.status >= 500 => {
+                    self.connected(true, None);
+                    backoff
+                }
+                Ok(response) => {
+                    self.connected(true, None);
+                    self.stats.send_failed();
+                    self.drop_records(
+                        messages,
+                        format_args!(
+                            "Graylog rejected {} log records with HTTP {}, dropped them",
+                            records, response.status
+                        ),
+                    );
+                    return;
+                }
+                Err(e) => {
+                    self.connected(false, Some(&e));
+                    backoff
+                }
+            };
+
+            self.stats.send_failed();
+            if attempt == self.max_attempts || !self.sleep(wait) {
+                break;
+            }
+            vinted_stats::retried();
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        self.drop_records(
+            messages,
+            format_args!(
+                "Log records can't be posted to Graylog at {} after {} attempts, dropped {} of them",
+                self.endpoint.addr, self.max_attempts, records
+            ),
+        );
+    }
+
+    fn connected(&mut self, connected: bool, error: Option<&io::Error>) {
+        if self.connected == Some(connected) {
+            return;
+        }
+        self.connected = Some(connected);
+        let addr = self.endpoint.addr;
+        match error {
+            None => vinted_events::emit(LoggerEvent::Connected { addr }),
+            Some(e) => {
+                vinted_diagnostics::report(
+                    Diagnostic::SendFailed,
+                    format_args!("Couldn't post log records to Graylog at {}: {}", addr, e),
+                );
+                vinted_events::emit(LoggerEvent::Disconnected {
+                    addr,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    fn drop_records(&self, messages: &[Bytes], why: std::fmt::Arguments<'_>) {
+        vinted_diagnostics::report(Diagnostic::Dropped, why);
+        for message in messages {
+            self.stats.dropped();
+            vinted_fallback::undelivered(message);
+        }
+        self.progress.done(messages.len() as u64);
+    }
+
+    /// Waits for `duration`, returns `false` right away once the writer is
+    /// dropped
+    fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.closing.load(Ordering::Relaxed) {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(MIN_BACKOFF));
+        }
+    }
+}
+
+/// Posts `body`, over a pooled connection if the agent has one
+fn request(
+    agent: &ureq::Agent,
+    endpoint: &Endpoint,
+    body: &[u8],
+    gzip: bool,
+) -> io::Result<Response> {
+    let mut request = agent
+        .post(&endpoint.url)
+        .set("Content-Type", "application/json");
+    if gzip {
+        request = request.set("Content-Encoding", "gzip");
+    }
+    let response = match request.send_bytes(body) {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => return Err(io::Error::other(e)),
+    };
+
+    let status = response.status();
+    let retry_after = retry_after(response.header("Retry-After"));
+    let addr = response.remote_addr();
+    // The connection goes back to the pool once the whole body is read
+    let _ = io::copy(
+        &mut response.into_reader().take(MAX_RESPONSE_BODY),
+        &mut io::sink(),
+    );
+    Ok(Response {
+        status,
+        retry_after,
+        addr,
+    })
+}
+
+/// `Retry-After` in seconds, HTTP dates are left to the backoff
+pub(crate) fn retry_after(value: Option<&str>) -> Option<Duration> {
+    value
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+impl<'a> MakeWriter<'a> for VintedGelfHttpWriter {
+    type Writer = WriterImpl;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer.clone()
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        WriterImpl {
+            error: *meta.level() == Level::ERROR,
+            ..self.writer.clone()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct WriterImpl {
+    sender: Arc<Mutex<SyncSender<Bytes>>>,
+    progress: Arc<Progress>,
+    stats: Arc<Destination>,
+    /// Whether the record is an ERROR one
+    error: bool,
+}
+
+impl io::Write for WriterImpl {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let message = match vinted_gelf::convert(buf) {
+            Some(message) => message,
+            None => return Ok(buf.len()),
+        };
+
+        self.progress.queued(self.error);
+        let result = self.sender.lock().try_send(Bytes::from(message));
+        if result.is_err() {
+            self.progress.not_queued();
+        }
+        match result {
+            Ok(()) => self.stats.enqueued(),
+            Err(TrySendError::Full(_)) => {
+                self.stats.dropped();
+                vinted_diagnostics::report(
+                    Diagnostic::Dropped,
+                    format_args!("GELF HTTP queue is full, dropped a log record"),
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => vinted_diagnostics::report(
+                Diagnostic::ChannelClosed,
+                format_args!("Log record can't be queued, the GELF HTTP thread stopped"),
+            ),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_after, Endpoint, VintedGelfHttpWriter};
+    use crate::{
+        logger_stats,
+        test_support::{wait_until, with_logger},
+        vinted_udp_writer::Batching,
+        LogDestination, Target,
+    };
+    use flate2::read::GzDecoder;
+    use parking_lot::Mutex;
+    use serde_json::Value;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    struct Request {
+        at: Instant,
+        /// Index of the connection it came over
+        connection: usize,
+        head: String,
+        body: Vec<u8>,
+    }
+
+    /// HTTP server answering requests with `responses` in turn, the last one
+    /// over and over, keeping connections open
+    fn server(responses: &'static [&'static str]) -> (Endpoint, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for (connection, stream) in listener.incoming().enumerate() {
+                let received = received.clone();
+                thread::spawn(move || {
+                    let mut stream = BufReader::new(stream.unwrap());
+                    loop {
+                        let mut head = String::new();
+                        while !head.ends_with("\r\n\r\n") {
+                            if stream.read_line(&mut head).unwrap_or(0) == 0 {
+                                return;
+                            }
+                        }
+                        let len = head
+                            .lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .map_or(0, |(_, len)| len.trim().parse().unwrap());
+                        let mut body = vec![0; len];
+                        stream.read_exact(&mut body).unwrap();
+                        let mut received = received.lock();
+                        let response = responses[received.len().min(responses.len() - 1)];
+                        received.push(Request {
+                            at: Instant::now(),
+                            connection,
+                            head,
+                            body,
+                        });
+                        drop(received);
+                        if stream.get_mut().write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        let endpoint = Endpoint {
+            url: format!("http://{}/gelf", addr),
+            addr,
+        };
+        (endpoint, requests)
+    }
+
+    fn gunzip(body: &[u8]) -> String {
+        let mut data = String::new();
+        GzDecoder::new(body).read_to_string(&mut data).unwrap();
+        data
+    }
+
+    const ACCEPTED: &str = "HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n";
+
+    fn record(message: &str) -> Vec<u8> {
+        format!(
+            r#"{{"@timestamp":"2021-04-20T12:42:57.123Z","level":"ERROR","facility":"svc-test","host":"host-1","message":"{}","user_id":7}}"#,
+            message
+        )
+        .into_bytes()
+    }
+
+    fn write(writer: &VintedGelfHttpWriter, message: &str) {
+        writer.make_writer().write_all(&record(message)).unwrap();
+    }
+
+    fn wait_requests(requests: &Mutex<Vec<Request>>, count: usize) {
+        assert!(
+            wait_until(|| requests.lock().len() >= count),
+            "{} of {} requests",
+            requests.lock().len(),
+            count
+        );
+    }
+
+    #[test]
+    fn records_are_posted_as_gelf_messages() {
+        let (endpoint, requests) = server(&[ACCEPTED]);
+        let destination = format!("http://{}/gelf", endpoint.addr);
+        let builder = crate::builder("svc-test", Target::GelfHttp)
+            .destination(destination.parse::<LogDestination>().unwrap());
+
+        with_logger(builder, || {
+            tracing::error!(user_id = 7, "payment failed");
+            wait_requests(&requests, 1);
+        });
+
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 1);
+        let head = &requests[0].head;
+        assert!(head.starts_with("POST /gelf HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains(&format!("\r\nHost: {}\r\n", endpoint.addr)));
+        assert!(head.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(!head.contains("Connection: close"), "{}", head);
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+
+        let message: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(message["version"], "1.1");
+        assert_eq!(message["short_message"], "payment failed");
+        assert_eq!(message["level"], 3);
+        assert_eq!(message["_facility"], "svc-test");
+        assert_eq!(message["_user_id"], 7);
+    }
+
+    #[test]
+    fn batches_are_gzipped_and_newline_delimited() {
+        let (endpoint, requests) = server(&[ACCEPTED]);
+        let batching = Batching {
+            max_bytes: 64 * 1024,
+            interval: Duration::from_millis(50),
+            key_table: false,
+        };
+        let writer = VintedGelfHttpWriter::new(endpoint, 100, Some(batching), 1);
+        for n in 0..10 {
+            write(&writer, &format!("request {}", n));
+        }
+        wait_requests(&requests, 1);
+        drop(writer);
+
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].head.contains("\r\nContent-Encoding: gzip\r\n"));
+        let body = gunzip(&requests[0].body);
+        let messages: Vec<&str> = body.split_terminator('\n').collect();
+        assert_eq!(messages.len(), 10, "{}", body);
+        for (n, message) in messages.iter().enumerate() {
+            let message: Value = serde_json::from_str(message).unwrap();
+            assert_eq!(message["short_message"], format!("request {}", n));
+        }
+    }
+
+    #[test]
+    fn requests_reuse_their_connection() {
+        let (endpoint, requests) = server(&[ACCEPTED]);
+        let writer = VintedGelfHttpWriter::new(endpoint, 100, None, 1);
+        for n in 0..3 {
+            write(&writer, &format!("request {}", n));
+            wait_requests(&requests, n + 1);
+        }
+        drop(writer);
+
+        let connections: Vec<usize> = requests.lock().iter().map(|r| r.connection).collect();
+        assert_eq!(connections, [0, 0, 0]);
+    }
+
+    #[test]
+    fn retry_after_is_honored_on_429() {
+        let (endpoint, requests) = server(&[
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\n\r\n",
+            ACCEPTED,
+        ]);
+        let writer = VintedGelfHttpWriter::new(endpoint, 100, None, 3);
+        let retries = logger_stats().retries;
+        write(&writer, "throttled");
+        wait_requests(&requests, 2);
+        drop(writer);
+
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body, requests[1].body);
+        let waited = requests[1].at - requests[0].at;
+        assert!(waited >= Duration::from_secs(1), "{:?}", waited);
+        assert!(logger_stats().retries > retries);
+    }
+
+    #[test]
+    fn server_errors_are_retried_with_a_growing_backoff() {
+        const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+        let (endpoint, requests) = server(&[UNAVAILABLE, UNAVAILABLE, ACCEPTED]);
+        let writer = VintedGelfHttpWriter::new(endpoint, 100, None, 5);
+        write(&writer, "flaky");
+        wait_requests(&requests, 3);
+        drop(writer);
+
+        let requests = requests.lock();
+        assert_eq!(requests.len(), 3);
+        let first = requests[1].at - requests[0].at;
+        let second = requests[2].at - requests[1].at;
+        assert!(first >= Duration::from_millis(100), "{:?}", first);
+        assert!(second >= Duration::from_millis(200), "{:?}", second);
+    }
+
+    #[test]
+    fn records_are_dropped_after_the_last_attempt() {
+        let (endpoint, requests) =
+            server(&["HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"]);
+        let writer = VintedGelfHttpWriter::new(endpoint, 100, None, 3);
+        let dropped = logger_stats().dropped;
+        write(&writer, "lost");
+        wait_requests(&requests, 3);
+        assert!(wait_until(|| logger_stats().dropped > dropped));
+        // No fourth attempt
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(requests.lock().len(), 3);
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let (endpoint, requests) =
+            server(&["HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n"]);
+        let writer = VintedGelfHttpWriter::new(endpoint, 100, None, 5);
+        let dropped = logger_stats().dropped;
+        write(&writer, "malformed");
+        wait_requests(&requests, 1);
+        assert!(wait_until(|| logger_stats().dropped > dropped));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(requests.lock().len(), 1);
+    }
+
+    #[test]
+    fn refused_connections_are_retried_then_dropped() {
+        // Nothing listens on the port once the listener is closed
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let endpoint = Endpoint {
+            url: format!("http://{}/gelf", addr),
+            addr,
+        };
+        let writer = VintedGelfHttpWriter::new(endpoint, 100, None, 2);
+        let (dropped, send_errors) = (logger_stats().dropped, logger_stats().send_errors);
+        write(&writer, "unreachable");
+        assert!(wait_until(|| logger_stats().dropped > dropped));
+        assert!(logger_stats().send_errors >= send_errors + 2);
+    }
+
+    #[test]
+    fn retry_after_is_read_in_seconds() {
+        assert_eq!(retry_after(Some(" 7 ")), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(None), None);
+        // HTTP dates are left to the backoff
+        assert_eq!(retry_after(Some("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+    }
+}
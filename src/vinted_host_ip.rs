@@ -0,0 +1,158 @@
+use std::{
+    env,
+    net::{IpAddr, UdpSocket},
+};
+
+/// Addresses recorded as `host_ip` and `node_ip`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HostIps {
+    pub(crate) host_ip: Option<String>,
+    pub(crate) node_ip: Option<String>,
+}
+
+/// Resolves addresses once: `POD_IP` and `NODE_IP`, as set by the Kubernetes
+/// downward API, or else the address of the interface routing to the
+/// internet as the host IP.
+pub(crate) fn resolve() -> HostIps {
+    resolve_with(|name| env::var(name).ok(), egress_ip)
+}
+
+fn resolve_with(
+    var: impl Fn(&str) -> Option<String>,
+    egress: impl FnOnce() -> Option<IpAddr>,
+) -> HostIps {
+    HostIps {
+        host_ip: var("POD_IP")
+            .and_then(|ips| from_list(&ips))
+            .or_else(egress)
+            .map(|ip| ip.to_string()),
+        node_ip: var("NODE_IP")
+            .and_then(|ips| from_list(&ips))
+            .map(|ip| ip.to_string()),
+    }
+}
+
+/// First usable address of a comma separated list, e.g. `POD_IPS` style values
+fn from_list(ips: &str) -> Option<IpAddr> {
+    first_global(ips.split(',').filter_map(|ip| ip.trim().parse().ok()))
+}
+
+fn first_global(ips: impl IntoIterator<Item = IpAddr>) -> Option<IpAddr> {
+    ips.into_iter().find(|ip| match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast())
+        }
+        // fe80::/10 addresses are link-local
+        IpAddr::V6(ip) => {
+            !(ip.is_loopback() || ip.is_unspecified() || (ip.segments()[0] & 0xffc0) == 0xfe80)
+        }
+    })
+}
+
+/// Connecting a UDP socket sends nothing, it only picks the route and with it
+/// the local address.
+fn egress_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    first_global(Some(socket.local_addr().ok()?.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_global, from_list, resolve_with};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use std::net::IpAddr;
+
+    fn ips(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn first_global_address_is_picked() {
+        let interfaces = ips(&[
+            "127.0.0.1",
+            "169.254.1.1",
+            "::1",
+            "fe80::1",
+            "fe9f::1",
+            "10.2.3.4",
+        ]);
+        assert_eq!(first_global(interfaces), "10.2.3.4".parse().ok());
+        let interfaces = ips(&[
+            "0.0.0.0",
+            "255.255.255.255",
+            "febf::1",
+            "2001:db8::7",
+            "10.2.3.4",
+        ]);
+        assert_eq!(first_global(interfaces), "2001:db8::7".parse().ok());
+        let interfaces = ips(&["127.0.0.1", "::", "fe80::1"]);
+        assert_eq!(first_global(interfaces), None);
+        assert_eq!(first_global(None), None);
+    }
+
+    #[test]
+    fn lists_skip_entries_which_are_not_addresses() {
+        assert_eq!(from_list("10.2.3.4"), "10.2.3.4".parse().ok());
+        assert_eq!(
+            from_list(" pod , 127.0.0.1,fe80::1, 2001:db8::7 ,10.2.3.4"),
+            "2001:db8::7".parse().ok()
+        );
+        assert_eq!(from_list(""), None);
+        assert_eq!(from_list("127.0.0.1,unknown"), None);
+    }
+
+    #[test]
+    fn env_vars_win_over_the_egress_interface() {
+        let env = |name: &str| match name {
+            "POD_IP" => Some("127.0.0.1,10.2.3.4".to_owned()),
+            "NODE_IP" => Some("10.0.0.9".to_owned()),
+            _ => None,
+        };
+        let resolved = resolve_with(env, || unreachable!("POD_IP is usable"));
+        assert_eq!(resolved.host_ip.as_deref(), Some("10.2.3.4"));
+        assert_eq!(resolved.node_ip.as_deref(), Some("10.0.0.9"));
+
+        // An unusable POD_IP falls back like an unset one
+        let env = |name: &str| match name {
+            "POD_IP" => Some("127.0.0.1".to_owned()),
+            _ => None,
+        };
+        let resolved = resolve_with(env, || "192.168.1.5".parse().ok());
+        assert_eq!(resolved.host_ip.as_deref(), Some("192.168.1.5"));
+        assert_eq!(resolved.node_ip, None);
+    }
+
+    #[test]
+    fn unresolved_addresses_are_none() {
+        let resolved = resolve_with(|_| None, || None);
+        assert_eq!(resolved.host_ip, None);
+        assert_eq!(resolved.node_ip, None);
+    }
+
+    #[test]
+    fn records_carry_resolved_addresses_only() {
+        // No other test builds a logger with `host_ip()`
+        std::env::set_var("POD_IP", "fe80::1,10.2.3.4");
+        std::env::remove_var("NODE_IP");
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .host_ip()
+            .capture(&capture);
+        with_logger(builder, || tracing::info!("served"));
+        std::env::remove_var("POD_IP");
+
+        let record = &capture.records()[0];
+        assert_eq!(record["host_ip"], "10.2.3.4");
+        assert!(record.get("node_ip").is_none(), "{}", record);
+
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, || tracing::info!("served"));
+        let record = &capture.records()[0];
+        assert!(record.get("host_ip").is_none(), "{}", record);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{builder::BoxedLayer, InitError, Target};
+use parking_lot::{const_mutex, Mutex};
+use std::panic::Location;
+use tracing_subscriber::{
+    layer::SubscriberExt,
+    reload,
+    util::{SubscriberInitExt, TryInitError},
+    Layer, Registry,
+};
+
+/// Logger installed as the global subscriber by this crate, `None` until one
+/// is, or if an outside subscriber was installed instead
+static INSTALLED: Mutex<Option<Installed>> = const_mutex(None);
+
+struct Installed {
+    facility: &'static str,
+    target: Target,
+    location: &'static Location<'static>,
+    /// Loggers of the same facility attached later
+    attached: reload::Handle<Vec<BoxedLayer>, Registry>,
+}
+
+/// What another init finds installed
+pub(crate) enum Existing {
+    /// No logger of this crate
+    None,
+    /// A logger of the same facility and target, the new one would only
+    /// duplicate its records
+    Same,
+    /// A logger of the same facility writing to another target
+    Compatible,
+}
+
+/// Installs `layer` as the global subscriber, leaving room for loggers of the
+/// same facility to be attached to it later
+pub(crate) fn install(
+    layer: BoxedLayer,
+    facility: &'static str,
+    target: Target,
+    location: &'static Location<'static>,
+) -> Result<(), TryInitError> {
+    let (slot, attached) = reload::Layer::new(Vec::new());
+    let mut installed = INSTALLED.lock();
+    tracing_subscriber::registry()
+        .with(vec![layer, slot.boxed()])
+        .try_init()?;
+    *installed = Some(Installed {
+        facility,
+        target,
+        location,
+        attached,
+    });
+    Ok(())
+}
+
+/// Compares an init of `facility` at `location` with the installed logger,
+/// failing with [`InitError::ConflictingInit`] if it's of another facility
+pub(crate) fn existing(
+    facility: &'static str,
+    target: Target,
+    location: &'static Location<'static>,
+) -> Result<Existing, InitError> {
+    match &*INSTALLED.lock() {
+        None => Ok(Existing::None),
+        Some(installed) if installed.facility != facility => Err(InitError::ConflictingInit {
+            installed_facility: installed.facility,
+            installed_at: installed.location,
+            facility,
+            location,
+        }),
+        Some(installed) if installed.target == target => Ok(Existing::Same),
+        Some(_) => Ok(Existing::Compatible),
+    }
+}
+
+/// Attaches `layer` next to the installed logger, returns `false` if there is
+/// none
+pub(crate) fn attach(layer: BoxedLayer) -> bool {
+    let installed = INSTALLED.lock();
+    let installed = match &*installed {
+        Some(installed) => installed,
+        None => return false,
+    };
+    // The subscriber is registered already, the layer learns about it from
+    // here instead
+    tracing::dispatcher::get_default(|dispatch| layer.on_register_dispatch(dispatch));
+    installed
+        .attached
+        .modify(|layers| layers.push(layer))
+        .is_ok()
+}
@@ -1,23 +1,331 @@
+#[cfg(feature = "runtime-context")]
+use crate::vinted_runtime_context;
+use crate::{
+    access_log, vinted_canary,
+    vinted_deployment::Deployment,
+    vinted_escaping::{self, EscapingProfile},
+    vinted_event_seq,
+    vinted_event_time::{self, EventTime},
+    vinted_facility_map::FacilityMap,
+    vinted_field_types::FieldTypes,
+    vinted_field_visitor::{
+        notice_untyped_fields, RecordKeys, SpanFields, SpanPii, VintedFieldVisitor,
+    },
+    vinted_host_ip::HostIps,
+    vinted_level_stats::{self, LevelStats},
+    vinted_message_template,
+    vinted_metric::{self, Metrics},
+    vinted_own_records::OwnRecords,
+    vinted_panic_hook,
+    vinted_pii::{self, PiiScanner},
+    vinted_redaction::Redaction,
+    vinted_stats,
+    vinted_timestamp::TimestampFormat,
+    vinted_udp_writer, vinted_volume_writer,
+};
 use serde::ser::{SerializeMap, Serializer as _};
 use serde_json::Serializer;
-use std::{fmt, io};
-use tracing_core::{Event, Subscriber};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::HashSet,
+    fmt, io,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tracing_core::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
 use tracing_serde::AsSerde;
 use tracing_subscriber::{
     fmt::{
-        format::{FormatEvent, FormatFields},
-        time::FormatTime,
-        FmtContext, FormattedFields,
+        format::{FormatEvent, FormatFields, Writer},
+        FmtContext,
     },
-    registry::LookupSpan,
+    registry::{Extensions, LookupSpan},
 };
+/// How much span context records carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PayloadTier {
+    /// Envelope, message and event fields only
+    Minimal,
+    /// Same as `Minimal` plus the fields of the current span, flattened into the record
+    Standard,
+    /// Same as `Minimal` plus the current span under `span` and the names of
+    /// the spans it's in, outermost first, under `spans`, e.g.
+    /// `"spans":"request:db_query"`. This is the default.
+    Full,
+}
+/// Key of the timestamp of records, unless
+/// [`LoggerBuilder::timestamp_key`](crate::LoggerBuilder::timestamp_key) sets
+/// another one
+pub(crate) const DEFAULT_TIMESTAMP_KEY: &str = "@timestamp";
+/// Keys `VintedJson` writes itself, besides the event fields
+pub(crate) const RECORD_KEYS: &[&str] = &[
+    "@timestamp",
+    "event_seq",
+    "level",
+    "facility",
+    "message",
+    "message_template",
+    "message_class",
+    "target",
+    "span",
+    "spans",
+    "thread_id",
+    "thread_name",
+    "file",
+    "module",
+    "line",
+    "host",
+    "host_ip",
+    "node_ip",
+    "deploy_slot",
+    "deploy_id",
+];
+/// Keys added to some records only, by `VintedJson` or by the writers after
+/// it, see `VintedCoalescingWriter`
+const ADDED_KEYS: &[&str] = &[
+    vinted_event_time::ERROR_FIELD,
+    vinted_metric::ERROR_FIELD,
+    "repeat_count",
+    "first_timestamp",
+    "last_timestamp",
+    vinted_level_stats::KEY,
+    vinted_udp_writer::TRUNCATED_KEY,
+    "runtime_context",
+    vinted_pii::KEY,
+    vinted_volume_writer::KEY,
+    FIELDS_DROPPED_KEY,
+];
+/// Count of the additional fields left out by
+/// [`LoggerBuilder::ignore_rejected_fields`](crate::LoggerBuilder::ignore_rejected_fields)
+const FIELDS_DROPPED_KEY: &str = "fields_dropped";
+/// Keys added by [`LoggerBuilder::trace_context`](crate::LoggerBuilder::trace_context),
+/// only taken when it's set so fields of the same name are kept otherwise
+const TRACE_CONTEXT_KEYS: &[&str] = &["trace_id", "span_id"];
+/// Callback of [`LoggerBuilder::trace_context`](crate::LoggerBuilder::trace_context),
+/// taking the extensions of a span
+pub(crate) type TraceContextFn = dyn Fn(&Extensions<'_>) -> Option<(String, String)> + Send + Sync;
+#[derive(Clone)]
+pub(crate) struct TraceContext(pub(crate) Arc<TraceContextFn>);
+impl fmt::Debug for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("TraceContext { .. }")
+    }
+}
+/// Payload tier of each level, indexed by `level_index`
+pub(crate) type PayloadTiers = [PayloadTier; 5];
+pub(crate) fn level_index(level: Level) -> usize {
+    match level {
+        Level::TRACE => 0,
+        Level::DEBUG => 1,
+        Level::INFO => 2,
+        Level::WARN => 3,
+        Level::ERROR => 4,
+    }
+}
 #[derive(Debug)]
 pub(crate) struct VintedJson {
     facility: &'static str,
+    timestamp_format: TimestampFormat,
+    timestamp_key: &'static str,
+    payload_tiers: PayloadTiers,
+    escaping_profile: EscapingProfile,
+    host_ips: HostIps,
+    deployment: Deployment,
+    drop_empty_fields: bool,
+    message_template: bool,
+    event_time_skew: Duration,
+    debug_budget: Option<Duration>,
+    field_types: Option<Arc<FieldTypes>>,
+    facility_map: FacilityMap,
+    additional_fields: serde_json::Map<String, serde_json::Value>,
+    /// Additional fields rejected by the builder's limits
+    fields_dropped: usize,
+    metrics: Metrics,
+    level_stats: Option<LevelStats>,
+    volume_top: bool,
+    pii: Option<Arc<PiiScanner>>,
+    redaction: Option<Arc<Redaction>>,
+    trace_context: Option<TraceContext>,
+    own_records: OwnRecords,
+    #[cfg(feature = "runtime-context")]
+    runtime_context: bool,
+    /// Keys event fields can't take, kept up to date by the setters of the
+    /// timestamp key, the additional fields and the trace context
+    reserved_keys: Arc<HashSet<Cow<'static, str>>>,
 }
 impl VintedJson {
     pub(crate) fn new(facility: &'static str) -> Self {
-        Self { facility }
+        Self {
+            facility,
+            timestamp_format: TimestampFormat::default(),
+            timestamp_key: DEFAULT_TIMESTAMP_KEY,
+            payload_tiers: [PayloadTier::Full; 5],
+            escaping_profile: EscapingProfile::Default,
+            host_ips: HostIps::default(),
+            deployment: Deployment::default(),
+            drop_empty_fields: false,
+            message_template: false,
+            event_time_skew: crate::DEFAULT_EVENT_TIME_SKEW,
+            debug_budget: None,
+            field_types: None,
+            facility_map: FacilityMap::default(),
+            additional_fields: serde_json::Map::new(),
+            fields_dropped: 0,
+            metrics: Metrics::default(),
+            level_stats: None,
+            volume_top: false,
+            pii: None,
+            redaction: None,
+            trace_context: None,
+            own_records: OwnRecords::default(),
+            #[cfg(feature = "runtime-context")]
+            runtime_context: false,
+            reserved_keys: Arc::default(),
+        }
+        .reserve_keys()
+    }
+    /// Event fields can't take the keys of the record itself, and come
+    /// before every other field of the same name
+    fn reserve_keys(self) -> Self {
+        let reserved_keys = RECORD_KEYS
+            .iter()
+            .chain(ADDED_KEYS)
+            .chain(&[self.timestamp_key])
+            .chain(match self.trace_context {
+                Some(_) => TRACE_CONTEXT_KEYS,
+                None => &[],
+            })
+            .filter(|&&key| key != "message")
+            .map(|&key| Cow::Borrowed(key))
+            .chain(
+                self.additional_fields
+                    .keys()
+                    .map(|key| Cow::Owned(key.clone())),
+            )
+            .collect();
+        Self {
+            reserved_keys: Arc::new(reserved_keys),
+            ..self
+        }
+    }
+    pub(crate) fn with_timestamp(
+        self,
+        timestamp_format: TimestampFormat,
+        timestamp_key: &'static str,
+    ) -> Self {
+        Self {
+            timestamp_format,
+            timestamp_key,
+            ..self
+        }
+        .reserve_keys()
+    }
+    pub(crate) fn with_payload_tiers(self, payload_tiers: PayloadTiers) -> Self {
+        Self {
+            payload_tiers,
+            ..self
+        }
+    }
+    pub(crate) fn with_escaping_profile(self, escaping_profile: EscapingProfile) -> Self {
+        Self {
+            escaping_profile,
+            ..self
+        }
+    }
+    pub(crate) fn with_host_ips(self, host_ips: HostIps) -> Self {
+        Self { host_ips, ..self }
+    }
+    pub(crate) fn with_deployment(self, deployment: Deployment) -> Self {
+        Self { deployment, ..self }
+    }
+    pub(crate) fn with_drop_empty_fields(self, drop_empty_fields: bool) -> Self {
+        Self {
+            drop_empty_fields,
+            ..self
+        }
+    }
+    pub(crate) fn with_message_template(self, message_template: bool) -> Self {
+        Self {
+            message_template,
+            ..self
+        }
+    }
+    pub(crate) fn with_event_time_skew(self, event_time_skew: Duration) -> Self {
+        Self {
+            event_time_skew,
+            ..self
+        }
+    }
+    pub(crate) fn with_debug_budget(self, debug_budget: Option<Duration>) -> Self {
+        Self {
+            debug_budget,
+            ..self
+        }
+    }
+    pub(crate) fn with_field_types(self, field_types: Option<Arc<FieldTypes>>) -> Self {
+        Self {
+            field_types,
+            ..self
+        }
+    }
+    pub(crate) fn with_additional_fields(
+        self,
+        additional_fields: serde_json::Map<String, serde_json::Value>,
+        fields_dropped: usize,
+    ) -> Self {
+        Self {
+            additional_fields,
+            fields_dropped,
+            ..self
+        }
+        .reserve_keys()
+    }
+    pub(crate) fn with_facility_map(self, facility_map: FacilityMap) -> Self {
+        Self {
+            facility_map,
+            ..self
+        }
+    }
+    pub(crate) fn with_metrics(self, metrics: Metrics) -> Self {
+        Self { metrics, ..self }
+    }
+    #[cfg(feature = "runtime-context")]
+    pub(crate) fn with_runtime_context(self, runtime_context: bool) -> Self {
+        Self {
+            runtime_context,
+            ..self
+        }
+    }
+    pub(crate) fn with_level_stats(self, level_stats: Option<LevelStats>) -> Self {
+        Self {
+            level_stats,
+            ..self
+        }
+    }
+    pub(crate) fn with_volume_top(self, volume_top: bool) -> Self {
+        Self { volume_top, ..self }
+    }
+    pub(crate) fn with_pii(self, pii: Option<Arc<PiiScanner>>) -> Self {
+        Self { pii, ..self }
+    }
+    pub(crate) fn with_redaction(self, redaction: Option<Arc<Redaction>>) -> Self {
+        Self { redaction, ..self }
+    }
+    pub(crate) fn with_trace_context(self, trace_context: Option<TraceContext>) -> Self {
+        Self {
+            trace_context,
+            ..self
+        }
+        .reserve_keys()
+    }
+    pub(crate) fn with_own_records(self, own_records: OwnRecords) -> Self {
+        Self {
+            own_records,
+            ..self
+        }
     }
 }
 impl<S, N> FormatEvent<S, N> for VintedJson
@@ -28,40 +336,166 @@ where
     fn format_event(
         &self,
         ctx: &FmtContext<'_, S, N>,
-        writer: &mut dyn fmt::Write,
+        mut writer: Writer<'_>,
         event: &Event<'_>,
     ) -> fmt::Result
     where
         S: Subscriber + for<'a> LookupSpan<'a>,
     {
-        let mut timestamp = String::new();
-        tracing_subscriber::fmt::time::ChronoUtc::rfc3339().format_time(&mut timestamp)?;
-        let meta = event.metadata();
+        let template = vinted_message_template::take();
+        let event_time = EventTime::of(event, self.event_time_skew);
+        let timestamp = self
+            .timestamp_format
+            .format(event_time.valid().unwrap_or_else(SystemTime::now));
+        let event_seq = vinted_event_seq::next();
+        // Records of the `log` crate have the bridge's own callsite, their
+        // `log.*` fields tell the actual one
+        let normalized = event.normalized_metadata();
+        let meta = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let metric = self.metrics.is_metric(meta.target());
+        let metric_error = if metric {
+            vinted_metric::validate(event)
+        } else {
+            None
+        };
+        let mut record = String::new();
+        let output: &mut dyn fmt::Write = match self.escaping_profile {
+            EscapingProfile::Default => &mut writer,
+            EscapingProfile::ClickHouse => &mut record,
+        };
+        let stats = self
+            .level_stats
+            .as_ref()
+            .and_then(|level_stats| level_stats.count(*meta.level()));
+        let written = Cell::new(0);
         let mut visit = || {
-            let format_field_marker: std::marker::PhantomData<N> = std::marker::PhantomData;
-            let mut serializer = Serializer::new(WriteAdaptor::new(writer));
+            let mut serializer = Serializer::new(WriteAdaptor::new(output, &written));
             let mut serializer = serializer.serialize_map(None)?;
-            serializer.serialize_entry("@timestamp", &timestamp)?;
+            serializer.serialize_entry(self.timestamp_key, &timestamp)?;
+            serializer.serialize_entry("event_seq", &event_seq)?;
             serializer.serialize_entry("level", &meta.level().as_serde())?;
-            serializer.serialize_entry("facility", self.facility)?;
-            let current_span = event
-                .parent()
-                .and_then(|id| ctx.span(id))
-                .or_else(|| ctx.lookup_current());
-            let mut visitor = tracing_serde::SerdeMapVisitor::new(serializer);
+            serializer.serialize_entry(
+                "facility",
+                self.facility_map
+                    .resolve(meta, normalized.is_some())
+                    .unwrap_or(self.facility),
+            )?;
+            for (key, value) in &self.additional_fields {
+                serializer.serialize_entry(key, value)?;
+            }
+            if self.fields_dropped > 0 {
+                serializer.serialize_entry(FIELDS_DROPPED_KEY, &self.fields_dropped)?;
+            }
+            let payload_tier = self.payload_tiers[level_index(*meta.level())];
+            let current_span = if payload_tier == PayloadTier::Minimal {
+                None
+            } else {
+                event
+                    .parent()
+                    .and_then(|id| ctx.span(id))
+                    .or_else(|| ctx.lookup_current())
+            };
+            let mut visitor = VintedFieldVisitor::new(serializer)
+                .unique_keys(RecordKeys::new(self.reserved_keys.clone()))
+                .drop_empty(self.drop_empty_fields)
+                .skip_event_time(event_time.valid().is_some())
+                .skip_log_fields(normalized.is_some())
+                .debug_budget(self.debug_budget)
+                .capture_message(self.message_template)
+                .field_types(if metric {
+                    Some(self.metrics.field_types())
+                } else {
+                    self.field_types.clone()
+                })
+                .pii(self.pii.clone())
+                .redaction(self.redaction.clone());
             event.record(&mut visitor);
+            notice_untyped_fields(meta, visitor.untyped_fields(), &self.own_records);
+            let mut pii_found = visitor.pii_found();
+            let template = match (template, visitor.message()) {
+                _ if !self.message_template => None,
+                (Some(template), _) => Some(template.to_owned()),
+                (None, Some(message)) => Some(vinted_message_template::normalize(message)),
+                (None, None) => None,
+            };
+            let mut keys = visitor.take_keys();
             serializer = visitor.take_serializer()?;
-            serializer.serialize_entry("target", meta.target())?;
-            if let Some(ref span) = current_span {
+            // Records always carry a message, if only an empty one
+            if keys.insert(Cow::Borrowed("message")) {
+                serializer.serialize_entry("message", "")?;
+            }
+            if let Some(ref template) = template {
+                serializer.serialize_entry("message_template", template)?;
                 serializer
-                    .serialize_entry("span", &SerializableSpan(span, format_field_marker))
-                    .unwrap_or(());
+                    .serialize_entry("message_class", &vinted_message_template::class(template))?;
+            }
+            if let EventTime::Invalid(ref error) = event_time {
+                serializer.serialize_entry(vinted_event_time::ERROR_FIELD, error)?;
+            }
+            if let Some(ref error) = metric_error {
+                serializer.serialize_entry(vinted_metric::ERROR_FIELD, error)?;
+            }
+            if meta.target() == access_log::TARGET {
+                access_log::for_each_extension(|key, value| {
+                    if !keys.insert(Cow::Owned(key.to_owned())) {
+                        return Ok(());
+                    }
+                    serializer.serialize_entry(key, value)
+                })?;
+            }
+            if meta.target() == vinted_panic_hook::TARGET {
+                vinted_panic_hook::for_each_span_field(|key, value| {
+                    if !keys.insert(Cow::Owned(key.to_owned())) {
+                        return Ok(());
+                    }
+                    serializer.serialize_entry(key, value)
+                })?;
+            }
+            serializer.serialize_entry("target", meta.target())?;
+            match current_span {
+                Some(ref span) if payload_tier == PayloadTier::Standard => {
+                    if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                        for (key, value) in fields {
+                            if !keys.insert(Cow::Owned(key.clone())) {
+                                continue;
+                            }
+                            serializer.serialize_entry(key, value)?;
+                        }
+                    }
+                }
+                Some(ref span) => {
+                    serializer
+                        .serialize_entry("span", &SerializableSpan(span))
+                        .unwrap_or(());
+                    let names: Vec<_> = span.scope().from_root().map(|span| span.name()).collect();
+                    serializer.serialize_entry("spans", &names.join(":"))?;
+                }
+                None => {}
+            }
+            if let Some(TraceContext(ref trace_context)) = self.trace_context {
+                // From the innermost span which has one, whatever the tier
+                let ids = event
+                    .parent()
+                    .and_then(|id| ctx.span(id))
+                    .or_else(|| ctx.lookup_current())
+                    .and_then(|span| {
+                        span.scope()
+                            .find_map(|span| trace_context(&span.extensions()))
+                    });
+                if let Some((trace_id, span_id)) = ids {
+                    serializer.serialize_entry("trace_id", &trace_id)?;
+                    serializer.serialize_entry("span_id", &span_id)?;
+                }
             }
             let current_thread = std::thread::current();
             serializer.serialize_entry("thread_id", &format!("{:?}", current_thread.id()))?;
             if let Some(thread_name) = current_thread.name() {
                 serializer.serialize_entry("thread_name", thread_name)?;
             }
+            #[cfg(feature = "runtime-context")]
+            if self.runtime_context {
+                serializer.serialize_entry("runtime_context", vinted_runtime_context::current())?;
+            }
             if let Some(file) = meta.file() {
                 serializer.serialize_entry("file", file)?;
             }
@@ -74,97 +508,84 @@ where
             if let Some(hostname) = gethostname::gethostname().to_str() {
                 serializer.serialize_entry("host", hostname)?;
             }
+            if let Some(ref host_ip) = self.host_ips.host_ip {
+                serializer.serialize_entry("host_ip", host_ip)?;
+            }
+            if let Some(ref node_ip) = self.host_ips.node_ip {
+                serializer.serialize_entry("node_ip", node_ip)?;
+            }
+            if let Some(ref slot) = self.deployment.slot {
+                serializer.serialize_entry("deploy_slot", slot)?;
+            }
+            if let Some(ref id) = self.deployment.id {
+                serializer.serialize_entry("deploy_id", id)?;
+            }
+            if let Some(ref span) = current_span {
+                if let Some(SpanPii(found)) = span.extensions().get::<SpanPii>() {
+                    pii_found.extend(*found);
+                }
+            }
+            if !pii_found.is_empty() {
+                serializer.serialize_entry(vinted_pii::KEY, &pii_found.names())?;
+            }
+            if meta.target() == vinted_canary::CANARY_TARGET {
+                serializer.serialize_entry(
+                    vinted_stats::BUCKETS_KEY,
+                    &vinted_stats::heartbeat_buckets(),
+                )?;
+            }
+            if self.volume_top && meta.target() == vinted_canary::CANARY_TARGET {
+                serializer.serialize_entry(
+                    vinted_volume_writer::KEY,
+                    &vinted_volume_writer::heartbeat_top(),
+                )?;
+            }
+            if let (Some(level_stats), Some(stats)) = (&self.level_stats, &stats) {
+                // `,"stats":{...}}` and the newline
+                let len = serde_json::to_vec(stats).map_or(usize::MAX, |stats| {
+                    stats.len() + vinted_level_stats::KEY.len() + 6
+                });
+                if level_stats.fits(written.get().saturating_add(len)) {
+                    serializer.serialize_entry(vinted_level_stats::KEY, stats)?;
+                }
+            }
             serializer.end()
         };
         visit().map_err(|_| fmt::Error)?;
-        writeln!(writer)
-    }
-}
-struct SerializableContext<'a, 'b, Span, N>(
-    &'b tracing_subscriber::fmt::FmtContext<'a, Span, N>,
-    std::marker::PhantomData<N>,
-)
-where
-    Span: Subscriber + for<'lookup> tracing_subscriber::registry::LookupSpan<'lookup>,
-    N: for<'writer> FormatFields<'writer> + 'static;
-impl<'a, 'b, Span, N> serde::ser::Serialize for SerializableContext<'a, 'b, Span, N>
-where
-    Span: Subscriber + for<'lookup> tracing_subscriber::registry::LookupSpan<'lookup>,
-    N: for<'writer> FormatFields<'writer> + 'static,
-{
-    fn serialize<Ser>(&self, serializer_o: Ser) -> Result<Ser::Ok, Ser::Error>
-    where
-        Ser: serde::ser::Serializer,
-    {
-        use serde::ser::SerializeSeq;
-        let mut serializer = serializer_o.serialize_seq(None)?;
-        for span in self.0.scope() {
-            serializer.serialize_element(&SerializableSpan(&span, self.1))?;
+        if self.escaping_profile == EscapingProfile::ClickHouse {
+            let record = vinted_escaping::clickhouse(&record).map_err(|_| fmt::Error)?;
+            writer.write_str(&record)?;
         }
-        serializer.end()
+        writeln!(writer)
     }
 }
-struct SerializableSpan<'a, 'b, Span, N>(
-    &'b tracing_subscriber::registry::SpanRef<'a, Span>,
-    std::marker::PhantomData<N>,
-)
+struct SerializableSpan<'a, 'b, Span>(&'b tracing_subscriber::registry::SpanRef<'a, Span>)
 where
-    Span: for<'lookup> tracing_subscriber::registry::LookupSpan<'lookup>,
-    N: for<'writer> FormatFields<'writer> + 'static;
-impl<'a, 'b, Span, N> serde::ser::Serialize for SerializableSpan<'a, 'b, Span, N>
+    Span: for<'lookup> tracing_subscriber::registry::LookupSpan<'lookup>;
+impl<'a, 'b, Span> serde::ser::Serialize for SerializableSpan<'a, 'b, Span>
 where
     Span: for<'lookup> tracing_subscriber::registry::LookupSpan<'lookup>,
-    N: for<'writer> FormatFields<'writer> + 'static,
 {
     fn serialize<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
     where
         Ser: serde::ser::Serializer,
     {
         let mut serializer = serializer.serialize_map(None)?;
-        let ext = self.0.extensions();
-        let data = ext
-            .get::<FormattedFields<N>>()
-            .expect("Unable to find FormattedFields in extensions; this is a bug");
-        // TODO: let's _not_ do this, but this resolves
-        // https://github.com/tokio-rs/tracing/issues/391.
-        // We should probably rework this to use a `serde_json::Value` or something
-        // similar in a JSON-specific layer, but I'd (david)
-        // rather have a uglier fix now rather than shipping broken JSON.
-        match serde_json::from_str::<serde_json::Value>(&data) {
-            Ok(serde_json::Value::Object(fields)) => {
-                for field in fields {
-                    serializer.serialize_entry(&field.0, &field.1)?;
+        if let Some(SpanFields(fields)) = self.0.extensions().get::<SpanFields>() {
+            for (key, value) in fields {
+                // `name` is the span's own name, keep a field of the same name
+                // apart, under an underscored key no other field has
+                if key == "name" {
+                    let mut renamed = String::from("_name");
+                    while fields.contains_key(&renamed) {
+                        renamed.insert(0, '_');
+                    }
+                    serializer.serialize_entry(&renamed, value)?;
+                } else {
+                    serializer.serialize_entry(key, value)?;
                 }
             }
-            // We have fields for this span which are valid JSON but not an object.
-            // This is probably a bug, so panic if we're in debug mode
-            Ok(_) if cfg!(debug_assertions) => panic!(
-                "span '{}' had malformed fields! this is a bug.\n  error: invalid JSON object\n  fields: {:?}",
-                self.0.metadata().name(),
-                data
-            ),
-            // If we *aren't* in debug mode, it's probably best not to
-            // crash the program, let's log the field found but also an
-            // message saying it's type  is invalid
-            Ok(value) => {
-                serializer.serialize_entry("field", &value)?;
-                serializer.serialize_entry("field_error", "field was no a valid object")?
-            }
-            // We have previously recorded fields for this span
-            // should be valid JSON. However, they appear to *not*
-            // be valid JSON. This is almost certainly a bug, so
-            // panic if we're in debug mode
-            Err(e) if cfg!(debug_assertions) => panic!(
-                "span '{}' had malformed fields! this is a bug.\n  error: {}\n  fields: {:?}",
-                self.0.metadata().name(),
-                e,
-                data
-            ),
-            // If we *aren't* in debug mode, it's probably best not
-            // crash the program, but let's at least make sure it's clear
-            // that the fields are not supposed to be missing.
-            Err(e) => serializer.serialize_entry("field_error", &format!("{}", e))?,
-        };
+        }
         serializer.serialize_entry("name", self.0.metadata().name())?;
         serializer.end()
     }
@@ -175,20 +596,21 @@ where
 /// while `serde_json`'s Serializer expects an io::Write.
 struct WriteAdaptor<'a> {
     fmt_write: &'a mut dyn fmt::Write,
+    /// Bytes written so far
+    written: &'a Cell<usize>,
 }
 impl<'a> WriteAdaptor<'a> {
-    fn new(fmt_write: &'a mut dyn fmt::Write) -> Self {
-        Self { fmt_write }
+    fn new(fmt_write: &'a mut dyn fmt::Write, written: &'a Cell<usize>) -> Self {
+        Self { fmt_write, written }
     }
 }
 impl<'a> io::Write for WriteAdaptor<'a> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let s =
             std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        self.fmt_write
-            .write_str(&s)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(s.as_bytes().len())
+        self.fmt_write.write_str(s).map_err(io::Error::other)?;
+        self.written.set(self.written.get() + s.len());
+        Ok(s.len())
     }
     fn flush(&mut self) -> io::Result<()> {
         Ok(())
@@ -199,3 +621,606 @@ impl<'a> fmt::Debug for WriteAdaptor<'a> {
         f.pad("WriteAdaptor { .. }")
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::PayloadTier;
+    use crate::{
+        test_support::{check_record, log_random_event, with_logger, Capture, Rng},
+        LoggerBuilder, Target,
+    };
+    use serde_json::{json, Value};
+    use tracing::Level;
+    fn records(builder: LoggerBuilder) -> Vec<Value> {
+        let capture = Capture::default();
+        with_logger(builder.capture(&capture), || {
+            let _outer = tracing::info_span!("outer", user_id = 7).entered();
+            let _request = tracing::info_span!("request", path = "/items").entered();
+            tracing::info!(items = 3, "listed");
+            tracing::error!("failed");
+        });
+        capture.records()
+    }
+    fn json_builder() -> LoggerBuilder {
+        crate::builder("svc-test", Target::ConsoleJson)
+    }
+    #[test]
+    fn full_tier_is_the_default() {
+        for record in records(json_builder()) {
+            assert_eq!(record["span"], json!({"path": "/items", "name": "request"}));
+            assert_eq!(record["spans"], "outer:request");
+            assert!(record.get("path").is_none(), "{}", record);
+        }
+    }
+    #[test]
+    fn full_tier_has_the_span_hierarchy() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            let _outer = tracing::info_span!("outer", user_id = 7).entered();
+            tracing::info!("in outer");
+            let _db = tracing::info_span!("db_query", table = "items").entered();
+            let _pool = tracing::info_span!("pool").entered();
+            tracing::info!("in pool");
+        });
+        let records = capture.records();
+        assert_eq!(records[0]["span"], json!({"name": "outer", "user_id": 7}));
+        assert_eq!(records[0]["spans"], "outer");
+        assert_eq!(records[1]["span"], json!({"name": "pool"}));
+        assert_eq!(records[1]["spans"], "outer:db_query:pool");
+    }
+    #[test]
+    fn explicit_parents_make_the_hierarchy() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            let outer = tracing::info_span!("outer", user_id = 7);
+            let job = tracing::info_span!(parent: &outer, "job");
+            let _unrelated = tracing::info_span!("unrelated").entered();
+            tracing::info!(parent: &job, "done");
+        });
+        assert_eq!(capture.records()[0]["spans"], "outer:job");
+    }
+    #[test]
+    fn standard_tier_flattens_the_current_span() {
+        for record in records(json_builder().payload_tier(PayloadTier::Standard)) {
+            assert!(record.get("span").is_none(), "{}", record);
+            assert!(record.get("spans").is_none(), "{}", record);
+            assert_eq!(record["path"], "/items");
+            // Only the current span, not its parents
+            assert!(record.get("user_id").is_none(), "{}", record);
+        }
+    }
+    #[test]
+    fn minimal_tier_has_no_span_context() {
+        for record in records(json_builder().payload_tier(PayloadTier::Minimal)) {
+            assert!(record.get("span").is_none(), "{}", record);
+            assert!(record.get("path").is_none(), "{}", record);
+            assert!(record["message"].is_string());
+        }
+    }
+    #[test]
+    fn tiers_are_overridden_per_level() {
+        let records = records(
+            json_builder()
+                .payload_tier(PayloadTier::Minimal)
+                .payload_tier_for(Level::ERROR, PayloadTier::Full),
+        );
+        assert_eq!(records.len(), 2);
+        let (info, error) = (&records[0], &records[1]);
+        assert_eq!(info["items"], 3);
+        assert!(info.get("span").is_none(), "{}", info);
+        assert_eq!(error["span"]["name"], "request");
+    }
+    #[test]
+    fn lower_tiers_make_smaller_records() {
+        let size = |tier| {
+            records(json_builder().payload_tier(tier))
+                .iter()
+                .map(|record| record.to_string().len())
+                .sum::<usize>()
+        };
+        let (minimal, standard, full) = (
+            size(PayloadTier::Minimal),
+            size(PayloadTier::Standard),
+            size(PayloadTier::Full),
+        );
+        assert!(
+            minimal < standard && standard < full,
+            "{} {} {}",
+            minimal,
+            standard,
+            full
+        );
+    }
+    /// Replaces the values of `"key":` in `line`, up to the next `,` or `}`,
+    /// with `with`
+    fn normalize(line: &str, key: &str, with: &str) -> String {
+        let start = match line.find(&format!("\"{}\":", key)) {
+            Some(start) => start + key.len() + 3,
+            None => return line.to_owned(),
+        };
+        let end = start + line[start..].find([',', '}']).unwrap();
+        format!("{}{}{}", &line[..start], with, &line[end..])
+    }
+    fn snapshot(line: &str) -> String {
+        let line = normalize(line, "event_seq", "0");
+        let line = normalize(&line, "thread_id", "\"T\"");
+        normalize(&line, "host", "\"HOST\"")
+    }
+    /// Logs the snapshot event, returns the line it should become
+    fn log_snapshot_event() -> String {
+        let _span = tracing::info_span!("request", path = "/items").entered();
+        let line = line!() + 1;
+        tracing::info!(
+            event_time = "2021-04-20T12:42:57.353Z",
+            items = 3,
+            ok = true,
+            ratio = 0.5,
+            "listed"
+        );
+        format!(
+            concat!(
+                r#"{{"@timestamp":"2021-04-20T12:42:57.353+00:00","event_seq":0,"level":"INFO","#,
+                r#""facility":"svc-test","message":"listed","items":3,"ok":true,"ratio":0.5,"#,
+                r#""target":"vinted_logger::vinted_json_formatter::tests","#,
+                r#""span":{{"path":"/items","name":"request"}},"spans":"request","thread_id":"T","#,
+                r#""thread_name":"{}","file":"src/vinted_json_formatter.rs","#,
+                r#""module":"vinted_logger::vinted_json_formatter::tests","line":{},"host":"HOST"}}"#,
+                "\n"
+            ),
+            std::thread::current().name().unwrap_or_default(),
+            line
+        )
+    }
+    #[test]
+    fn console_json_records_match_the_snapshot() {
+        let capture = Capture::default();
+        let expected = with_logger(json_builder().capture(&capture), log_snapshot_event);
+        assert_eq!(snapshot(&capture.lines()[0]), expected);
+    }
+    #[test]
+    fn network_records_match_console_json_byte_for_byte() {
+        use std::{
+            io::{BufRead, BufReader},
+            net::{TcpListener, UdpSocket},
+            time::Duration,
+        };
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let builder =
+            crate::builder("svc-test", Target::UdpJson).destination(udp.local_addr().unwrap());
+        let expected = with_logger(builder, log_snapshot_event);
+        let mut datagram = vec![0; 65_536];
+        let len = udp.recv(&mut datagram).unwrap();
+        assert_eq!(
+            snapshot(std::str::from_utf8(&datagram[..len]).unwrap()),
+            expected
+        );
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let destination = format!("tcp://{}", tcp.local_addr().unwrap());
+        let builder = crate::builder("svc-test", Target::TcpJson)
+            .destination(destination.parse::<crate::LogDestination>().unwrap());
+        let expected = with_logger(builder, || {
+            let expected = log_snapshot_event();
+            crate::flush(Duration::from_secs(5));
+            expected
+        });
+        let (stream, _) = tcp.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut received = String::new();
+        BufReader::new(stream).read_line(&mut received).unwrap();
+        assert_eq!(snapshot(&received), expected);
+    }
+    #[test]
+    fn console_lines_match_the_snapshot() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console).capture(&capture);
+        with_logger(builder, log_snapshot_event);
+        let line = &capture.lines()[0];
+        // The time and event_seq, e.g. `\u{1b}[2m2021-04-20T12:42:57.353+00:00 #0\u{1b}[0m `
+        let (time, line) = line.split_at(line.find("\u{1b}[0m ").unwrap() + 5);
+        assert!(
+            time.starts_with("\u{1b}[2m2021-04-20T12:42:57.353+00:00 #"),
+            "{:?}",
+            time
+        );
+        assert_eq!(
+            line,
+            concat!(
+                "\u{1b}[32m INFO\u{1b}[0m \u{1b}[1mrequest\u{1b}[0m\u{1b}[1m{\u{1b}[0m",
+                "\u{1b}[3mpath\u{1b}[0m\u{1b}[2m=\u{1b}[0m\"/items\"\u{1b}[1m}\u{1b}[0m",
+                "\u{1b}[2m:\u{1b}[0m \u{1b}[2mvinted_logger::vinted_json_formatter::tests\u{1b}[0m",
+                "\u{1b}[2m:\u{1b}[0m listed \u{1b}[3mevent_time\u{1b}[0m\u{1b}[2m=\u{1b}[0m",
+                "\"2021-04-20T12:42:57.353Z\" \u{1b}[3mitems\u{1b}[0m\u{1b}[2m=\u{1b}[0m3 ",
+                "\u{1b}[3mok\u{1b}[0m\u{1b}[2m=\u{1b}[0mtrue \u{1b}[3mratio\u{1b}[0m",
+                "\u{1b}[2m=\u{1b}[0m0.5\n"
+            )
+        );
+    }
+    #[test]
+    fn fields_named_like_record_keys_are_renamed() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            tracing::info!(
+                level = "custom",
+                facility = "foo",
+                host = 1,
+                span = "s",
+                "hi"
+            );
+        });
+        let line = &capture.lines()[0];
+        let record = check_record(line.as_bytes()).unwrap_or_else(|e| panic!("{}: {}", e, line));
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["facility"], "svc-test");
+        assert_eq!(record["message"], "hi");
+        assert_eq!(record["_level"], "custom");
+        assert_eq!(record["_facility"], "foo");
+        assert_eq!(record["_host"], 1);
+        assert_eq!(record["_span"], "s");
+    }
+    #[test]
+    fn fields_named_like_renamed_ones_are_left_out() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            tracing::info!(level = "a", __level = "b", _level = "c", "hi");
+        });
+        let line = &capture.lines()[0];
+        let record = check_record(line.as_bytes()).unwrap_or_else(|e| panic!("{}: {}", e, line));
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["_level"], "a");
+        assert_eq!(record["__level"], "b");
+        assert!(!line.contains(r#""c""#), "{}", line);
+    }
+    #[test]
+    fn fields_only_take_their_keys_in_their_own_record() {
+        let capture = Capture::default();
+        let builder = json_builder()
+            .additional_field("datacenter", "eu-1")
+            .capture(&capture);
+        with_logger(builder, || {
+            tracing::info!(user = "a", datacenter = "x", "first");
+            tracing::info!(user = "b", datacenter = "y", "second");
+        });
+        for (record, user) in capture.records().iter().zip(["a", "b"]) {
+            assert_eq!(record["user"], user, "{}", record);
+            assert!(record.get("_user").is_none(), "{}", record);
+            assert_eq!(record["datacenter"], "eu-1");
+        }
+        let records = capture.records();
+        assert_eq!(records[0]["_datacenter"], "x");
+        assert_eq!(records[1]["_datacenter"], "y");
+    }
+    #[test]
+    fn records_without_a_message_have_an_empty_one() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            tracing::info!(items = 3);
+        });
+        let record = check_record(capture.lines()[0].as_bytes()).unwrap();
+        assert_eq!(record["message"], "");
+        assert_eq!(record["items"], 3);
+    }
+    #[test]
+    fn span_fields_keep_their_types() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            let span = tracing::info_span!(
+                "request",
+                user_id = 7,
+                offset = -3,
+                ratio = 0.5,
+                cached = true,
+                path = "/items",
+                status = tracing::field::Empty,
+                retried = false,
+            );
+            let _entered = span.enter();
+            tracing::info!("started");
+            span.record("status", 200);
+            span.record("retried", true);
+            tracing::info!("done");
+        });
+        let records = capture.records();
+        assert_eq!(
+            records[0]["span"],
+            json!({
+                "name": "request",
+                "user_id": 7,
+                "offset": -3,
+                "ratio": 0.5,
+                "cached": true,
+                "path": "/items",
+                "retried": false,
+            })
+        );
+        assert_eq!(records[1]["span"]["status"], 200);
+        assert_eq!(records[1]["span"]["retried"], true);
+        for record in &records {
+            assert!(record.get("field_error").is_none(), "{}", record);
+        }
+    }
+    #[test]
+    fn flattened_span_fields_keep_their_types() {
+        let capture = Capture::default();
+        let builder = json_builder()
+            .payload_tier(PayloadTier::Standard)
+            .capture(&capture);
+        with_logger(builder, || {
+            let span = tracing::info_span!("request", user_id = 7, status = tracing::field::Empty);
+            let _entered = span.enter();
+            span.record("status", 404);
+            tracing::info!("done");
+        });
+        let record = &capture.records()[0];
+        assert_eq!(record["user_id"], 7);
+        assert_eq!(record["status"], 404);
+        assert!(record.get("field_error").is_none(), "{}", record);
+    }
+    #[test]
+    fn span_fields_named_name_keep_apart_from_the_span_name() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            let _span = tracing::info_span!("request", name = "a", _name = "b").entered();
+            tracing::info!("renamed");
+        });
+        let record = &capture.records()[0];
+        assert_eq!(
+            record["span"],
+            json!({"name": "request", "_name": "b", "__name": "a"})
+        );
+        check_record(capture.lines()[0].as_bytes()).unwrap();
+    }
+    #[test]
+    fn random_events_make_valid_console_records() {
+        const MAX_LINE: usize = 16 * 1024;
+        let capture = Capture::default();
+        let builder = json_builder()
+            .default_level(Level::TRACE)
+            .console_max_line(MAX_LINE)
+            .capture(&capture);
+        let mut rng = Rng::new(724);
+        with_logger(builder, || {
+            for _ in 0..2_000 {
+                log_random_event(&mut rng);
+            }
+        });
+        let lines = capture.lines();
+        assert!(lines.len() >= 2_000, "{} records", lines.len());
+        for line in lines {
+            let record =
+                check_record(line.as_bytes()).unwrap_or_else(|e| panic!("{}: {}", e, line));
+            // Longer records are replaced by a stub
+            assert!(
+                line.len() <= MAX_LINE || record["original_bytes"].is_u64(),
+                "{} bytes",
+                line.len()
+            );
+        }
+    }
+    #[test]
+    fn random_events_make_valid_datagrams() {
+        use std::{net::UdpSocket, thread, time::Duration};
+        const MAX_DATAGRAM: usize = 4_096;
+        const EVENTS: usize = 500;
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .default_level(Level::TRACE)
+            .max_datagram_size(MAX_DATAGRAM)
+            .destination(socket.local_addr().unwrap());
+        let receiver = thread::spawn(move || {
+            let mut datagrams = Vec::new();
+            let mut buf = vec![0; 65_536];
+            while let Ok(len) = socket.recv(&mut buf) {
+                datagrams.push(buf[..len].to_vec());
+            }
+            datagrams
+        });
+        let mut rng = Rng::new(7_240);
+        with_logger(builder, || {
+            for _ in 0..EVENTS {
+                log_random_event(&mut rng);
+            }
+            crate::flush(Duration::from_secs(5));
+        });
+        let datagrams = receiver.join().unwrap();
+        // Loopback may still drop a few under load
+        assert!(
+            datagrams.len() >= EVENTS / 2,
+            "{} datagrams",
+            datagrams.len()
+        );
+        for datagram in datagrams {
+            assert!(datagram.len() <= MAX_DATAGRAM, "{} bytes", datagram.len());
+            if let Err(e) = check_record(&datagram) {
+                panic!("{}: {}", e, String::from_utf8_lossy(&datagram));
+            }
+        }
+    }
+    #[test]
+    fn rejected_additional_fields_fail_the_build() {
+        let over_count = json_builder()
+            .additional_field_limits(2, 1024)
+            .additional_field("datacenter", "eu-1")
+            .additional_field("zone", "a")
+            .additional_field("rack", "7");
+        let over_bytes = json_builder()
+            .additional_field_limits(128, 32)
+            .additional_field("datacenter", "eu-1")
+            .additional_field("build", "x".repeat(32));
+        let reserved = json_builder().additional_field("level", "custom");
+        for (builder, name) in [
+            (over_count, "`rack`"),
+            (over_bytes, "`build`"),
+            (reserved, "`level`"),
+        ] {
+            match builder.build() {
+                Err(crate::InitError::InvalidConfig(message)) => {
+                    assert!(message.contains(name), "{}", message)
+                }
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+        }
+    }
+    #[test]
+    fn ignored_additional_fields_are_counted_in_records() {
+        let builder = json_builder()
+            .additional_field_limits(2, 64)
+            .ignore_rejected_fields()
+            .additional_field("datacenter", "eu-1")
+            .additional_field("build", "x".repeat(64))
+            .additional_field("zone", "a")
+            .additional_field("rack", "7")
+            .additional_field("level", "custom");
+        for record in records(builder) {
+            assert_eq!(record["datacenter"], "eu-1");
+            assert_eq!(record["zone"], "a");
+            assert_eq!(record["fields_dropped"], 3);
+            for key in &["build", "rack"] {
+                assert!(record.get(*key).is_none(), "{}", record);
+            }
+            assert_ne!(record["level"], "custom");
+        }
+        for record in records(json_builder().additional_field("datacenter", "eu-1")) {
+            assert!(record.get("fields_dropped").is_none(), "{}", record);
+        }
+    }
+    /// Sends a `log` record through the bridge, as `LogTracer` does
+    fn log_bridged(message: &str) {
+        tracing_log::format_trace(
+            &log::Record::builder()
+                .args(format_args!("{}", message))
+                .level(log::Level::Warn)
+                .target("payments::charge")
+                .module_path_static(Some("payments::charge"))
+                .file_static(Some("src/charge.rs"))
+                .line(Some(42))
+                .build(),
+        )
+        .unwrap();
+    }
+    #[test]
+    fn bridged_log_records_have_their_own_callsite() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || log_bridged("charged"));
+        let record = &capture.records()[0];
+        assert_eq!(record["message"], "charged");
+        assert_eq!(record["level"], "WARN");
+        assert_eq!(record["target"], "payments::charge");
+        assert_eq!(record["module"], "payments::charge");
+        assert_eq!(record["file"], "src/charge.rs");
+        assert_eq!(record["line"], 42);
+        let keys: Vec<_> = record.as_object().unwrap().keys().collect();
+        assert!(!keys.iter().any(|key| key.starts_with("log")), "{:?}", keys);
+    }
+    #[test]
+    fn bridged_log_records_have_their_own_target_in_text() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::Console).capture(&capture);
+        with_logger(builder, || log_bridged("charged"));
+        let lines = capture.lines();
+        assert!(lines[0].contains("payments::charge"), "{:?}", lines);
+        assert!(lines[0].contains("charged"), "{:?}", lines);
+        assert!(!lines[0].contains("log."), "{:?}", lines);
+    }
+    /// Trace ids of a span, kept in its extensions as `tracing-opentelemetry`
+    /// keeps its `OtelData`
+    struct TraceIds(u128, u64);
+    /// Starts a trace at each root span, and gives spans named "untraced" no
+    /// ids
+    struct Traces;
+    impl<S> tracing_subscriber::Layer<S> for Traces
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            _attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let span = ctx.span(id).unwrap();
+            if span.name() == "untraced" {
+                return;
+            }
+            let trace_id = span
+                .scope()
+                .skip(1)
+                .find_map(|parent| parent.extensions().get::<TraceIds>().map(|ids| ids.0))
+                .unwrap_or(0xabc0 + u128::from(id.into_u64()));
+            let span_id = 0xdef0 + id.into_u64();
+            span.extensions_mut().insert(TraceIds(trace_id, span_id));
+        }
+    }
+    #[test]
+    fn records_have_the_ids_of_their_trace() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let capture = Capture::default();
+        let (layer, _filter) = json_builder()
+            .capture(&capture)
+            .trace_context(|extensions| {
+                let ids = extensions.get::<TraceIds>()?;
+                Some((format!("{:032x}", ids.0), format!("{:016x}", ids.1)))
+            })
+            .build()
+            .unwrap();
+        let dispatch = tracing::Dispatch::new(
+            tracing_subscriber::Registry::default()
+                .with(layer)
+                .with(Traces),
+        );
+        let mut expected = Vec::new();
+        tracing::dispatcher::with_default(&dispatch, || {
+            let ids = |span: &tracing::Span| {
+                let ids = span.with_subscriber(|(id, dispatch)| {
+                    let registry = dispatch
+                        .downcast_ref::<tracing_subscriber::Registry>()
+                        .unwrap();
+                    let span =
+                        tracing_subscriber::registry::LookupSpan::span(registry, id).unwrap();
+                    let extensions = span.extensions();
+                    let ids = extensions.get::<TraceIds>().unwrap();
+                    (format!("{:032x}", ids.0), format!("{:016x}", ids.1))
+                });
+                ids.unwrap()
+            };
+            tracing::info!(trace_id = "mine", "outside");
+            let request = tracing::info_span!("request", path = "/items");
+            let _request = request.clone().entered();
+            tracing::info!("in request");
+            expected.push(ids(&request));
+            let query = tracing::info_span!("query");
+            let _query = query.clone().entered();
+            tracing::info!(trace_id = "mine", "in query");
+            expected.push(ids(&query));
+            let _untraced = tracing::info_span!("untraced").entered();
+            tracing::info!("in untraced");
+            expected.push(ids(&query));
+        });
+        let records = capture.records();
+        assert!(records[0].get("trace_id").is_none(), "{}", records[0]);
+        assert!(records[0].get("span_id").is_none(), "{}", records[0]);
+        assert_eq!(records[0]["_trace_id"], "mine");
+        for (record, (trace_id, span_id)) in records[1..].iter().zip(&expected) {
+            assert_eq!(&record["trace_id"], trace_id, "{}", record);
+            assert_eq!(&record["span_id"], span_id, "{}", record);
+        }
+        assert_eq!(records[1]["trace_id"], records[2]["trace_id"]);
+        assert_ne!(records[1]["span_id"], records[2]["span_id"]);
+        assert_eq!(records[2]["_trace_id"], "mine");
+    }
+    #[test]
+    fn trace_keys_are_event_fields_without_an_extractor() {
+        let capture = Capture::default();
+        with_logger(json_builder().capture(&capture), || {
+            let _request = tracing::info_span!("request").entered();
+            tracing::info!(trace_id = "mine", "listed");
+        });
+        let record = &capture.records()[0];
+        assert_eq!(record["trace_id"], "mine");
+        assert!(record.get("span_id").is_none(), "{}", record);
+    }
+}
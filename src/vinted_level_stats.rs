@@ -0,0 +1,200 @@
+use crate::vinted_json_formatter::level_index;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+use tracing_core::Level;
+
+/// Default of [`LoggerBuilder::level_stats_every`](crate::LoggerBuilder::level_stats_every)
+pub const DEFAULT_LEVEL_STATS_EVERY: u64 = 1_000;
+
+/// Largest record carrying stats on targets without a size limit of their
+/// own, the largest payload of a UDP datagram
+pub(crate) const MAX_RECORD_LEN: usize = 65_507;
+
+/// Key of the stats object
+pub(crate) const KEY: &str = "stats";
+
+/// Records formatted per level since the logger was installed, carried by
+/// every Nth record
+#[derive(Debug)]
+pub(crate) struct LevelStats {
+    every: u64,
+    /// Stats are left out of records they would grow beyond it
+    max_record_len: usize,
+    started: Instant,
+    records: AtomicU64,
+    levels: [AtomicU64; 5],
+}
+
+impl LevelStats {
+    pub(crate) fn new(every: u64, max_record_len: usize) -> Self {
+        Self {
+            every: every.max(1),
+            max_record_len,
+            started: Instant::now(),
+            records: AtomicU64::new(0),
+            levels: Default::default(),
+        }
+    }
+
+    /// Counts a record of `level`, returning the stats it carries if it's
+    /// one of every Nth
+    pub(crate) fn count(&self, level: Level) -> Option<Stats> {
+        self.levels[level_index(level)].fetch_add(1, Ordering::Relaxed);
+        let records = self.records.fetch_add(1, Ordering::Relaxed) + 1;
+        if !records.is_multiple_of(self.every) {
+            return None;
+        }
+
+        let mut levels = [0; 5];
+        for (count, level) in levels.iter_mut().zip(&self.levels) {
+            *count = level.load(Ordering::Relaxed);
+        }
+        Some(Stats {
+            levels,
+            uptime_secs: self.started.elapsed().as_secs(),
+        })
+    }
+
+    /// Whether a record of `len` bytes, stats included, may carry them
+    pub(crate) fn fits(&self, len: usize) -> bool {
+        len <= self.max_record_len
+    }
+}
+
+/// Cumulative counts, serialized as the `stats` object
+#[derive(Debug)]
+pub(crate) struct Stats {
+    levels: [u64; 5],
+    uptime_secs: u64,
+}
+
+impl Serialize for Stats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(6))?;
+        for level in [
+            Level::ERROR,
+            Level::WARN,
+            Level::INFO,
+            Level::DEBUG,
+            Level::TRACE,
+        ] {
+            map.serialize_entry(
+                &level.as_str().to_ascii_lowercase(),
+                &self.levels[level_index(level)],
+            )?;
+        }
+        map.serialize_entry("uptime_secs", &self.uptime_secs)?;
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use serde_json::{json, Value};
+    use std::{net::UdpSocket, time::Duration};
+    use tracing::Level;
+
+    #[test]
+    fn every_nth_record_carries_the_counts_so_far() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .default_level(Level::DEBUG)
+            .level_stats_every(4)
+            .capture(&capture);
+
+        with_logger(builder, || {
+            for n in 0..12 {
+                match n % 4 {
+                    0 => tracing::error!(n),
+                    1 => tracing::warn!(n),
+                    _ => tracing::info!(n),
+                }
+            }
+            tracing::debug!("not a 4th");
+        });
+
+        let records = capture.records();
+        let carrying: Vec<_> = records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| record.get("stats").is_some())
+            .map(|(n, _)| n)
+            .collect();
+        assert_eq!(carrying, [3, 7, 11]);
+        let mut stats = records[7]["stats"].clone();
+        assert!(stats["uptime_secs"].is_u64());
+        stats.as_object_mut().unwrap().remove("uptime_secs");
+        assert_eq!(
+            stats,
+            json!({"error": 2, "warn": 2, "info": 4, "debug": 0, "trace": 0})
+        );
+    }
+
+    #[test]
+    fn stats_are_off_by_default() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+
+        with_logger(builder, || {
+            for _ in 0..2_000 {
+                tracing::info!("quiet");
+            }
+        });
+
+        assert!(capture
+            .records()
+            .iter()
+            .all(|record| record.get("stats").is_none()));
+    }
+
+    #[test]
+    fn stats_never_grow_records_past_the_datagram_size() {
+        const MAX_DATAGRAM: usize = 1_024;
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(socket.local_addr().unwrap())
+            .max_datagram_size(MAX_DATAGRAM)
+            .level_stats_every(1);
+
+        with_logger(builder, || {
+            for len in (0..MAX_DATAGRAM).step_by(16) {
+                tracing::info!("{}", "x".repeat(len));
+            }
+            crate::flush(Duration::from_secs(5));
+        });
+
+        let mut buf = vec![0; 65_536];
+        let (mut with_stats, mut without_stats) = (0, 0);
+        while let Ok(len) = socket.recv(&mut buf) {
+            assert!(len <= MAX_DATAGRAM, "{} bytes", len);
+            let record: Value = serde_json::from_slice(&buf[..len]).unwrap();
+            let truncated = record
+                .get(crate::vinted_udp_writer::TRUNCATED_KEY)
+                .is_some();
+            match record.get("stats") {
+                Some(_) => {
+                    assert!(!truncated, "stats made the record too large");
+                    with_stats += 1;
+                }
+                None if !truncated => without_stats += 1,
+                None => {}
+            }
+        }
+        assert!(
+            with_stats > 0 && without_stats > 0,
+            "{} {}",
+            with_stats,
+            without_stats
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::cell::Cell;
+
+/// Longest part of a message normalized into a template, the rest is left out
+const MAX_NORMALIZED_LEN: usize = 1024;
+
+thread_local! {
+    /// Format string of the `*f!` event being dispatched on this thread
+    static TEMPLATE: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Runs `dispatch`, which emits an event formatted from `template`, not
+/// part of the public API
+pub fn with_template<R>(template: &'static str, dispatch: impl FnOnce() -> R) -> R {
+    let outer = TEMPLATE.with(|current| current.replace(Some(template)));
+    let result = dispatch();
+    TEMPLATE.with(|current| current.set(outer));
+    result
+}
+
+/// Takes the format string of the event being formatted, so events logged
+/// while formatting it don't inherit it
+pub(crate) fn take() -> Option<&'static str> {
+    TEMPLATE.with(Cell::take)
+}
+
+/// Turns a rendered message into a template by replacing the parts which
+/// likely vary between messages of the same kind: UUIDs become `{uuid}`,
+/// hex ids (`0x1f`, or 8+ hex digits mixing digits and letters) `{hex}` and
+/// any other run of digits `{n}`.
+pub(crate) fn normalize(message: &str) -> String {
+    let mut end = message.len().min(MAX_NORMALIZED_LEN);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    let message = &message[..end];
+    let bytes = message.as_bytes();
+
+    let mut template = String::with_capacity(message.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        let word_start = at == 0 || !bytes[at - 1].is_ascii_alphanumeric();
+        if word_start && is_uuid(&bytes[at..]) {
+            template.push_str("{uuid}");
+            at += 36;
+            continue;
+        }
+        if word_start {
+            let word = bytes[at..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_alphanumeric())
+                .count();
+            if is_hex_id(&bytes[at..at + word]) {
+                template.push_str("{hex}");
+                at += word;
+                continue;
+            }
+        }
+        if bytes[at].is_ascii_digit() {
+            template.push_str("{n}");
+            at += bytes[at..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_digit())
+                .count();
+            continue;
+        }
+
+        // Letters, or else anything up to the next ASCII alphanumeric byte,
+        // which is always a char boundary
+        let run = if bytes[at].is_ascii_alphabetic() {
+            bytes[at..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_alphabetic())
+                .count()
+        } else {
+            bytes[at..]
+                .iter()
+                .take_while(|byte| !byte.is_ascii_alphanumeric())
+                .count()
+        };
+        template.push_str(&message[at..at + run]);
+        at += run;
+    }
+
+    template
+}
+
+fn is_uuid(bytes: &[u8]) -> bool {
+    bytes.len() >= 36
+        && bytes[..36].iter().enumerate().all(|(i, byte)| match i {
+            8 | 13 | 18 | 23 => *byte == b'-',
+            _ => byte.is_ascii_hexdigit(),
+        })
+        && !bytes.get(36).is_some_and(u8::is_ascii_alphanumeric)
+}
+
+fn is_hex_id(word: &[u8]) -> bool {
+    match word {
+        [b'0', b'x' | b'X', digits @ ..] => {
+            !digits.is_empty() && digits.iter().all(u8::is_ascii_hexdigit)
+        }
+        _ => {
+            word.len() >= 8
+                && word.iter().all(u8::is_ascii_hexdigit)
+                && word.iter().any(u8::is_ascii_digit)
+                && word.iter().any(u8::is_ascii_alphabetic)
+        }
+    }
+}
+
+/// Stable hash of a template, 64-bit FNV-1a in hex
+pub(crate) fn class(template: &str) -> String {
+    let hash = template
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{class, normalize, MAX_NORMALIZED_LEN};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use serde_json::Value;
+
+    fn records(log: impl FnOnce()) -> Vec<Value> {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .message_template()
+            .capture(&capture);
+        with_logger(builder, log);
+        capture.records()
+    }
+
+    #[test]
+    fn structured_macros_record_their_format_string() {
+        let user_id = 12_345;
+        let records = records(|| {
+            crate::infof!("failed to fetch user {user_id}");
+            // Plain macros logged right after don't inherit it
+            tracing::info!("failed to fetch user 67890");
+        });
+
+        assert_eq!(records[0]["message"], "failed to fetch user 12345");
+        assert_eq!(
+            records[0]["message_template"],
+            "failed to fetch user {user_id}"
+        );
+        assert_eq!(records[1]["message_template"], "failed to fetch user {n}");
+        assert_eq!(
+            records[0]["message_class"],
+            class("failed to fetch user {user_id}")
+        );
+    }
+
+    #[test]
+    fn messages_differing_in_ids_share_a_class() {
+        let records = records(|| {
+            tracing::info!("failed to fetch user 12345");
+            tracing::info!("failed to fetch user 67890");
+            tracing::info!("failed to fetch item 12345");
+        });
+
+        assert_eq!(records[0]["message_class"], records[1]["message_class"]);
+        assert_ne!(records[0]["message_class"], records[2]["message_class"]);
+    }
+
+    #[test]
+    fn varying_parts_are_replaced() {
+        for (message, template) in &[
+            ("failed to fetch user 12345", "failed to fetch user {n}"),
+            ("retry 3 of 5 after 250ms", "retry {n} of {n} after {n}ms"),
+            (
+                "order 3f2b8c1e-9a4d-4e2b-8f1c-7d6e5a4b3c2d not found",
+                "order {uuid} not found",
+            ),
+            (
+                "pointer 0x7ffd3a2c at offset 0XFF",
+                "pointer {hex} at offset {hex}",
+            ),
+            (
+                "commit 9fceb02d0ae598e95dc970b74767f19372d61af8",
+                "commit {hex}",
+            ),
+            // Words of hex letters only, or too short, are words
+            ("cafebabe deadbeef", "cafebabe deadbeef"),
+            ("build a1b2c3", "build a{n}b{n}c{n}"),
+            ("user_42 in eu-1", "user_{n} in eu-{n}"),
+            ("ąžuolas 7 kartus", "ąžuolas {n} kartus"),
+            ("no variable parts", "no variable parts"),
+        ] {
+            assert_eq!(&normalize(message), template, "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn long_messages_are_cut_between_characters() {
+        let message = format!("{}ž tail 42", "a".repeat(MAX_NORMALIZED_LEN - 1));
+
+        let template = normalize(&message);
+        assert_eq!(template, "a".repeat(MAX_NORMALIZED_LEN - 1));
+    }
+
+    #[test]
+    fn classes_are_the_same_on_every_run() {
+        // FNV-1a test vectors, the hash doesn't depend on the process
+        assert_eq!(class(""), "cbf29ce484222325");
+        assert_eq!(class("a"), "af63dc4c8601ec8c");
+        assert_eq!(class("foobar"), "85944171f73967e8");
+        assert_eq!(
+            class("failed to fetch user {n}"),
+            class(&normalize("failed to fetch user 1"))
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use crate::vinted_field_types::{self, FieldType, FieldTypes};
+use parking_lot::{const_mutex, Mutex};
+use std::{collections::HashSet, fmt, sync::Arc};
+use tracing_core::{
+    field::{Field, Visit},
+    Event,
+};
+
+/// Default of [`LoggerBuilder::metric_target`](crate::LoggerBuilder::metric_target)
+pub const DEFAULT_METRIC_TARGET: &str = "metric";
+
+/// Distinct metric names a process may log, names beyond them are rejected
+/// so an id slipping into a name can't blow up the metrics downstream
+pub const MAX_METRIC_NAMES: usize = 1_000;
+
+/// Field added to metric records which don't follow the schema
+pub(crate) const ERROR_FIELD: &str = "metric_error";
+
+const MAX_NAME_LEN: usize = 64;
+const MAX_UNIT_LEN: usize = 16;
+
+static NAMES: Mutex<Option<HashSet<String>>> = const_mutex(None);
+
+/// Which records are metrics, and the field types they're formatted with
+#[derive(Debug, Clone)]
+pub(crate) struct Metrics {
+    target: &'static str,
+    field_types: Arc<FieldTypes>,
+}
+
+impl Metrics {
+    /// `value` is declared a number on top of `field_types`
+    pub(crate) fn new(target: &'static str, field_types: Option<&FieldTypes>) -> Self {
+        let mut field_types = field_types.cloned().unwrap_or_default();
+        field_types.insert("value".to_owned(), FieldType::Number);
+
+        Self {
+            target,
+            field_types: Arc::new(field_types),
+        }
+    }
+
+    pub(crate) fn is_metric(&self, target: &str) -> bool {
+        target == self.target
+    }
+
+    pub(crate) fn field_types(&self) -> Arc<FieldTypes> {
+        self.field_types.clone()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(DEFAULT_METRIC_TARGET, None)
+    }
+}
+
+/// How a field of a metric record was recorded
+enum Recorded {
+    String(String),
+    Number,
+    Other,
+}
+
+#[derive(Default)]
+struct MetricFields {
+    name: Option<Recorded>,
+    value: Option<Recorded>,
+    unit: Option<Recorded>,
+    tags: Option<Recorded>,
+}
+
+impl MetricFields {
+    fn record(&mut self, field: &Field, recorded: Recorded) {
+        let slot = match field.name() {
+            "name" => &mut self.name,
+            "value" => &mut self.value,
+            "unit" => &mut self.unit,
+            "tags" => &mut self.tags,
+            _ => return,
+        };
+        *slot = Some(recorded);
+    }
+}
+
+impl Visit for MetricFields {
+    fn record_f64(&mut self, field: &Field, _: f64) {
+        self.record(field, Recorded::Number);
+    }
+
+    fn record_i64(&mut self, field: &Field, _: i64) {
+        self.record(field, Recorded::Number);
+    }
+
+    fn record_u64(&mut self, field: &Field, _: u64) {
+        self.record(field, Recorded::Number);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, Recorded::String(value.to_owned()));
+    }
+
+    fn record_debug(&mut self, field: &Field, _: &dyn fmt::Debug) {
+        self.record(field, Recorded::Other);
+    }
+}
+
+/// Checks a metric record against the schema, returning what's wrong with it
+///
+/// - `name` - a string of at most 64 `a-z`, `0-9`, `_` and `.`, one of at
+///   most [`MAX_METRIC_NAMES`]
+/// - `value` - a number, or a string of an integer, which is converted
+/// - `unit` - optional, a string of at most 16 characters without whitespace
+/// - `tags` - optional, a string of `key:value` pairs separated by commas
+pub(crate) fn validate(event: &Event<'_>) -> Option<String> {
+    let mut fields = MetricFields::default();
+    event.record(&mut fields);
+
+    let mut errors = Vec::new();
+    match fields.name {
+        None => errors.push("`name` is missing".to_owned()),
+        Some(Recorded::String(ref name)) if !valid_name(name) => errors.push(format!(
+            "`name` must be at most {} characters of `a-z`, `0-9`, `_` and `.`",
+            MAX_NAME_LEN
+        )),
+        Some(Recorded::String(name)) => {
+            let mut names = NAMES.lock();
+            let names = names.get_or_insert_with(HashSet::new);
+            if !names.contains(&name) {
+                if names.len() < MAX_METRIC_NAMES {
+                    names.insert(name);
+                } else {
+                    errors.push(format!(
+                        "`name` is new after {} distinct metric names",
+                        MAX_METRIC_NAMES
+                    ));
+                }
+            }
+        }
+        Some(_) => errors.push("`name` must be a string".to_owned()),
+    }
+    match fields.value {
+        None => errors.push("`value` is missing".to_owned()),
+        Some(Recorded::Number) => {}
+        Some(Recorded::String(ref value)) if vinted_field_types::integer(value).is_some() => {}
+        Some(_) => errors.push("`value` must be a number".to_owned()),
+    }
+    match fields.unit {
+        None => {}
+        Some(Recorded::String(ref unit))
+            if !unit.is_empty()
+                && unit.chars().count() <= MAX_UNIT_LEN
+                && !unit.chars().any(char::is_whitespace) => {}
+        Some(_) => errors.push(format!(
+            "`unit` must be a string of at most {} characters without whitespace",
+            MAX_UNIT_LEN
+        )),
+    }
+    match fields.tags {
+        None => {}
+        Some(Recorded::String(ref tags)) if valid_tags(tags) => {}
+        Some(_) => errors
+            .push("`tags` must be a string of `key:value` pairs separated by commas".to_owned()),
+    }
+
+    if errors.is_empty() {
+        None
+    } else {
+        Some(errors.join("; "))
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .bytes()
+            .all(|byte| matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'_' | b'.'))
+}
+
+fn valid_tags(tags: &str) -> bool {
+    tags.split(',').all(|tag| {
+        let mut parts = tag.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => !key.trim().is_empty() && !value.trim().is_empty(),
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use serde_json::{json, Value};
+    use std::{net::UdpSocket, time::Duration};
+
+    fn metric_records(log: impl FnOnce()) -> Vec<Value> {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+        with_logger(builder, log);
+        capture.records()
+    }
+
+    fn collector() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    fn recv(socket: &UdpSocket) -> Value {
+        let mut datagram = vec![0; 65_536];
+        let len = socket.recv(&mut datagram).expect("record is received");
+        serde_json::from_slice(&datagram[..len]).unwrap()
+    }
+
+    #[test]
+    fn valid_metrics_have_no_error() {
+        let records = metric_records(|| {
+            tracing::info!(target: "metric", name = "orders_created", value = 1);
+            tracing::info!(
+                target: "metric",
+                name = "checkout.latency",
+                value = 12.5,
+                unit = "ms",
+                tags = "country:lt,platform:ios"
+            );
+        });
+
+        assert_eq!(records[0]["value"], json!(1));
+        assert_eq!(records[1]["value"], json!(12.5));
+        assert_eq!(records[1]["unit"], "ms");
+        for record in &records {
+            assert_eq!(record.get("metric_error"), None, "{}", record);
+        }
+    }
+
+    #[test]
+    fn integer_strings_are_coerced_to_numbers() {
+        let records = metric_records(|| {
+            tracing::info!(target: "metric", name = "orders_created", value = "3");
+        });
+
+        assert_eq!(records[0]["value"], json!(3));
+        assert_eq!(records[0].get("metric_error"), None);
+    }
+
+    #[test]
+    fn invalid_metrics_are_logged_with_an_error() {
+        let records = metric_records(|| {
+            tracing::info!(target: "metric", name = "orders_created", value = "many");
+            tracing::info!(target: "metric", name = "Orders Created", value = 1);
+            tracing::info!(target: "metric", value = 1);
+            tracing::info!(target: "metric", name = "orders_created", value = 1, unit = "per second");
+            tracing::info!(target: "metric", name = "orders_created", value = 1, tags = "country");
+            // Other targets aren't checked
+            tracing::info!(value = "many");
+        });
+
+        let errors: Vec<_> = records
+            .iter()
+            .map(|record| record.get("metric_error").and_then(Value::as_str))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                Some("`value` must be a number"),
+                Some("`name` must be at most 64 characters of `a-z`, `0-9`, `_` and `.`"),
+                Some("`name` is missing"),
+                Some("`unit` must be a string of at most 16 characters without whitespace"),
+                Some("`tags` must be a string of `key:value` pairs separated by commas"),
+                None,
+            ]
+        );
+        assert_eq!(records[0]["value_raw"], "many", "delivered as logged");
+    }
+
+    #[test]
+    fn metric_targets_can_be_configured() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .metric_target("business")
+            .capture(&capture);
+
+        with_logger(builder, || {
+            tracing::info!(target: "business", value = 1);
+            tracing::info!(target: "metric", value = 1);
+        });
+
+        let records = capture.records();
+        assert_eq!(records[0]["metric_error"], "`name` is missing");
+        assert_eq!(records[1].get("metric_error"), None);
+    }
+
+    #[test]
+    fn metrics_can_go_to_their_own_destination() {
+        let (logs, metrics) = (collector(), collector());
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(logs.local_addr().unwrap())
+            .metric_destination(metrics.local_addr().unwrap());
+
+        with_logger(builder, || {
+            tracing::info!(target: "metric", name = "orders_created", value = 1);
+            tracing::info!(target: "metric", name = "orders_created", value = "many");
+            tracing::info!("order created");
+            crate::flush(Duration::from_secs(5));
+        });
+
+        assert_eq!(recv(&metrics)["value"], json!(1));
+        // Invalid metrics still go with metrics
+        assert_eq!(recv(&metrics)["metric_error"], "`value` must be a number");
+        assert_eq!(recv(&logs)["message"], "order created");
+        metrics
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut datagram = [0; 1];
+        assert!(
+            metrics.recv(&mut datagram).is_err(),
+            "only metrics are routed"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use crate::vinted_console_writer::{ConsoleWriterImpl, VintedConsoleWriter};
+use std::io;
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Wraps a `MakeWriter` of JSON records and writes those of a level and
+/// above to the console as well, as `ConsoleJson` would, see
+/// [`LoggerBuilder::mirror_to_console`](crate::LoggerBuilder::mirror_to_console).
+///
+/// The console gets every record first and its errors are ignored, so
+/// neither sink can hold up or fail the other.
+pub(crate) struct VintedMirrorWriter<W> {
+    inner: W,
+    console: Option<(Level, VintedConsoleWriter)>,
+}
+
+impl<W> VintedMirrorWriter<W> {
+    /// Mirrors records of `level` and above to `console`, none if `None`
+    pub(crate) fn new(inner: W, console: Option<(Level, VintedConsoleWriter)>) -> Self {
+        Self { inner, console }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for VintedMirrorWriter<W> {
+    type Writer = MirrorWriterImpl<'a, W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        MirrorWriterImpl {
+            inner: self.inner.make_writer(),
+            console: None,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        MirrorWriterImpl {
+            inner: self.inner.make_writer_for(meta),
+            console: match self.console {
+                Some((level, ref console)) if *meta.level() <= level => {
+                    Some(console.make_writer_for(meta))
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+pub(crate) struct MirrorWriterImpl<'a, W> {
+    inner: W,
+    console: Option<ConsoleWriterImpl<'a>>,
+}
+
+impl<'a, W: io::Write> io::Write for MirrorWriterImpl<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(ref mut console) = self.console {
+            let _ = console.write_all(buf);
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(ref mut console) = self.console {
+            let _ = console.flush();
+        }
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        LogDestination, Target,
+    };
+    use std::{net::UdpSocket, time::Duration};
+    use tracing::Level;
+
+    fn collector() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    fn log_every_level() {
+        tracing::debug!("debug");
+        tracing::info!("info");
+        tracing::warn!(user_id = 7, "warn");
+        tracing::error!("error");
+        crate::flush(Duration::from_secs(5));
+    }
+
+    #[test]
+    fn records_of_the_level_and_above_go_to_both_sinks() {
+        let collector = collector();
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(collector.local_addr().unwrap())
+            .default_level(Level::DEBUG)
+            .mirror_to_console(Level::WARN)
+            .capture(&capture);
+        with_logger(builder, log_every_level);
+
+        let mut datagram = vec![0; 65_536];
+        let sent: Vec<_> = (0..4)
+            .map(|_| {
+                let len = collector.recv(&mut datagram).expect("record is received");
+                String::from_utf8(datagram[..len].to_vec()).unwrap()
+            })
+            .collect();
+        assert_eq!(capture.stdout(), sent[2..]);
+        assert!(capture.stderr().is_empty(), "{:?}", capture.stderr());
+    }
+
+    #[test]
+    fn failing_network_sinks_still_mirror() {
+        let capture = Capture::default();
+        // Broadcasts are refused without SO_BROADCAST
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(LogDestination::Udp("255.255.255.255:9".to_owned()))
+            .mirror_to_console(Level::INFO)
+            .capture(&capture);
+        with_logger(builder, log_every_level);
+
+        let messages: Vec<_> = capture
+            .records()
+            .iter()
+            .map(|record| record["message"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(messages, ["info", "warn", "error"]);
+    }
+
+    #[test]
+    fn console_targets_are_not_mirrored() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .mirror_to_console(Level::INFO)
+            .capture(&capture);
+        with_logger(builder, log_every_level);
+        assert_eq!(capture.lines().len(), 3, "{:?}", capture.lines());
+    }
+}
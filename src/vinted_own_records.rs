@@ -0,0 +1,37 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing_core::{
+    dispatcher::{self, WeakDispatch},
+    Dispatch, Subscriber,
+};
+use tracing_subscriber::Layer;
+
+/// Lets layers and formatters of a logger log records of their own.
+///
+/// `tracing` doesn't dispatch events re-entrantly: anything logged from a
+/// layer callback or while a record is formatted goes nowhere. This layer
+/// keeps a weak handle on the subscriber from `on_register_dispatch`, and
+/// [`OwnRecords::log`] logs from a thread of its own with that subscriber as
+/// the default instead. Only meant for rare records, each takes a thread.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OwnRecords(Arc<Mutex<Option<WeakDispatch>>>);
+
+impl OwnRecords {
+    /// Runs `log` with the logger as the default subscriber, once the logger
+    /// is installed and as long as it's not dropped
+    pub(crate) fn log(&self, log: impl FnOnce() + Send + 'static) {
+        let dispatch = match self.0.lock().as_ref().and_then(WeakDispatch::upgrade) {
+            Some(dispatch) => dispatch,
+            None => return,
+        };
+        let _ = ::std::thread::Builder::new()
+            .name("vinted-logger-own-records".to_owned())
+            .spawn(move || dispatcher::with_default(&dispatch, log));
+    }
+}
+
+impl<S: Subscriber> Layer<S> for OwnRecords {
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        *self.0.lock() = Some(subscriber.downgrade());
+    }
+}
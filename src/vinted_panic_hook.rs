@@ -0,0 +1,101 @@
+use crate::vinted_field_visitor::SpanFields;
+use std::{
+    cell::RefCell,
+    panic::{self, PanicHookInfo},
+};
+use tracing::Span;
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+/// Target of panic records
+pub(crate) const TARGET: &str = "vinted::panic";
+
+/// Fields of the panic record itself, never replaced by span fields
+const FIELDS: &[&str] = &["message", "panic_location", "span_path"];
+
+thread_local! {
+    /// Fields of the spans of the panic being logged, picked up by the JSON
+    /// formatter while it formats the record on the same thread
+    static SPAN_FIELDS: RefCell<serde_json::Map<String, serde_json::Value>> =
+        RefCell::new(serde_json::Map::new());
+}
+
+/// Replaces the panic hook with one logging panics at ERROR, with where they
+/// happened and the spans they happened in. Panics the logger doesn't take,
+/// e.g. because of the level filter, still go to the previous hook.
+pub(crate) fn install() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !tracing::enabled!(target: TARGET, tracing::Level::ERROR) {
+            return previous(info);
+        }
+
+        let location = info.location().map(|location| {
+            format!(
+                "{}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            )
+        });
+        let (span_path, span_fields) = span_context();
+        SPAN_FIELDS.with(|fields| *fields.borrow_mut() = span_fields);
+        tracing::error!(
+            target: TARGET,
+            panic_location = location.as_deref(),
+            span_path = span_path.as_deref(),
+            "panicked: {}",
+            message(info)
+        );
+        SPAN_FIELDS.with(|fields| fields.borrow_mut().clear());
+    }));
+}
+
+fn message<'a>(info: &'a PanicHookInfo<'_>) -> &'a str {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+/// Names of the spans the panic happened in, outermost first and joined by
+/// ` > `, and the fields of all of them as JSON targets record them, inner
+/// spans replacing fields of the same name
+fn span_context() -> (Option<String>, serde_json::Map<String, serde_json::Value>) {
+    let mut fields = serde_json::Map::new();
+    let id = match Span::current().id() {
+        Some(id) => id,
+        None => return (None, fields),
+    };
+
+    let span_path = tracing::dispatcher::get_default(|dispatch| {
+        let span = dispatch
+            .downcast_ref::<Registry>()
+            .and_then(|registry| registry.span(&id))?;
+
+        let mut names = Vec::new();
+        for span in span.scope().from_root() {
+            names.push(span.name());
+            if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                fields.extend(span_fields.clone());
+            }
+        }
+        Some(names.join(" > "))
+    });
+
+    (span_path, fields)
+}
+
+/// Calls `f` with every span field of the panic being logged on this thread
+pub(crate) fn for_each_span_field<E>(
+    mut f: impl FnMut(&str, &serde_json::Value) -> Result<(), E>,
+) -> Result<(), E> {
+    SPAN_FIELDS.with(|fields| {
+        fields
+            .borrow()
+            .iter()
+            .filter(|(key, _)| !FIELDS.contains(&key.as_str()))
+            .try_for_each(|(key, value)| f(key, value))
+    })
+}
@@ -0,0 +1,638 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    iter::FromIterator,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Longest string value scanned by
+/// [`LoggerBuilder::mask_pii`](crate::LoggerBuilder::mask_pii), longer ones
+/// are left as they are
+pub const PII_SCAN_MAX_LEN: usize = 4 * 1024;
+
+/// Key of the detectors which masked something in a record
+pub(crate) const KEY: &str = "pii_masked";
+
+/// Kinds of personal data [`LoggerBuilder::mask_pii`](crate::LoggerBuilder::mask_pii)
+/// looks for in string values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PiiDetector {
+    /// Email addresses, `jane@example.com` becomes `j***@example.com`
+    Email,
+    /// IBANs passing their mod-97 check, compact or in groups of 4, masked
+    /// but for the country code and the last 4 characters
+    Iban,
+    /// Card numbers of 13 to 19 digits passing the Luhn check, with spaces
+    /// or dashes between groups or without, masked but for the last 4
+    /// digits: `************1234`
+    Card,
+    /// Lithuanian personal codes passing their check digit and US social
+    /// security numbers (`123-45-6789`), masked but for the last 4 digits
+    NationalId,
+}
+
+const DETECTORS: [PiiDetector; 4] = [
+    PiiDetector::Email,
+    PiiDetector::Iban,
+    PiiDetector::Card,
+    PiiDetector::NationalId,
+];
+
+static MASKED: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+impl PiiDetector {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Name of the detector in `pii_masked`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Iban => "iban",
+            Self::Card => "card",
+            Self::NationalId => "national_id",
+        }
+    }
+}
+
+/// How many values `detector` masked since the process started, for
+/// auditing
+///
+/// ```
+/// let cards = vinted_logger::pii_masked(vinted_logger::PiiDetector::Card);
+/// println!("{} card numbers masked", cards);
+/// ```
+pub fn pii_masked(detector: PiiDetector) -> u64 {
+    MASKED[detector.index()].load(Ordering::Relaxed)
+}
+
+/// Detectors by bit, see [`PiiDetector::index`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Detectors(u8);
+
+impl Detectors {
+    pub(crate) fn all() -> Self {
+        DETECTORS.iter().copied().collect()
+    }
+
+    pub(crate) fn insert(&mut self, detector: PiiDetector) {
+        self.0 |= 1 << detector.index();
+    }
+
+    pub(crate) fn extend(&mut self, other: Detectors) {
+        self.0 |= other.0;
+    }
+
+    fn contains(self, detector: PiiDetector) -> bool {
+        self.0 & (1 << detector.index()) != 0
+    }
+
+    pub(crate) fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Names of the detectors, in the order of [`PiiDetector`]
+    pub(crate) fn names(self) -> Vec<&'static str> {
+        DETECTORS
+            .iter()
+            .filter(|&&detector| self.contains(detector))
+            .map(|detector| detector.name())
+            .collect()
+    }
+}
+
+impl FromIterator<PiiDetector> for Detectors {
+    fn from_iter<I: IntoIterator<Item = PiiDetector>>(detectors: I) -> Self {
+        let mut all = Self::default();
+        for detector in detectors {
+            all.insert(detector);
+        }
+        all
+    }
+}
+
+/// Masks personal data in string values of fields not exempted
+#[derive(Debug)]
+pub(crate) struct PiiScanner {
+    detectors: Detectors,
+    exempt: HashSet<String>,
+}
+
+impl PiiScanner {
+    pub(crate) fn new(detectors: Detectors, exempt: HashSet<String>) -> Self {
+        Self { detectors, exempt }
+    }
+
+    /// Masks what the detectors find in `value` of the field `name`, adding
+    /// the detectors which found something to `found`
+    pub(crate) fn mask<'a>(
+        &self,
+        name: &str,
+        value: &'a str,
+        found: &mut Detectors,
+    ) -> Cow<'a, str> {
+        if value.len() > PII_SCAN_MAX_LEN || self.exempt.contains(name) {
+            return Cow::Borrowed(value);
+        }
+        // Every detector needs an `@` or 9 digits at least, which most
+        // values don't have
+        let bytes = value.as_bytes();
+        let digits = bytes.iter().filter(|byte| byte.is_ascii_digit()).count();
+        if digits < 9 && !(self.detectors.contains(PiiDetector::Email) && bytes.contains(&b'@')) {
+            return Cow::Borrowed(value);
+        }
+
+        let mut matches = Vec::new();
+        if self.detectors.contains(PiiDetector::Email) {
+            find_emails(value, &mut matches);
+        }
+        if self.detectors.contains(PiiDetector::Iban) {
+            find_ibans(value, &mut matches);
+        }
+        if self.detectors.contains(PiiDetector::Card)
+            || self.detectors.contains(PiiDetector::NationalId)
+        {
+            find_numbers(value, self.detectors, &mut matches);
+        }
+        if matches.is_empty() {
+            return Cow::Borrowed(value);
+        }
+
+        matches.sort_by_key(|found| found.start);
+        let mut masked = String::with_capacity(value.len());
+        let mut end = 0;
+        for Match {
+            start,
+            end: match_end,
+            detector,
+        } in matches
+        {
+            // Overlaps an earlier match
+            if start < end {
+                continue;
+            }
+            masked.push_str(&value[end..start]);
+            mask(detector, &value[start..match_end], &mut masked);
+            end = match_end;
+
+            found.insert(detector);
+            MASKED[detector.index()].fetch_add(1, Ordering::Relaxed);
+        }
+        masked.push_str(&value[end..]);
+        Cow::Owned(masked)
+    }
+}
+
+struct Match {
+    start: usize,
+    end: usize,
+    detector: PiiDetector,
+}
+
+fn mask(detector: PiiDetector, found: &str, masked: &mut String) {
+    match detector {
+        PiiDetector::Email => {
+            let at = found.find('@').unwrap_or_default();
+            masked.extend(found[..at].chars().take(1));
+            masked.push_str("***");
+            masked.push_str(&found[at..]);
+        }
+        PiiDetector::Iban => mask_alphanumeric(found, 2, masked),
+        PiiDetector::Card | PiiDetector::NationalId => mask_alphanumeric(found, 0, masked),
+    }
+}
+
+/// Masks letters and digits with `*`, but for the first `keep` and the last 4
+/// of them, keeping separators
+fn mask_alphanumeric(found: &str, keep: usize, masked: &mut String) {
+    let total = found.bytes().filter(u8::is_ascii_alphanumeric).count();
+    let mut seen = 0;
+    for c in found.chars() {
+        if c.is_ascii_alphanumeric() {
+            masked.push(if seen < keep || seen + 4 >= total {
+                c
+            } else {
+                '*'
+            });
+            seen += 1;
+        } else {
+            masked.push(c);
+        }
+    }
+}
+
+fn is_boundary(value: &[u8], at: Option<usize>) -> bool {
+    at.and_then(|at| value.get(at))
+        .is_none_or(|byte| !byte.is_ascii_alphanumeric())
+}
+
+fn find_emails(value: &str, matches: &mut Vec<Match>) {
+    let bytes = value.as_bytes();
+    let is_local = |byte: u8| byte.is_ascii_alphanumeric() || b"._%+-".contains(&byte);
+    let is_domain = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'.' || byte == b'-';
+
+    for (at, _) in value.match_indices('@') {
+        let mut start = at;
+        while start > 0 && is_local(bytes[start - 1]) {
+            start -= 1;
+        }
+        while start < at && bytes[start] == b'.' {
+            start += 1;
+        }
+
+        let mut end = at + 1;
+        while end < bytes.len() && is_domain(bytes[end]) {
+            end += 1;
+        }
+        while end > at + 1 && (bytes[end - 1] == b'.' || bytes[end - 1] == b'-') {
+            end -= 1;
+        }
+
+        let domain = &value[at + 1..end];
+        let tld = domain.rsplit('.').next().unwrap_or_default();
+        if start < at
+            && domain.contains('.')
+            && !domain.starts_with('.')
+            && tld.len() >= 2
+            && tld.bytes().all(|byte| byte.is_ascii_alphabetic())
+        {
+            matches.push(Match {
+                start,
+                end,
+                detector: PiiDetector::Email,
+            });
+        }
+    }
+}
+
+fn find_ibans(value: &str, matches: &mut Vec<Match>) {
+    let bytes = value.as_bytes();
+    let is_iban = |byte: u8| byte.is_ascii_uppercase() || byte.is_ascii_digit();
+
+    let mut start = 0;
+    while start + 4 <= bytes.len() {
+        let candidate = bytes[start].is_ascii_uppercase()
+            && bytes[start + 1].is_ascii_uppercase()
+            && bytes[start + 2].is_ascii_digit()
+            && bytes[start + 3].is_ascii_digit()
+            && is_boundary(bytes, start.checked_sub(1));
+        if !candidate {
+            start += 1;
+            continue;
+        }
+
+        // Ends of the groups of characters, separated by single spaces
+        let mut ends = Vec::new();
+        let mut end = start;
+        while end < bytes.len() && is_iban(bytes[end]) {
+            end += 1;
+            if end == bytes.len() || !is_iban(bytes[end]) {
+                ends.push(end);
+                if end + 1 < bytes.len() && bytes[end] == b' ' && is_iban(bytes[end + 1]) {
+                    end += 1;
+                }
+            }
+        }
+
+        // Trailing words may follow, take the longest valid prefix
+        let found = ends
+            .iter()
+            .rev()
+            .copied()
+            .filter(|&end| is_boundary(bytes, Some(end)))
+            .find(|&end| is_valid_iban(&value[start..end]));
+        match found {
+            Some(end) => {
+                matches.push(Match {
+                    start,
+                    end,
+                    detector: PiiDetector::Iban,
+                });
+                start = end;
+            }
+            None => start += 1,
+        }
+    }
+}
+
+fn is_valid_iban(iban: &str) -> bool {
+    let compact: Vec<u8> = iban.bytes().filter(|&byte| byte != b' ').collect();
+    if !(15..=34).contains(&compact.len()) {
+        return false;
+    }
+
+    let (head, tail) = compact.split_at(4);
+    let mut remainder = 0u32;
+    for &byte in tail.iter().chain(head) {
+        remainder = if byte.is_ascii_digit() {
+            (remainder * 10 + u32::from(byte - b'0')) % 97
+        } else {
+            (remainder * 100 + u32::from(byte - b'A') + 10) % 97
+        };
+    }
+    remainder == 1
+}
+
+/// Runs of digits with single spaces or dashes in between, checked as a
+/// whole and group by group
+fn find_numbers(value: &str, detectors: Detectors, matches: &mut Vec<Match>) {
+    let bytes = value.as_bytes();
+    let is_separator = |byte: u8| byte == b' ' || byte == b'-';
+
+    let mut start = 0;
+    while start < bytes.len() {
+        if !bytes[start].is_ascii_digit() || !is_boundary(bytes, start.checked_sub(1)) {
+            start += 1;
+            continue;
+        }
+
+        let mut groups = vec![(start, start)];
+        let mut end = start;
+        loop {
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+            if let Some(group) = groups.last_mut() {
+                group.1 = end;
+            }
+            if end + 1 < bytes.len() && is_separator(bytes[end]) && bytes[end + 1].is_ascii_digit()
+            {
+                end += 1;
+                groups.push((end, end));
+            } else {
+                break;
+            }
+        }
+        if !is_boundary(bytes, Some(end)) {
+            start = end;
+            continue;
+        }
+
+        let run = &value[start..end];
+        let detector = if detectors.contains(PiiDetector::Card) && is_card(run) {
+            Some(PiiDetector::Card)
+        } else if detectors.contains(PiiDetector::NationalId) && is_ssn(run) {
+            Some(PiiDetector::NationalId)
+        } else {
+            None
+        };
+        match detector {
+            Some(detector) => matches.push(Match {
+                start,
+                end,
+                detector,
+            }),
+            None => {
+                for (group_start, group_end) in groups {
+                    let group = &value[group_start..group_end];
+                    let detector = if detectors.contains(PiiDetector::Card) && is_card(group) {
+                        PiiDetector::Card
+                    } else if detectors.contains(PiiDetector::NationalId)
+                        && is_lithuanian_code(group)
+                    {
+                        PiiDetector::NationalId
+                    } else {
+                        continue;
+                    };
+                    matches.push(Match {
+                        start: group_start,
+                        end: group_end,
+                        detector,
+                    });
+                }
+            }
+        }
+        start = end;
+    }
+}
+
+fn digits(number: &str) -> Vec<u32> {
+    number.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+fn is_card(number: &str) -> bool {
+    let digits = digits(number);
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// `AAA-GG-SSSS`, without the numbers which are never assigned
+fn is_ssn(number: &str) -> bool {
+    let groups: Vec<&str> = number.split('-').collect();
+    match groups[..] {
+        [area, group, serial] => {
+            area.len() == 3
+                && group.len() == 2
+                && serial.len() == 4
+                && area != "000"
+                && area != "666"
+                && !area.starts_with('9')
+                && group != "00"
+                && serial != "0000"
+        }
+        _ => false,
+    }
+}
+
+/// `GYYMMDDNNNC`: century and sex, date of birth, serial number and check
+/// digit
+fn is_lithuanian_code(number: &str) -> bool {
+    let digits = digits(number);
+    if digits.len() != 11 || number.len() != 11 {
+        return false;
+    }
+    let month = digits[3] * 10 + digits[4];
+    let day = digits[5] * 10 + digits[6];
+    if !(1..=6).contains(&digits[0]) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return false;
+    }
+
+    let check =
+        |weights: [u32; 10]| digits.iter().zip(&weights).map(|(d, w)| d * w).sum::<u32>() % 11;
+    let mut expected = check([1, 2, 3, 4, 5, 6, 7, 8, 9, 1]);
+    if expected == 10 {
+        expected = check([3, 4, 5, 6, 7, 8, 9, 1, 2, 3]) % 10;
+    }
+    expected == digits[10]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pii_masked, Detectors, PiiDetector, PiiScanner, PII_SCAN_MAX_LEN};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    /// `value` masked by `detectors`, and the names of those which found
+    /// something
+    fn masked(detectors: &[PiiDetector], value: &str) -> (String, Vec<&'static str>) {
+        let scanner = PiiScanner::new(detectors.iter().copied().collect(), HashSet::new());
+        let mut found = Detectors::default();
+        let masked = scanner.mask("field", value, &mut found).into_owned();
+        (masked, found.names())
+    }
+
+    fn masked_by_all(value: &str) -> String {
+        masked(&super::DETECTORS, value).0
+    }
+
+    #[test]
+    fn emails_are_masked() {
+        assert_eq!(
+            masked(&[PiiDetector::Email], "sent to jane.doe@example.com."),
+            ("sent to j***@example.com.".to_owned(), vec!["email"])
+        );
+        for value in &["user@localhost", "@example.com", "a@b.c", "x@1.23"] {
+            assert_eq!(masked_by_all(value), *value);
+        }
+    }
+
+    #[test]
+    fn ibans_are_masked() {
+        assert_eq!(
+            masked(&[PiiDetector::Iban], "to GB82WEST12345698765432 today"),
+            ("to GB****************5432 today".to_owned(), vec!["iban"])
+        );
+        assert_eq!(
+            masked_by_all("LT12 1000 0111 0100 1000"),
+            "LT** **** **** **** 1000"
+        );
+        // The check digits don't match
+        assert_eq!(
+            masked_by_all("GB83WEST12345698765432"),
+            "GB83WEST12345698765432"
+        );
+    }
+
+    #[test]
+    fn card_numbers_are_masked() {
+        assert_eq!(
+            masked(&[PiiDetector::Card], "card 4111111111111111"),
+            ("card ************1111".to_owned(), vec!["card"])
+        );
+        assert_eq!(
+            masked_by_all("4111 1111 1111 1111 and 5500-0000-0000-0004"),
+            "**** **** **** 1111 and ****-****-****-0004"
+        );
+        // Fails the Luhn check, or is too short
+        assert_eq!(masked_by_all("4111111111111112"), "4111111111111112");
+        assert_eq!(masked_by_all("411111111111"), "411111111111");
+    }
+
+    #[test]
+    fn national_ids_are_masked() {
+        assert_eq!(
+            masked(&[PiiDetector::NationalId], "ssn 123-45-6789"),
+            ("ssn ***-**-6789".to_owned(), vec!["national_id"])
+        );
+        assert_eq!(masked_by_all("code 33309240064"), "code *******0064");
+        // Never assigned, or failing the check digit
+        assert_eq!(masked_by_all("666-45-6789"), "666-45-6789");
+        assert_eq!(masked_by_all("33309240065"), "33309240065");
+    }
+
+    #[test]
+    fn detectors_can_be_turned_off() {
+        let value = "jane@example.com paid with 4111111111111111";
+        assert_eq!(
+            masked(&[PiiDetector::Card], value),
+            (
+                "jane@example.com paid with ************1111".to_owned(),
+                vec!["card"]
+            )
+        );
+        assert_eq!(masked(&[], value), (value.to_owned(), vec![]));
+    }
+
+    #[test]
+    fn long_values_and_exempt_fields_are_not_scanned() {
+        let scanner = PiiScanner::new(Detectors::all(), ["order_id".to_owned()].into());
+        let mut found = Detectors::default();
+
+        assert_eq!(
+            scanner.mask("order_id", "4111111111111111", &mut found),
+            "4111111111111111"
+        );
+        let long = format!("{:<1$}", "4111111111111111", PII_SCAN_MAX_LEN + 1);
+        assert_eq!(scanner.mask("card", &long, &mut found), long);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn masked_values_are_counted() {
+        let before = pii_masked(PiiDetector::Email);
+        masked(&[PiiDetector::Email], "a@example.com, b@example.com");
+        assert!(pii_masked(PiiDetector::Email) >= before + 2);
+    }
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Buyer {
+        email: &'static str,
+        card: &'static str,
+    }
+
+    #[test]
+    fn records_list_the_detectors_which_masked_something() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .mask_pii()
+            .pii_exempt_field("order_id")
+            .capture(&capture);
+
+        with_logger(builder, || {
+            let span = tracing::info_span!("checkout", contact = "jane@example.com");
+            let _entered = span.enter();
+            tracing::info!(
+                buyer = ?Buyer {
+                    email: "john@example.com",
+                    card: "4111 1111 1111 1111",
+                },
+                order_id = "4111111111111111",
+                amount = 4111111111111111u64,
+                "paid by SSN 123-45-6789"
+            );
+            tracing::info!(order_id = "4111111111111111", "nothing to mask");
+        });
+
+        let records = capture.records();
+        let paid = &records[0];
+        assert_eq!(paid["message"], "paid by SSN ***-**-6789");
+        assert_eq!(
+            paid["buyer"],
+            "Buyer { email: \"j***@example.com\", card: \"**** **** **** 1111\" }"
+        );
+        assert_eq!(paid["order_id"], "4111111111111111");
+        assert_eq!(paid["amount"], 4111111111111111u64);
+        assert_eq!(paid["span"]["contact"], "j***@example.com");
+        // The span's email counts for the records within it
+        assert_eq!(paid["pii_masked"], json!(["email", "card", "national_id"]));
+
+        let nothing = &records[1];
+        assert_eq!(nothing["order_id"], "4111111111111111");
+        assert_eq!(nothing["pii_masked"], json!(["email"]));
+    }
+}
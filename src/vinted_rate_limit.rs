@@ -0,0 +1,317 @@
+use parking_lot::RwLock;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
+};
+use tracing_core::{callsite::Identifier, Event, Level, Metadata, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Target of the summaries of suppressed records
+pub(crate) const TARGET: &str = "vinted::rate_limit";
+
+/// How often suppressed records are summarized
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Upper bound of callsites counted apart, records of further ones are only
+/// limited by the global limit and summarized together
+const MAX_CALLSITES: usize = 4096;
+
+/// Limits of [`LoggerBuilder::rate_limit`](crate::LoggerBuilder::rate_limit)
+/// and [`LoggerBuilder::rate_limit_per_callsite`](crate::LoggerBuilder::rate_limit_per_callsite)
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RateLimit {
+    pub(crate) per_second: Option<u32>,
+    pub(crate) per_callsite: Option<u32>,
+    pub(crate) exempt_errors: bool,
+}
+
+impl RateLimit {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.per_second.is_some() || self.per_callsite.is_some()
+    }
+}
+
+/// Records admitted within the current second
+#[derive(Debug, Default)]
+struct Window {
+    second: AtomicU64,
+    count: AtomicU32,
+}
+
+impl Window {
+    fn admit(&self, second: u64, limit: u32) -> bool {
+        if self.second.load(Ordering::Relaxed) != second {
+            // Threads racing here may both start the new second, admitting a
+            // few more records than the limit
+            self.second.store(second, Ordering::Relaxed);
+            self.count.store(0, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed) < limit
+    }
+}
+
+#[derive(Debug)]
+struct Callsite {
+    meta: &'static Metadata<'static>,
+    window: Window,
+    suppressed: AtomicU64,
+}
+
+/// Drops records beyond the limits before they're formatted, and logs how
+/// many were every [`SUMMARY_INTERVAL`] from a background thread living as
+/// long as the limiter
+#[derive(Debug)]
+pub(crate) struct VintedRateLimiter(Arc<Limiter>);
+
+#[derive(Debug)]
+struct Limiter {
+    limit: RateLimit,
+    start: Instant,
+    global: Window,
+    callsites: RwLock<HashMap<Identifier, Callsite>>,
+    /// Suppressed records of callsites beyond [`MAX_CALLSITES`]
+    other_suppressed: AtomicU64,
+}
+
+impl VintedRateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        let limiter = Arc::new(Limiter {
+            limit,
+            start: Instant::now(),
+            global: Window::default(),
+            callsites: RwLock::new(HashMap::new()),
+            other_suppressed: AtomicU64::new(0),
+        });
+
+        let weak = Arc::downgrade(&limiter);
+        let _ = ::std::thread::Builder::new()
+            .name("vinted-logger-rate-limit".to_owned())
+            .spawn(move || summarize(weak));
+        Self(limiter)
+    }
+}
+
+impl Limiter {
+    fn admit(&self, callsite: Option<&Callsite>, second: u64) -> bool {
+        let admitted = match (callsite, self.limit.per_callsite) {
+            (Some(callsite), Some(limit)) => callsite.window.admit(second, limit),
+            _ => true,
+        } && self
+            .limit
+            .per_second
+            .is_none_or(|limit| self.global.admit(second, limit));
+
+        if !admitted {
+            callsite
+                .map_or(&self.other_suppressed, |callsite| &callsite.suppressed)
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        admitted
+    }
+
+    /// Logs the records suppressed since the last summary, one record per
+    /// callsite
+    fn summarize(&self) {
+        let suppressed: Vec<_> = self
+            .callsites
+            .read()
+            .values()
+            .filter_map(
+                |callsite| match callsite.suppressed.swap(0, Ordering::Relaxed) {
+                    0 => None,
+                    suppressed => Some((callsite.meta, suppressed)),
+                },
+            )
+            .collect();
+
+        let interval = SUMMARY_INTERVAL.as_secs();
+        for (meta, suppressed) in suppressed {
+            let callsite = format!(
+                "{}:{}",
+                meta.target(),
+                meta.line().map(|line| line.to_string()).unwrap_or_default()
+            );
+            tracing::warn!(
+                target: TARGET,
+                suppressed,
+                callsite = callsite.as_str(),
+                "suppressed {} events from {} in the last {}s",
+                suppressed,
+                callsite,
+                interval
+            );
+        }
+
+        let suppressed = self.other_suppressed.swap(0, Ordering::Relaxed);
+        if suppressed > 0 {
+            tracing::warn!(
+                target: TARGET,
+                suppressed,
+                "suppressed {} events from other callsites in the last {}s",
+                suppressed,
+                interval
+            );
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for VintedRateLimiter {
+    fn event_enabled(&self, event: &Event<'_>, _: Context<'_, S>) -> bool {
+        let limiter = &self.0;
+        let meta = event.metadata();
+        if meta.target() == TARGET || (limiter.limit.exempt_errors && *meta.level() == Level::ERROR)
+        {
+            return true;
+        }
+
+        let second = limiter.start.elapsed().as_secs();
+        let identifier = meta.callsite();
+        {
+            let callsites = limiter.callsites.read();
+            if let Some(callsite) = callsites.get(&identifier) {
+                return limiter.admit(Some(callsite), second);
+            }
+            if callsites.len() >= MAX_CALLSITES {
+                return limiter.admit(None, second);
+            }
+        }
+
+        let mut callsites = limiter.callsites.write();
+        let callsite = callsites.entry(identifier).or_insert_with(|| Callsite {
+            meta,
+            window: Window::default(),
+            suppressed: AtomicU64::new(0),
+        });
+        limiter.admit(Some(callsite), second)
+    }
+}
+
+fn summarize(limiter: Weak<Limiter>) {
+    loop {
+        ::std::thread::sleep(SUMMARY_INTERVAL);
+        match limiter.upgrade() {
+            Some(limiter) => limiter.summarize(),
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, VintedRateLimiter};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use serde_json::Value;
+    use tracing::Dispatch;
+    use tracing_subscriber::{layer::SubscriberExt, Layer, Registry};
+
+    fn messages(capture: &Capture) -> Vec<String> {
+        capture
+            .records()
+            .iter()
+            .map(|record| record["message"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn records_beyond_the_callsite_limit_are_dropped() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .rate_limit_per_callsite(3)
+            .capture(&capture);
+
+        with_logger(builder, || {
+            for n in 0..10 {
+                tracing::info!(n, "hot");
+                if n % 5 == 0 {
+                    tracing::info!(n, "cold");
+                }
+            }
+        });
+
+        assert_eq!(messages(&capture), ["hot", "cold", "hot", "hot", "cold"]);
+    }
+
+    #[test]
+    fn records_beyond_the_global_limit_are_dropped() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .rate_limit(4)
+            .capture(&capture);
+
+        with_logger(builder, || {
+            for _ in 0..10 {
+                tracing::info!("hot");
+                tracing::warn!("cold");
+            }
+        });
+
+        assert_eq!(messages(&capture), ["hot", "cold", "hot", "cold"]);
+    }
+
+    #[test]
+    fn errors_can_be_exempt() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .rate_limit(1)
+            .rate_limit_exempt_errors()
+            .capture(&capture);
+
+        with_logger(builder, || {
+            for _ in 0..5 {
+                tracing::info!("hot");
+                tracing::error!("failed");
+            }
+        });
+
+        assert_eq!(
+            messages(&capture),
+            ["hot", "failed", "failed", "failed", "failed", "failed"]
+        );
+    }
+
+    #[test]
+    fn suppressed_records_are_summarized_per_callsite() {
+        let capture = Capture::default();
+        let (layer, _filter) = crate::builder("svc-test", Target::ConsoleJson)
+            .capture(&capture)
+            .build()
+            .unwrap();
+        let limiter = VintedRateLimiter::new(RateLimit {
+            per_callsite: Some(2),
+            ..RateLimit::default()
+        });
+        let summarize = limiter.0.clone();
+        let dispatch = Dispatch::new(Registry::default().with(layer.and_then(limiter)));
+
+        let line = tracing::dispatcher::with_default(&dispatch, || {
+            let line = line!() + 2;
+            for _ in 0..12 {
+                tracing::info!("hot");
+            }
+            summarize.summarize();
+            // Counted from zero again
+            summarize.summarize();
+            line
+        });
+
+        let records = capture.records();
+        assert_eq!(records.len(), 3, "{:?}", records);
+        let summary = &records[2];
+        let callsite = format!("vinted_logger::vinted_rate_limit::tests:{}", line);
+        assert_eq!(summary["target"], super::TARGET);
+        assert_eq!(summary["level"], "WARN");
+        assert_eq!(summary["suppressed"], 10);
+        assert_eq!(summary["callsite"], Value::from(callsite.as_str()));
+        assert_eq!(
+            summary["message"],
+            format!("suppressed 10 events from {} in the last 10s", callsite)
+        );
+    }
+}
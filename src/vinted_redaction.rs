@@ -0,0 +1,152 @@
+use serde_json::Value;
+use std::{collections::HashSet, fmt, sync::Arc};
+
+/// Value of redacted fields
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// Callback of [`LoggerBuilder::redact_with`](crate::LoggerBuilder::redact_with)
+pub(crate) type RedactFn = dyn Fn(&str, &mut Value) + Send + Sync;
+
+/// Fields redacted by name, whatever their case, and a callback given every
+/// other field
+#[derive(Clone, Default)]
+pub(crate) struct Redaction {
+    fields: HashSet<String>,
+    callback: Option<Arc<RedactFn>>,
+}
+
+impl Redaction {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.callback.is_none()
+    }
+
+    pub(crate) fn insert(&mut self, field: &str) {
+        self.fields.insert(field.to_lowercase());
+    }
+
+    pub(crate) fn set_callback(&mut self, callback: Arc<RedactFn>) {
+        self.callback = Some(callback);
+    }
+
+    /// Whether the field `name` is redacted whatever its value
+    pub(crate) fn redacts(&self, name: &str) -> bool {
+        !self.fields.is_empty() && self.fields.contains(&name.to_lowercase())
+    }
+
+    /// Value to record instead of `value` for the field `name`, `None` to
+    /// keep `value`
+    pub(crate) fn redact<V: serde::Serialize + ?Sized>(
+        &self,
+        name: &str,
+        value: &V,
+    ) -> Option<Value> {
+        if self.redacts(name) {
+            return Some(Value::from(REDACTED));
+        }
+        let callback = self.callback.as_ref()?;
+        let mut value = serde_json::to_value(value).ok()?;
+        callback(name, &mut value);
+        Some(value)
+    }
+}
+
+impl fmt::Debug for Redaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Redaction")
+            .field("fields", &self.fields)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::REDACTED;
+    use crate::{
+        test_support::{with_logger, Capture},
+        LogDestination, LoggerBuilder, Target,
+    };
+    use serde_json::{json, Value};
+    use std::{net::UdpSocket, time::Duration};
+
+    fn log_secrets() {
+        let span = tracing::info_span!("request", Authorization = "Bearer abc");
+        let _entered = span.enter();
+        tracing::info!(password = "hunter2", user_id = 7, "signed in");
+    }
+
+    fn check_redacted(record: &Value) {
+        assert_eq!(record["password"], REDACTED, "{}", record);
+        assert_eq!(record["user_id"], 7);
+        assert_eq!(record["span"]["Authorization"], REDACTED, "{}", record);
+    }
+
+    fn redacted(builder: LoggerBuilder) -> LoggerBuilder {
+        builder.redact_fields(["PASSWORD", "authorization"])
+    }
+
+    #[test]
+    fn console_json_records_are_redacted() {
+        let capture = Capture::default();
+        let builder = redacted(crate::builder("svc-test", Target::ConsoleJson)).capture(&capture);
+        with_logger(builder, log_secrets);
+
+        let lines = capture.lines();
+        assert!(!lines.concat().contains("hunter2"));
+        assert!(
+            lines[0].contains("\"password\":\"[REDACTED]\""),
+            "{}",
+            lines[0]
+        );
+        check_redacted(&capture.records()[0]);
+    }
+
+    #[test]
+    fn udp_json_records_are_redacted() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let destination = LogDestination::Udp(collector.local_addr().unwrap().to_string());
+        let builder =
+            redacted(crate::builder("svc-test", Target::UdpJson)).destination(destination);
+        with_logger(builder, || {
+            log_secrets();
+            crate::flush(Duration::from_secs(5));
+        });
+
+        let mut datagram = vec![0; 65_536];
+        let len = collector.recv(&mut datagram).expect("record is received");
+        let datagram = std::str::from_utf8(&datagram[..len]).unwrap();
+        assert!(
+            datagram.contains("\"password\":\"[REDACTED]\""),
+            "{}",
+            datagram
+        );
+        check_redacted(&serde_json::from_str(datagram).unwrap());
+    }
+
+    #[test]
+    fn the_callback_scrubs_the_other_fields() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .redact_fields(["password"])
+            .redact_with(|name, value| {
+                assert_ne!(name, "password");
+                if let Some(email) = value.as_str().filter(|value| value.contains('@')) {
+                    *value = json!(email.replace(|c: char| c != '@', "x"));
+                }
+            })
+            .capture(&capture);
+
+        with_logger(builder, || {
+            tracing::info!(password = "hunter2", email = "a@b", count = 3, "signed up");
+        });
+
+        let record = &capture.records()[0];
+        assert_eq!(record["password"], REDACTED);
+        assert_eq!(record["email"], "x@x");
+        assert_eq!(record["count"], 3);
+        assert_eq!(record["message"], "signed up");
+    }
+}
@@ -0,0 +1,159 @@
+use std::cell::Cell;
+use tokio::runtime::Handle;
+
+thread_local! {
+    static CONTEXT: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Where the current thread logs from:
+///
+/// - `tokio-worker` on a worker thread of a Tokio runtime, where blocking
+///   holds up every task scheduled on it;
+/// - `tokio-blocking` on another thread within a runtime, e.g. one of
+///   `spawn_blocking` or the thread of `Runtime::block_on`;
+/// - `thread` anywhere else.
+///
+/// Threads are classified on their first record, the next ones only read
+/// the thread local.
+pub(crate) fn current() -> &'static str {
+    CONTEXT.with(|context| match context.get() {
+        Some(current) => current,
+        None => {
+            let current = classify();
+            context.set(Some(current));
+            current
+        }
+    })
+}
+
+fn classify() -> &'static str {
+    if Handle::try_current().is_err() {
+        "thread"
+    } else if is_worker() {
+        "tokio-worker"
+    } else {
+        "tokio-blocking"
+    }
+}
+
+#[cfg(tokio_unstable)]
+fn is_worker() -> bool {
+    tokio::runtime::worker_index().is_some()
+}
+
+/// Tokio only tells workers apart with `--cfg tokio_unstable`, otherwise
+/// threads of the blocking pool take the same name as workers and are
+/// classified as workers too
+#[cfg(not(tokio_unstable))]
+fn is_worker() -> bool {
+    // Names Tokio gives its threads unless told otherwise, older versions first
+    const TOKIO_THREAD_NAMES: &[&str] = &["tokio-runtime-worker", "tokio-rt-worker"];
+
+    std::thread::current().name().is_some_and(|name| {
+        TOKIO_THREAD_NAMES
+            .iter()
+            .any(|&prefix| name.starts_with(prefix))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing::Dispatch;
+
+    const WORKERS: usize = 2;
+
+    /// The runtime context of a record logged by `log`, given the dispatch
+    /// records are captured with
+    fn context_of(log: impl FnOnce(Dispatch)) -> String {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .runtime_context()
+            .capture(&capture);
+        with_logger(builder, || {
+            log(tracing::dispatcher::get_default(Dispatch::clone))
+        });
+        let records = capture.records();
+        assert_eq!(records.len(), 1, "{:?}", records);
+        records[0]["runtime_context"]
+            .as_str()
+            .expect("runtime_context is a string")
+            .to_owned()
+    }
+
+    /// A runtime whose blocking threads aren't named like its workers,
+    /// which Tokio starts first, for builds without `tokio_unstable`
+    fn runtime() -> tokio::runtime::Runtime {
+        let threads = AtomicUsize::new(0);
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(WORKERS)
+            .thread_name_fn(move || match threads.fetch_add(1, Ordering::Relaxed) {
+                n if n < WORKERS => "tokio-runtime-worker".to_owned(),
+                _ => "app-blocking".to_owned(),
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn worker_tasks_log_from_a_tokio_worker() {
+        let runtime = runtime();
+        let context = context_of(|dispatch| {
+            runtime.block_on(async {
+                tokio::spawn(async move {
+                    tracing::dispatcher::with_default(&dispatch, || tracing::info!("task"));
+                })
+                .await
+                .unwrap()
+            })
+        });
+        assert_eq!(context, "tokio-worker");
+    }
+
+    #[test]
+    fn spawn_blocking_logs_from_a_blocking_thread() {
+        let runtime = runtime();
+        let context = context_of(|dispatch| {
+            runtime.block_on(async {
+                tokio::task::spawn_blocking(move || {
+                    tracing::dispatcher::with_default(&dispatch, || tracing::info!("blocking"));
+                })
+                .await
+                .unwrap()
+            })
+        });
+        assert_eq!(context, "tokio-blocking");
+    }
+
+    #[test]
+    fn plain_threads_log_from_a_thread() {
+        let context = context_of(|dispatch| {
+            std::thread::spawn(move || {
+                tracing::dispatcher::with_default(&dispatch, || tracing::info!("thread"));
+            })
+            .join()
+            .unwrap()
+        });
+        assert_eq!(context, "thread");
+    }
+
+    #[test]
+    fn threads_are_classified_once() {
+        let runtime = runtime();
+        let context = context_of(|dispatch| {
+            std::thread::spawn(move || {
+                super::current();
+                // Entering a runtime later doesn't change the class
+                let _runtime = runtime.enter();
+                tracing::dispatcher::with_default(&dispatch, || tracing::info!("thread"));
+            })
+            .join()
+            .unwrap()
+        });
+        assert_eq!(context, "thread");
+    }
+}
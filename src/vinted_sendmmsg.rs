@@ -0,0 +1,234 @@
+use bytes::Bytes;
+use std::{
+    io, mem,
+    net::{SocketAddr, UdpSocket},
+    os::unix::io::AsRawFd,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Most records sent with a single `sendmmsg(2)` call
+pub(crate) const MAX_BATCH: usize = 64;
+
+static CALLS: AtomicU64 = AtomicU64::new(0);
+static DATAGRAMS: AtomicU64 = AtomicU64::new(0);
+
+/// How records were batched into `sendmmsg(2)` calls since the process
+/// started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SendmmsgStats {
+    /// Successful `sendmmsg(2)` calls
+    pub calls: u64,
+    /// Records sent by them
+    pub datagrams: u64,
+}
+
+impl SendmmsgStats {
+    /// Records sent per call, 0 before the first one
+    pub fn average_batch_size(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            self.datagrams as f64 / self.calls as f64
+        }
+    }
+}
+
+/// Batching of `UdpJson` records, with the `sendmmsg` feature on Linux
+///
+/// ```
+/// let stats = vinted_logger::sendmmsg_stats();
+/// println!("{:.1} records per syscall", stats.average_batch_size());
+/// ```
+pub fn sendmmsg_stats() -> SendmmsgStats {
+    SendmmsgStats {
+        calls: CALLS.load(Ordering::Relaxed),
+        datagrams: DATAGRAMS.load(Ordering::Relaxed),
+    }
+}
+
+/// Sends `datagrams` in order with as few `sendmmsg(2)` calls as it takes,
+/// calling again with the rest when only some were sent. Fails with the
+/// number sent before the error.
+pub(crate) fn send_all(
+    socket: &UdpSocket,
+    addr: SocketAddr,
+    datagrams: &[Bytes],
+) -> Result<(), (usize, io::Error)> {
+    let (name, name_len) = sockaddr(addr);
+    let mut iovecs: Vec<libc::iovec> = datagrams
+        .iter()
+        .map(|datagram| libc::iovec {
+            iov_base: datagram.as_ptr() as *mut libc::c_void,
+            iov_len: datagram.len(),
+        })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .map(|iovec| {
+            // SAFETY: all-zero is a valid `mmsghdr`, null pointers included
+            let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+            message.msg_hdr.msg_name = &name as *const libc::sockaddr_storage as *mut libc::c_void;
+            message.msg_hdr.msg_namelen = name_len;
+            message.msg_hdr.msg_iov = iovec;
+            message.msg_hdr.msg_iovlen = 1;
+            message
+        })
+        .collect();
+
+    let mut sent = 0;
+    while sent < messages.len() {
+        // SAFETY: every message points to `name` and to an iovec of a
+        // datagram, all of which outlive the call
+        let result = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                messages[sent..].as_mut_ptr(),
+                (messages.len() - sent) as libc::c_uint,
+                0,
+            )
+        };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err((sent, error));
+        }
+
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        DATAGRAMS.fetch_add(result as u64, Ordering::Relaxed);
+        sent += result as usize;
+    }
+
+    Ok(())
+}
+
+fn sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: all-zero is a valid `sockaddr_storage`, which is large and
+    // aligned enough for both address families
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = &mut storage as *mut _ as *mut libc::sockaddr_in;
+            // SAFETY: see above
+            unsafe {
+                (*sockaddr).sin_family = libc::AF_INET as libc::sa_family_t;
+                (*sockaddr).sin_port = addr.port().to_be();
+                (*sockaddr).sin_addr = libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                };
+            }
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sockaddr = &mut storage as *mut _ as *mut libc::sockaddr_in6;
+            // SAFETY: see above
+            unsafe {
+                (*sockaddr).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                (*sockaddr).sin6_port = addr.port().to_be();
+                (*sockaddr).sin6_flowinfo = addr.flowinfo();
+                (*sockaddr).sin6_addr = libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                };
+                (*sockaddr).sin6_scope_id = addr.scope_id();
+            }
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{send_all, sendmmsg_stats, MAX_BATCH};
+    use crate::vinted_udp_writer::VintedUdpWriter;
+    use bytes::Bytes;
+    use std::{io::Write, net::UdpSocket, time::Duration};
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// Larger than any UDP datagram can be
+    const OVERSIZED: usize = 70_000;
+
+    fn collector() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    fn received(collector: &UdpSocket, count: usize) -> Vec<Vec<u8>> {
+        let mut datagram = vec![0; 65_536];
+        (0..count)
+            .map(|_| {
+                let len = collector.recv(&mut datagram).expect("datagram is received");
+                datagram[..len].to_vec()
+            })
+            .collect()
+    }
+
+    fn numbered(n: usize) -> Vec<u8> {
+        format!("{{\"n\":{}}}\n", n).into_bytes()
+    }
+
+    #[test]
+    fn datagrams_are_sent_in_order_with_few_calls() {
+        let collector = collector();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let datagrams: Vec<_> = (0..MAX_BATCH * 2)
+            .map(|n| Bytes::from(numbered(n)))
+            .collect();
+        let before = sendmmsg_stats();
+
+        send_all(&socket, collector.local_addr().unwrap(), &datagrams).unwrap();
+
+        let after = sendmmsg_stats();
+        // Other tests may send at the same time, never fewer
+        assert!(after.datagrams - before.datagrams >= datagrams.len() as u64);
+        assert!(after.calls > before.calls);
+        assert!(after.average_batch_size() >= 1.0);
+        assert_eq!(received(&collector, datagrams.len()), datagrams);
+    }
+
+    #[test]
+    fn failures_tell_how_many_were_sent_before() {
+        let collector = collector();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let datagrams = [
+            Bytes::from(numbered(0)),
+            Bytes::from(numbered(1)),
+            Bytes::from(vec![b'x'; OVERSIZED]),
+            Bytes::from(numbered(3)),
+        ];
+
+        let (sent, error) = send_all(&socket, collector.local_addr().unwrap(), &datagrams)
+            .expect_err("the oversized datagram can't be sent");
+
+        assert_eq!(sent, 2, "{}", error);
+        assert_eq!(error.raw_os_error(), Some(libc::EMSGSIZE));
+        assert_eq!(received(&collector, 2), [numbered(0), numbered(1)]);
+    }
+
+    #[test]
+    fn records_after_a_failed_one_are_neither_lost_nor_reordered() {
+        let collector = collector();
+        let writer = VintedUdpWriter::new(collector.local_addr().unwrap(), 1_024, 1 << 20, None);
+
+        // Queued faster than they're sent, so they're sent in batches which
+        // fail in the middle
+        // Few enough for the collector's receive buffer
+        for n in 0..120 {
+            let record = match n % 40 {
+                20 => vec![b'x'; OVERSIZED],
+                _ => numbered(n),
+            };
+            writer.make_writer().write_all(&record).unwrap();
+        }
+        assert!(crate::flush(Duration::from_secs(5)));
+
+        let expected: Vec<_> = (0..120).filter(|n| n % 40 != 20).map(numbered).collect();
+        assert_eq!(received(&collector, expected.len()), expected);
+    }
+}
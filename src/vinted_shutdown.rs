@@ -0,0 +1,70 @@
+use crate::LoggerGuard;
+
+/// Waits for the process to be asked to stop, then drains and flushes the
+/// logger, see [`LoggerGuard::drain`]
+///
+/// SIGTERM and SIGINT stop it on unix, ctrl-c elsewhere. Once the first
+/// poll registers the handlers, those signals no longer end the process on
+/// their own: the service is expected to return from `main` once this
+/// future, or whatever it raced against, completes.
+///
+/// It needs a Tokio runtime with IO enabled, but any task may await it,
+/// e.g. in a `tokio::select!` against the server. Dropping it before a
+/// signal comes drops the guard, which flushes the logger without draining.
+/// The flush runs on a blocking thread, so dropping the future once it
+/// started finishes the flush all the same.
+///
+/// ```no_run
+/// # async fn serve() {}
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+///     let guard = vinted_logger::builder("svc-search", vinted_logger::Target::UdpJson)
+///         .try_init_with_guard()?;
+///     tokio::select! {
+///         () = serve() => {}
+///         () = vinted_logger::run_until_shutdown(guard) => {}
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn run_until_shutdown(guard: LoggerGuard) {
+    signalled().await;
+    // Waits for the sender threads, which would hold up a runtime worker
+    let _ = tokio::task::spawn_blocking(move || {
+        guard.drain();
+    })
+    .await;
+}
+
+#[cfg(unix)]
+async fn signalled() {
+    use std::{future::poll_fn, task::Poll};
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(mut terminate), Ok(mut interrupt)) => {
+            poll_fn(|cx| {
+                if terminate.poll_recv(cx).is_ready() || interrupt.poll_recv(cx).is_ready() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+        // The handlers can't be registered, e.g. outside of a runtime; the
+        // default ones stop the process then, and the guard is never
+        // drained
+        _ => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn signalled() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
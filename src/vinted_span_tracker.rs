@@ -0,0 +1,187 @@
+use crate::vinted_own_records::OwnRecords;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing_core::{
+    span::{Attributes, Id},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+static LIVE_SPANS: AtomicU64 = AtomicU64::new(0);
+
+/// How often the live span warning may be repeated
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many span names the live span warning lists
+const TOP_SPANS: usize = 5;
+
+/// Number of spans created but not closed yet, see
+/// [`LoggerStats::live_spans`](crate::LoggerStats::live_spans)
+pub(crate) fn live_spans() -> u64 {
+    LIVE_SPANS.load(Ordering::Relaxed)
+}
+
+/// Counts live spans and, past a threshold, warns with the span names most
+/// responsible for them so never-closed spans can be identified.
+pub(crate) struct VintedSpanTracker {
+    warning: Option<LeakWarning>,
+}
+
+struct LeakWarning {
+    threshold: u64,
+    by_name: Mutex<HashMap<&'static str, u64>>,
+    last_warning: Mutex<Option<Instant>>,
+    /// Logged from `on_new_span`, the warning would go nowhere
+    own_records: OwnRecords,
+}
+
+impl VintedSpanTracker {
+    /// `threshold` of `None` only counts spans without tracking their names.
+    pub(crate) fn new(threshold: Option<u64>, own_records: OwnRecords) -> Self {
+        Self {
+            warning: threshold.map(|threshold| LeakWarning {
+                threshold,
+                by_name: Mutex::new(HashMap::new()),
+                last_warning: Mutex::new(None),
+                own_records,
+            }),
+        }
+    }
+}
+
+impl<S> Layer<S> for VintedSpanTracker
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+        let live = LIVE_SPANS.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(ref warning) = self.warning {
+            *warning
+                .by_name
+                .lock()
+                .entry(attrs.metadata().name())
+                .or_insert(0) += 1;
+
+            if live > warning.threshold {
+                warning.warn(live);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        // Spans created before the logger was attached were never counted
+        let _ = LIVE_SPANS.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+            live.checked_sub(1)
+        });
+
+        if let (Some(ref warning), Some(span)) = (&self.warning, ctx.span(&id)) {
+            if let Some(count) = warning.by_name.lock().get_mut(span.name()) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+}
+
+impl LeakWarning {
+    fn warn(&self, live: u64) {
+        {
+            let mut last_warning = self.last_warning.lock();
+            if matches!(*last_warning, Some(at) if at.elapsed() < WARNING_INTERVAL) {
+                return;
+            }
+            *last_warning = Some(Instant::now());
+        }
+
+        let top_spans = {
+            let by_name = self.by_name.lock();
+            let mut top: Vec<_> = by_name.iter().filter(|(_, count)| **count > 0).collect();
+            top.sort_unstable_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            top.iter()
+                .take(TOP_SPANS)
+                .map(|(name, count)| format!("{}={}", name, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let threshold = self.threshold;
+        self.own_records.log(move || {
+            tracing::warn!(
+                target: "vinted_logger",
+                live_spans = live,
+                threshold,
+                top_spans = %top_spans,
+                "too many live spans, some spans might never be closed"
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_support::{wait_until, with_logger, Capture},
+        Target,
+    };
+    use tracing::{info_span, Span};
+
+    #[test]
+    fn never_closed_spans_are_counted_and_reported_once() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .warn_on_live_spans(10)
+            .capture(&capture);
+
+        let leaked = with_logger(builder, || {
+            // Closed spans no longer count towards their name
+            for _ in 0..20 {
+                let _ = info_span!("closed").entered();
+            }
+            let leaked: Vec<Span> = (0..30).map(|_| info_span!("leaky")).collect();
+            let other: Vec<Span> = (0..3).map(|_| info_span!("other")).collect();
+            // Other tests open and close spans of their own meanwhile, but
+            // never close more than they opened
+            let live = crate::logger_stats().live_spans;
+            assert!(live >= 33, "{}", live);
+            drop(other);
+            // The warning is logged from a thread of its own
+            wait_until(|| !capture.lines().is_empty());
+            leaked
+        });
+
+        let warnings: Vec<_> = capture
+            .records()
+            .into_iter()
+            .filter(|record| {
+                record["message"] == "too many live spans, some spans might never be closed"
+            })
+            .collect();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        let warning = &warnings[0];
+        assert_eq!(warning["level"], "WARN");
+        assert_eq!(warning["threshold"], 10);
+        assert!(warning["live_spans"].as_u64().unwrap() > 10, "{}", warning);
+        let top_spans = warning["top_spans"].as_str().unwrap();
+        assert!(top_spans.starts_with("leaky="), "{}", top_spans);
+        assert!(!top_spans.contains("closed"), "{}", top_spans);
+        drop(leaked);
+    }
+
+    #[test]
+    fn spans_are_counted_without_a_threshold() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson).capture(&capture);
+
+        with_logger(builder, || {
+            let spans: Vec<Span> = (0..5).map(|_| info_span!("counted")).collect();
+            let live = crate::logger_stats().live_spans;
+            assert!(live >= 5, "{}", live);
+            drop(spans);
+        });
+        assert!(capture.lines().is_empty(), "{:?}", capture.lines());
+    }
+}
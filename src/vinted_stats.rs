@@ -0,0 +1,518 @@
+use crate::{
+    vinted_console_writer,
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_drain, vinted_fallback, vinted_span_tracker, vinted_timestamp,
+};
+use parking_lot::{const_mutex, Mutex};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+static ENQUEUED: AtomicU64 = AtomicU64::new(0);
+static SENT: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static SEND_ERRORS: AtomicU64 = AtomicU64::new(0);
+static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+/// Milliseconds since the epoch of the last canary reflection, 0 before the first
+static CANARY_LAST_SUCCESS: AtomicU64 = AtomicU64::new(0);
+static CANARY_RTT_MS: AtomicU64 = AtomicU64::new(u64::MAX);
+static CANARY_MISSED: AtomicBool = AtomicBool::new(false);
+/// Destinations of the installed loggers, forgotten once their writer is
+/// dropped
+static DESTINATIONS: Mutex<Vec<Weak<Destination>>> = const_mutex(Vec::new());
+static MINUTE_BUCKETS: MinuteBuckets = MinuteBuckets::new();
+
+/// Key of the per-minute counters in canary records
+pub(crate) const BUCKETS_KEY: &str = "minute_buckets";
+
+/// Minutes the per-minute counters are kept for, a canary record carries at
+/// most this many
+pub(crate) const BUCKET_MINUTES: usize = 15;
+
+/// Records of the `UdpJson`, `TcpJson`, `UnixJson` and `GelfHttp` targets on
+/// their way out of the process, and the logger's own problems, since it
+/// started
+///
+/// Destinations for errors and metrics count as well, a record sent to two
+/// destinations counts twice, see [`destination_stats`] for the counters of
+/// each. GELF chunks count as one datagram each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LoggerStats {
+    /// Records, or datagrams, queued for a sender thread
+    pub enqueued: u64,
+    /// Records, or datagrams, handed to the socket
+    pub sent: u64,
+    /// Records dropped because a queue or buffer was full, including the
+    /// queue of a [non-blocking](crate::LoggerBuilder::non_blocking) console,
+    /// or `GelfHttp` records which were rejected or ran out of attempts
+    pub dropped: u64,
+    /// Sends which failed, including `TcpJson` connections which couldn't be
+    /// opened, `UdpJson` sockets which couldn't be bound, `UnixJson`
+    /// sockets which couldn't be connected to and `FileJson` writes
+    pub send_errors: u64,
+    /// `TcpJson` and `UnixJson` connection attempts after the first one
+    pub reconnects: u64,
+    /// `GelfHttp` requests sent again after a 429, a 5xx or a connection
+    /// failure
+    pub retries: u64,
+    /// Records written to stderr because they couldn't be delivered, see
+    /// [`LoggerBuilder::fallback_per_minute`](crate::LoggerBuilder::fallback_per_minute).
+    /// `FileJson` records count as well.
+    pub fallback_emissions: u64,
+    /// When the collector last reflected a canary, see
+    /// [`LoggerBuilder::canary_reflections`](crate::LoggerBuilder::canary_reflections)
+    pub canary_last_success: Option<SystemTime>,
+    /// Round trip of the last reflected canary, in milliseconds
+    pub canary_rtt_ms: Option<u64>,
+    /// When [`LoggerGuard::drain`](crate::LoggerGuard::drain) was first
+    /// called, `None` while not draining
+    pub draining_since: Option<SystemTime>,
+    /// Spans created but not closed yet, whatever the target. Never-closed
+    /// spans show up as a count which keeps growing, see
+    /// [`LoggerBuilder::warn_on_live_spans`](crate::LoggerBuilder::warn_on_live_spans).
+    pub live_spans: u64,
+    /// Sockets which couldn't be bound, of `UdpJson` senders or of canary
+    /// reflections
+    pub bind_failures: u64,
+    /// Records which couldn't be queued because their writer thread stopped
+    pub channel_closures: u64,
+    /// Reports of the logger's problems left out of stderr to stay within
+    /// the budget, see
+    /// [`LoggerBuilder::diagnostics_per_minute`](crate::LoggerBuilder::diagnostics_per_minute)
+    pub diagnostics_suppressed: u64,
+    /// Console lines longer than `PIPE_BUF` (4096 bytes), which other
+    /// processes writing to the same pipe may interleave with
+    pub oversized_console_lines: u64,
+}
+
+/// Counters of records sent over the network, cheap enough to read as often
+/// as needed from any thread
+///
+/// ```
+/// let stats = vinted_logger::logger_stats();
+/// println!("{} of {} records sent", stats.sent, stats.enqueued);
+/// ```
+pub fn logger_stats() -> LoggerStats {
+    LoggerStats {
+        enqueued: ENQUEUED.load(Ordering::Relaxed),
+        sent: SENT.load(Ordering::Relaxed),
+        dropped: DROPPED.load(Ordering::Relaxed),
+        send_errors: SEND_ERRORS.load(Ordering::Relaxed),
+        reconnects: RECONNECTS.load(Ordering::Relaxed),
+        retries: RETRIES.load(Ordering::Relaxed),
+        fallback_emissions: vinted_fallback::emissions(),
+        canary_last_success: match CANARY_LAST_SUCCESS.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        },
+        canary_rtt_ms: match CANARY_RTT_MS.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            rtt => Some(rtt),
+        },
+        draining_since: vinted_drain::draining_since(),
+        live_spans: vinted_span_tracker::live_spans(),
+        bind_failures: vinted_diagnostics::count(Diagnostic::BindFailed),
+        channel_closures: vinted_diagnostics::count(Diagnostic::ChannelClosed),
+        diagnostics_suppressed: vinted_diagnostics::suppressed(),
+        oversized_console_lines: vinted_console_writer::oversized_lines(),
+    }
+}
+
+/// Whether the last attempt to send a record to the destination of the
+/// target succeeded, e.g. for a `/health` endpoint. `true` until the first
+/// attempt.
+///
+/// Destinations for errors and metrics are left out, a failing one only
+/// shows in its own [`DestinationStats::healthy`]. With
+/// [canary reflections](crate::LoggerBuilder::canary_reflections) it's also
+/// `false` while the last canary wasn't reflected back: records leave the
+/// process but don't get through.
+pub fn healthy() -> bool {
+    let destinations = DESTINATIONS.lock();
+    let failing = destinations
+        .iter()
+        .filter_map(Weak::upgrade)
+        .any(|destination| {
+            destination.role == DestinationRole::Records
+                && !destination.healthy.load(Ordering::Relaxed)
+        });
+    !failing && !CANARY_MISSED.load(Ordering::Relaxed)
+}
+
+/// What a destination receives, see [`DestinationStats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DestinationRole {
+    /// Records of the target
+    Records,
+    /// Copies of the ERROR records, see
+    /// [`LoggerBuilder::error_destination`](crate::LoggerBuilder::error_destination)
+    Errors,
+    /// Metric records, see
+    /// [`LoggerBuilder::metric_destination`](crate::LoggerBuilder::metric_destination)
+    Metrics,
+}
+
+/// Counters of a single destination of the installed logger, the share of
+/// it in [`LoggerStats`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DestinationStats {
+    /// Where records are sent, e.g. `udp://127.0.0.1:9091`
+    pub destination: String,
+    /// What it receives
+    pub role: DestinationRole,
+    /// Records, or datagrams, queued for its sender thread
+    pub enqueued: u64,
+    /// Records, or datagrams, handed to its socket
+    pub sent: u64,
+    /// Records dropped from its queue, or rejected by it
+    pub dropped: u64,
+    /// Sends to it which failed
+    pub send_errors: u64,
+    /// Whether the last send to it succeeded, `true` until the first one
+    pub healthy: bool,
+}
+
+/// Counters of every destination of the installed logger, in the order they
+/// were set up
+///
+/// ```
+/// for stats in vinted_logger::destination_stats() {
+///     if !stats.healthy {
+///         eprintln!("{:?} destination {} is failing", stats.role, stats.destination);
+///     }
+/// }
+/// ```
+pub fn destination_stats() -> Vec<DestinationStats> {
+    let mut destinations = DESTINATIONS.lock();
+    destinations.retain(|destination| destination.strong_count() > 0);
+    destinations
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|destination| DestinationStats {
+            destination: destination.destination.clone(),
+            role: destination.role,
+            enqueued: destination.enqueued.load(Ordering::Relaxed),
+            sent: destination.sent.load(Ordering::Relaxed),
+            dropped: destination.dropped.load(Ordering::Relaxed),
+            send_errors: destination.send_errors.load(Ordering::Relaxed),
+            healthy: destination.healthy.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+/// Counters of a destination, adding to the process-wide ones too
+#[derive(Debug)]
+pub(crate) struct Destination {
+    destination: String,
+    role: DestinationRole,
+    enqueued: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    send_errors: AtomicU64,
+    healthy: AtomicBool,
+}
+
+impl Destination {
+    /// Counters of `destination`, reported until they're dropped
+    pub(crate) fn register(destination: String, role: DestinationRole) -> Arc<Self> {
+        let stats = Arc::new(Self {
+            destination,
+            role,
+            enqueued: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+            healthy: AtomicBool::new(true),
+        });
+        let mut destinations = DESTINATIONS.lock();
+        destinations.retain(|destination| destination.strong_count() > 0);
+        destinations.push(Arc::downgrade(&stats));
+        stats
+    }
+
+    pub(crate) fn enqueued(&self) {
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+        ENQUEUED.fetch_add(1, Ordering::Relaxed);
+        MINUTE_BUCKETS.add(current_minute(), Counter::Emitted, 1);
+    }
+
+    pub(crate) fn sent(&self, records: u64) {
+        self.sent.fetch_add(records, Ordering::Relaxed);
+        self.healthy.store(true, Ordering::Relaxed);
+        SENT.fetch_add(records, Ordering::Relaxed);
+        MINUTE_BUCKETS.add(current_minute(), Counter::Delivered, records);
+    }
+
+    pub(crate) fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        MINUTE_BUCKETS.add(current_minute(), Counter::DroppedClient, 1);
+    }
+
+    pub(crate) fn send_failed(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+        self.healthy.store(false, Ordering::Relaxed);
+        SEND_ERRORS.fetch_add(1, Ordering::Relaxed);
+        MINUTE_BUCKETS.add(current_minute(), Counter::SendErrors, 1);
+    }
+}
+
+/// `minute_buckets` of canary records: the counters of every minute since
+/// the one of the previous canary record, oldest first
+pub(crate) fn heartbeat_buckets() -> serde_json::Value {
+    MINUTE_BUCKETS.since_heartbeat(current_minute())
+}
+
+/// Minutes since the epoch
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / 60)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Counter {
+    Emitted,
+    Delivered,
+    DroppedClient,
+    SendErrors,
+}
+
+/// Counters of one minute
+#[derive(Debug)]
+struct Bucket {
+    /// Minute since the epoch the counters are of
+    minute: AtomicU64,
+    counters: [AtomicU64; 4],
+}
+
+impl Bucket {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        minute: AtomicU64::new(0),
+        counters: [
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+            AtomicU64::new(0),
+        ],
+    };
+}
+
+/// Ring of the counters of the last [`BUCKET_MINUTES`] minutes, the bucket of
+/// a minute taking over the one of 15 minutes earlier
+#[derive(Debug)]
+struct MinuteBuckets {
+    buckets: [Bucket; BUCKET_MINUTES],
+    /// Minute of the last canary record, 0 before the first
+    last_heartbeat: AtomicU64,
+}
+
+impl MinuteBuckets {
+    const fn new() -> Self {
+        Self {
+            buckets: [Bucket::EMPTY; BUCKET_MINUTES],
+            last_heartbeat: AtomicU64::new(0),
+        }
+    }
+
+    fn add(&self, minute: u64, counter: Counter, n: u64) {
+        let bucket = &self.buckets[minute as usize % BUCKET_MINUTES];
+        let seen = bucket.minute.load(Ordering::Acquire);
+        // The thread swapping the minute in clears the counts of 15 minutes
+        // ago. Counts added by other threads in between are lost, the
+        // buckets are approximate around minute boundaries.
+        if seen < minute
+            && bucket
+                .minute
+                .compare_exchange(seen, minute, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            for count in &bucket.counters {
+                count.store(0, Ordering::Relaxed);
+            }
+        }
+        bucket.counters[counter as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Counters of the minutes since the last heartbeat, including both its
+    /// minute, whose counts were partial then, and `minute`. Minutes without
+    /// traffic count zeros.
+    fn since_heartbeat(&self, minute: u64) -> serde_json::Value {
+        let last = self.last_heartbeat.swap(minute, Ordering::AcqRel);
+        let oldest = minute.saturating_sub(BUCKET_MINUTES as u64 - 1);
+        let first = if last == 0 {
+            minute
+        } else {
+            last.clamp(oldest, minute)
+        };
+        (first..=minute)
+            .map(|at| {
+                let bucket = &self.buckets[at as usize % BUCKET_MINUTES];
+                let count = |counter: Counter| match bucket.minute.load(Ordering::Acquire) {
+                    seen if seen == at => bucket.counters[counter as usize].load(Ordering::Relaxed),
+                    _ => 0,
+                };
+                serde_json::json!({
+                    "start": vinted_timestamp::rfc3339(UNIX_EPOCH + Duration::from_secs(at * 60)),
+                    "emitted": count(Counter::Emitted),
+                    "delivered": count(Counter::Delivered),
+                    "dropped_client": count(Counter::DroppedClient),
+                    "send_errors": count(Counter::SendErrors),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A record the queue of a non-blocking console had no room for
+pub(crate) fn console_dropped() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn file_write_failed() {
+    SEND_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn reconnecting() {
+    RECONNECTS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(feature = "gelf-http")]
+pub(crate) fn retried() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn canary_reflected(rtt: Duration) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |since| since.as_millis() as u64);
+    CANARY_LAST_SUCCESS.store(now, Ordering::Relaxed);
+    CANARY_RTT_MS.store(rtt.as_millis() as u64, Ordering::Relaxed);
+    CANARY_MISSED.store(false, Ordering::Relaxed);
+}
+
+pub(crate) fn canary_missed() {
+    CANARY_MISSED.store(true, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counter, MinuteBuckets, BUCKET_MINUTES};
+    use crate::vinted_diagnostics::{self, Diagnostic};
+    use serde_json::json;
+
+    /// 2024-01-01T00:00:00Z in minutes since the epoch
+    const MINUTE: u64 = 28_401_120;
+
+    fn bucket(start: &str, counts: [u64; 4]) -> serde_json::Value {
+        json!({
+            "start": start,
+            "emitted": counts[0],
+            "delivered": counts[1],
+            "dropped_client": counts[2],
+            "send_errors": counts[3],
+        })
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn problems_of_the_logger_are_counted_in_the_stats() {
+        let before = crate::logger_stats();
+        vinted_diagnostics::report(
+            Diagnostic::BindFailed,
+            format_args!("test diagnostic, bind failed"),
+        );
+        vinted_diagnostics::report(
+            Diagnostic::ChannelClosed,
+            format_args!("test diagnostic, channel closed"),
+        );
+        super::console_dropped();
+        super::file_write_failed();
+        let after = crate::logger_stats();
+
+        // Other tests may run into problems at the same time, never fewer
+        assert!(after.bind_failures > before.bind_failures);
+        assert!(after.channel_closures > before.channel_closures);
+        assert!(after.dropped > before.dropped);
+        assert!(after.send_errors > before.send_errors);
+        let diagnostics = crate::internal_diagnostics();
+        assert!(diagnostics.bind_failures >= after.bind_failures);
+        assert!(diagnostics.dropped >= after.dropped);
+    }
+
+    #[test]
+    fn heartbeats_carry_the_minutes_since_the_previous_one() {
+        let buckets = MinuteBuckets::new();
+        buckets.add(MINUTE, Counter::Emitted, 3);
+        buckets.add(MINUTE, Counter::Delivered, 2);
+        assert_eq!(
+            buckets.since_heartbeat(MINUTE),
+            json!([bucket("2024-01-01T00:00:00+00:00", [3, 2, 0, 0])])
+        );
+
+        buckets.add(MINUTE, Counter::Delivered, 1);
+        buckets.add(MINUTE + 2, Counter::Emitted, 5);
+        buckets.add(MINUTE + 2, Counter::DroppedClient, 1);
+        buckets.add(MINUTE + 2, Counter::SendErrors, 4);
+        // The minute of the previous heartbeat again, with its final counts
+        assert_eq!(
+            buckets.since_heartbeat(MINUTE + 2),
+            json!([
+                bucket("2024-01-01T00:00:00+00:00", [3, 3, 0, 0]),
+                bucket("2024-01-01T00:01:00+00:00", [0, 0, 0, 0]),
+                bucket("2024-01-01T00:02:00+00:00", [5, 0, 1, 4]),
+            ])
+        );
+    }
+
+    #[test]
+    fn buckets_rotate_after_fifteen_minutes() {
+        let buckets = MinuteBuckets::new();
+        buckets.since_heartbeat(MINUTE);
+        for minute in MINUTE..MINUTE + 20 {
+            buckets.add(minute, Counter::Emitted, minute - MINUTE + 1);
+        }
+
+        // Heartbeats missed for 20 minutes, the oldest 5 are gone
+        let since = buckets.since_heartbeat(MINUTE + 19);
+        let since = since.as_array().unwrap();
+        assert_eq!(since.len(), BUCKET_MINUTES);
+        assert_eq!(since[0], bucket("2024-01-01T00:05:00+00:00", [6, 0, 0, 0]));
+        assert_eq!(
+            since[14],
+            bucket("2024-01-01T00:19:00+00:00", [20, 0, 0, 0])
+        );
+
+        // The bucket of minute 20 took over the one of minute 5
+        buckets.add(MINUTE + 20, Counter::Delivered, 1);
+        let since = buckets.since_heartbeat(MINUTE + 20);
+        assert_eq!(
+            since,
+            json!([
+                bucket("2024-01-01T00:19:00+00:00", [20, 0, 0, 0]),
+                bucket("2024-01-01T00:20:00+00:00", [0, 1, 0, 0]),
+            ])
+        );
+    }
+
+    #[test]
+    fn late_counts_go_to_the_current_minute() {
+        let buckets = MinuteBuckets::new();
+        buckets.add(MINUTE + BUCKET_MINUTES as u64, Counter::Emitted, 1);
+        // A thread which read the clock before the minute rotated
+        buckets.add(MINUTE, Counter::Emitted, 1);
+        assert_eq!(
+            buckets.since_heartbeat(MINUTE + BUCKET_MINUTES as u64)[0]["emitted"],
+            2
+        );
+    }
+}
@@ -0,0 +1,583 @@
+use crate::{
+    vinted_delivery,
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_events::{self, LoggerEvent},
+    vinted_fallback,
+    vinted_flush::Progress,
+    vinted_stats::{self, Destination, DestinationRole},
+};
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    io::{self, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Default of [`LoggerBuilder::tcp_buffer`](crate::LoggerBuilder::tcp_buffer)
+pub const DEFAULT_TCP_BUFFER: usize = 10_000;
+
+/// How long dropping a `VintedTcpWriter` waits for buffered records to be sent
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How `TcpJson` records are delimited on the stream, see
+/// [`LoggerBuilder::tcp_framing`](crate::LoggerBuilder::tcp_framing)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Framing {
+    /// Every record ends with `\n`, e.g. for fluentd `in_tcp` with
+    /// `format json`. This is the default.
+    #[default]
+    NewlineDelimited,
+    /// Every record is preceded by its length in bytes, without a trailing
+    /// `\n`, e.g. for fluent-bit `tcp` inputs with length-prefix separators
+    LengthPrefixed {
+        /// Encoding of the length
+        header: LengthHeader,
+    },
+}
+
+/// Length header of [`Framing::LengthPrefixed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LengthHeader {
+    /// 4 bytes, most significant first
+    U32BigEndian,
+    /// 4 bytes, least significant first
+    U32LittleEndian,
+}
+
+impl Framing {
+    /// The bytes sent for a record, written to the stream as a whole
+    fn frame(self, record: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::NewlineDelimited => {
+                let mut frame = record.to_owned();
+                if !frame.ends_with(b"\n") {
+                    frame.push(b'\n');
+                }
+                Ok(frame)
+            }
+            Self::LengthPrefixed { header } => {
+                let record = record.strip_suffix(b"\n").unwrap_or(record);
+                let len = u32::try_from(record.len()).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "log record is too long for a 32-bit length header",
+                    )
+                })?;
+                let len = match header {
+                    LengthHeader::U32BigEndian => len.to_be_bytes(),
+                    LengthHeader::U32LittleEndian => len.to_le_bytes(),
+                };
+
+                let mut frame = Vec::with_capacity(len.len() + record.len());
+                frame.extend_from_slice(&len);
+                frame.extend_from_slice(record);
+                Ok(frame)
+            }
+        }
+    }
+
+    /// The record of a frame
+    fn unframe(self, frame: &[u8]) -> &[u8] {
+        match self {
+            Self::NewlineDelimited => frame,
+            Self::LengthPrefixed { .. } => frame.get(4..).unwrap_or_default(),
+        }
+    }
+}
+
+/// Sends records over TCP, newline terminated or length prefixed (see
+/// [`Framing`]), from a background thread.
+///
+/// Records are buffered while the thread connects, reconnecting with
+/// exponential backoff whenever the connection drops, up to a number of
+/// records beyond which the oldest are dropped. Writing never blocks on the
+/// connection. A frame which couldn't be written entirely is sent again
+/// as a whole over the next connection, never resumed in the middle.
+/// Dropping the `VintedTcpWriter` stops the thread once the buffer is sent,
+/// waiting up to [`SHUTDOWN_TIMEOUT`].
+pub(crate) struct VintedTcpWriter {
+    writer: WriterImpl,
+    stopped: Mutex<Receiver<()>>,
+}
+
+impl VintedTcpWriter {
+    pub(crate) fn new(addr: SocketAddr, capacity: usize, framing: Framing) -> Self {
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Buffer {
+                records: VecDeque::new(),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            capacity: capacity.max(1),
+            framing,
+            progress: Progress::register(),
+            stats: Destination::register(format!("tcp://{}", addr), DestinationRole::Records),
+        });
+        let (stopping, stopped) = channel::<()>();
+
+        let thread_shared = shared.clone();
+        let _ = ::std::thread::spawn(move || {
+            let _stopping = stopping;
+            send_loop(addr, &thread_shared);
+            thread_shared.progress.stopped();
+        });
+
+        Self {
+            writer: WriterImpl {
+                shared,
+                framing,
+                error: false,
+            },
+            stopped: Mutex::new(stopped),
+        }
+    }
+}
+
+impl Drop for VintedTcpWriter {
+    fn drop(&mut self) {
+        self.writer.shared.buffer.lock().closed = true;
+        self.writer.shared.ready.notify_one();
+        let _ = self.stopped.get_mut().recv_timeout(SHUTDOWN_TIMEOUT);
+    }
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    ready: Condvar,
+    capacity: usize,
+    framing: Framing,
+    progress: Arc<Progress>,
+    stats: Arc<Destination>,
+}
+
+struct Buffer {
+    records: VecDeque<Bytes>,
+    closed: bool,
+}
+
+/// Sends buffered records until the writer is dropped and the buffer is
+/// empty, or until it's dropped while disconnected
+fn send_loop(addr: SocketAddr, shared: &Shared) {
+    let mut stream: Option<TcpStream> = None;
+    let mut backoff = MIN_BACKOFF;
+    let mut delivered = false;
+    // Whether the last attempt connected, events are only emitted when it changes
+    let mut was_connected = None;
+
+    loop {
+        let record = {
+            let mut buffer = shared.buffer.lock();
+            while buffer.records.is_empty() && !buffer.closed {
+                shared.ready.wait(&mut buffer);
+            }
+            match buffer.records.pop_front() {
+                Some(record) => record,
+                None => return,
+            }
+        };
+
+        if stream.is_none() && was_connected.is_some() {
+            vinted_stats::reconnecting();
+        }
+        let connected = match stream {
+            Some(ref mut connected) => connected,
+            None => match connect(addr) {
+                Ok(connected) => {
+                    backoff = MIN_BACKOFF;
+                    was_connected = Some(true);
+                    vinted_events::emit(LoggerEvent::Connected { addr });
+                    stream.get_or_insert(connected)
+                }
+                Err(e) => {
+                    shared.stats.send_failed();
+                    vinted_diagnostics::report(
+                        Diagnostic::SendFailed,
+                        format_args!("Couldn't connect to fluentd at {}: {}", addr, e),
+                    );
+                    if was_connected != Some(false) {
+                        was_connected = Some(false);
+                        vinted_events::emit(LoggerEvent::Disconnected {
+                            addr,
+                            error: e.to_string(),
+                        });
+                    }
+                    if !requeue(shared, record) {
+                        return;
+                    }
+                    // Wakes up early only to stop
+                    let mut buffer = shared.buffer.lock();
+                    if !buffer.closed {
+                        let _ = shared.ready.wait_for(&mut buffer, backoff);
+                    }
+                    if buffer.closed {
+                        return;
+                    }
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            },
+        };
+
+        match connected.write_all(&record) {
+            Ok(()) => {
+                shared.progress.done(1);
+                shared.stats.sent(1);
+                if !delivered {
+                    delivered = true;
+                    vinted_delivery::delivered();
+                }
+            }
+            Err(e) => {
+                shared.stats.send_failed();
+                vinted_diagnostics::report(
+                    Diagnostic::SendFailed,
+                    format_args!("Log record can't be sent to fluentd, reconnecting: {}", e),
+                );
+                stream = None;
+                was_connected = Some(false);
+                vinted_events::emit(LoggerEvent::Disconnected {
+                    addr,
+                    error: e.to_string(),
+                });
+                if !requeue(shared, record) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Puts a record which couldn't be sent back in front, unless the buffer
+/// filled up meanwhile, being the oldest it's dropped then. Returns `false`
+/// once the writer is dropped, giving up on the buffer.
+fn requeue(shared: &Shared, record: Bytes) -> bool {
+    let mut buffer = shared.buffer.lock();
+    if buffer.closed {
+        return false;
+    }
+    if buffer.records.len() < shared.capacity {
+        buffer.records.push_front(record);
+    } else {
+        drop(buffer);
+        shared.progress.done(1);
+        report_dropped(shared, &record);
+    }
+    true
+}
+
+/// Reports a record dropped before it could be sent, which goes to stderr
+/// instead
+fn report_dropped(shared: &Shared, record: &[u8]) {
+    vinted_fallback::undelivered(shared.framing.unframe(record));
+    shared.stats.dropped();
+    vinted_diagnostics::report(
+        Diagnostic::Dropped,
+        format_args!("TCP buffer is full, dropped the oldest log record"),
+    );
+}
+
+impl<'a> MakeWriter<'a> for VintedTcpWriter {
+    type Writer = WriterImpl;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer.clone()
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        WriterImpl {
+            error: *meta.level() == Level::ERROR,
+            ..self.writer.clone()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct WriterImpl {
+    shared: Arc<Shared>,
+    framing: Framing,
+    /// Whether the record is an ERROR one
+    error: bool,
+}
+
+impl io::Write for WriterImpl {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let record = self.framing.frame(buf)?;
+
+        self.shared.progress.queued(self.error);
+        self.shared.stats.enqueued();
+        let dropped = {
+            let mut buffer = self.shared.buffer.lock();
+            let dropped = if buffer.records.len() >= self.shared.capacity {
+                buffer.records.pop_front()
+            } else {
+                None
+            };
+            buffer.records.push_back(Bytes::from(record));
+            dropped
+        };
+        self.shared.ready.notify_one();
+
+        if let Some(dropped) = dropped {
+            self.shared.progress.done(1);
+            report_dropped(&self.shared, &dropped);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Framing, LengthHeader, VintedTcpWriter};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        time::{Duration, Instant},
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    fn write(writer: &VintedTcpWriter, record: &str) {
+        writer.make_writer().write_all(record.as_bytes()).unwrap();
+    }
+
+    fn accept(listener: &TcpListener) -> TcpStream {
+        let (stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    fn lines(stream: TcpStream, count: usize) -> Vec<String> {
+        BufReader::new(stream)
+            .lines()
+            .take(count)
+            .map(|line| line.expect("line is received"))
+            .collect()
+    }
+
+    /// Address nothing listens on, until it's bound again
+    fn closed_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn records_are_framed() {
+        let record = b"{\"n\":1}\n";
+        let framed = |framing: Framing| framing.frame(record).unwrap();
+
+        assert_eq!(framed(Framing::NewlineDelimited), record);
+        assert_eq!(
+            Framing::NewlineDelimited.frame(b"{\"n\":1}").unwrap(),
+            record
+        );
+        assert_eq!(
+            framed(Framing::LengthPrefixed {
+                header: LengthHeader::U32BigEndian
+            }),
+            b"\0\0\0\x07{\"n\":1}"
+        );
+        assert_eq!(
+            framed(Framing::LengthPrefixed {
+                header: LengthHeader::U32LittleEndian
+            }),
+            b"\x07\0\0\0{\"n\":1}"
+        );
+    }
+
+    #[test]
+    fn records_are_sent_newline_delimited() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let writer = VintedTcpWriter::new(
+            listener.local_addr().unwrap(),
+            16,
+            Framing::NewlineDelimited,
+        );
+
+        write(&writer, "{\"n\":1}\n");
+        write(&writer, "{\"n\":2}\n");
+
+        assert_eq!(lines(accept(&listener), 2), ["{\"n\":1}", "{\"n\":2}"]);
+    }
+
+    #[test]
+    fn length_prefixed_records_are_sent_whole() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let framing = Framing::LengthPrefixed {
+            header: LengthHeader::U32BigEndian,
+        };
+        let writer = VintedTcpWriter::new(listener.local_addr().unwrap(), 16, framing);
+
+        let big = format!("{{\"big\":\"{}\"}}\n", "x".repeat(100_000));
+        write(&writer, &big);
+
+        let mut stream = accept(&listener);
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut record = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut record).unwrap();
+        assert_eq!(record, big.trim_end().as_bytes());
+    }
+
+    #[test]
+    fn records_are_buffered_until_the_collector_is_up() {
+        let addr = closed_addr();
+        let writer = VintedTcpWriter::new(addr, 16, Framing::NewlineDelimited);
+
+        // Writing doesn't wait for the connection
+        let start = Instant::now();
+        for n in 0..3 {
+            write(&writer, &format!("{{\"n\":{}}}\n", n));
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        std::thread::sleep(Duration::from_millis(150));
+
+        let listener = TcpListener::bind(addr).unwrap();
+        assert_eq!(
+            lines(accept(&listener), 3),
+            ["{\"n\":0}", "{\"n\":1}", "{\"n\":2}"]
+        );
+    }
+
+    #[test]
+    fn the_oldest_records_are_dropped_beyond_the_buffer() {
+        let addr = closed_addr();
+        let writer = VintedTcpWriter::new(addr, 3, Framing::NewlineDelimited);
+
+        for n in 0..5 {
+            write(&writer, &format!("{{\"n\":{}}}\n", n));
+        }
+
+        let listener = TcpListener::bind(addr).unwrap();
+        assert_eq!(
+            lines(accept(&listener), 3),
+            ["{\"n\":2}", "{\"n\":3}", "{\"n\":4}"]
+        );
+    }
+
+    #[test]
+    fn records_are_sent_again_over_a_new_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let writer = VintedTcpWriter::new(
+            listener.local_addr().unwrap(),
+            16,
+            Framing::NewlineDelimited,
+        );
+
+        write(&writer, "{\"n\":1}\n");
+        let first = accept(&listener);
+        assert_eq!(lines(first.try_clone().unwrap(), 1), ["{\"n\":1}"]);
+        drop(first);
+
+        // Writes to the closed connection fail sooner or later, and the
+        // record failing is sent again once reconnected
+        let second = loop {
+            write(&writer, "{\"n\":2}\n");
+            listener.set_nonblocking(true).unwrap();
+            let accepted = listener.accept();
+            listener.set_nonblocking(false).unwrap();
+            match accepted {
+                Ok((stream, _)) => break stream,
+                Err(_) => std::thread::sleep(Duration::from_millis(20)),
+            }
+        };
+        second.set_nonblocking(false).unwrap();
+        second
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(lines(second, 1), ["{\"n\":2}"]);
+    }
+
+    /// Next length-prefixed frame, as JSON
+    fn read_frame(stream: &mut TcpStream, header: LengthHeader) -> serde_json::Value {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).expect("header is received");
+        let len = match header {
+            LengthHeader::U32BigEndian => u32::from_be_bytes(len),
+            LengthHeader::U32LittleEndian => u32::from_le_bytes(len),
+        };
+        let mut record = vec![0; len as usize];
+        stream.read_exact(&mut record).expect("record is received");
+        serde_json::from_slice(&record).expect("frame is a whole record")
+    }
+
+    #[test]
+    fn length_prefixed_frames_stay_whole_across_reconnects() {
+        for &header in &[LengthHeader::U32BigEndian, LengthHeader::U32LittleEndian] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let writer = VintedTcpWriter::new(
+                listener.local_addr().unwrap(),
+                64,
+                Framing::LengthPrefixed { header },
+            );
+            let record = |n: usize| format!("{{\"n\":{},\"pad\":\"{}\"}}\n", n, "x".repeat(n));
+
+            for n in 0..3 {
+                write(&writer, &record(n));
+            }
+            let mut first = accept(&listener);
+            for n in 0..3 {
+                assert_eq!(read_frame(&mut first, header)["n"], n);
+            }
+            drop(first);
+
+            // Records written while the connection breaks start over on the
+            // next one, none of them is cut
+            let mut n = 3;
+            let mut second = loop {
+                write(&writer, &record(n));
+                n += 1;
+                listener.set_nonblocking(true).unwrap();
+                let accepted = listener.accept();
+                listener.set_nonblocking(false).unwrap();
+                match accepted {
+                    Ok((stream, _)) => break stream,
+                    Err(_) => std::thread::sleep(Duration::from_millis(20)),
+                }
+            };
+            second.set_nonblocking(false).unwrap();
+            second
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            write(&writer, &record(1_000));
+
+            let mut last = 0;
+            while last != 1_000 {
+                let frame = read_frame(&mut second, header);
+                let received = frame["n"].as_u64().unwrap();
+                assert!(received > last, "{} after {}", received, last);
+                assert_eq!(frame["pad"].as_str().unwrap().len() as u64, received);
+                last = received;
+            }
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::{
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How JSON records are timestamped, see
+/// [`LoggerBuilder::timestamp_format`](crate::LoggerBuilder::timestamp_format)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum TimestampFormat {
+    /// RFC 3339 in UTC with as many fractional digits (none, 3, 6 or 9) as
+    /// needed, e.g. `2021-04-20T12:42:57.353066+00:00`, the way chrono
+    /// formats it. This is the default.
+    #[default]
+    Rfc3339,
+    /// RFC 3339 in UTC with exactly 3 fractional digits, e.g.
+    /// `2021-04-20T12:42:57.353Z`
+    Rfc3339Millis,
+    /// RFC 3339 in UTC with exactly 9 fractional digits, e.g.
+    /// `2021-04-20T12:42:57.353066000Z`
+    Rfc3339Nanos,
+    /// Milliseconds since the Unix epoch, as a number, e.g. `1618922577353`
+    EpochMillis,
+}
+
+impl TimestampFormat {
+    pub(crate) fn format(self, time: SystemTime) -> serde_json::Value {
+        match self {
+            Self::Rfc3339 => rfc3339(time).into(),
+            Self::Rfc3339Millis => {
+                let (secs, nanos) = split(time);
+                let mut timestamp = date_time(secs);
+                let _ = write!(timestamp, ".{:03}Z", nanos / 1_000_000);
+                timestamp.into()
+            }
+            Self::Rfc3339Nanos => {
+                let (secs, nanos) = split(time);
+                let mut timestamp = date_time(secs);
+                let _ = write!(timestamp, ".{:09}Z", nanos);
+                timestamp.into()
+            }
+            Self::EpochMillis => {
+                let (secs, nanos) = split(time);
+                (secs * 1_000 + i64::from(nanos / 1_000_000)).into()
+            }
+        }
+    }
+}
+
+/// Formats a UTC timestamp the way chrono's `DateTime<Utc>::to_rfc3339` does:
+/// `2021-04-20T12:42:57.353066+00:00`, with as many fractional digits (none,
+/// 3, 6 or 9) as needed to show every non-zero sub-second digit.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let (secs, nanos) = split(time);
+    let mut timestamp = date_time(secs);
+    let _ = if nanos == 0 {
+        Ok(())
+    } else if nanos % 1_000_000 == 0 {
+        write!(timestamp, ".{:03}", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        write!(timestamp, ".{:06}", nanos / 1_000)
+    } else {
+        write!(timestamp, ".{:09}", nanos)
+    };
+    timestamp.push_str("+00:00");
+    timestamp
+}
+
+/// Seconds since the Unix epoch, rounded down, and nanoseconds past them
+fn split(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+/// `2021-04-20T12:42:57`
+fn date_time(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let secs_of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+
+    let mut timestamp = String::with_capacity(35);
+    let _ = match year {
+        0..=9999 => write!(timestamp, "{:04}", year),
+        10_000.. => write!(timestamp, "+{}", year),
+        _ => write!(timestamp, "-{:04}", -year),
+    };
+    let _ = write!(
+        timestamp,
+        "-{:02}-{:02}T{:02}:{:02}:{:02}",
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    );
+    timestamp
+}
+
+/// Parses an RFC 3339 timestamp, e.g. `2021-04-20T12:42:57.353Z` or
+/// `2021-04-20 14:42:57+02:00`. Fractions beyond nanoseconds are truncated.
+pub(crate) fn parse_rfc3339(timestamp: &str) -> Option<SystemTime> {
+    let bytes = timestamp.as_bytes();
+    let digits = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = bytes.get(range)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        digits
+            .iter()
+            .try_fold(0, |n: i64, digit| Some(n * 10 + i64::from(digit - b'0')))
+    };
+    let separated =
+        |at: usize, separators: &[u8]| bytes.get(at).is_some_and(|b| separators.contains(b));
+
+    if !separated(4, b"-")
+        || !separated(7, b"-")
+        || !separated(10, b"Tt ")
+        || !separated(13, b":")
+        || !separated(16, b":")
+    {
+        return None;
+    }
+    let (year, month, day) = (digits(0..4)?, digits(5..7)?, digits(8..10)?);
+    let (hour, minute, second) = (digits(11..13)?, digits(14..16)?, digits(17..19)?);
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > i64::from(days_in_month(year, month as u32))
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut at = 19;
+    let mut nanos = 0;
+    if separated(at, b".") {
+        at += 1;
+        let start = at;
+        while bytes.get(at).is_some_and(u8::is_ascii_digit) {
+            if at - start < 9 {
+                nanos = nanos * 10 + u32::from(bytes[at] - b'0');
+            }
+            at += 1;
+        }
+        if at == start {
+            return None;
+        }
+        nanos *= 10u32.pow(9 - (at - start).min(9) as u32);
+    }
+
+    let offset = match bytes.get(at..)? {
+        b"Z" | b"z" => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let (hours, minutes) = (digits(at + 1..at + 3)?, digits(at + 4..at + 6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+
+    // Leap seconds are folded into the next second
+    let secs = days_from_civil(year, month as u32, day as u32) * 86_400
+        + hour * 3600
+        + minute * 60
+        + second
+        - offset;
+    let since_epoch = Duration::new(secs.unsigned_abs(), 0);
+    if secs >= 0 {
+        UNIX_EPOCH.checked_add(since_epoch + Duration::from_nanos(u64::from(nanos)))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(since_epoch)?
+            .checked_add(Duration::from_nanos(u64::from(nanos)))
+    }
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts a proleptic Gregorian date to days since 1970-01-01, see
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian `(year, month, day)`,
+/// see <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_rfc3339, rfc3339, TimestampFormat};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target,
+    };
+    use chrono::{DateTime, Utc};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// 9999-12-31T23:59:59
+    const LAST_SECOND_OF_9999: i64 = 253_402_300_799;
+
+    fn at(secs: i64, nanos: u32) -> SystemTime {
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::new(secs as u64, nanos)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+                + Duration::from_nanos(nanos.into())
+        }
+    }
+
+    fn chrono_rfc3339(secs: i64, nanos: u32) -> String {
+        DateTime::<Utc>::from_timestamp(secs, nanos)
+            .expect("timestamp is in chrono's range")
+            .to_rfc3339()
+    }
+
+    #[test]
+    fn timestamps_match_chrono() {
+        let mut edges = vec![
+            0,
+            -1,
+            1,
+            // 2016-12-31T23:59:59 and 2017-01-01T00:00:00, around the last
+            // leap second
+            1_483_228_799,
+            1_483_228_800,
+            // 2015-06-30T23:59:59 and 2015-07-01T00:00:00
+            1_435_708_799,
+            1_435_708_800,
+            // 2000-02-29 and 2100-03-01, leap year rules
+            951_782_400,
+            4_107_542_400,
+            LAST_SECOND_OF_9999,
+            LAST_SECOND_OF_9999 + 1,
+            // 0000-01-01T00:00:00
+            -62_167_219_200,
+        ];
+        // Deterministic xorshift, up to year 20000 either way of the epoch
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..100_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            edges.push((state % 1_200_000_000_000) as i64 - 600_000_000_000);
+        }
+
+        for secs in edges {
+            for &nanos in &[0, 1, 999, 1_000, 353_066_000, 353_000_000, 999_999_999] {
+                assert_eq!(
+                    rfc3339(at(secs, nanos)),
+                    chrono_rfc3339(secs, nanos),
+                    "{}s {}ns",
+                    secs,
+                    nanos
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_formats() {
+        let time = at(1_618_922_577, 353_066_000);
+        assert_eq!(
+            TimestampFormat::Rfc3339Millis.format(time),
+            "2021-04-20T12:42:57.353Z"
+        );
+        assert_eq!(
+            TimestampFormat::Rfc3339Nanos.format(time),
+            "2021-04-20T12:42:57.353066000Z"
+        );
+        assert_eq!(
+            TimestampFormat::EpochMillis.format(time),
+            1_618_922_577_353i64
+        );
+
+        let last = at(LAST_SECOND_OF_9999, 999_999_999);
+        assert_eq!(
+            TimestampFormat::Rfc3339Millis.format(last),
+            "9999-12-31T23:59:59.999Z"
+        );
+        assert_eq!(
+            TimestampFormat::Rfc3339.format(last),
+            "9999-12-31T23:59:59.999999999+00:00"
+        );
+        assert_eq!(
+            TimestampFormat::Rfc3339Millis.format(at(1_483_228_799, 999_999_999)),
+            "2016-12-31T23:59:59.999Z"
+        );
+        assert_eq!(
+            TimestampFormat::EpochMillis.format(at(-1, 500_000_000)),
+            -500i64
+        );
+    }
+
+    #[test]
+    fn formatted_timestamps_parse_back() {
+        for &(secs, nanos) in &[
+            (0, 0),
+            (1_618_922_577, 353_066_000),
+            (1_483_228_799, 999_999_999),
+            (LAST_SECOND_OF_9999, 999_999_999),
+            (-1, 1),
+        ] {
+            let time = at(secs, nanos);
+            assert_eq!(parse_rfc3339(&rfc3339(time)), Some(time));
+            let nanos_format = TimestampFormat::Rfc3339Nanos.format(time);
+            assert_eq!(parse_rfc3339(nanos_format.as_str().unwrap()), Some(time));
+        }
+    }
+
+    #[test]
+    fn leap_seconds_and_offsets_are_parsed() {
+        assert_eq!(
+            parse_rfc3339("2016-12-31T23:59:60Z"),
+            Some(at(1_483_228_800, 0))
+        );
+        assert_eq!(
+            parse_rfc3339("2021-04-20 14:42:57.353+02:00"),
+            Some(at(1_618_922_577, 353_000_000))
+        );
+        assert_eq!(
+            parse_rfc3339("2021-04-20T12:42:57.1234567891Z"),
+            Some(at(1_618_922_577, 123_456_789))
+        );
+        for invalid in &[
+            "",
+            "2021-04-20",
+            "2021-02-29T00:00:00Z",
+            "2021-04-20T24:00:00Z",
+            "2021-04-20T12:42:61Z",
+            "2021-04-20T12:42:57.Z",
+            "2021-04-20T12:42:57",
+            "2021-04-20T12:42:57+2:00",
+        ] {
+            assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+        }
+    }
+
+    /// Whether `value` has the shape of `pattern`, in which `d` stands for
+    /// any digit
+    fn has_shape(value: &str, pattern: &str) -> bool {
+        value.len() == pattern.len()
+            && value
+                .bytes()
+                .zip(pattern.bytes())
+                .all(|(byte, expected)| match expected {
+                    b'd' => byte.is_ascii_digit(),
+                    expected => byte == expected,
+                })
+    }
+
+    #[test]
+    fn records_are_timestamped_in_the_format() {
+        for &format in &[
+            TimestampFormat::Rfc3339,
+            TimestampFormat::Rfc3339Millis,
+            TimestampFormat::Rfc3339Nanos,
+            TimestampFormat::EpochMillis,
+        ] {
+            let capture = Capture::default();
+            let builder = crate::builder("svc-test", Target::ConsoleJson)
+                .timestamp_format(format)
+                .timestamp_key("time")
+                .capture(&capture);
+            let before = SystemTime::now() - Duration::from_millis(1);
+            with_logger(builder, || tracing::info!("timestamped"));
+            let after = SystemTime::now();
+
+            let record = &capture.records()[0];
+            assert!(record.get("@timestamp").is_none(), "{}", record);
+            let time = match (format, &record["time"]) {
+                (TimestampFormat::EpochMillis, millis) => {
+                    UNIX_EPOCH + Duration::from_millis(millis.as_u64().expect("time is a number"))
+                }
+                (TimestampFormat::Rfc3339, serde_json::Value::String(time)) => {
+                    let utc = time.strip_suffix("+00:00").expect("time is in UTC");
+                    let (seconds, fraction) = utc.split_at(19.min(utc.len()));
+                    assert!(has_shape(seconds, "dddd-dd-ddTdd:dd:dd"), "{}", time);
+                    assert!(
+                        [0, 4, 7, 10].contains(&fraction.len())
+                            && has_shape(fraction, &".ddddddddd"[..fraction.len()]),
+                        "{}",
+                        time
+                    );
+                    parse_rfc3339(time).expect("time parses back")
+                }
+                (_, serde_json::Value::String(time)) => {
+                    let shape = match format {
+                        TimestampFormat::Rfc3339Millis => "dddd-dd-ddTdd:dd:dd.dddZ",
+                        _ => "dddd-dd-ddTdd:dd:dd.dddddddddZ",
+                    };
+                    assert!(has_shape(time, shape), "{} isn't {}", time, shape);
+                    parse_rfc3339(time).expect("time parses back")
+                }
+                (_, time) => panic!("{:?} time {}", format, time),
+            };
+            assert!(before <= time && time <= after, "{:?}: {}", format, record);
+        }
+    }
+}
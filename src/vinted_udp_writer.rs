@@ -1,64 +1,513 @@
+#[cfg(all(feature = "sendmmsg", target_os = "linux"))]
+use crate::vinted_sendmmsg;
+use crate::{
+    vinted_delivery,
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_dict, vinted_drain,
+    vinted_escaping::Entries,
+    vinted_events::{self, LoggerEvent},
+    vinted_fallback,
+    vinted_flush::Progress,
+    vinted_gelf,
+    vinted_stats::{Destination, DestinationRole},
+};
 use bytes::Bytes;
 use parking_lot::Mutex;
+use serde_json::Value;
 use std::{
     io,
-    net::UdpSocket,
+    net::{SocketAddr, UdpSocket},
     sync::{
-        mpsc::{channel, Sender},
+        mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
         Arc,
     },
+    time::{Duration, Instant},
 };
+use tracing_core::{Level, Metadata};
 use tracing_subscriber::fmt::MakeWriter;
 
+/// Default of [`LoggerBuilder::udp_buffer`](crate::LoggerBuilder::udp_buffer)
+pub const DEFAULT_UDP_BUFFER: usize = 4_096;
+
+/// Default of [`LoggerBuilder::max_datagram_size`](crate::LoggerBuilder::max_datagram_size),
+/// the largest datagram fluentd's `in_udp` is configured to read
+pub const DEFAULT_MAX_DATAGRAM_SIZE: usize = 8_192;
+
+/// Default of [`LoggerBuilder::udp_batching`](crate::LoggerBuilder::udp_batching),
+/// just under [`DEFAULT_MAX_DATAGRAM_SIZE`]
+pub const DEFAULT_UDP_BATCH_BYTES: usize = 7_900;
+
+/// Default of [`LoggerBuilder::udp_batching`](crate::LoggerBuilder::udp_batching)
+pub const DEFAULT_UDP_BATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Key marking records whose message was shortened to fit a datagram
+pub(crate) const TRUNCATED_KEY: &str = "truncated";
+
+/// How `UdpJson` records are encoded, see
+/// [`LoggerBuilder::wire_format`](crate::LoggerBuilder::wire_format)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum WireFormat {
+    /// The JSON record itself, one per datagram, for fluentd `in_udp`.
+    /// Records larger than a datagram have their message shortened. This is
+    /// the default.
+    #[default]
+    Json,
+    /// GELF 1.1 messages for Graylog UDP inputs, uncompressed. Messages
+    /// larger than a datagram are split into GELF chunks, those needing more
+    /// than [`GELF_MAX_CHUNKS`](crate::GELF_MAX_CHUNKS) are dropped.
+    Gelf,
+    /// Batches of `Json` records with their keys in a table, for collectors
+    /// decoding them with [`validate::decode_batch`](crate::validate::decode_batch).
+    /// Needs [`udp_batching`](crate::LoggerBuilder::udp_batching), batches
+    /// the table wouldn't make smaller are sent as they are.
+    JsonDict,
+}
+
+/// Limits of the datagrams batching records, see
+/// [`LoggerBuilder::udp_batching_with`](crate::LoggerBuilder::udp_batching_with)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Batching {
+    pub(crate) max_bytes: usize,
+    pub(crate) interval: Duration,
+    /// Batches are sent in key table containers, see [`WireFormat::JsonDict`]
+    pub(crate) key_table: bool,
+}
+
+/// How long dropping a `VintedUdpWriter` waits for queued records to be sent
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends records from background threads, one per destination.
+///
+/// Every thread queues up to a number of records, beyond which new ones are
+/// dropped, so writing never blocks and a socket which can't keep up doesn't
+/// grow the queue without bounds. A thread lives as long as the
+/// `VintedUdpWriter` it was spawned for, no matter how many writers are made
+/// and dropped in between. Dropping the `VintedUdpWriter` stops it once every
+/// record queued until then is sent, waiting up to [`SHUTDOWN_TIMEOUT`].
+///
+/// Records going to several destinations are serialized once and shared
+/// between their queues, so a destination which can't keep up only drops
+/// records of its own queue.
+///
+/// Records larger than a datagram may be sent with a shortened message
+/// instead, see [`truncate`], or in chunks in the GELF wire format.
+///
+/// With [`Batching`], threads send the records they queued as few newline
+/// delimited datagrams as the limits allow, never splitting a record.
 pub(crate) struct VintedUdpWriter {
     writer: WriterImpl,
+    error_sender: Option<(Level, Arc<Queue>)>,
+    metric_sender: Option<(&'static str, Arc<Queue>)>,
+    capacity: usize,
+    batching: Option<Batching>,
+    // Declared last so it's dropped after the senders above, which is what
+    // lets the threads stop
+    stopped: Stopped,
 }
 
 impl VintedUdpWriter {
-    pub(crate) fn new(addr: &'static str) -> Self {
-        let (sender, receiver) = channel::<Bytes>();
-
-        let _ = ::std::thread::spawn(move || {
-            match UdpSocket::bind("127.0.0.1:0") {
-                Ok(socket) => loop {
-                    match receiver.recv() {
-                        Ok(bytes) => {
-                            if let Err(e) = socket.send_to(&bytes, addr) {
-                                eprintln!("Log record can't be sent to fluentd: {}", e);
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Can't receive new log record: {}", e);
-                            break;
+    pub(crate) fn new(
+        addr: SocketAddr,
+        capacity: usize,
+        max_datagram_size: usize,
+        batching: Option<Batching>,
+    ) -> Self {
+        let (sender, stopped) = spawn_sender(addr, capacity, batching, DestinationRole::Records);
+
+        Self {
+            writer: WriterImpl {
+                sender,
+                error_sender: None,
+                max_datagram_size,
+                wire_format: WireFormat::Json,
+                error: false,
+            },
+            error_sender: None,
+            metric_sender: None,
+            capacity,
+            batching,
+            stopped: Stopped(Mutex::new(vec![stopped])),
+        }
+    }
+
+    pub(crate) fn with_wire_format(mut self, wire_format: WireFormat) -> Self {
+        self.writer.wire_format = wire_format;
+        self
+    }
+
+    /// Also sends records of `level` and above to `addr`, through a socket
+    /// and a thread of its own so a slow or failing destination doesn't
+    /// affect the other one.
+    pub(crate) fn with_error_destination(mut self, addr: SocketAddr, level: Level) -> Self {
+        let (error_sender, stopped) =
+            spawn_sender(addr, self.capacity, self.batching, DestinationRole::Errors);
+        self.error_sender = Some((level, error_sender));
+        self.stopped.0.get_mut().push(stopped);
+        self
+    }
+
+    /// Sends records of `target` to `addr` instead, through a socket and a
+    /// thread of their own too
+    pub(crate) fn with_metric_destination(
+        mut self,
+        addr: SocketAddr,
+        target: &'static str,
+    ) -> Self {
+        let (metric_sender, stopped) =
+            spawn_sender(addr, self.capacity, self.batching, DestinationRole::Metrics);
+        self.metric_sender = Some((target, metric_sender));
+        self.stopped.0.get_mut().push(stopped);
+        self
+    }
+}
+
+/// Waits for sender threads to stop when dropped. `Receiver` isn't `Sync`,
+/// hence the mutex.
+struct Stopped(Mutex<Vec<Receiver<()>>>);
+
+impl Drop for Stopped {
+    fn drop(&mut self) {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        for stopped in self.0.get_mut().iter() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(timeout) {
+                return;
+            }
+        }
+    }
+}
+
+/// Queue of the thread sending records to a destination
+#[derive(Debug)]
+struct Queue {
+    addr: SocketAddr,
+    sender: Mutex<SyncSender<Bytes>>,
+    progress: Arc<Progress>,
+    stats: Arc<Destination>,
+}
+
+/// Spawns the thread sending records queued on the returned queue, which
+/// holds up to `capacity` records. The returned receiver disconnects once the
+/// thread stops, which it does when every sender is dropped and the queue is
+/// drained.
+///
+/// Destinations of the target's records report their first delivery, see
+/// [`first_delivery`](crate::first_delivery).
+fn spawn_sender(
+    addr: SocketAddr,
+    capacity: usize,
+    batching: Option<Batching>,
+    role: DestinationRole,
+) -> (Arc<Queue>, Receiver<()>) {
+    // A capacity of 0 would make every write wait for the thread
+    let (sender, receiver) = sync_channel::<Bytes>(capacity.max(1));
+    let (stopping, stopped) = channel::<()>();
+    let progress = Progress::register();
+    let stats = Destination::register(format!("udp://{}", addr), role);
+    let primary = role == DestinationRole::Records;
+
+    let thread_progress = progress.clone();
+    let thread_stats = stats.clone();
+    let _ = ::std::thread::spawn(move || {
+        let _stopping = stopping;
+        let progress = thread_progress;
+        #[cfg(test)]
+        tests::wait_while_stalled(addr);
+        // Any local address of the destination's family, so remote hosts
+        // are reachable too
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        match UdpSocket::bind(local) {
+            // Ends once every sender is dropped, after the queue is drained
+            Ok(socket) => {
+                let mut socket = Socket {
+                    socket,
+                    addr,
+                    stats: thread_stats,
+                    delivered: !primary,
+                };
+                match batching {
+                    Some(batching) => socket.send_in_batches(receiver, batching, &progress),
+                    None => {
+                        #[cfg(all(feature = "sendmmsg", target_os = "linux"))]
+                        socket.send_batched(receiver, &progress);
+                        #[cfg(not(all(feature = "sendmmsg", target_os = "linux")))]
+                        for bytes in receiver {
+                            socket.send(&bytes);
+                            progress.done(1);
                         }
                     }
-                },
-                Err(e) => {
-                    eprintln!("Couldn't bind to UDP socket: {}", e);
                 }
+            }
+            Err(e) => {
+                thread_stats.send_failed();
+                vinted_diagnostics::report(
+                    Diagnostic::BindFailed,
+                    format_args!("Couldn't bind to UDP socket: {}", e),
+                );
+                if primary {
+                    vinted_delivery::failed(format!("couldn't bind to UDP socket: {}", e));
+                }
+                vinted_events::emit(LoggerEvent::Disconnected {
+                    addr,
+                    error: format!("couldn't bind to UDP socket: {}", e),
+                });
+                for bytes in receiver {
+                    vinted_fallback::undelivered(&bytes);
+                    progress.done(1);
+                }
+            }
+        };
+        progress.stopped();
+    });
+
+    (
+        Arc::new(Queue {
+            addr,
+            sender: Mutex::new(sender),
+            progress,
+            stats,
+        }),
+        stopped,
+    )
+}
+
+/// Socket of a sender thread
+struct Socket {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    stats: Arc<Destination>,
+    /// Whether a record was delivered already, or doesn't need to be
+    /// reported
+    delivered: bool,
+}
+
+impl Socket {
+    fn send(&mut self, bytes: &[u8]) {
+        self.send_records(bytes, 1, bytes);
+    }
+
+    /// Sends a datagram of `records` records, which are `undelivered` if it
+    /// fails
+    fn send_records(&mut self, bytes: &[u8], records: u64, undelivered: &[u8]) {
+        match self.socket.send_to(bytes, self.addr) {
+            Ok(_) => {
+                self.stats.sent(records);
+                self.delivered();
+            }
+            Err(e) => {
+                self.stats.send_failed();
+                vinted_diagnostics::report(
+                    Diagnostic::SendFailed,
+                    format_args!("Log record can't be sent to fluentd: {}", e),
+                );
+                vinted_fallback::undelivered(undelivered);
+            }
+        }
+    }
+
+    fn delivered(&mut self) {
+        if !self.delivered {
+            self.delivered = true;
+            vinted_delivery::delivered();
+        }
+    }
+
+    /// Sends queued records together, as a datagram once it would grow beyond
+    /// `max_bytes` or once its first record waited for `interval`, or the drain
+    /// batch interval while draining. A record larger than `max_bytes` goes in a
+    /// datagram of its own.
+    ///
+    /// Records end with a newline already, so datagrams are newline delimited,
+    /// unless they're sent in key table containers.
+    fn send_in_batches(
+        &mut self,
+        receiver: Receiver<Bytes>,
+        batching: Batching,
+        progress: &Progress,
+    ) {
+        let mut batch = Vec::with_capacity(batching.max_bytes);
+        let mut records = 0;
+        // When the first record of the batch was queued
+        let mut started: Option<Instant> = None;
+        let mut send_batch = |batch: &mut Vec<u8>, records: &mut u64| {
+            if *records > 0 {
+                let encoded = match batching.key_table {
+                    true => vinted_dict::encode(batch),
+                    false => None,
+                };
+                let datagram = encoded.as_deref().unwrap_or(batch);
+                self.send_records(datagram, *records, batch);
+                progress.done(*records);
+            }
+            batch.clear();
+            *records = 0;
+        };
+        loop {
+            // Checked again on every record, which shortens it once draining
+            // starts
+            let next = match started {
+                Some(started) => {
+                    let deadline = started + vinted_drain::batch_interval(batching.interval);
+                    receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
             };
-        });
+            match next {
+                Ok(bytes) => {
+                    if batch.len() + bytes.len() > batching.max_bytes {
+                        send_batch(&mut batch, &mut records);
+                    }
+                    if records == 0 {
+                        started = Some(Instant::now());
+                    }
+                    batch.extend_from_slice(&bytes);
+                    records += 1;
+                    if batch.len() >= batching.max_bytes {
+                        send_batch(&mut batch, &mut records);
+                        started = None;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    send_batch(&mut batch, &mut records);
+                    started = None;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    send_batch(&mut batch, &mut records);
+                    return;
+                }
+            }
+        }
+    }
 
-        Self {
-            writer: WriterImpl(Arc::new(Mutex::new(sender))),
+    /// Sends whatever is queued, up to [`MAX_BATCH`](vinted_sendmmsg::MAX_BATCH)
+    /// records at a time, with a single `sendmmsg(2)` call. Records left over by
+    /// an error go through [`send`](Self::send) one by one, which reports them
+    /// if they fail again.
+    #[cfg(all(feature = "sendmmsg", target_os = "linux"))]
+    fn send_batched(&mut self, receiver: Receiver<Bytes>, progress: &Progress) {
+        let mut batch = Vec::with_capacity(vinted_sendmmsg::MAX_BATCH);
+        while let Ok(bytes) = receiver.recv() {
+            batch.push(bytes);
+            batch.extend(receiver.try_iter().take(vinted_sendmmsg::MAX_BATCH - 1));
+
+            let sent = match vinted_sendmmsg::send_all(&self.socket, self.addr, &batch) {
+                Ok(()) => batch.len(),
+                Err((sent, _)) => sent,
+            };
+            if sent > 0 {
+                self.stats.sent(sent as u64);
+                self.delivered();
+            }
+            for bytes in &batch[sent..] {
+                self.send(bytes);
+            }
+            progress.done(batch.len() as u64);
+            batch.clear();
         }
     }
 }
 
-impl MakeWriter for VintedUdpWriter {
+impl<'a> MakeWriter<'a> for VintedUdpWriter {
     type Writer = WriterImpl;
 
-    fn make_writer(&self) -> Self::Writer {
+    fn make_writer(&'a self) -> Self::Writer {
         self.writer.clone()
     }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        let sender = match self.metric_sender {
+            Some((target, ref metric_sender)) if meta.target() == target => metric_sender.clone(),
+            _ => self.writer.sender.clone(),
+        };
+        let error_sender = match self.error_sender {
+            Some((level, ref error_sender)) if *meta.level() <= level => Some(error_sender.clone()),
+            _ => None,
+        };
+
+        WriterImpl {
+            sender,
+            error_sender,
+            max_datagram_size: self.writer.max_datagram_size,
+            wire_format: self.writer.wire_format,
+            error: *meta.level() == Level::ERROR,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct WriterImpl(Arc<Mutex<Sender<Bytes>>>);
+pub(crate) struct WriterImpl {
+    sender: Arc<Queue>,
+    error_sender: Option<Arc<Queue>>,
+    max_datagram_size: usize,
+    wire_format: WireFormat,
+    /// Whether the record is an ERROR one
+    error: bool,
+}
+
+impl WriterImpl {
+    fn json_datagram(&self, record: &[u8]) -> Option<Vec<Bytes>> {
+        if record.len() <= self.max_datagram_size {
+            return Some(vec![Bytes::from(record.to_owned())]);
+        }
+        match truncate(record, self.max_datagram_size) {
+            Some(truncated) => Some(vec![Bytes::from(truncated)]),
+            None => {
+                vinted_diagnostics::report(
+                    Diagnostic::Dropped,
+                    format_args!(
+                        "Log record of {} bytes doesn't fit a {} bytes datagram, dropped it",
+                        record.len(),
+                        self.max_datagram_size
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    /// The record as a GELF message, in chunks if it's larger than a datagram
+    fn gelf_datagrams(&self, record: &[u8]) -> Option<Vec<Bytes>> {
+        let message = vinted_gelf::convert(record)?;
+        if message.len() <= self.max_datagram_size {
+            return Some(vec![Bytes::from(message)]);
+        }
+        match vinted_gelf::chunks(&message, self.max_datagram_size) {
+            Some(chunks) => Some(chunks.into_iter().map(Bytes::from).collect()),
+            None => {
+                vinted_diagnostics::report(
+                    Diagnostic::Dropped,
+                    format_args!(
+                        "GELF message of {} bytes takes more than {} chunks of {} bytes, dropped it",
+                        message.len(),
+                        vinted_gelf::GELF_MAX_CHUNKS,
+                        self.max_datagram_size
+                    ),
+                );
+                None
+            }
+        }
+    }
+}
 
 impl io::Write for WriterImpl {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let _ = self.0.lock().send(Bytes::from(buf.to_owned()));
+        let datagrams = match self.wire_format {
+            WireFormat::Json | WireFormat::JsonDict => self.json_datagram(buf),
+            WireFormat::Gelf => self.gelf_datagrams(buf),
+        };
+        // Chunks are queued one by one, a full queue may drop some of them,
+        // which Graylog then discards with the rest of the message
+        for bytes in datagrams.unwrap_or_default() {
+            if let Some(ref error_sender) = self.error_sender {
+                queue(error_sender, bytes.clone(), self.error);
+            }
+            queue(&self.sender, bytes, self.error);
+        }
 
         Ok(buf.len())
     }
@@ -67,3 +516,383 @@ impl io::Write for WriterImpl {
         Ok(())
     }
 }
+
+/// Shortens the `message` of `record` so it takes at most `max_size` bytes,
+/// newline included, and marks it `"truncated": true`. The record is
+/// parsed and serialized again, so it's still valid JSON and the message is
+/// only ever cut between characters.
+///
+/// Returns `None` for records which don't fit even without a message, or
+/// which aren't JSON objects.
+pub(crate) fn truncate(record: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    let mut record: Entries = serde_json::from_slice(record).ok()?;
+    record.0.retain(|(key, _)| key != TRUNCATED_KEY);
+    record.0.push((TRUNCATED_KEY.to_owned(), Value::Bool(true)));
+
+    let message_index = record.0.iter().position(|(key, _)| key == "message");
+    let message = match message_index.map(|index| &mut record.0[index].1) {
+        Some(Value::String(message)) => std::mem::take(message),
+        _ => String::new(),
+    };
+
+    // The record without a message leaves this much of the datagram to it
+    let mut truncated = serde_json::to_vec(&record).ok()?;
+    truncated.push(b'\n');
+    let budget = max_size.checked_sub(truncated.len())?;
+    let index = match message_index {
+        Some(index) if budget > 0 => index,
+        _ => return Some(truncated),
+    };
+
+    let mut len = 0;
+    let mut escaped = 0;
+    for (at, c) in message.char_indices() {
+        escaped += escaped_len(c);
+        if escaped > budget {
+            break;
+        }
+        len = at + c.len_utf8();
+    }
+    record.0[index].1 = Value::String(message[..len].to_owned());
+
+    truncated = serde_json::to_vec(&record).ok()?;
+    truncated.push(b'\n');
+    Some(truncated)
+}
+
+/// Bytes `c` takes in a JSON string as `serde_json` escapes it
+fn escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\u{8}' | '\u{c}' | '\n' | '\r' | '\t' => 2,
+        '\0'..='\u{1f}' => 6,
+        c => c.len_utf8(),
+    }
+}
+
+/// Drops the record when the queue is full. Queueing fails otherwise only
+/// once the thread stopped, which it does on its own when the socket couldn't
+/// be bound.
+fn queue(queue: &Queue, bytes: Bytes, error: bool) {
+    queue.progress.queued(error);
+    let result = queue.sender.lock().try_send(bytes);
+    if result.is_err() {
+        queue.progress.not_queued();
+    }
+
+    match result {
+        Ok(()) => queue.stats.enqueued(),
+        Err(TrySendError::Full(_)) => {
+            queue.stats.dropped();
+            vinted_diagnostics::report(
+                Diagnostic::Dropped,
+                format_args!("UDP queue of {} is full, dropped a log record", queue.addr),
+            );
+        }
+        Err(TrySendError::Disconnected(_)) => vinted_diagnostics::report(
+            Diagnostic::ChannelClosed,
+            format_args!(
+                "Log record can't be queued, the UDP sender thread of {} stopped",
+                queue.addr
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spawn_sender, VintedUdpWriter, SHUTDOWN_TIMEOUT};
+    use crate::{
+        destination_stats, test_support::with_logger, DestinationRole, DestinationStats,
+        LogDestination, Target,
+    };
+    use bytes::Bytes;
+    use parking_lot::{const_mutex, Mutex};
+    use std::{
+        io::Write,
+        net::{SocketAddr, UdpSocket},
+        sync::mpsc::RecvTimeoutError,
+        thread,
+        time::{Duration, Instant},
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// Destinations whose sender thread waits before sending anything, as if
+    /// sending to them blocked
+    static STALLED: Mutex<Vec<SocketAddr>> = const_mutex(Vec::new());
+
+    pub(super) fn wait_while_stalled(addr: SocketAddr) {
+        while STALLED.lock().contains(&addr) {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn collector() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    fn received(collector: &UdpSocket, count: usize) -> Vec<String> {
+        let mut datagram = vec![0; 65_536];
+        (0..count)
+            .map(|_| {
+                let len = collector.recv(&mut datagram).expect("record is received");
+                String::from_utf8(datagram[..len].to_vec()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_keep_flowing_while_writers_come_and_go() {
+        let collector = collector();
+        let writer = VintedUdpWriter::new(collector.local_addr().unwrap(), 64, 8_192, None);
+
+        for n in 0..20 {
+            // Every record through a writer of its own, dropped right after
+            let mut record = writer.make_writer();
+            record
+                .write_all(format!("{{\"n\":{}}}\n", n).as_bytes())
+                .unwrap();
+            if n % 5 == 0 {
+                // Long pauses without any writer alive
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        }
+
+        let expected: Vec<_> = (0..20).map(|n| format!("{{\"n\":{}}}\n", n)).collect();
+        assert_eq!(received(&collector, 20), expected);
+    }
+
+    #[test]
+    fn dropping_the_writer_sends_queued_records_and_stops() {
+        let collector = collector();
+        let writer = VintedUdpWriter::new(collector.local_addr().unwrap(), 256, 8_192, None);
+        let mut record = writer.make_writer();
+        for n in 0..100 {
+            record
+                .write_all(format!("{{\"n\":{}}}\n", n).as_bytes())
+                .unwrap();
+        }
+        drop(record);
+
+        let start = Instant::now();
+        drop(writer);
+        assert!(start.elapsed() < SHUTDOWN_TIMEOUT, "{:?}", start.elapsed());
+        assert_eq!(received(&collector, 100).len(), 100);
+    }
+
+    #[test]
+    fn sender_threads_end_with_their_queue() {
+        let collector = collector();
+        let (queue, stopped) = spawn_sender(
+            collector.local_addr().unwrap(),
+            8,
+            None,
+            DestinationRole::Errors,
+        );
+        queue
+            .sender
+            .lock()
+            .send(Bytes::from_static(b"{}\n"))
+            .unwrap();
+        assert_eq!(
+            stopped.recv_timeout(Duration::from_millis(100)),
+            Err(RecvTimeoutError::Timeout),
+            "the thread waits for more records"
+        );
+
+        drop(queue);
+        assert_eq!(
+            stopped.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected),
+            "the thread stopped"
+        );
+        assert_eq!(received(&collector, 1), ["{}\n"]);
+    }
+
+    #[test]
+    fn huge_messages_are_cut_to_one_datagram() {
+        const MAX_DATAGRAM: usize = 8_192;
+        let collector = collector();
+        let builder = crate::builder("svc-test", crate::Target::UdpJson)
+            .destination(collector.local_addr().unwrap())
+            .max_datagram_size(MAX_DATAGRAM);
+
+        let message = "\u{e9}\"".repeat(1024 * 1024 / 3);
+        crate::test_support::with_logger(builder, || {
+            tracing::info!(user_id = 7, "{}", message);
+            tracing::info!("next");
+            crate::flush(Duration::from_secs(5));
+        });
+
+        let datagrams = received(&collector, 2);
+        let record: serde_json::Value = serde_json::from_str(&datagrams[0]).unwrap();
+        assert!(
+            datagrams[0].len() <= MAX_DATAGRAM,
+            "{} bytes",
+            datagrams[0].len()
+        );
+        assert!(
+            datagrams[0].len() > MAX_DATAGRAM - 16,
+            "the message takes what's left"
+        );
+        assert!(datagrams[0].ends_with('\n'));
+        assert_eq!(record[super::TRUNCATED_KEY], true);
+        assert_eq!(record["user_id"], 7);
+        let cut = record["message"].as_str().unwrap();
+        assert!(message.starts_with(cut) && !cut.is_empty());
+        assert!(
+            datagrams[1].contains("\"message\":\"next\""),
+            "{}",
+            datagrams[1]
+        );
+    }
+
+    #[test]
+    fn records_too_large_without_a_message_are_dropped() {
+        let record = format!("{{\"message\":\"hi\",\"pad\":\"{}\"}}\n", "x".repeat(200));
+
+        assert_eq!(super::truncate(record.as_bytes(), 100), None);
+        // 241 bytes without a message, one left for it
+        let truncated = super::truncate(record.as_bytes(), 242).unwrap();
+        assert_eq!(truncated.len(), 242);
+        let truncated: serde_json::Value = serde_json::from_slice(&truncated).unwrap();
+        assert_eq!(truncated["message"], "h");
+        assert_eq!(super::truncate(b"[1]\n", 100), None, "not an object");
+    }
+
+    #[test]
+    fn batches_never_split_records() {
+        const MAX_BYTES: usize = 1_000;
+        let collector = collector();
+        let batching = super::Batching {
+            max_bytes: MAX_BYTES,
+            interval: Duration::from_millis(50),
+            key_table: false,
+        };
+        let writer =
+            VintedUdpWriter::new(collector.local_addr().unwrap(), 256, 8_192, Some(batching));
+        // One record larger than a batch on its own, amid others of all sizes
+        let records: Vec<_> = (0..100)
+            .map(|n| {
+                let pad = if n == 50 { 2_000 } else { n * 37 % 400 };
+                format!("{{\"n\":{},\"pad\":\"{}\"}}\n", n, "x".repeat(pad))
+            })
+            .collect();
+        let mut record = writer.make_writer();
+        for line in &records {
+            record.write_all(line.as_bytes()).unwrap();
+        }
+        drop(record);
+
+        let mut datagram = vec![0; 65_536];
+        let mut lines = Vec::new();
+        let mut datagrams = 0;
+        while lines.len() < records.len() {
+            let len = collector.recv(&mut datagram).expect("batch is received");
+            let batch = std::str::from_utf8(&datagram[..len]).unwrap();
+            let batch_lines: Vec<_> = batch.split_inclusive('\n').collect();
+            assert!(
+                len <= MAX_BYTES || batch_lines.len() == 1,
+                "{} bytes in {} records",
+                len,
+                batch_lines.len()
+            );
+            assert!(batch.ends_with('\n'));
+            lines.extend(batch_lines.into_iter().map(str::to_owned));
+            datagrams += 1;
+        }
+        assert_eq!(lines, records);
+        assert!(datagrams < records.len() / 2, "{} datagrams", datagrams);
+    }
+
+    #[test]
+    fn a_lone_record_is_sent_after_the_batch_interval() {
+        let collector = collector();
+        let batching = super::Batching {
+            max_bytes: 8_000,
+            interval: Duration::from_millis(50),
+            key_table: false,
+        };
+        let writer =
+            VintedUdpWriter::new(collector.local_addr().unwrap(), 16, 8_192, Some(batching));
+        let start = Instant::now();
+        writer.make_writer().write_all(b"{\"n\":1}\n").unwrap();
+        assert_eq!(received(&collector, 1), ["{\"n\":1}\n"]);
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+
+    #[test]
+    fn a_stalled_destination_only_fills_its_own_queue() {
+        const CAPACITY: usize = 8;
+        const RECORDS: usize = 40;
+        let primary = collector();
+        let errors = collector();
+        let errors_addr = errors.local_addr().unwrap();
+        STALLED.lock().push(errors_addr);
+
+        let builder = crate::builder("svc-test", Target::UdpJson)
+            .destination(primary.local_addr().unwrap())
+            .error_destination(LogDestination::Udp(errors_addr.to_string()))
+            .udp_buffer(CAPACITY);
+        with_logger(builder, || {
+            let started = Instant::now();
+            for n in 0..RECORDS {
+                tracing::error!(n, "stalled");
+                // Keeps the primary queue from filling up
+                if n % 4 == 3 {
+                    thread::sleep(Duration::from_millis(2));
+                }
+            }
+            assert!(started.elapsed() < Duration::from_secs(2));
+
+            // The primary destination gets every record on time
+            let records = received(&primary, RECORDS);
+            for (n, record) in records.iter().enumerate() {
+                assert!(record.contains(&format!("\"n\":{},", n)), "{}", record);
+            }
+            assert!(started.elapsed() < Duration::from_secs(2));
+
+            // The stalled one gets what its queue kept, and drops the rest
+            STALLED.lock().retain(|&addr| addr != errors_addr);
+            let kept = received(&errors, CAPACITY);
+            for (n, record) in kept.iter().enumerate() {
+                assert!(record.contains(&format!("\"n\":{},", n)), "{}", record);
+            }
+            errors
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            let mut datagram = [0; 65_536];
+            assert!(errors.recv(&mut datagram).is_err(), "the rest is dropped");
+
+            // Each destination counts its own records
+            let stats = |addr: SocketAddr| -> DestinationStats {
+                destination_stats()
+                    .into_iter()
+                    .find(|stats| stats.destination == format!("udp://{}", addr))
+                    .expect("destination is reported")
+            };
+            let (primary, errors) = (stats(primary.local_addr().unwrap()), stats(errors_addr));
+            assert_eq!(primary.role, DestinationRole::Records);
+            assert_eq!(
+                (primary.enqueued, primary.sent, primary.dropped),
+                (RECORDS as u64, RECORDS as u64, 0)
+            );
+            assert_eq!(errors.role, DestinationRole::Errors);
+            assert_eq!(
+                (errors.enqueued, errors.sent, errors.dropped),
+                (
+                    CAPACITY as u64,
+                    CAPACITY as u64,
+                    (RECORDS - CAPACITY) as u64
+                )
+            );
+        });
+    }
+}
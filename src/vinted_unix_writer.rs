@@ -0,0 +1,364 @@
+use crate::{
+    vinted_delivery,
+    vinted_diagnostics::{self, Diagnostic},
+    vinted_fallback,
+    vinted_flush::Progress,
+    vinted_stats::{self, Destination, DestinationRole},
+    vinted_udp_writer,
+};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::{
+    io,
+    os::unix::net::UnixDatagram,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, sync_channel, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Default socket of [`Target::UnixJson`](crate::Target::UnixJson), the one
+/// of the local fluent-bit agent
+pub const DEFAULT_UNIX_SOCKET: &str = "/run/fluent/fluent.sock";
+
+/// How long dropping a `VintedUnixWriter` waits for queued records to be sent
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Sends records as datagrams to a unix socket from a background thread,
+/// queueing up to a number of records beyond which new ones are dropped,
+/// the same as [`VintedUdpWriter`](crate::vinted_udp_writer::VintedUdpWriter).
+///
+/// The socket may not exist yet, e.g. while the agent starts after the
+/// service, or go away while it restarts: the thread then connects again
+/// with exponential backoff, holding records back meanwhile, and reports
+/// the outage once. Dropping the `VintedUnixWriter` stops the thread once
+/// every record queued until then is sent, waiting up to
+/// [`SHUTDOWN_TIMEOUT`].
+pub(crate) struct VintedUnixWriter {
+    writer: WriterImpl,
+    // Declared last so it's dropped after the writer, which is what lets
+    // the thread stop
+    _stopped: Stopped,
+}
+
+impl VintedUnixWriter {
+    pub(crate) fn new(path: PathBuf, capacity: usize, max_datagram_size: usize) -> Self {
+        // A capacity of 0 would make every write wait for the thread
+        let (sender, receiver) = sync_channel::<Bytes>(capacity.max(1));
+        let (stopping, stopped) = channel::<()>();
+        let progress = Progress::register();
+        let stats = Destination::register(
+            format!("unix://{}", path.display()),
+            DestinationRole::Records,
+        );
+
+        let thread_progress = progress.clone();
+        let thread_stats = stats.clone();
+        let thread_path = path.clone();
+        let _ = thread::spawn(move || {
+            let _stopping = stopping;
+            send_loop(&thread_path, receiver, &thread_progress, &thread_stats);
+            thread_progress.stopped();
+        });
+
+        Self {
+            writer: WriterImpl {
+                queue: Arc::new(Queue {
+                    path,
+                    sender: Mutex::new(sender),
+                    progress,
+                    stats,
+                }),
+                max_datagram_size,
+                error: false,
+            },
+            _stopped: Stopped(Mutex::new(stopped)),
+        }
+    }
+}
+
+/// Waits for the sender thread to stop when dropped. `Receiver` isn't
+/// `Sync`, hence the mutex.
+struct Stopped(Mutex<Receiver<()>>);
+
+impl Drop for Stopped {
+    fn drop(&mut self) {
+        let _ = self.0.get_mut().recv_timeout(SHUTDOWN_TIMEOUT);
+    }
+}
+
+/// Queue of the thread sending records to the socket
+#[derive(Debug)]
+struct Queue {
+    path: PathBuf,
+    sender: Mutex<SyncSender<Bytes>>,
+    progress: Arc<Progress>,
+    stats: Arc<Destination>,
+}
+
+/// Sends queued records until every sender is dropped and the queue is
+/// drained. A record which couldn't be sent because the socket is missing
+/// or nobody reads it is sent again once connected, others which fail are
+/// reported and written to stderr instead.
+fn send_loop(path: &Path, receiver: Receiver<Bytes>, progress: &Progress, stats: &Destination) {
+    let mut socket: Option<UnixDatagram> = None;
+    let mut backoff = MIN_BACKOFF;
+    let mut delivered = false;
+    // Whether the last attempt connected, outages are only reported once
+    let mut was_connected = None;
+
+    for bytes in receiver {
+        loop {
+            let connected = match socket {
+                Some(ref connected) => connected,
+                None => match connect(path) {
+                    Ok(connected) => {
+                        backoff = MIN_BACKOFF;
+                        was_connected = Some(true);
+                        socket.get_or_insert(connected)
+                    }
+                    Err(e) => {
+                        stats.send_failed();
+                        if was_connected != Some(false) {
+                            was_connected = Some(false);
+                            vinted_diagnostics::report(
+                                Diagnostic::SendFailed,
+                                format_args!(
+                                    "Couldn't connect to the log socket {}, retrying: {}",
+                                    path.display(),
+                                    e
+                                ),
+                            );
+                        }
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        vinted_stats::reconnecting();
+                        continue;
+                    }
+                },
+            };
+
+            match connected.send(&bytes) {
+                Ok(_) => {
+                    stats.sent(1);
+                    if !delivered {
+                        delivered = true;
+                        vinted_delivery::delivered();
+                    }
+                }
+                Err(ref e) if is_disconnected(e) => {
+                    // The agent went away, e.g. to restart
+                    socket = None;
+                    continue;
+                }
+                Err(e) => {
+                    stats.send_failed();
+                    vinted_diagnostics::report(
+                        Diagnostic::SendFailed,
+                        format_args!("Log record can't be sent to {}: {}", path.display(), e),
+                    );
+                    vinted_fallback::undelivered(&bytes);
+                }
+            }
+            break;
+        }
+        progress.done(1);
+    }
+}
+
+fn connect(path: &Path) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+/// Errors of a socket whose reader is gone, or never was there
+fn is_disconnected(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused | io::ErrorKind::NotConnected
+    )
+}
+
+impl<'a> MakeWriter<'a> for VintedUnixWriter {
+    type Writer = WriterImpl;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer.clone()
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        WriterImpl {
+            error: *meta.level() == Level::ERROR,
+            ..self.writer.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct WriterImpl {
+    queue: Arc<Queue>,
+    max_datagram_size: usize,
+    /// Whether the record is an ERROR one
+    error: bool,
+}
+
+impl io::Write for WriterImpl {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = if buf.len() <= self.max_datagram_size {
+            Bytes::from(buf.to_owned())
+        } else {
+            match vinted_udp_writer::truncate(buf, self.max_datagram_size) {
+                Some(truncated) => Bytes::from(truncated),
+                None => {
+                    vinted_diagnostics::report(
+                        Diagnostic::Dropped,
+                        format_args!(
+                            "Log record of {} bytes doesn't fit a {} bytes datagram, dropped it",
+                            buf.len(),
+                            self.max_datagram_size
+                        ),
+                    );
+                    return Ok(buf.len());
+                }
+            }
+        };
+
+        let queue = &self.queue;
+        queue.progress.queued(self.error);
+        let result = queue.sender.lock().try_send(bytes);
+        if result.is_err() {
+            queue.progress.not_queued();
+        }
+        match result {
+            Ok(()) => queue.stats.enqueued(),
+            Err(TrySendError::Full(_)) => {
+                queue.stats.dropped();
+                vinted_diagnostics::report(
+                    Diagnostic::Dropped,
+                    format_args!(
+                        "Queue of the log socket {} is full, dropped a log record",
+                        queue.path.display()
+                    ),
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => vinted_diagnostics::report(
+                Diagnostic::ChannelClosed,
+                format_args!(
+                    "Log record can't be queued, the sender thread of {} stopped",
+                    queue.path.display()
+                ),
+            ),
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VintedUnixWriter;
+    use crate::{test_support::with_logger, LogDestination, Target};
+    use serde_json::Value;
+    use std::{
+        io::Write, net::UdpSocket, os::unix::net::UnixDatagram, path::PathBuf, time::Duration,
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// Socket standing in for the agent, at a path of its own
+    fn agent(name: &str) -> (UnixDatagram, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "vinted-logger-{}-{}.sock",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (socket, path)
+    }
+
+    fn recv(socket: &UnixDatagram) -> Vec<u8> {
+        let mut datagram = vec![0; 65_536];
+        let len = socket.recv(&mut datagram).expect("datagram is received");
+        datagram.truncate(len);
+        datagram
+    }
+
+    /// Record logged by `builder`, without the keys which change between
+    /// two records
+    fn logged(builder: crate::LoggerBuilder, recv: impl FnOnce() -> Vec<u8>) -> Value {
+        with_logger(builder, || {
+            tracing::info!(user_id = 7, ok = true, "signed in");
+            crate::flush(Duration::from_secs(5));
+        });
+        let datagram = recv();
+        assert!(datagram.ends_with(b"\n"), "{:?}", datagram);
+        let mut record: Value = serde_json::from_slice(&datagram).unwrap();
+        for key in ["@timestamp", "event_seq", "line", "thread_id"] {
+            record.as_object_mut().unwrap().remove(key);
+        }
+        record
+    }
+
+    #[test]
+    fn records_are_the_ones_sent_over_udp() {
+        let (agent, path) = agent("round-trip");
+        let unix = logged(
+            crate::builder("svc-test", Target::UnixJson)
+                .destination(LogDestination::Unix(path.clone())),
+            || recv(&agent),
+        );
+
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let udp = logged(
+            crate::builder("svc-test", Target::UdpJson)
+                .destination(collector.local_addr().unwrap()),
+            || {
+                let mut datagram = vec![0; 65_536];
+                let len = collector.recv(&mut datagram).unwrap();
+                datagram.truncate(len);
+                datagram
+            },
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(unix["message"], "signed in");
+        assert_eq!(unix["user_id"], 7);
+        assert_eq!(unix, udp);
+    }
+
+    #[test]
+    fn huge_records_are_cut_to_one_datagram() {
+        const MAX_DATAGRAM: usize = 8_192;
+        let (agent, path) = agent("huge");
+        let writer = VintedUnixWriter::new(path.clone(), 16, MAX_DATAGRAM);
+        let record = format!("{{\"message\":\"{}\",\"n\":1}}\n", "x".repeat(20_000));
+        writer.make_writer().write_all(record.as_bytes()).unwrap();
+        writer.make_writer().write_all(b"{\"n\":2}\n").unwrap();
+
+        let cut = recv(&agent);
+        assert!(cut.len() <= MAX_DATAGRAM, "{} bytes", cut.len());
+        let cut: Value = serde_json::from_slice(&cut).unwrap();
+        assert_eq!(cut["n"], 1);
+        assert_eq!(recv(&agent), b"{\"n\":2}\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
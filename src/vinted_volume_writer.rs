@@ -0,0 +1,236 @@
+use parking_lot::{const_mutex, Mutex};
+use std::{
+    collections::HashMap,
+    io,
+    time::{Duration, Instant},
+};
+use tracing_core::Metadata;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Number of target prefixes tracked by [`LoggerBuilder::track_volume`](crate::LoggerBuilder::track_volume)
+pub const TRACKED_TARGETS: usize = 64;
+
+/// Key of the array of the heaviest prefixes in canary records
+pub(crate) const KEY: &str = "volume_top";
+
+/// Prefixes in the `volume_top` of canary records
+pub(crate) const HEARTBEAT_TOP: usize = 10;
+
+/// Counts are halved this often so the report follows current traffic
+const DECAY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+static VOLUME: Mutex<Option<Volume>> = const_mutex(None);
+
+/// Target prefixes, e.g. `svc_search::db`, which serialized the most bytes
+/// recently, most first. Empty unless volume tracking is enabled.
+///
+/// Counts are approximate once more than [`TRACKED_TARGETS`] prefixes log:
+/// a new prefix takes over the count of the least logging one, so counts
+/// can be overestimated but heavy prefixes are never missed.
+///
+/// ```
+/// for (prefix, bytes) in vinted_logger::top_targets_by_bytes(10) {
+///     println!("{}: {} bytes", prefix, bytes);
+/// }
+/// ```
+pub fn top_targets_by_bytes(n: usize) -> Vec<(String, u64)> {
+    VOLUME
+        .lock()
+        .as_ref()
+        .map_or_else(Vec::new, |volume| volume.top(n))
+}
+
+/// `volume_top` of canary records, objects of `target` and `bytes`, most
+/// first
+pub(crate) fn heartbeat_top() -> serde_json::Value {
+    top_targets_by_bytes(HEARTBEAT_TOP)
+        .into_iter()
+        .map(|(target, bytes)| serde_json::json!({"target": target, "bytes": bytes}))
+        .collect()
+}
+
+/// Wraps a `MakeWriter` and counts the bytes written per target prefix.
+pub(crate) struct VintedVolumeWriter<W> {
+    inner: W,
+    enabled: bool,
+}
+
+impl<W> VintedVolumeWriter<W> {
+    /// `enabled` of `false` passes every record through uncounted.
+    pub(crate) fn new(inner: W, enabled: bool) -> Self {
+        if enabled {
+            VOLUME.lock().get_or_insert_with(Volume::new);
+        }
+
+        Self { inner, enabled }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for VintedVolumeWriter<W> {
+    type Writer = VolumeWriterImpl<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        VolumeWriterImpl {
+            inner: self.inner.make_writer(),
+            prefix: None,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        VolumeWriterImpl {
+            inner: self.inner.make_writer_for(meta),
+            prefix: if self.enabled {
+                Some(prefix(meta.target()).to_owned())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// First two path segments of a target
+fn prefix(target: &str) -> &str {
+    match target.match_indices("::").nth(1) {
+        Some((at, _)) => &target[..at],
+        None => target,
+    }
+}
+
+pub(crate) struct VolumeWriterImpl<W> {
+    inner: W,
+    prefix: Option<String>,
+}
+
+impl<W: io::Write> io::Write for VolumeWriterImpl<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let (Some(prefix), Some(volume)) = (&self.prefix, VOLUME.lock().as_mut()) {
+            volume.add(prefix, written as u64);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Space-saving top-K counter
+struct Volume {
+    bytes: HashMap<String, u64>,
+    decayed: Instant,
+}
+
+impl Volume {
+    fn new() -> Self {
+        Self {
+            bytes: HashMap::with_capacity(TRACKED_TARGETS),
+            decayed: Instant::now(),
+        }
+    }
+
+    fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let mut top: Vec<_> = self
+            .bytes
+            .iter()
+            .map(|(prefix, bytes)| (prefix.clone(), *bytes))
+            .collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+
+    fn add(&mut self, prefix: &str, bytes: u64) {
+        if self.decayed.elapsed() >= DECAY_INTERVAL {
+            self.bytes.values_mut().for_each(|count| *count /= 2);
+            self.decayed = Instant::now();
+        }
+
+        if let Some(count) = self.bytes.get_mut(prefix) {
+            *count += bytes;
+            return;
+        }
+
+        let mut count = bytes;
+        if self.bytes.len() >= TRACKED_TARGETS {
+            if let Some(least) = self
+                .bytes
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(prefix, _)| prefix.clone())
+            {
+                count += self.bytes.remove(&least).unwrap_or_default();
+            }
+        }
+        self.bytes.insert(prefix.to_owned(), count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Volume, HEARTBEAT_TOP, KEY, TRACKED_TARGETS};
+    use crate::{
+        test_support::{with_logger, Capture},
+        Target, CANARY_TARGET,
+    };
+
+    #[test]
+    fn heavy_prefixes_lead_the_report() {
+        let mut volume = Volume::new();
+        // Prefix n logs n KB, over four times as many prefixes as are tracked
+        for round in 0..10 {
+            for n in 1..=TRACKED_TARGETS as u64 * 4 {
+                if round < 3 || n > TRACKED_TARGETS as u64 * 3 {
+                    volume.add(&format!("svc_{}::db", n), n * 100);
+                }
+            }
+        }
+
+        assert_eq!(volume.bytes.len(), TRACKED_TARGETS);
+        let top = volume.top(5);
+        let prefixes: Vec<_> = top.iter().map(|(prefix, _)| prefix.as_str()).collect();
+        assert_eq!(
+            prefixes,
+            [
+                "svc_256::db",
+                "svc_255::db",
+                "svc_254::db",
+                "svc_253::db",
+                "svc_252::db"
+            ]
+        );
+        // Overestimated at most, never missed
+        assert!(top[0].1 >= 10 * 25_600, "{:?}", top);
+        assert!(
+            top.windows(2).all(|pair| pair[0].1 >= pair[1].1),
+            "{:?}",
+            top
+        );
+    }
+
+    #[test]
+    fn canary_records_carry_the_top_prefixes() {
+        let capture = Capture::default();
+        let builder = crate::builder("svc-test", Target::ConsoleJson)
+            .track_volume()
+            .capture(&capture);
+        with_logger(builder, || {
+            for n in 0..20 {
+                tracing::info!(target: "volume_heavy::db", n, "queried");
+            }
+            tracing::info!(target: "volume_light::http", "served");
+            tracing::info!(target: CANARY_TARGET, canary_id = "test", "canary");
+            tracing::info!("after the canary");
+        });
+
+        let records = capture.records();
+        let canary = &records[records.len() - 2];
+        let top = canary[KEY].as_array().expect("volume_top is an array");
+        assert!(top.len() <= HEARTBEAT_TOP, "{}", canary);
+        let position = |target: &str| top.iter().position(|entry| entry["target"] == target);
+        let (heavy, light) = (position("volume_heavy::db"), position("volume_light::http"));
+        assert!(heavy.is_some() && heavy < light, "{}", canary);
+        assert!(top[heavy.unwrap()]["bytes"].as_u64().unwrap() > 20 * 100);
+        assert!(records.last().unwrap().get(KEY).is_none());
+    }
+}
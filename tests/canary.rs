@@ -0,0 +1,77 @@
+mod common;
+
+use std::{
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use vinted_logger::{Target, CANARY_TARGET};
+
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+#[test]
+fn reflected_canaries_keep_the_logger_healthy() {
+    let (collector, destination) = common::udp_collector();
+    collector
+        .set_read_timeout(Some(Duration::from_millis(20)))
+        .expect("read timeout can be set");
+
+    let _guard = vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .canary_every(Duration::from_millis(200))
+        .canary_reflections(0)
+        .try_init_with_guard()
+        .expect("logger can be installed");
+
+    // Stands in for the collector sending canaries back
+    let reflecting = Arc::new(AtomicBool::new(true));
+    let reflector = {
+        let reflecting = reflecting.clone();
+        thread::spawn(move || {
+            let socket = UdpSocket::bind("127.0.0.1:0").expect("socket can be bound");
+            while reflecting.load(Ordering::Relaxed) {
+                let record = match common::recv_record(&collector) {
+                    Some(record) => record,
+                    None => continue,
+                };
+                assert_eq!(record["target"], CANARY_TARGET);
+                assert_eq!(record["message"], "canary");
+                let id = record["canary_id"].as_str().expect("canary has an id");
+                let reply_to = record["canary_reply_to"]
+                    .as_str()
+                    .expect("canary has an address");
+                socket
+                    .send_to(id.as_bytes(), reply_to)
+                    .expect("reflection is sent");
+            }
+        })
+    };
+
+    assert!(wait_until(|| vinted_logger::logger_stats()
+        .canary_last_success
+        .is_some()));
+    let stats = vinted_logger::logger_stats();
+    assert!(
+        stats.canary_rtt_ms.expect("round trip is known") < 200,
+        "{:?}",
+        stats
+    );
+    assert!(vinted_logger::healthy());
+
+    reflecting.store(false, Ordering::Relaxed);
+    reflector.join().expect("reflector doesn't panic");
+    assert!(wait_until(|| !vinted_logger::healthy()));
+}
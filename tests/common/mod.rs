@@ -0,0 +1,40 @@
+//! Helpers shared by the integration tests, each of which runs in a process
+//! of its own so it can install a global subscriber
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::{net::UdpSocket, time::Duration};
+use vinted_logger::LogDestination;
+
+/// Socket standing in for the collector, with the destination sending to it
+pub fn udp_collector() -> (UdpSocket, LogDestination) {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("collector socket can be bound");
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("read timeout can be set");
+    let destination = socket.local_addr().expect("socket has an address").into();
+    (socket, destination)
+}
+
+/// Next record received, `None` once the read times out
+pub fn recv_record(socket: &UdpSocket) -> Option<Value> {
+    let mut buf = vec![0; 65_536];
+    let len = socket.recv(&mut buf).ok()?;
+    let line = std::str::from_utf8(&buf[..len]).expect("record is UTF-8");
+    assert!(
+        line.ends_with('\n'),
+        "{:?} doesn't end with a newline",
+        line
+    );
+    Some(serde_json::from_str(line).expect("record is JSON"))
+}
+
+/// Waits a little for a record which shouldn't come
+pub fn assert_no_record(socket: &UdpSocket) {
+    socket
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .expect("read timeout can be set");
+    if let Some(record) = recv_record(socket) {
+        panic!("unexpected record {}", record);
+    }
+}
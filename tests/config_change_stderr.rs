@@ -0,0 +1,57 @@
+//! Runs itself as a child process which hides filter changes from its own
+//! filter, to find them on its stderr
+mod common;
+
+use std::process::Command;
+use tracing::Level;
+use vinted_logger::Target;
+
+const CHILD_ENV: &str = "VINTED_LOGGER_CONFIG_CHANGE_CHILD";
+
+fn hide_config_changes() -> ! {
+    std::env::remove_var("RUST_LOG");
+    let (collector, destination) = common::udp_collector();
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .default_level(Level::INFO)
+        .try_init()
+        .expect("logger can be installed");
+    let filter = vinted_logger::filter_handle().expect("logger is installed");
+
+    // Neither record passes `error`
+    filter.set_filter("error").expect("filter is valid");
+    filter
+        .set_filter("error,=[")
+        .expect_err("filter is invalid");
+    common::assert_no_record(&collector);
+    // Before the harness reports the test
+    std::process::exit(0)
+}
+
+#[test]
+fn filtered_out_config_changes_reach_stderr() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        hide_config_changes();
+    }
+
+    let output = Command::new(std::env::current_exe().expect("test binary is known"))
+        .args(["--exact", "filtered_out_config_changes_reach_stderr"])
+        .args(["--quiet", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .expect("child runs");
+    let stderr = String::from_utf8(output.stderr).expect("stderr is UTF-8");
+    assert!(output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("Logger setting `filter` changed from `info` to `error` by api"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(
+            "Logger setting `filter` wasn't changed by api: invalid log filter `error,=[`"
+        ),
+        "{}",
+        stderr
+    );
+}
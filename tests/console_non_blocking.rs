@@ -0,0 +1,72 @@
+//! Runs itself as a child process whose stdout is a pipe nobody reads, which
+//! must not hold up logging with a non-blocking console
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+use vinted_logger::Target;
+
+const CHILD_ENV: &str = "VINTED_LOGGER_BLOCKED_STDOUT_CHILD";
+const RECORDS: usize = 20_000;
+
+fn log_to_blocked_stdout() -> ! {
+    vinted_logger::builder("svc-test", Target::ConsoleJson)
+        .non_blocking()
+        .console_buffer(256)
+        .try_init()
+        .expect("logger can be installed");
+
+    // About 20 MB, far beyond what the pipe holds
+    let pad = "x".repeat(1_000);
+    let start = Instant::now();
+    for n in 0..RECORDS {
+        tracing::info!(n, pad = pad.as_str(), "blocked");
+    }
+    eprintln!(
+        "logged in {}ms, dropped {}",
+        start.elapsed().as_millis(),
+        vinted_logger::logger_stats().dropped
+    );
+    // The writer thread never gets its stdout back
+    loop {
+        std::thread::park();
+    }
+}
+
+#[test]
+fn a_blocked_stdout_doesnt_hold_up_logging() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        log_to_blocked_stdout();
+    }
+
+    let mut child = Command::new(std::env::current_exe().expect("test binary is known"))
+        .args(["--exact", "a_blocked_stdout_doesnt_hold_up_logging"])
+        .args(["--quiet", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("child can be spawned");
+
+    let started = Instant::now();
+    let stderr = BufReader::new(child.stderr.take().expect("stderr is piped"));
+    let result = stderr
+        .lines()
+        .map(|line| line.expect("stderr is UTF-8"))
+        .find(|line| line.starts_with("logged in "));
+    child.kill().expect("child can be killed");
+    child.wait().expect("child exits");
+
+    let result = result.expect("child logged every record");
+    assert!(started.elapsed() < Duration::from_secs(10), "{}", result);
+    let (elapsed, dropped) = result
+        .strip_prefix("logged in ")
+        .and_then(|result| result.split_once("ms, dropped "))
+        .expect("result is parsed");
+    let elapsed: u64 = elapsed.parse().unwrap();
+    let dropped: usize = dropped.parse().unwrap();
+    assert!(elapsed < 5_000, "logging took {}ms", elapsed);
+    // Whatever didn't fit in the pipe and the queue
+    assert!(dropped > RECORDS / 2, "{} dropped", dropped);
+}
@@ -0,0 +1,75 @@
+//! Runs itself twice as child processes logging from 16 threads each to the
+//! same pipe, which must then hold whole lines only
+use std::{
+    io::Read,
+    process::{Command, Stdio},
+    thread,
+};
+use vinted_logger::Target;
+
+const CHILD_ENV: &str = "VINTED_LOGGER_PIPE_CHILD";
+const THREADS: usize = 16;
+const RECORDS: usize = 200;
+
+fn log_from_threads() -> ! {
+    vinted_logger::try_init("svc-test", Target::ConsoleJson).expect("logger can be installed");
+    let threads: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            thread::spawn(move || {
+                for n in 0..RECORDS {
+                    // Up to about 3 KiB, under PIPE_BUF
+                    let pad = "x".repeat((thread * RECORDS + n) * 7 % 3_000);
+                    tracing::info!(thread, n, pad = pad.as_str(), "piped");
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("thread doesn't panic");
+    }
+    // Before the harness reports the test
+    std::process::exit(0)
+}
+
+#[test]
+fn processes_sharing_a_pipe_write_whole_lines() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        log_from_threads();
+    }
+
+    let (mut reader, writer) = std::io::pipe().expect("pipe can be created");
+    let children: Vec<_> = (0..2)
+        .map(|_| {
+            Command::new(std::env::current_exe().expect("test binary is known"))
+                .args(["--exact", "processes_sharing_a_pipe_write_whole_lines"])
+                .args(["--quiet", "--nocapture", "--test-threads=1"])
+                .env(CHILD_ENV, "1")
+                .stdout(writer.try_clone().expect("pipe can be shared"))
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("child can be spawned")
+        })
+        .collect();
+    drop(writer);
+
+    let mut output = String::new();
+    reader
+        .read_to_string(&mut output)
+        .expect("pipe holds UTF-8");
+    for mut child in children {
+        assert!(child.wait().expect("child exits").success());
+    }
+
+    let mut records = 0;
+    for line in output.lines() {
+        // The harness announces the test before it runs
+        if line.is_empty() || line.starts_with("running ") {
+            continue;
+        }
+        let record: serde_json::Value =
+            serde_json::from_str(line).unwrap_or_else(|e| panic!("{} in {:?}", e, line));
+        assert_eq!(record["message"], "piped");
+        records += 1;
+    }
+    assert_eq!(records, 2 * THREADS * RECORDS);
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use tracing::Level;
+use vinted_logger::Target;
+
+#[test]
+fn rust_log_cant_loosen_denied_targets() {
+    std::env::set_var("RUST_LOG", "trace,sqlx::pool=trace");
+    let (collector, destination) = common::udp_collector();
+
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .deny_below("sqlx", Level::WARN)
+        .deny_below("sqlx::query", Level::ERROR)
+        .deny_below("hyper", Level::INFO)
+        .deny_below("hyper", Level::DEBUG)
+        .try_init()
+        .expect("logger can be installed");
+
+    tracing::debug!(target: "sqlx::pool", "denied debug");
+    tracing::info!(target: "sqlx", "denied info");
+    tracing::warn!(target: "sqlx::query", "denied by the longer prefix");
+    tracing::warn!(target: "sqlx::pool", "sqlx warn");
+    tracing::error!(target: "sqlx::query", "query error");
+    tracing::trace!(target: "hyper::client", "denied trace");
+    tracing::debug!(target: "hyper::client", "hyper debug");
+    tracing::debug!(target: "diesel", "other debug");
+    tracing::trace!(target: "app", "app trace");
+
+    let messages: Vec<_> = std::iter::from_fn(|| common::recv_record(&collector))
+        .take(5)
+        .map(|record| record["message"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "sqlx warn",
+            "query error",
+            "hyper debug",
+            "other debug",
+            "app trace"
+        ]
+    );
+    common::assert_no_record(&collector);
+}
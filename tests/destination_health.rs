@@ -0,0 +1,41 @@
+mod common;
+
+use std::{thread, time::Duration};
+use vinted_logger::{DestinationRole, LogDestination, Target};
+
+#[test]
+fn a_failing_error_destination_leaves_the_logger_healthy() {
+    let (collector, destination) = common::udp_collector();
+    let _guard = vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination.clone())
+        // The kernel refuses broadcasts to sockets without SO_BROADCAST
+        .error_destination(LogDestination::Udp("255.255.255.255:9".to_owned()))
+        .try_init_with_guard()
+        .expect("logger can be installed");
+
+    tracing::error!("payment failed");
+    let record = common::recv_record(&collector).expect("record is received");
+    assert_eq!(record["message"], "payment failed");
+    let mut stats = vinted_logger::destination_stats();
+    for _ in 0..500 {
+        if stats.iter().all(|stats| stats.sent + stats.send_errors > 0) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+        stats = vinted_logger::destination_stats();
+    }
+
+    assert_eq!(stats.len(), 2, "{:?}", stats);
+    assert_eq!(stats[0].destination, destination.to_string());
+    assert_eq!(stats[0].role, DestinationRole::Records);
+    assert_eq!((stats[0].sent, stats[0].send_errors), (1, 0));
+    assert!(stats[0].healthy);
+    assert_eq!(stats[1].destination, "udp://255.255.255.255:9");
+    assert_eq!(stats[1].role, DestinationRole::Errors);
+    assert_eq!((stats[1].sent, stats[1].send_errors), (0, 1));
+    assert!(!stats[1].healthy);
+
+    // Only the destination of the target's records counts
+    assert!(vinted_logger::healthy());
+    assert_eq!(vinted_logger::logger_stats().send_errors, 1);
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use serde_json::Value;
+use std::{
+    net::UdpSocket,
+    thread,
+    time::{Duration, Instant},
+};
+use vinted_logger::{Target, DRAIN_TARGET};
+
+/// Records of the next batch received
+fn recv_batch(socket: &UdpSocket) -> Vec<Value> {
+    let mut buf = vec![0; 65_536];
+    let len = socket.recv(&mut buf).expect("batch is received");
+    std::str::from_utf8(&buf[..len])
+        .expect("batch is UTF-8")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("record is JSON"))
+        .collect()
+}
+
+fn messages(batch: &[Value]) -> Vec<&str> {
+    batch
+        .iter()
+        .filter_map(|record| record["message"].as_str())
+        .collect()
+}
+
+#[test]
+fn draining_sends_batches_sooner_and_waits_longer_for_errors() {
+    let (collector, destination) = common::udp_collector();
+    let guard = vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .udp_batching_with(
+            vinted_logger::DEFAULT_UDP_BATCH_BYTES,
+            Duration::from_secs(5),
+        )
+        .deploy_slot("blue")
+        .deploy_id("deploy-42")
+        .flush_timeout(Duration::from_millis(100))
+        .drain_batch_interval(Duration::from_millis(400))
+        .drain_error_timeout(Duration::from_secs(3))
+        .try_init_with_guard()
+        .expect("logger can be installed");
+    assert_eq!(vinted_logger::logger_stats().draining_since, None);
+
+    // Batched for 5 seconds until draining starts, then for 400ms
+    let logged = Instant::now();
+    tracing::info!("before draining");
+    thread::sleep(Duration::from_millis(50));
+    let started = Instant::now();
+    assert!(!guard.drain(), "the batch is still waiting");
+    // Nothing but INFO records is waited for beyond the flush timeout
+    assert!(started.elapsed() < Duration::from_millis(300));
+    assert!(vinted_logger::logger_stats().draining_since.is_some());
+
+    let batch = recv_batch(&collector);
+    assert!(logged.elapsed() < Duration::from_secs(2));
+    assert_eq!(
+        messages(&batch),
+        ["before draining", "draining_started"],
+        "{:?}",
+        batch
+    );
+    let draining = &batch[1];
+    assert_eq!(draining["target"], DRAIN_TARGET);
+    assert_eq!(draining["level"], "INFO");
+    assert_eq!(draining["deploy_slot"], "blue");
+    assert_eq!(draining["deploy_id"], "deploy-42");
+
+    // ERROR records are waited for past the flush timeout, until their
+    // batch is sent
+    tracing::error!("failed while draining");
+    let started = Instant::now();
+    assert!(guard.drain());
+    let waited = started.elapsed();
+    assert!(
+        waited >= Duration::from_millis(300) && waited < Duration::from_secs(3),
+        "{:?}",
+        waited
+    );
+    // Draining started once
+    assert_eq!(messages(&recv_batch(&collector)), ["failed while draining"]);
+}
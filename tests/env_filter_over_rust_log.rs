@@ -0,0 +1,27 @@
+mod common;
+
+use vinted_logger::Target;
+
+#[test]
+fn env_filters_replace_rust_log() {
+    std::env::set_var("RUST_LOG", "error");
+    let (collector, destination) = common::udp_collector();
+
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .env_filter("my_crate=debug,info")
+        .try_init()
+        .expect("logger can be installed");
+
+    tracing::trace!(target: "my_crate::db", "my_crate trace");
+    tracing::debug!(target: "my_crate::db", "my_crate debug");
+    tracing::debug!(target: "other", "other debug");
+    tracing::info!(target: "other", "other info");
+
+    let messages: Vec<_> = std::iter::from_fn(|| common::recv_record(&collector))
+        .take(2)
+        .map(|record| record["message"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(messages, ["my_crate debug", "other info"]);
+    common::assert_no_record(&collector);
+}
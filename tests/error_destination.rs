@@ -0,0 +1,46 @@
+mod common;
+
+use serde_json::Value;
+use std::{net::UdpSocket, time::Duration};
+use tracing::Level;
+use vinted_logger::Target;
+
+fn recv_line(socket: &UdpSocket) -> String {
+    let mut datagram = vec![0; 65_536];
+    let len = socket.recv(&mut datagram).expect("record is sent");
+    String::from_utf8(datagram[..len].to_vec()).expect("record is UTF-8")
+}
+
+#[test]
+fn errors_go_to_both_destinations() {
+    let (primary, destination) = common::udp_collector();
+    let (on_call, error_destination) = common::udp_collector();
+
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .error_destination(error_destination)
+        .error_destination_level(Level::WARN)
+        .try_init()
+        .expect("logger can be installed");
+
+    tracing::info!("served");
+    tracing::warn!("slow");
+    tracing::error!("failed");
+    tracing::debug!("ignored");
+
+    let primary_lines: Vec<_> = (0..3).map(|_| recv_line(&primary)).collect();
+    let messages: Vec<_> = primary_lines
+        .iter()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["message"].clone())
+        .collect();
+    assert_eq!(messages, ["served", "slow", "failed"]);
+
+    // Only WARN and above, byte for byte the records of the primary
+    assert_eq!(recv_line(&on_call), primary_lines[1]);
+    assert_eq!(recv_line(&on_call), primary_lines[2]);
+    common::assert_no_record(&on_call);
+
+    assert!(vinted_logger::flush(Duration::from_secs(5)));
+    assert_eq!(vinted_logger::logger_stats().sent, 5);
+    common::assert_no_record(&primary);
+}
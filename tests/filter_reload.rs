@@ -0,0 +1,97 @@
+mod common;
+
+use std::time::Duration;
+use tracing::Level;
+use vinted_logger::Target;
+
+#[test]
+fn set_filter_lets_debug_records_through() {
+    std::env::remove_var("RUST_LOG");
+    let (collector, destination) = common::udp_collector();
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .default_level(Level::INFO)
+        .try_init()
+        .expect("logger can be installed");
+    let filter = vinted_logger::filter_handle().expect("logger is installed");
+
+    tracing::debug!("before");
+    tracing::info!("marker");
+    let record = common::recv_record(&collector).expect("record is received");
+    assert_eq!(record["message"], "marker", "DEBUG was filtered out");
+
+    filter.set_filter("debug").expect("filter is valid");
+    let changed = common::recv_record(&collector).expect("change is logged");
+    assert_eq!(changed["message"], "logger_config_changed");
+    assert_eq!(changed["target"], "vinted::config");
+    assert_eq!(changed["level"], "INFO");
+    assert_eq!(changed["setting"], "filter");
+    assert_eq!(changed["old"], "info");
+    assert_eq!(changed["new"], "debug");
+    assert_eq!(changed["source"], "api");
+    assert!(changed.get("path").is_none(), "{}", changed);
+
+    tracing::debug!("after");
+    let record = common::recv_record(&collector).expect("record is received");
+    assert_eq!(record["message"], "after");
+    assert_eq!(record["level"], "DEBUG");
+
+    // Invalid directives leave the filter alone
+    let error = filter
+        .set_filter("debug,=[")
+        .expect_err("filter is invalid");
+    assert!(
+        error
+            .to_string()
+            .starts_with("invalid log filter `debug,=[`"),
+        "{}",
+        error
+    );
+    let rejected = common::recv_record(&collector).expect("rejection is logged");
+    assert_eq!(rejected["message"], "logger_config_rejected");
+    assert_eq!(rejected["level"], "WARN");
+    assert_eq!(rejected["setting"], "filter");
+    assert_eq!(rejected["value"], "debug,=[");
+    assert_eq!(rejected["source"], "api");
+    assert!(
+        error
+            .to_string()
+            .ends_with(rejected["error"].as_str().unwrap()),
+        "{} {}",
+        error,
+        rejected
+    );
+    assert!(filter
+        .filter()
+        .split(',')
+        .any(|directive| directive == "debug"));
+    tracing::debug!("still");
+    assert_eq!(common::recv_record(&collector).unwrap()["message"], "still");
+
+    // Applying the filter in use changes nothing, and isn't logged
+    filter.set_filter("debug").expect("filter is valid");
+    tracing::debug!("unchanged");
+    assert_eq!(
+        common::recv_record(&collector).unwrap()["message"],
+        "unchanged"
+    );
+
+    // Changes made by a watched file carry its path
+    let path = std::env::temp_dir().join(format!("vinted-logger-level-{}", std::process::id()));
+    std::fs::write(&path, "hyper=warn,debug\n").unwrap();
+    filter.watch_file(&path, Duration::from_millis(20));
+    let changed = common::recv_record(&collector).expect("change is logged");
+    assert_eq!(changed["message"], "logger_config_changed");
+    assert_eq!(changed["old"], "debug");
+    assert_eq!(changed["new"], "hyper=warn,debug");
+    assert_eq!(changed["source"], "file");
+    assert_eq!(changed["path"], path.display().to_string());
+
+    std::fs::write(&path, "=[\n").unwrap();
+    let rejected = common::recv_record(&collector).expect("rejection is logged");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(rejected["message"], "logger_config_rejected");
+    assert_eq!(rejected["value"], "=[");
+    assert_eq!(rejected["source"], "file");
+    assert_eq!(rejected["path"], path.display().to_string());
+}
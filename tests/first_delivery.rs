@@ -0,0 +1,37 @@
+mod common;
+
+use std::time::Duration;
+use vinted_logger::Target;
+
+#[test]
+fn first_delivery_resolves_once_a_record_is_sent() {
+    let (collector, destination) = common::udp_collector();
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .try_init()
+        .expect("logger can be installed");
+
+    // Nothing was logged yet
+    assert_eq!(
+        vinted_logger::wait_first_delivery(Duration::from_millis(100)),
+        Err(vinted_logger::FirstDeliveryError::Timeout)
+    );
+
+    tracing::info!("ready");
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("runtime can be built");
+    runtime
+        .block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), vinted_logger::first_delivery()).await
+        })
+        .expect("record is delivered in time")
+        .expect("records can be delivered");
+    assert_eq!(
+        vinted_logger::wait_first_delivery(Duration::ZERO),
+        Ok(()),
+        "the outcome sticks"
+    );
+    assert!(common::recv_record(&collector).is_some());
+}
@@ -0,0 +1,29 @@
+use std::{net::TcpListener, time::Duration};
+use vinted_logger::{FirstDeliveryError, LogDestination, Target};
+
+#[test]
+fn first_delivery_times_out_without_a_collector() {
+    // A port nothing listens on any more, connections are refused
+    let address = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("port can be picked");
+    vinted_logger::builder("svc-test", Target::TcpJson)
+        .destination(LogDestination::Tcp(address.to_string()))
+        .try_init()
+        .expect("logger can be installed");
+
+    tracing::info!("not ready");
+    assert_eq!(
+        vinted_logger::wait_first_delivery(Duration::from_millis(500)),
+        Err(FirstDeliveryError::Timeout)
+    );
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("runtime can be built");
+    let waited = runtime.block_on(async {
+        tokio::time::timeout(Duration::from_millis(200), vinted_logger::first_delivery()).await
+    });
+    assert!(waited.is_err(), "{:?}", waited);
+}
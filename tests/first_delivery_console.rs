@@ -0,0 +1,9 @@
+use std::time::Duration;
+use vinted_logger::Target;
+
+#[test]
+fn first_delivery_of_console_targets_resolves_at_init() {
+    vinted_logger::try_init("svc-test", Target::ConsoleJson).expect("logger can be installed");
+
+    assert_eq!(vinted_logger::wait_first_delivery(Duration::ZERO), Ok(()));
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use vinted_logger::Target;
+
+#[test]
+fn dropping_the_guard_sends_every_record() {
+    let (collector, destination) = common::udp_collector();
+    let guard = vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .try_init_with_guard()
+        .expect("logger can be installed");
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("runtime can be built");
+    runtime.block_on(async {
+        for n in 0..100 {
+            tracing::info!(n, "job step");
+        }
+    });
+    drop(guard);
+
+    let received: Vec<_> = (0..100)
+        .map(|_| common::recv_record(&collector).expect("record is received")["n"].clone())
+        .collect();
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+    common::assert_no_record(&collector);
+}
@@ -0,0 +1,19 @@
+mod common;
+
+use vinted_logger::{InitBehavior, Target};
+
+#[test]
+fn logger_is_installed_when_no_subscriber_is() {
+    let (collector, destination) = common::udp_collector();
+
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .init_behavior(InitBehavior::AttachLayerIfPossible)
+        .try_init()
+        .expect("logger can be installed");
+
+    tracing::info!("installed");
+    let record = common::recv_record(&collector).expect("record is sent");
+    assert_eq!(record["message"], "installed");
+    assert!(tracing::dispatcher::get_default(vinted_logger::introspect).is_some());
+}
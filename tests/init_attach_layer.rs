@@ -0,0 +1,63 @@
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+use vinted_logger::{AttachPoint, InitBehavior, Target};
+
+/// Counts the events of the layers installed with the attach point
+struct Counter(Arc<AtomicUsize>);
+
+impl<S: tracing::Subscriber> Layer<S> for Counter {
+    fn on_event(&self, _: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn logger_is_attached_to_the_installed_subscriber() {
+    let events = Arc::new(AtomicUsize::new(0));
+    tracing_subscriber::registry()
+        .with(AttachPoint::default())
+        .with(Counter(events.clone()))
+        .init();
+    tracing::info!("before the logger");
+    let before = tracing::info_span!("before").entered();
+
+    let (collector, destination) = common::udp_collector();
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .init_behavior(InitBehavior::AttachLayerIfPossible)
+        .try_init()
+        .expect("logger can be attached");
+
+    let _span = tracing::info_span!("request", path = "/items").entered();
+    tracing::info!(user_id = 7, "attached");
+    let record = common::recv_record(&collector).expect("record is sent");
+    assert_eq!(record["message"], "attached");
+    assert_eq!(record["facility"], "svc-test");
+    assert_eq!(record["user_id"], 7);
+    assert_eq!(record["span"]["path"], "/items");
+    assert_eq!(events.load(Ordering::Relaxed), 2);
+
+    // Spans created before it was attached are closed without a trace
+    drop(before);
+
+    // The attached logger is found like an installed one
+    let report = tracing::dispatcher::get_default(vinted_logger::introspect)
+        .expect("attached logger can be introspected");
+    assert_eq!(report.facility, "svc-test");
+    assert!(vinted_logger::filter_handle().is_some());
+
+    // The attach point takes a single logger
+    vinted_logger::builder("svc-other", Target::UdpJson)
+        .init_behavior(InitBehavior::AttachLayerIfPossible)
+        .try_init()
+        .expect("second logger degrades to a noop");
+    tracing::warn!("still the first");
+    let record = common::recv_record(&collector).expect("record is sent");
+    assert_eq!(record["message"], "still the first");
+    assert_eq!(record["facility"], "svc-test");
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use tracing_subscriber::util::SubscriberInitExt;
+use vinted_logger::{InitBehavior, Target};
+
+#[test]
+fn subscriber_without_attach_point_is_kept() {
+    tracing_subscriber::registry().init();
+    let (collector, destination) = common::udp_collector();
+
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .init_behavior(InitBehavior::AttachLayerIfPossible)
+        .try_init()
+        .expect("attaching degrades to a noop");
+
+    tracing::info!("not sent");
+    common::assert_no_record(&collector);
+    assert!(tracing::dispatcher::get_default(vinted_logger::introspect).is_none());
+}
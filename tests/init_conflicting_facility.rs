@@ -0,0 +1,52 @@
+use vinted_logger::{InitBehavior, InitError, Target};
+
+#[test]
+fn logger_of_another_facility_fails_naming_both_inits() {
+    let installed_line = line!() + 1;
+    let result = vinted_logger::builder("svc-library", Target::ConsoleJson).try_init();
+    result.expect("first logger is installed");
+
+    let line = line!() + 1;
+    let result = vinted_logger::try_init("svc-test", Target::Console);
+    match result {
+        Err(
+            ref e @ InitError::ConflictingInit {
+                installed_facility,
+                installed_at,
+                facility,
+                location,
+            },
+        ) => {
+            assert_eq!(installed_facility, "svc-library");
+            assert_eq!(
+                (installed_at.file(), installed_at.line()),
+                (file!(), installed_line)
+            );
+            assert_eq!(facility, "svc-test");
+            assert_eq!((location.file(), location.line()), (file!(), line));
+            let message = e.to_string();
+            assert!(message.contains("`svc-library`"), "{}", message);
+            assert!(
+                message.contains(&format!("{}:{}", file!(), line)),
+                "{}",
+                message
+            );
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // The `init_*` functions report their own callers too
+    let line = line!() + 1;
+    let result = vinted_logger::init_for_environment("test", "svc-test");
+    match result {
+        Err(InitError::ConflictingInit { location, .. }) => {
+            assert_eq!((location.file(), location.line()), (file!(), line));
+        }
+        result => panic!("unexpected result {:?}", result.map(drop)),
+    }
+
+    vinted_logger::builder("svc-test", Target::Console)
+        .init_behavior(InitBehavior::NoopIfSet)
+        .try_init()
+        .expect("conflicts are ignored like any installed subscriber");
+}
@@ -0,0 +1,23 @@
+use tracing_subscriber::util::SubscriberInitExt;
+use vinted_logger::{InitBehavior, InitError, Target};
+
+#[test]
+fn installed_subscriber_fails_the_default_init() {
+    tracing_subscriber::registry().init();
+
+    let result = vinted_logger::builder("svc-test", Target::ConsoleJson).try_init();
+    assert!(
+        matches!(result, Err(InitError::AlreadyInitialized(_))),
+        "{:?}",
+        result
+    );
+
+    let result = vinted_logger::builder("svc-test", Target::ConsoleJson)
+        .init_behavior(InitBehavior::FailIfSet)
+        .try_init();
+    assert!(
+        matches!(result, Err(InitError::AlreadyInitialized(_))),
+        "{:?}",
+        result
+    );
+}
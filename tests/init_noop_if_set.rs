@@ -0,0 +1,50 @@
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+use vinted_logger::{InitBehavior, InitError, LoggerConfig, Target};
+
+/// Counts the events of the subscriber installed first
+struct Counter(Arc<AtomicUsize>);
+
+impl<S: tracing::Subscriber> Layer<S> for Counter {
+    fn on_event(&self, _: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn installed_subscriber_is_kept() {
+    let events = Arc::new(AtomicUsize::new(0));
+    tracing_subscriber::registry()
+        .with(Counter(events.clone()))
+        .init();
+    let (collector, destination) = common::udp_collector();
+
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination.clone())
+        .init_behavior(InitBehavior::NoopIfSet)
+        .try_init()
+        .expect("installed subscriber is left alone");
+
+    // `init_from_config` fails like `init_for_environment` does
+    let config: LoggerConfig = serde_json::from_value(serde_json::json!({
+        "environment": "production",
+        "facility": "svc-test",
+        "target": "udp_json",
+        "address": destination.to_string(),
+    }))
+    .unwrap();
+    assert!(matches!(
+        vinted_logger::init_from_config(&config),
+        Err(InitError::AlreadyInitialized(_))
+    ));
+
+    tracing::info!("only counted");
+    assert_eq!(events.load(Ordering::Relaxed), 1);
+    common::assert_no_record(&collector);
+    assert!(vinted_logger::filter_handle().is_none());
+}
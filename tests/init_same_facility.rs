@@ -0,0 +1,46 @@
+mod common;
+
+use std::{fs, time::Duration};
+use vinted_logger::{InitError, Target};
+
+#[test]
+fn logger_of_the_same_facility_is_attached_next_to_the_installed_one() {
+    let (collector, destination) = common::udp_collector();
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination.clone())
+        .try_init()
+        .expect("first logger is installed");
+
+    let path = std::env::temp_dir().join(format!("vinted-logger-{}.json", std::process::id()));
+    vinted_logger::builder("svc-test", Target::FileJson)
+        .file_path(&path)
+        .try_init()
+        .expect("logger of the same facility is attached");
+
+    tracing::info!(user_id = 7, "both");
+    vinted_logger::flush(Duration::from_secs(5));
+    let record = common::recv_record(&collector).expect("record is sent");
+    assert_eq!(record["message"], "both");
+    let file = fs::read_to_string(&path).expect("file is written");
+    fs::remove_file(&path).expect("file can be removed");
+    let line = file.lines().next().expect("record is written");
+    let written: serde_json::Value = serde_json::from_str(line).expect("record is JSON");
+    assert_eq!(written["message"], "both");
+    assert_eq!(written["user_id"], 7);
+
+    // Another logger of the same target would only duplicate its records
+    let result = vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .try_init();
+    assert!(
+        matches!(result, Err(InitError::AlreadyInitialized(_))),
+        "{:?}",
+        result
+    );
+    tracing::info!("once");
+    assert_eq!(
+        common::recv_record(&collector).expect("record is sent")["message"],
+        "once"
+    );
+    common::assert_no_record(&collector);
+}
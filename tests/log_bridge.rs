@@ -0,0 +1,33 @@
+mod common;
+
+use vinted_logger::Target;
+
+/// `try_init` installs the `log` bridge too
+#[test]
+fn log_records_are_like_tracing_ones() {
+    let (collector, destination) = common::udp_collector();
+    let _guard = vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .try_init_with_guard()
+        .expect("logger can be installed");
+
+    let line = line!() + 1;
+    log::info!(target: "payments", "charged {} items", 3);
+    tracing::info!(target: "payments", "charged natively");
+
+    let bridged = common::recv_record(&collector).expect("record is received");
+    assert_eq!(bridged["message"], "charged 3 items");
+    assert_eq!(bridged["target"], "payments");
+    assert_eq!(bridged["module"], module_path!());
+    assert_eq!(bridged["file"], file!());
+    assert_eq!(bridged["line"], line);
+
+    // Same keys as a native record's
+    let native = common::recv_record(&collector).expect("record is received");
+    let keys = |record: &serde_json::Value| {
+        let mut keys: Vec<String> = record.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+    assert_eq!(keys(&bridged), keys(&native));
+}
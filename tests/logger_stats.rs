@@ -0,0 +1,34 @@
+mod common;
+
+use std::time::Duration;
+use vinted_logger::Target;
+
+const RECORDS: u64 = 50;
+
+#[test]
+fn records_sent_to_the_collector_are_counted() {
+    let (collector, destination) = common::udp_collector();
+    assert!(vinted_logger::healthy(), "nothing was sent yet");
+
+    let _guard = vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .try_init_with_guard()
+        .expect("logger can be installed");
+    let before = vinted_logger::logger_stats();
+    for n in 0..RECORDS {
+        tracing::info!(n, "counted");
+    }
+    assert!(vinted_logger::flush(Duration::from_secs(5)));
+
+    for n in 0..RECORDS {
+        let record = common::recv_record(&collector).expect("record is received");
+        assert_eq!(record["n"], n);
+    }
+    let stats = vinted_logger::logger_stats();
+    assert_eq!(stats.enqueued - before.enqueued, RECORDS, "{:?}", stats);
+    assert_eq!(stats.sent - before.sent, RECORDS, "{:?}", stats);
+    assert_eq!(stats.dropped, 0, "{:?}", stats);
+    assert_eq!(stats.send_errors, 0, "{:?}", stats);
+    assert_eq!(stats.reconnects, 0, "{:?}", stats);
+    assert!(vinted_logger::healthy());
+}
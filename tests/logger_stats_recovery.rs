@@ -0,0 +1,50 @@
+#![cfg(unix)]
+
+use std::{
+    os::unix::net::UnixDatagram,
+    thread,
+    time::{Duration, Instant},
+};
+use vinted_logger::{LogDestination, Target};
+
+fn wait_until(condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(
+            Instant::now() < deadline,
+            "{:?}",
+            vinted_logger::logger_stats()
+        );
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn failed_sends_make_the_logger_unhealthy_until_one_succeeds() {
+    let path =
+        std::env::temp_dir().join(format!("vinted-logger-{}-stats.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let _guard = vinted_logger::builder("svc-test", Target::UnixJson)
+        .destination(LogDestination::Unix(path.clone()))
+        .try_init_with_guard()
+        .expect("logger can be installed");
+
+    // Nothing listens on the socket yet
+    tracing::info!("retried");
+    wait_until(|| vinted_logger::logger_stats().reconnects > 0);
+    assert!(!vinted_logger::healthy());
+    assert!(vinted_logger::logger_stats().send_errors > 0);
+
+    let collector = UnixDatagram::bind(&path).expect("socket can be bound");
+    collector
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut datagram = vec![0; 65_536];
+    let len = collector.recv(&mut datagram).expect("record is received");
+    let record: serde_json::Value = serde_json::from_slice(&datagram[..len]).unwrap();
+    assert_eq!(record["message"], "retried");
+    wait_until(|| vinted_logger::logger_stats().sent == 1);
+    assert!(vinted_logger::healthy());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::{
+    panic,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tracing::subscriber::NoSubscriber;
+use vinted_logger::Target;
+
+fn payload(info: &panic::PanicHookInfo<'_>) -> String {
+    info.payload()
+        .downcast_ref::<&str>()
+        .map_or_else(String::new, |message| (*message).to_owned())
+}
+
+#[test]
+fn panics_carry_the_context_of_their_spans() {
+    // Stands in for the default hook, which the logger's hook falls back to
+    let previous: Arc<Mutex<Vec<String>>> = Arc::default();
+    let seen = previous.clone();
+    panic::set_hook(Box::new(move |info| {
+        seen.lock().unwrap().push(payload(info))
+    }));
+
+    let (collector, destination) = common::udp_collector();
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .log_panics()
+        .try_init()
+        .expect("logger can be installed");
+
+    let crashed = thread::spawn(|| {
+        let _request = tracing::info_span!("request", request_id = "req-42").entered();
+        let _query = tracing::info_span!("db_query", table = "users").entered();
+        panic!("boom");
+    })
+    .join();
+    assert!(crashed.is_err());
+
+    let record = common::recv_record(&collector).expect("panic is logged");
+    assert_eq!(record["target"], "vinted::panic");
+    assert_eq!(record["level"], "ERROR");
+    assert_eq!(record["message"], "panicked: boom");
+    assert_eq!(record["request_id"], "req-42");
+    assert_eq!(record["table"], "users");
+    assert_eq!(record["span_path"], "request > db_query");
+    let location = record["panic_location"].as_str().unwrap();
+    assert!(
+        location.starts_with("tests/panic_in_span.rs:"),
+        "{}",
+        location
+    );
+    assert!(previous.lock().unwrap().is_empty());
+
+    // Without the logger, e.g. on a thread with a subscriber of its own
+    let crashed = thread::spawn(|| {
+        tracing::subscriber::with_default(NoSubscriber::default(), || panic!("outside"))
+    })
+    .join();
+    assert!(crashed.is_err());
+
+    assert_eq!(*previous.lock().unwrap(), ["outside"]);
+    common::assert_no_record(&collector);
+}
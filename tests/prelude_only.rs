@@ -0,0 +1,46 @@
+//! Logs through nothing but `vinted_logger::prelude`, the way services
+//! without a `tracing` dependency do
+mod common;
+
+use vinted_logger::prelude::*;
+
+#[test]
+fn prelude_is_enough_to_log() {
+    let (collector, destination) = common::udp_collector();
+    builder("svc-test", Target::UdpJson)
+        .destination(destination)
+        .default_level(Level::DEBUG)
+        .try_init()
+        .expect("logger can be installed");
+
+    // `introspect` takes the `Dispatch` of the `tracing-core` the layers are
+    // registered with, this only compiles if the re-exported one is the same
+    let report = tracing::dispatcher::get_default(vinted_logger::introspect)
+        .expect("the logger is the global dispatcher");
+    assert_eq!(report.target, Target::UdpJson);
+
+    let span = info_span!("search", query = "shoes");
+    span.in_scope(|| info!(results = 42, "search completed"));
+    event!(Level::WARN, "slow search");
+    debug!(parent: &Span::current(), "no span");
+    let user_id = 7;
+    errorf!("user {user_id} not found");
+
+    let records: Vec<_> = std::iter::from_fn(|| common::recv_record(&collector))
+        .take(4)
+        .collect();
+    let messages: Vec<_> = records.iter().map(|record| &record["message"]).collect();
+    assert_eq!(
+        messages,
+        [
+            "search completed",
+            "slow search",
+            "no span",
+            "user 7 not found"
+        ]
+    );
+    assert_eq!(records[0]["span"]["query"], "shoes");
+    assert_eq!(records[0]["level"], "INFO");
+    assert_eq!(records[1]["level"], "WARN");
+    assert_eq!(records[3]["user_id"], 7);
+}
@@ -0,0 +1,176 @@
+//! Textual snapshot of the crate's `pub` declarations in
+//! `tests/public_api.txt`, so that every change of the public API, intended
+//! or not, shows up in review
+//!
+//! `cargo xtask regen-public-api` rewrites the snapshot after an intended
+//! change. Declarations are listed file by file with their `cfg`,
+//! `non_exhaustive` and `deprecated` attributes, including those of
+//! `pub(crate)` modules which a `pub use` would expose. Bodies, comments and
+//! `#[cfg(test)]` modules are left out.
+use std::{collections::HashSet, env, fs, path::Path};
+
+/// Set by `cargo xtask regen-public-api`, the test then writes the snapshot
+const REGEN_ENV: &str = "VINTED_REGEN_PUBLIC_API";
+
+/// Source files of test support only, never part of the API
+const TEST_ONLY: &[&str] = &["golden.rs", "test_support.rs"];
+
+/// Attributes changing what a declaration means to callers
+const KEPT_ATTRIBUTES: &[&str] = &["#[cfg(", "#[non_exhaustive]", "#[deprecated"];
+
+#[test]
+fn public_api_matches_the_snapshot() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut api = String::new();
+    for crate_dir in ["src", "vinted-logger-macros/src"] {
+        let mut files: Vec<_> = fs::read_dir(root.join(crate_dir))
+            .expect("sources can be listed")
+            .map(|entry| entry.expect("source can be listed").path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "rs"))
+            .collect();
+        files.sort();
+        for path in files {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            if TEST_ONLY.contains(&name) {
+                continue;
+            }
+            let source = fs::read_to_string(&path).expect("source can be read");
+            for declaration in declarations(&source) {
+                api.push_str(&format!("{}/{}: {}\n", crate_dir, name, declaration));
+            }
+        }
+    }
+
+    let snapshot_path = root.join("tests/public_api.txt");
+    if env::var_os(REGEN_ENV).is_some() {
+        fs::write(&snapshot_path, &api).expect("snapshot can be written");
+        return;
+    }
+    let snapshot = fs::read_to_string(&snapshot_path).expect("snapshot can be read");
+    if api == snapshot {
+        return;
+    }
+    let (current, expected): (HashSet<_>, HashSet<_>) =
+        (api.lines().collect(), snapshot.lines().collect());
+    let mut diff = String::new();
+    for line in snapshot.lines().filter(|line| !current.contains(line)) {
+        diff.push_str(&format!("- {}\n", line));
+    }
+    for line in api.lines().filter(|line| !expected.contains(line)) {
+        diff.push_str(&format!("+ {}\n", line));
+    }
+    panic!(
+        "the public API changed, run `cargo xtask regen-public-api` if that's intended:\n{}",
+        if diff.is_empty() {
+            "(order only)\n"
+        } else {
+            &diff
+        }
+    );
+}
+
+/// `pub` declarations of a source file, one line each
+fn declarations(source: &str) -> Vec<String> {
+    let mut declarations = Vec::new();
+    let mut attributes: Vec<&str> = Vec::new();
+    let mut lines = source.lines().map(str::trim).peekable();
+    while let Some(line) = lines.next() {
+        if line == "#[cfg(test)]"
+            && lines
+                .peek()
+                .is_some_and(|next| next.starts_with("mod tests"))
+        {
+            break;
+        }
+        if line.starts_with("//") {
+            continue;
+        }
+        if line.starts_with("#[") {
+            if KEPT_ATTRIBUTES.iter().any(|kept| line.starts_with(kept)) {
+                attributes.push(line);
+            }
+            continue;
+        }
+        if is_public(line) {
+            let mut declaration = line.to_owned();
+            while !is_complete(&declaration) {
+                match lines.next() {
+                    Some(next) => {
+                        declaration.push(' ');
+                        declaration.push_str(next);
+                    }
+                    None => break,
+                }
+            }
+            let mut kept = attributes.join(" ");
+            if !kept.is_empty() {
+                kept.push(' ');
+            }
+            declarations.push(kept + &normalize(&declaration));
+        }
+        attributes.clear();
+    }
+    declarations
+}
+
+/// Whether `line` starts a declaration visible outside of the crate
+fn is_public(line: &str) -> bool {
+    line.starts_with("pub ") && !line.starts_with("pub(")
+}
+
+/// Whether the whole signature of the declaration was read: up to its body,
+/// or to the `;` or `,` ending it
+fn is_complete(declaration: &str) -> bool {
+    if declaration.starts_with("pub use ") {
+        return declaration.ends_with(';');
+    }
+    let depth = declaration.chars().fold(0i32, |depth, c| match c {
+        '(' | '[' => depth + 1,
+        ')' | ']' => depth - 1,
+        _ => depth,
+    });
+    let in_bounds = declaration.contains(" where") && declaration.ends_with(',');
+    depth <= 0 && !in_bounds && declaration.ends_with(['{', '}', ';', ','])
+}
+
+/// The signature without its body, trailing commas or rustfmt's line breaks
+fn normalize(declaration: &str) -> String {
+    let mut signature = declaration.to_owned();
+    let is_value = (signature.starts_with("pub const ") && !signature.starts_with("pub const fn "))
+        || signature.starts_with("pub static ");
+    if is_value {
+        // Values aren't part of the API, their types are
+        if let Some(value) = signature.find(" = ") {
+            signature.truncate(value);
+        }
+    } else if !signature.starts_with("pub use ") {
+        if let Some(body) = top_level_brace(&signature) {
+            signature.truncate(body);
+        }
+    }
+    let signature = signature
+        .trim()
+        .trim_end_matches([',', ';'])
+        .trim_end()
+        .replace("( ", "(")
+        .replace(", )", ")")
+        .replace(" )", ")")
+        .replace("{ ", "{")
+        .replace(", }", "}")
+        .replace(" }", "}");
+    signature.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Position of the `{` opening the body, outside of parentheses and brackets
+fn top_level_brace(signature: &str) -> Option<usize> {
+    let mut depth = 0i32;
+    for (at, c) in signature.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '{' if depth == 0 => return Some(at),
+            _ => {}
+        }
+    }
+    None
+}
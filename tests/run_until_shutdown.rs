@@ -0,0 +1,129 @@
+//! Runs itself as a child process waiting in `run_until_shutdown`, sends it
+//! SIGTERM and expects its batched records before it exits
+#![cfg(all(unix, feature = "shutdown"))]
+
+mod common;
+
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    time::Duration,
+};
+use vinted_logger::{LogDestination, Target};
+
+const CHILD_ENV: &str = "VINTED_LOGGER_SHUTDOWN_CHILD";
+const RECORDS: u32 = 20;
+
+fn wait_for_sigterm(collector: String) -> ! {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .expect("runtime can be built");
+    runtime.block_on(async {
+        let guard = vinted_logger::builder("svc-test", Target::UdpJson)
+            .destination(LogDestination::Udp(collector))
+            // Nothing is sent before the guard flushes
+            .udp_batching_with(8_000, Duration::from_secs(60))
+            .try_init_with_guard()
+            .expect("logger can be installed");
+        // Awaited from a task other than the one of `block_on`
+        let shutdown = tokio::spawn(vinted_logger::run_until_shutdown(guard));
+        for n in 0..RECORDS {
+            tracing::info!("served {}", n);
+        }
+        // The handlers are registered by the first poll
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        println!("ready");
+        shutdown.await.expect("shutdown task doesn't panic");
+    });
+    println!("cpu_ms={}", cpu_ms());
+    std::process::exit(0)
+}
+
+/// User and system CPU time of this process, from `/proc/self/stat`
+fn cpu_ms() -> u64 {
+    let stat = std::fs::read_to_string("/proc/self/stat").unwrap_or_default();
+    // The fields after the parenthesized command name, utime and stime are
+    // the 14th and 15th of the whole line
+    let fields: Vec<_> = match stat.rsplit_once(')') {
+        Some((_, rest)) => rest.split_whitespace().collect(),
+        None => return 0,
+    };
+    let ticks: u64 = fields[11..13]
+        .iter()
+        .filter_map(|f| f.parse::<u64>().ok())
+        .sum();
+    // USER_HZ is 100 on every Linux platform
+    ticks * 10
+}
+
+#[test]
+fn sigterm_drains_the_logger() {
+    if let Some(collector) = std::env::var_os(CHILD_ENV) {
+        wait_for_sigterm(collector.into_string().expect("address is UTF-8"));
+    }
+
+    let (socket, destination) = common::udp_collector();
+    let collector = match destination {
+        LogDestination::Udp(addr) => addr,
+        other => panic!("unexpected destination {:?}", other),
+    };
+    let mut child = Command::new(std::env::current_exe().expect("test binary is known"))
+        .args(["--exact", "sigterm_drains_the_logger"])
+        .args(["--quiet", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, collector)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("child runs");
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut line = String::new();
+    while line.trim() != "ready" {
+        line.clear();
+        let read = stdout.read_line(&mut line).expect("stdout is readable");
+        assert_ne!(read, 0, "child exited before being ready");
+    }
+
+    // Idling in the meantime, the child shouldn't use any CPU nor send its
+    // batch
+    socket
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .expect("read timeout can be set");
+    let mut buf = vec![0; 65_536];
+    assert!(
+        socket.recv(&mut buf).is_err(),
+        "records sent before SIGTERM"
+    );
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("read timeout can be set");
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .expect("kill runs");
+    assert!(status.success());
+
+    let mut messages = Vec::new();
+    while messages.len() < RECORDS as usize + 1 {
+        let len = socket.recv(&mut buf).expect("records are sent on SIGTERM");
+        let batch = std::str::from_utf8(&buf[..len]).expect("batch is UTF-8");
+        for line in batch.lines() {
+            let record: serde_json::Value = serde_json::from_str(line).expect("record is JSON");
+            messages.push(record["message"].as_str().unwrap_or_default().to_owned());
+        }
+    }
+    let mut expected: Vec<_> = (0..RECORDS).map(|n| format!("served {}", n)).collect();
+    expected.push("draining_started".to_owned());
+    assert_eq!(messages, expected);
+
+    let mut rest = String::new();
+    std::io::Read::read_to_string(&mut stdout, &mut rest).expect("stdout is readable");
+    assert!(child.wait().expect("child exits").success());
+    let cpu_ms: u64 = rest
+        .lines()
+        .find_map(|line| line.strip_prefix("cpu_ms="))
+        .unwrap_or_else(|| panic!("no CPU time in {:?}", rest))
+        .parse()
+        .expect("CPU time is a number");
+    assert!(cpu_ms < 500, "child used {}ms of CPU", cpu_ms);
+}
@@ -0,0 +1,92 @@
+//! Runs itself as a child process whose records the kernel refuses to send,
+//! to find them on its stderr
+use std::{process::Command, thread, time::Duration};
+use tracing::Level;
+use vinted_logger::{LogDestination, Target};
+
+const CHILD_ENV: &str = "VINTED_LOGGER_FALLBACK_CHILD";
+const PER_MINUTE: u32 = 60;
+const RECORDS: u32 = 70;
+
+fn log_undeliverable() -> ! {
+    vinted_logger::builder("svc-test", Target::UdpJson)
+        // Broadcasts are refused without SO_BROADCAST
+        .destination(LogDestination::Udp("255.255.255.255:9".to_owned()))
+        .fallback_per_minute(PER_MINUTE)
+        .stderr_echo(Level::WARN)
+        .try_init()
+        .expect("logger can be installed");
+
+    tracing::warn!("echoed");
+    for n in 0..RECORDS {
+        tracing::info!("undeliverable {}", n);
+    }
+    vinted_logger::flush(Duration::from_secs(5));
+    // A line of the budget is back after a second
+    thread::sleep(Duration::from_millis(1_100));
+    tracing::info!("after a second");
+    vinted_logger::flush(Duration::from_secs(5));
+
+    let stats = vinted_logger::logger_stats();
+    println!("fallback_emissions={}", stats.fallback_emissions);
+    // Before the harness reports the test
+    std::process::exit(0)
+}
+
+#[test]
+fn undeliverable_records_are_written_to_stderr() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        log_undeliverable();
+    }
+
+    let output = Command::new(std::env::current_exe().expect("test binary is known"))
+        .args(["--exact", "undeliverable_records_are_written_to_stderr"])
+        .args(["--quiet", "--nocapture", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .expect("child runs");
+    let stdout = String::from_utf8(output.stdout).expect("stdout is UTF-8");
+    let stderr = String::from_utf8(output.stderr).expect("stderr is UTF-8");
+    assert!(output.status.success(), "{}", stderr);
+
+    let fallback: Vec<_> = stderr
+        .lines()
+        .filter(|line| line.contains(" INFO svc-test: "))
+        .collect();
+    assert_eq!(fallback.len(), PER_MINUTE as usize + 1, "{}", stderr);
+    for (n, line) in fallback[..PER_MINUTE as usize].iter().enumerate() {
+        let (timestamp, rest) = line.split_once(' ').unwrap();
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(timestamp).is_ok(),
+            "{}",
+            line
+        );
+        assert_eq!(rest, format!("INFO svc-test: undeliverable {}", n));
+    }
+    assert!(fallback[PER_MINUTE as usize].ends_with("svc-test: after a second"));
+
+    let lines: Vec<_> = stderr.lines().collect();
+    let summary = format!(
+        "{} undelivered log records left out of stderr",
+        RECORDS - PER_MINUTE
+    );
+    let at = lines
+        .iter()
+        .position(|&line| line == summary)
+        .unwrap_or_else(|| panic!("no summary in {}", stderr));
+    assert!(
+        lines[at + 1].ends_with("svc-test: after a second"),
+        "{}",
+        stderr
+    );
+
+    // Echoed records aren't written twice
+    let echoed = lines.iter().filter(|line| line.contains("echoed")).count();
+    assert_eq!(echoed, 1, "{}", stderr);
+
+    assert!(
+        stdout.contains(&format!("fallback_emissions={}", PER_MINUTE + 1)),
+        "{}",
+        stdout
+    );
+}
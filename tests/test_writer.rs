@@ -0,0 +1,52 @@
+//! Runs itself as a child process, with and without the harness capturing
+//! output, which must hide records of passing tests only when it captures
+use std::process::Command;
+use vinted_logger::Target;
+
+const CHILD_ENV: &str = "VINTED_LOGGER_TEST_WRITER_CHILD";
+const TEST: &str = "records_of_passing_tests_are_captured";
+
+/// stdout and stderr of the test run as a child
+fn run_child(capture: bool) -> (String, String) {
+    let mut command = Command::new(std::env::current_exe().expect("test binary is known"));
+    command
+        .args(["--exact", TEST, "--quiet", "--test-threads=1"])
+        .env(CHILD_ENV, "1");
+    if !capture {
+        command.arg("--nocapture");
+    }
+    let output = command.output().expect("child runs");
+    assert!(output.status.success(), "{:?}", output);
+    (
+        String::from_utf8(output.stdout).expect("stdout is UTF-8"),
+        String::from_utf8(output.stderr).expect("stderr is UTF-8"),
+    )
+}
+
+#[test]
+fn records_of_passing_tests_are_captured() {
+    if std::env::var_os(CHILD_ENV).is_some() {
+        vinted_logger::builder("svc-test", Target::ConsoleJson)
+            .test_writer()
+            .split_streams()
+            .try_init()
+            .expect("logger can be installed");
+        tracing::info!("captured-info");
+        tracing::warn!("captured-warn");
+        return;
+    }
+
+    let (stdout, stderr) = run_child(true);
+    assert!(stdout.contains("test result: ok"), "{}", stdout);
+    assert!(!stdout.contains("captured-"), "{}", stdout);
+    assert!(!stderr.contains("captured-"), "{}", stderr);
+
+    let (stdout, stderr) = run_child(false);
+    let info = stdout
+        .lines()
+        .find(|line| line.contains("captured-info"))
+        .unwrap_or_else(|| panic!("INFO isn't on stdout: {}", stdout));
+    let record: serde_json::Value = serde_json::from_str(info).expect("record is JSON");
+    assert_eq!(record["level"], "INFO");
+    assert!(stderr.contains("captured-warn"), "{}", stderr);
+}
@@ -0,0 +1,179 @@
+//! Procedural macros re-exported by `vinted-logger`, see the macros there.
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span};
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    LitStr, Token,
+};
+
+/// The single format string accepted by the macros.
+struct Template(LitStr);
+
+impl Parse for Template {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let template = input.parse()?;
+        let _ = input.parse::<Option<Token![,]>>()?;
+        if !input.is_empty() {
+            return Err(input.error(
+                "expected only a format string, interpolate values inline by name, e.g. `\"user {user_id}\"`",
+            ));
+        }
+
+        Ok(Self(template))
+    }
+}
+
+/// Returns the names interpolated in a format string, in order of first use.
+fn interpolated_names(template: &str) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err("unterminated `{` in format string".to_owned()),
+                    }
+                }
+
+                let name = placeholder.split(':').next().unwrap_or_default().trim();
+                if name.is_empty() || name.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(format!(
+                        "positional argument `{{{}}}` is not supported, name the value instead, e.g. `{{user_id}}`",
+                        placeholder
+                    ));
+                }
+                if syn::parse_str::<Ident>(name).is_err() {
+                    return Err(format!(
+                        "`{}` is not an identifier, only local variables can be interpolated",
+                        name
+                    ));
+                }
+                if !names.iter().any(|known| known == name) {
+                    names.push(name.to_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(names)
+}
+
+fn expand(level: &str, input: TokenStream) -> TokenStream {
+    let Template(template) = syn::parse_macro_input!(input as Template);
+
+    let names = match interpolated_names(&template.value()) {
+        Ok(names) => names,
+        Err(e) => {
+            return syn::Error::new(template.span(), e)
+                .to_compile_error()
+                .into()
+        }
+    };
+    let fields = names.iter().map(|name| Ident::new(name, template.span()));
+    let level = Ident::new(level, Span::call_site());
+
+    quote!(::vinted_logger::__private::with_template(#template, || {
+        ::vinted_logger::__private::tracing::event!(
+            ::vinted_logger::__private::tracing::Level::#level,
+            #(
+                #fields = {
+                    use ::vinted_logger::__private::{ViaDisplay as _, ViaValue as _};
+                    (&&::vinted_logger::__private::FieldWrap(&#fields)).field()
+                },
+            )*
+            #template
+        )
+    }))
+    .into()
+}
+
+/// Same as [`infof!`] at TRACE level
+#[proc_macro]
+pub fn tracef(input: TokenStream) -> TokenStream {
+    expand("TRACE", input)
+}
+
+/// Same as [`infof!`] at DEBUG level
+#[proc_macro]
+pub fn debugf(input: TokenStream) -> TokenStream {
+    expand("DEBUG", input)
+}
+
+/// Logs an INFO event from a format string with inline named values
+///
+/// The rendered string becomes `message` and every interpolated value is also
+/// recorded as a separate field under its own name, keeping its type when it
+/// implements `tracing::Value` and recorded via `Display` otherwise:
+///
+/// ```ignore
+/// let user_id = 42;
+/// let count = 3;
+/// vinted_logger::infof!("user {user_id} bought {count} items");
+/// // {"message": "user 42 bought 3 items", "user_id": 42, "count": 3, ...}
+/// ```
+///
+/// Positional arguments (`{}`, `{0}`) and trailing arguments are rejected at
+/// compile time.
+#[proc_macro]
+pub fn infof(input: TokenStream) -> TokenStream {
+    expand("INFO", input)
+}
+
+/// Same as [`infof!`] at WARN level
+#[proc_macro]
+pub fn warnf(input: TokenStream) -> TokenStream {
+    expand("WARN", input)
+}
+
+/// Same as [`infof!`] at ERROR level
+#[proc_macro]
+pub fn errorf(input: TokenStream) -> TokenStream {
+    expand("ERROR", input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::interpolated_names;
+
+    #[test]
+    fn names_are_listed_once_in_order() {
+        assert_eq!(
+            interpolated_names("{b} {a:>4} {b:?} {{c}}").unwrap(),
+            ["b", "a"]
+        );
+        assert!(interpolated_names("no values").unwrap().is_empty());
+    }
+
+    #[test]
+    fn positional_arguments_are_rejected() {
+        for template in ["{}", "{0}", "{:?}", "{ }"] {
+            let err = interpolated_names(template).unwrap_err();
+            assert!(
+                err.starts_with("positional argument"),
+                "{}: {}",
+                template,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_placeholders_are_rejected() {
+        assert!(interpolated_names("{user.id}")
+            .unwrap_err()
+            .contains("not an identifier"));
+        assert!(interpolated_names("{user")
+            .unwrap_err()
+            .starts_with("unterminated"));
+    }
+}
@@ -0,0 +1,59 @@
+//! Maintenance tasks, run with `cargo xtask <task>`:
+//!
+//! - `regen-golden` rewrites `tests/golden/` from the current output, after
+//!   an intended change of the records
+//! - `regen-public-api` rewrites `tests/public_api.txt` from the current
+//!   sources, after an intended change of the public API
+
+use std::{
+    env,
+    path::Path,
+    process::{exit, Command},
+};
+
+fn main() {
+    match env::args().nth(1).as_deref() {
+        Some("regen-golden") => regen_golden(),
+        Some("regen-public-api") => regen_public_api(),
+        _ => {
+            eprintln!("usage: cargo xtask regen-golden | regen-public-api");
+            exit(2);
+        }
+    }
+}
+
+fn regen_golden() {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask is in the workspace");
+    let status = Command::new(cargo)
+        .current_dir(root)
+        .args(["test", "--package", "vinted-logger", "--lib", "golden::"])
+        .env("VINTED_REGEN_GOLDEN", "1")
+        .status()
+        .expect("cargo can be run");
+    if !status.success() {
+        exit(status.code().unwrap_or(1));
+    }
+    println!("tests/golden/ regenerated, review the changes with `git diff tests/golden`");
+}
+
+fn regen_public_api() {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask is in the workspace");
+    let status = Command::new(cargo)
+        .current_dir(root)
+        .args(["test", "--package", "vinted-logger", "--test", "public_api"])
+        .env("VINTED_REGEN_PUBLIC_API", "1")
+        .status()
+        .expect("cargo can be run");
+    if !status.success() {
+        exit(status.code().unwrap_or(1));
+    }
+    println!(
+        "tests/public_api.txt regenerated, review the changes with `git diff tests/public_api.txt`"
+    );
+}